@@ -0,0 +1,160 @@
+use crate::models::errors::DbError;
+use database::events::{
+    Event, CHAT_TYPING_CHANNEL, CREDENTIAL_REVOCATIONS_CHANNEL, EVENTS_CHANNEL,
+};
+use sqlx::postgres::PgListener;
+use sqlx::PgPool;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+/// How far a slow connection may fall behind before it replays events from the database
+const HUB_CAPACITY: usize = 1024;
+const LISTENER_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Clone)]
+pub enum HubEvent {
+    Chat(Arc<Event>),
+    /// A project event with the users who see it, looked up once for every connection
+    Project {
+        event: Arc<Event>,
+        audience: Arc<HashSet<Uuid>>,
+    },
+    /// Typing indicators are never stored, so they have no id and are not replayed
+    Typing {
+        chat_id: Uuid,
+        user_id: Uuid,
+    },
+    /// An access token, a session or an API key of the user has been revoked
+    Revoked {
+        user_id: Uuid,
+    },
+}
+
+/// Fans chat and project events out to the connections of this instance
+///
+/// Events are written by any instance and announced with Postgres `NOTIFY`,
+/// so every instance delivers the same events in the same order.
+pub struct EventHub {
+    pool: PgPool,
+    sender: broadcast::Sender<HubEvent>,
+}
+
+impl EventHub {
+    /// Starts listening to the events of every instance
+    pub fn start(pool: PgPool) -> Arc<Self> {
+        let (sender, _) = broadcast::channel(HUB_CAPACITY);
+        tokio::spawn(listen(pool.clone(), sender.clone()));
+
+        Arc::new(Self { pool, sender })
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<HubEvent> {
+        self.sender.subscribe()
+    }
+
+    pub async fn get_last_event_id(&self) -> Result<i64, DbError> {
+        database::events::get_last_event_id(&self.pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    pub async fn get_user_events(
+        &self,
+        user_id: Uuid,
+        after: i64,
+        limit: i64,
+    ) -> Result<Vec<Event>, DbError> {
+        database::events::get_user_events(&self.pool, user_id, after, limit)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// `None` for somebody who is not a member or a message of another chat
+    pub async fn mark_read(
+        &self,
+        chat_id: Uuid,
+        user_id: Uuid,
+        message_id: Uuid,
+    ) -> Result<Option<i64>, DbError> {
+        database::events::mark_chat_read(&self.pool, chat_id, user_id, message_id)
+            .await
+            .map_err(Into::into)
+    }
+
+    pub async fn send_typing(&self, chat_id: Uuid, user_id: Uuid) -> Result<(), DbError> {
+        database::events::notify_chat_typing(&self.pool, chat_id, user_id)
+            .await
+            .map_err(Into::into)
+    }
+}
+
+async fn listen(pool: PgPool, sender: broadcast::Sender<HubEvent>) {
+    loop {
+        if let Err(error) = forward_notifications(&pool, &sender).await {
+            tracing::error!("events listener error: {error}");
+        }
+        tokio::time::sleep(LISTENER_RETRY_DELAY).await;
+    }
+}
+
+async fn forward_notifications(
+    pool: &PgPool,
+    sender: &broadcast::Sender<HubEvent>,
+) -> Result<(), sqlx::Error> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener
+        .listen_all([
+            EVENTS_CHANNEL,
+            CHAT_TYPING_CHANNEL,
+            CREDENTIAL_REVOCATIONS_CHANNEL,
+        ])
+        .await?;
+
+    loop {
+        let notification = listener.recv().await?;
+        let event = match notification.channel() {
+            EVENTS_CHANNEL => {
+                let Ok(id) = notification.payload().parse() else {
+                    continue;
+                };
+                match load_event(pool, id).await {
+                    Ok(event) => event,
+                    Err(error) => {
+                        tracing::error!("event {id} error: {error}");
+                        continue;
+                    }
+                }
+            }
+            CREDENTIAL_REVOCATIONS_CHANNEL => match notification.payload().parse() {
+                Ok(user_id) => HubEvent::Revoked { user_id },
+                Err(_) => continue,
+            },
+            _ => match notification.payload().split_once(' ') {
+                Some((chat_id, user_id)) => match (chat_id.parse(), user_id.parse()) {
+                    (Ok(chat_id), Ok(user_id)) => HubEvent::Typing { chat_id, user_id },
+                    _ => continue,
+                },
+                None => continue,
+            },
+        };
+
+        // Fails only when nobody is connected
+        let _ = sender.send(event);
+    }
+}
+
+async fn load_event(pool: &PgPool, id: i64) -> Result<HubEvent, sqlx::Error> {
+    let event = Arc::new(database::events::get_event(pool, id).await?);
+    let Some(project_id) = event.project_id else {
+        return Ok(HubEvent::Chat(event));
+    };
+
+    let audience = database::events::get_project_audience(pool, project_id).await?;
+    Ok(HubEvent::Project {
+        event,
+        audience: Arc::new(audience.into_iter().collect()),
+    })
+}
@@ -0,0 +1,222 @@
+use lettre::address::AddressError;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::transport::smtp::AsyncSmtpTransport;
+use lettre::{AsyncTransport, Message, Tokio1Executor};
+use std::path::PathBuf;
+use std::sync::Arc;
+#[cfg(test)]
+use std::sync::Mutex;
+use uuid::Uuid;
+
+/// A mail to send, its body may hold secret links and tokens, so it is never traced
+#[derive(Clone, Debug, PartialEq)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Debug)]
+pub enum MailerError {
+    InvalidAddress(AddressError),
+    Build(lettre::error::Error),
+    Smtp(lettre::transport::smtp::Error),
+    Io(std::io::Error),
+}
+
+#[async_trait::async_trait]
+pub trait Mailer: Send + Sync + 'static {
+    async fn send(&self, email: Email) -> Result<(), MailerError>;
+}
+
+/// Picks SMTP when `SMTP_HOST` is set, otherwise drops mails into `MAIL_DIR`
+pub fn mailer_from_env() -> Arc<dyn Mailer> {
+    match std::env::var("SMTP_HOST") {
+        Ok(host) => {
+            let credentials = Credentials::new(
+                std::env::var("SMTP_USERNAME").expect("SMTP_USERNAME must be in environment"),
+                std::env::var("SMTP_PASSWORD").expect("SMTP_PASSWORD must be in environment"),
+            );
+
+            Arc::new(
+                SmtpMailer::new(host, credentials, mail_from()).expect("SMTP_HOST must be valid"),
+            )
+        }
+        Err(_) => Arc::new(FileMailer::new(
+            std::env::var("MAIL_DIR").expect("SMTP_HOST or MAIL_DIR must be in environment"),
+            mail_from(),
+        )),
+    }
+}
+
+fn mail_from() -> Mailbox {
+    std::env::var("MAIL_FROM")
+        .expect("MAIL_FROM must be in environment")
+        .parse()
+        .expect("MAIL_FROM must be a valid mailbox")
+}
+
+fn build_message(from: &Mailbox, email: Email) -> Result<Message, MailerError> {
+    Message::builder()
+        .from(from.clone())
+        .to(email.to.parse().map_err(MailerError::InvalidAddress)?)
+        .subject(email.subject)
+        .body(email.body)
+        .map_err(MailerError::Build)
+}
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    /// Connects to a relay over TLS
+    pub fn new(
+        host: impl AsRef<str>,
+        credentials: Credentials,
+        from: Mailbox,
+    ) -> Result<Self, MailerError> {
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::relay(host.as_ref())
+            .map_err(MailerError::Smtp)?
+            .credentials(credentials)
+            .build();
+
+        Ok(Self { transport, from })
+    }
+}
+
+#[async_trait::async_trait]
+impl Mailer for SmtpMailer {
+    #[tracing::instrument(skip(self, email), fields(to = %email.to, subject = %email.subject))]
+    async fn send(&self, email: Email) -> Result<(), MailerError> {
+        let message = build_message(&self.from, email)?;
+
+        self.transport
+            .send(message)
+            .await
+            .map(|_| ())
+            .map_err(MailerError::Smtp)
+    }
+}
+
+/// Writes every mail as an `.eml` file, meant for local development
+pub struct FileMailer {
+    directory: PathBuf,
+    from: Mailbox,
+}
+
+impl FileMailer {
+    pub fn new(directory: impl Into<PathBuf>, from: Mailbox) -> Self {
+        Self {
+            directory: directory.into(),
+            from,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Mailer for FileMailer {
+    #[tracing::instrument(skip(self, email), fields(to = %email.to, subject = %email.subject))]
+    async fn send(&self, email: Email) -> Result<(), MailerError> {
+        let message = build_message(&self.from, email)?;
+
+        std::fs::create_dir_all(&self.directory).map_err(MailerError::Io)?;
+        std::fs::write(
+            self.directory.join(std::format!("{}.eml", Uuid::new_v4())),
+            message.formatted(),
+        )
+        .map_err(MailerError::Io)
+    }
+}
+
+/// Keeps sent mails in memory, clones share them
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct InMemoryMailer {
+    emails: Arc<Mutex<Vec<Email>>>,
+}
+
+#[cfg(test)]
+impl InMemoryMailer {
+    /// Mails sent to a given address, the oldest first
+    pub fn emails_to(&self, to: impl AsRef<str>) -> Vec<Email> {
+        self.emails
+            .lock()
+            .expect("mailer lock is poisoned")
+            .iter()
+            .filter(|email| email.to == to.as_ref())
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+#[async_trait::async_trait]
+impl Mailer for InMemoryMailer {
+    #[tracing::instrument(skip(self, email), fields(to = %email.to, subject = %email.subject))]
+    async fn send(&self, email: Email) -> Result<(), MailerError> {
+        self.emails
+            .lock()
+            .expect("mailer lock is poisoned")
+            .push(email);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_email(to: &str) -> Email {
+        Email {
+            to: to.to_owned(),
+            subject: "subject".to_owned(),
+            body: "body".to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_mailer_keeps_emails_per_recipient() {
+        let mailer = InMemoryMailer::default();
+        let shared_mailer = mailer.clone();
+
+        shared_mailer
+            .send(create_email("first@test.test"))
+            .await
+            .expect("sent");
+        shared_mailer
+            .send(create_email("second@test.test"))
+            .await
+            .expect("sent");
+
+        assert_eq!(
+            mailer.emails_to("first@test.test"),
+            vec![create_email("first@test.test")]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_file_mailer_writes_eml_files() {
+        let directory = std::env::temp_dir().join(std::format!("mails-{}", Uuid::new_v4()));
+        let mailer = FileMailer::new(
+            &directory,
+            "Exchange <noreply@test.test>"
+                .parse()
+                .expect("valid mailbox"),
+        );
+
+        mailer
+            .send(create_email("first@test.test"))
+            .await
+            .expect("sent");
+
+        let files = std::fs::read_dir(&directory)
+            .expect("mail directory exists")
+            .collect::<Vec<_>>();
+        assert_eq!(files.len(), 1);
+
+        std::fs::remove_dir_all(directory).expect("mail directory is removed");
+    }
+}
@@ -1,13 +1,29 @@
-use std::{net::SocketAddr, time::Duration};
-
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use crate::blob_store::blob_store_from_env;
+use crate::event_hub::EventHub;
+use crate::mailer::mailer_from_env;
+use crate::models::audit::PgAuditLog;
+use crate::models::chat_members::PgChatMemberDb;
+use crate::models::chat_message::PgChatMessageDb;
+use crate::models::chats::PgChatDb;
+use crate::models::company::PgCompanyDb;
 use crate::models::project::PgProjectDb;
 use crate::models::user::PgUserDb;
+use crate::oidc::OidcProviders;
+use crate::rate_limit::RateLimiter;
 use crate::web_service::WebService;
 use dotenvy::dotenv;
 use opentelemetry::sdk;
 use sqlx::{postgres::PgPoolOptions, PgPool};
 
+mod blob_store;
+mod event_hub;
+mod mailer;
 mod models;
+mod oidc;
+mod policy;
+mod rate_limit;
 mod utils;
 mod web;
 mod web_service;
@@ -31,14 +47,32 @@ async fn main() {
     let pool = pg_pool().await.expect("failed to connect to postgres");
 
     let user_db = PgUserDb::new(pool.clone());
-    let project_db = PgProjectDb::new(pool);
-    let router = WebService::new(user_db, project_db).into_router();
+    let project_db = PgProjectDb::new(pool.clone());
+    let company_db = PgCompanyDb::new(pool.clone());
+    let chat_db = PgChatDb::new(pool.clone());
+    let chat_member_db = PgChatMemberDb::new(pool.clone());
+    let chat_message_db = PgChatMessageDb::new(pool.clone());
+    let router = WebService::new(
+        user_db,
+        project_db,
+        company_db,
+        chat_db,
+        chat_member_db,
+        chat_message_db,
+        mailer_from_env(),
+        Arc::new(PgAuditLog::new(pool.clone())),
+        OidcProviders::from_env(),
+        RateLimiter::from_env(pool.clone()),
+        blob_store_from_env(),
+        EventHub::start(pool),
+    )
+    .into_router();
 
     let addr = SocketAddr::from(([127, 0, 0, 1], 4000));
     tracing::info!("listening on http://{}", addr);
 
     axum::Server::bind(&addr)
-        .serve(router.into_make_service())
+        .serve(router.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .expect("failed to serve");
 
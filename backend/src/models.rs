@@ -1,6 +1,8 @@
-mod chat_members;
-mod chat_message;
+pub mod audit;
+pub mod chat_members;
+pub mod chat_message;
 pub mod chats;
+pub mod company;
 pub mod errors;
 pub mod project;
 pub mod user;
@@ -0,0 +1,49 @@
+use crate::models::errors::DbError;
+use database::audit_events::{AuditEvent, AuditEventFilter, AuditEventInput};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub type OwnedAuditEventInput = AuditEventInput<String, String>;
+
+/// Append-only record of who did what and when
+#[async_trait::async_trait]
+pub trait AuditLog: Send + Sync + 'static {
+    async fn record(&self, input: &OwnedAuditEventInput) -> Result<Uuid, DbError>;
+
+    async fn get_events(
+        &self,
+        filter: &AuditEventFilter,
+        limit: i64,
+    ) -> Result<Vec<AuditEvent>, DbError>;
+}
+
+pub struct PgAuditLog {
+    pool: PgPool,
+}
+
+impl PgAuditLog {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl AuditLog for PgAuditLog {
+    #[tracing::instrument(skip(self))]
+    async fn record(&self, input: &OwnedAuditEventInput) -> Result<Uuid, DbError> {
+        database::audit_events::insert_audit_event(&self.pool, input)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_events(
+        &self,
+        filter: &AuditEventFilter,
+        limit: i64,
+    ) -> Result<Vec<AuditEvent>, DbError> {
+        database::audit_events::get_audit_events(&self.pool, filter, limit)
+            .await
+            .map_err(Into::into)
+    }
+}
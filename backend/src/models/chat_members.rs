@@ -1,5 +1,5 @@
 use crate::models::errors::DbError;
-use database::chats::{ChatMember, ChatMemberRole};
+use database::chats::{ChatMember, ChatMemberProfile, ChatMemberRole};
 use sqlx::PgPool;
 use uuid::Uuid;
 
@@ -24,6 +24,24 @@ pub trait ChatMemberDb: Clone + Send + Sync + 'static {
         member: impl AsRef<str> + std::fmt::Debug + Send,
         role: ChatMemberRole,
     ) -> Result<Uuid, DbError>;
+
+    /// `None` for somebody who is in the chat already or banned
+    async fn add_chat_member(
+        &self,
+        chat_id: Uuid,
+        member: impl AsRef<str> + std::fmt::Debug + Send,
+        role: ChatMemberRole,
+    ) -> Result<Option<Uuid>, DbError>;
+
+    async fn get_chat_members(&self, chat_id: Uuid) -> Result<Vec<ChatMemberProfile>, DbError>;
+
+    /// 0 for somebody who is not a current member or the creator
+    async fn update_chat_member_role(
+        &self,
+        chat_id: Uuid,
+        member: impl AsRef<str> + std::fmt::Debug + Send,
+        role: ChatMemberRole,
+    ) -> Result<u64, DbError>;
 }
 
 #[async_trait::async_trait]
@@ -46,4 +64,35 @@ impl ChatMemberDb for PgChatMemberDb {
             .await
             .map_err(Into::into)
     }
+
+    #[tracing::instrument(skip(self))]
+    async fn add_chat_member(
+        &self,
+        chat_id: Uuid,
+        member: impl AsRef<str> + std::fmt::Debug + Send,
+        role: ChatMemberRole,
+    ) -> Result<Option<Uuid>, DbError> {
+        database::chats::add_chat_member(&self.pool, chat_id, member, role)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_chat_members(&self, chat_id: Uuid) -> Result<Vec<ChatMemberProfile>, DbError> {
+        database::chats::get_chat_members(&self.pool, chat_id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn update_chat_member_role(
+        &self,
+        chat_id: Uuid,
+        member: impl AsRef<str> + std::fmt::Debug + Send,
+        role: ChatMemberRole,
+    ) -> Result<u64, DbError> {
+        database::chats::update_chat_member_role(&self.pool, chat_id, member, role)
+            .await
+            .map_err(Into::into)
+    }
 }
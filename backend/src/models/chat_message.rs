@@ -1,5 +1,5 @@
 use crate::models::errors::DbError;
-use database::chats::ChatMessage;
+use database::chats::{ChatMessage, ChatMessageRevision};
 use sqlx::PgPool;
 use uuid::Uuid;
 
@@ -25,6 +25,62 @@ pub trait ChatMessageDb: Clone + Send + Sync + 'static {
         message: impl AsRef<str> + std::fmt::Debug + Send,
         parent_id: Option<Uuid>,
     ) -> Result<Uuid, DbError>;
+
+    /// The newest first, optionally before a given message
+    async fn get_chat_messages(
+        &self,
+        chat_id: Uuid,
+        before: Option<Uuid>,
+        limit: i64,
+    ) -> Result<Vec<ChatMessage>, DbError>;
+
+    /// The oldest first, after a given message
+    async fn get_chat_messages_after(
+        &self,
+        chat_id: Uuid,
+        after: Uuid,
+        limit: i64,
+    ) -> Result<Vec<ChatMessage>, DbError>;
+
+    /// A message between up to `limit` messages before and after it, the oldest first
+    async fn get_chat_message_context(
+        &self,
+        chat_id: Uuid,
+        id: Uuid,
+        limit: i64,
+    ) -> Result<Vec<ChatMessage>, DbError>;
+
+    /// Replies to a message and the replies to them, the oldest first
+    async fn get_chat_thread(
+        &self,
+        chat_id: Uuid,
+        parent_id: Uuid,
+        after: Option<Uuid>,
+        limit: i64,
+    ) -> Result<Vec<ChatMessage>, DbError>;
+
+    /// `None` for a message of another chat or sender and for a deleted one
+    async fn update_chat_message(
+        &self,
+        chat_id: Uuid,
+        id: Uuid,
+        sender_id: Uuid,
+        message: impl AsRef<str> + std::fmt::Debug + Send,
+    ) -> Result<Option<ChatMessage>, DbError>;
+
+    /// Soft deletes a message, returns 0 when it is gone already
+    async fn delete_chat_message(
+        &self,
+        chat_id: Uuid,
+        id: Uuid,
+        user_id: Uuid,
+    ) -> Result<u64, DbError>;
+
+    /// The oldest first
+    async fn get_chat_message_revisions(
+        &self,
+        message_id: Uuid,
+    ) -> Result<Vec<ChatMessageRevision>, DbError>;
 }
 
 #[async_trait::async_trait]
@@ -48,4 +104,88 @@ impl ChatMessageDb for PgChatMessageDb {
             .await
             .map_err(Into::into)
     }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_chat_messages(
+        &self,
+        chat_id: Uuid,
+        before: Option<Uuid>,
+        limit: i64,
+    ) -> Result<Vec<ChatMessage>, DbError> {
+        database::chats::get_chat_messages(&self.pool, chat_id, before, limit)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_chat_messages_after(
+        &self,
+        chat_id: Uuid,
+        after: Uuid,
+        limit: i64,
+    ) -> Result<Vec<ChatMessage>, DbError> {
+        database::chats::get_chat_messages_after(&self.pool, chat_id, after, limit)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_chat_message_context(
+        &self,
+        chat_id: Uuid,
+        id: Uuid,
+        limit: i64,
+    ) -> Result<Vec<ChatMessage>, DbError> {
+        database::chats::get_chat_message_context(&self.pool, chat_id, id, limit)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_chat_thread(
+        &self,
+        chat_id: Uuid,
+        parent_id: Uuid,
+        after: Option<Uuid>,
+        limit: i64,
+    ) -> Result<Vec<ChatMessage>, DbError> {
+        database::chats::get_chat_thread(&self.pool, chat_id, parent_id, after, limit)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn update_chat_message(
+        &self,
+        chat_id: Uuid,
+        id: Uuid,
+        sender_id: Uuid,
+        message: impl AsRef<str> + std::fmt::Debug + Send,
+    ) -> Result<Option<ChatMessage>, DbError> {
+        database::chats::update_chat_message(&self.pool, chat_id, id, sender_id, message)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn delete_chat_message(
+        &self,
+        chat_id: Uuid,
+        id: Uuid,
+        user_id: Uuid,
+    ) -> Result<u64, DbError> {
+        database::chats::delete_chat_message(&self.pool, chat_id, id, user_id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_chat_message_revisions(
+        &self,
+        message_id: Uuid,
+    ) -> Result<Vec<ChatMessageRevision>, DbError> {
+        database::chats::get_chat_message_revisions(&self.pool, message_id)
+            .await
+            .map_err(Into::into)
+    }
 }
@@ -25,6 +25,18 @@ pub trait ChatDb: Clone + Send + Sync + 'static {
         description: impl AsRef<str> + std::fmt::Debug + Send,
         avatar: impl AsRef<str> + std::fmt::Debug + Send,
     ) -> Result<Uuid, DbError>;
+
+    /// Members are aliases, the creator becomes `ChatMemberRole::Creator` and the rest members
+    async fn insert_chat_with_members(
+        &self,
+        r#type: ChatType,
+        title: String,
+        description: Option<String>,
+        creator: String,
+        members: Vec<String>,
+    ) -> Result<Uuid, DbError>;
+
+    async fn get_user_chats(&self, user_id: Uuid) -> Result<Vec<Chat>, DbError>;
 }
 
 #[async_trait::async_trait]
@@ -48,4 +60,32 @@ impl ChatDb for PgChatDb {
             .await
             .map_err(Into::into)
     }
+
+    #[tracing::instrument(skip(self))]
+    async fn insert_chat_with_members(
+        &self,
+        r#type: ChatType,
+        title: String,
+        description: Option<String>,
+        creator: String,
+        members: Vec<String>,
+    ) -> Result<Uuid, DbError> {
+        database::chats::insert_chat_with_members(
+            &self.pool,
+            r#type,
+            title,
+            description.as_deref(),
+            creator,
+            &members,
+        )
+        .await
+        .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_user_chats(&self, user_id: Uuid) -> Result<Vec<Chat>, DbError> {
+        database::chats::get_user_chats(&self.pool, user_id)
+            .await
+            .map_err(Into::into)
+    }
 }
@@ -0,0 +1,261 @@
+use crate::models::errors::DbError;
+use database::addresses::{Address, AddressInput};
+use database::companies::{Company, CompanyMemberProfile, CompanyMemberRole};
+use database::company_invitations::{CompanyInvitation, CompanyInvitationInput};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub type OwnedAddressInput = AddressInput<String, String, String, String, String, String, String>;
+
+pub type OwnedCompanyInvitationInput = CompanyInvitationInput<String, String>;
+
+#[derive(Clone)]
+pub struct PgCompanyDb {
+    pool: PgPool,
+}
+
+impl PgCompanyDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+pub trait CompanyDb: Clone + Send + Sync + 'static {
+    async fn get_company(&self, id: Uuid) -> Result<Company, DbError>;
+
+    async fn get_address(&self, id: Uuid) -> Result<Address, DbError>;
+
+    async fn insert_company(
+        &self,
+        name: String,
+        address: &OwnedAddressInput,
+        owner_id: Uuid,
+    ) -> Result<Uuid, DbError>;
+
+    async fn get_user_companies(&self, user_id: Uuid) -> Result<Vec<Company>, DbError>;
+
+    async fn update_company_name(&self, id: Uuid, name: String) -> Result<u64, DbError>;
+
+    async fn update_address(&self, id: Uuid, address: &OwnedAddressInput) -> Result<u64, DbError>;
+
+    async fn get_company_members(
+        &self,
+        company_id: Uuid,
+    ) -> Result<Vec<CompanyMemberProfile>, DbError>;
+
+    async fn insert_company_member(
+        &self,
+        company_id: Uuid,
+        user_id: Uuid,
+        role: CompanyMemberRole,
+    ) -> Result<Uuid, DbError>;
+
+    /// 0 for somebody who is not a member or the last owner
+    async fn update_company_member_role(
+        &self,
+        company_id: Uuid,
+        user_id: Uuid,
+        role: CompanyMemberRole,
+    ) -> Result<u64, DbError>;
+
+    /// 0 for somebody who is not a member or the last owner
+    async fn delete_company_member(&self, company_id: Uuid, user_id: Uuid) -> Result<u64, DbError>;
+
+    async fn insert_company_project(
+        &self,
+        company_id: Uuid,
+        project_id: Uuid,
+    ) -> Result<Uuid, DbError>;
+
+    async fn delete_company_project(
+        &self,
+        company_id: Uuid,
+        project_id: Uuid,
+    ) -> Result<u64, DbError>;
+
+    async fn insert_company_invitation(
+        &self,
+        input: &OwnedCompanyInvitationInput,
+    ) -> Result<Uuid, DbError>;
+
+    async fn get_company_invitation_by_hash(
+        &self,
+        token_hash: String,
+    ) -> Result<CompanyInvitation, DbError>;
+
+    async fn get_pending_company_invitations(
+        &self,
+        company_id: Uuid,
+    ) -> Result<Vec<CompanyInvitation>, DbError>;
+
+    async fn delete_company_invitation(&self, company_id: Uuid, id: Uuid) -> Result<u64, DbError>;
+
+    /// `None` when the invitation can not be accepted anymore
+    async fn accept_company_invitation(
+        &self,
+        id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<Uuid>, DbError>;
+}
+
+#[async_trait::async_trait]
+impl CompanyDb for PgCompanyDb {
+    #[tracing::instrument(skip(self))]
+    async fn get_company(&self, id: Uuid) -> Result<Company, DbError> {
+        database::companies::get_company(&self.pool, id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_address(&self, id: Uuid) -> Result<Address, DbError> {
+        database::addresses::get_addresses(&self.pool, id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self, address))]
+    async fn insert_company(
+        &self,
+        name: String,
+        address: &OwnedAddressInput,
+        owner_id: Uuid,
+    ) -> Result<Uuid, DbError> {
+        database::companies::insert_company_with_owner(&self.pool, name, address, owner_id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_user_companies(&self, user_id: Uuid) -> Result<Vec<Company>, DbError> {
+        database::companies::get_user_companies(&self.pool, user_id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn update_company_name(&self, id: Uuid, name: String) -> Result<u64, DbError> {
+        database::companies::update_company_name(&self.pool, id, name)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self, address))]
+    async fn update_address(&self, id: Uuid, address: &OwnedAddressInput) -> Result<u64, DbError> {
+        database::addresses::update_addresses(&self.pool, id, address)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_company_members(
+        &self,
+        company_id: Uuid,
+    ) -> Result<Vec<CompanyMemberProfile>, DbError> {
+        database::companies::get_company_members(&self.pool, company_id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn insert_company_member(
+        &self,
+        company_id: Uuid,
+        user_id: Uuid,
+        role: CompanyMemberRole,
+    ) -> Result<Uuid, DbError> {
+        database::companies::insert_company_member(&self.pool, user_id, company_id, role)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn update_company_member_role(
+        &self,
+        company_id: Uuid,
+        user_id: Uuid,
+        role: CompanyMemberRole,
+    ) -> Result<u64, DbError> {
+        database::companies::update_company_member_role(&self.pool, company_id, user_id, role)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn delete_company_member(&self, company_id: Uuid, user_id: Uuid) -> Result<u64, DbError> {
+        database::companies::delete_company_member(&self.pool, company_id, user_id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn insert_company_project(
+        &self,
+        company_id: Uuid,
+        project_id: Uuid,
+    ) -> Result<Uuid, DbError> {
+        database::projects::insert_company_project(&self.pool, company_id, project_id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn delete_company_project(
+        &self,
+        company_id: Uuid,
+        project_id: Uuid,
+    ) -> Result<u64, DbError> {
+        database::projects::delete_company_project(&self.pool, company_id, project_id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn insert_company_invitation(
+        &self,
+        input: &OwnedCompanyInvitationInput,
+    ) -> Result<Uuid, DbError> {
+        database::company_invitations::insert_company_invitation(&self.pool, input)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_company_invitation_by_hash(
+        &self,
+        token_hash: String,
+    ) -> Result<CompanyInvitation, DbError> {
+        database::company_invitations::get_company_invitation_by_hash(&self.pool, token_hash)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_pending_company_invitations(
+        &self,
+        company_id: Uuid,
+    ) -> Result<Vec<CompanyInvitation>, DbError> {
+        database::company_invitations::get_pending_company_invitations(&self.pool, company_id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn delete_company_invitation(&self, company_id: Uuid, id: Uuid) -> Result<u64, DbError> {
+        database::company_invitations::delete_company_invitation(&self.pool, company_id, id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn accept_company_invitation(
+        &self,
+        id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<Uuid>, DbError> {
+        database::company_invitations::accept_company_invitation(&self.pool, id, user_id)
+            .await
+            .map_err(Into::into)
+    }
+}
@@ -4,6 +4,8 @@ use std::borrow::Cow;
 pub enum DbError {
     NotFoundError,
     UnavailableTryAgain,
+    /// A unique index, named by the variant, already has a given value
+    UniqueViolation(String),
     UnexpectedError(String),
 }
 
@@ -15,6 +17,10 @@ impl From<sqlx::Error> for DbError {
             sqlx::Error::Database(db_error) if db_error.code() == Some(Cow::Borrowed("40001")) => {
                 DbError::UnavailableTryAgain
             }
+            // pg error: unique_violation
+            sqlx::Error::Database(db_error) if db_error.code() == Some(Cow::Borrowed("23505")) => {
+                DbError::UniqueViolation(db_error.constraint().unwrap_or_default().to_owned())
+            }
             _ => Self::UnexpectedError(value.to_string()),
         }
     }
@@ -1,8 +1,15 @@
 use crate::models::errors::DbError;
-use database::projects::{Project, ProjectInput};
+use database::project_invitations::{ProjectInvitation, ProjectInvitationInput};
+use database::projects::{
+    Project, ProjectCursor, ProjectFilter, ProjectInput, ProjectMemberProfile, ProjectMemberRole,
+    ProjectSort, ProjectUpdate, SortOrder,
+};
+use sqlx::types::time::PrimitiveDateTime;
 use sqlx::PgPool;
 use uuid::Uuid;
 
+pub type OwnedProjectInvitationInput = ProjectInvitationInput<String, String>;
+
 #[derive(Clone)]
 pub struct PgProjectDb {
     pool: PgPool,
@@ -22,6 +29,72 @@ pub trait ProjectDb: Clone + Send + Sync + 'static {
         &self,
         user_input: &ProjectInput<String, String>,
     ) -> Result<Uuid, DbError>;
+
+    async fn get_projects(
+        &self,
+        user_id: Uuid,
+        filter: &ProjectFilter,
+        sort: ProjectSort,
+        order: SortOrder,
+        after: Option<ProjectCursor>,
+        limit: i64,
+    ) -> Result<Vec<Project>, DbError>;
+
+    /// `None` when the project changed after `updated_at` or is gone
+    async fn update_project(
+        &self,
+        id: &Uuid,
+        update: &ProjectUpdate,
+        updated_at: PrimitiveDateTime,
+        user_id: Uuid,
+    ) -> Result<Option<Project>, DbError>;
+
+    async fn delete_project(&self, id: &Uuid, user_id: Uuid) -> Result<u64, DbError>;
+
+    async fn get_project_members(
+        &self,
+        project_id: Uuid,
+    ) -> Result<Vec<ProjectMemberProfile>, DbError>;
+
+    async fn insert_project_member(
+        &self,
+        project_id: Uuid,
+        user_id: Uuid,
+        role: ProjectMemberRole,
+    ) -> Result<Uuid, DbError>;
+
+    async fn update_project_member_role(
+        &self,
+        project_id: Uuid,
+        user_id: Uuid,
+        role: ProjectMemberRole,
+    ) -> Result<u64, DbError>;
+
+    async fn delete_project_member(&self, project_id: Uuid, user_id: Uuid) -> Result<u64, DbError>;
+
+    async fn insert_project_invitation(
+        &self,
+        input: &OwnedProjectInvitationInput,
+    ) -> Result<Uuid, DbError>;
+
+    async fn get_project_invitation_by_hash(
+        &self,
+        token_hash: String,
+    ) -> Result<ProjectInvitation, DbError>;
+
+    async fn get_pending_project_invitations(
+        &self,
+        project_id: Uuid,
+    ) -> Result<Vec<ProjectInvitation>, DbError>;
+
+    async fn delete_project_invitation(&self, project_id: Uuid, id: Uuid) -> Result<u64, DbError>;
+
+    /// `None` when the invitation can not be accepted anymore
+    async fn accept_project_invitation(
+        &self,
+        id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<Uuid>, DbError>;
 }
 
 #[async_trait::async_trait]
@@ -42,4 +115,128 @@ impl ProjectDb for PgProjectDb {
             .await
             .map_err(Into::into)
     }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_projects(
+        &self,
+        user_id: Uuid,
+        filter: &ProjectFilter,
+        sort: ProjectSort,
+        order: SortOrder,
+        after: Option<ProjectCursor>,
+        limit: i64,
+    ) -> Result<Vec<Project>, DbError> {
+        database::projects::get_projects(&self.pool, user_id, filter, sort, order, after, limit)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn update_project(
+        &self,
+        id: &Uuid,
+        update: &ProjectUpdate,
+        updated_at: PrimitiveDateTime,
+        user_id: Uuid,
+    ) -> Result<Option<Project>, DbError> {
+        database::projects::update_project(&self.pool, id, update, updated_at, user_id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn delete_project(&self, id: &Uuid, user_id: Uuid) -> Result<u64, DbError> {
+        database::projects::delete_project(&self.pool, id, user_id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_project_members(
+        &self,
+        project_id: Uuid,
+    ) -> Result<Vec<ProjectMemberProfile>, DbError> {
+        database::projects::get_project_members(&self.pool, project_id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn insert_project_member(
+        &self,
+        project_id: Uuid,
+        user_id: Uuid,
+        role: ProjectMemberRole,
+    ) -> Result<Uuid, DbError> {
+        database::projects::insert_project_member(&self.pool, project_id, user_id, role)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn update_project_member_role(
+        &self,
+        project_id: Uuid,
+        user_id: Uuid,
+        role: ProjectMemberRole,
+    ) -> Result<u64, DbError> {
+        database::projects::update_project_member_role(&self.pool, project_id, user_id, role)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn delete_project_member(&self, project_id: Uuid, user_id: Uuid) -> Result<u64, DbError> {
+        database::projects::delete_project_member(&self.pool, project_id, user_id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn insert_project_invitation(
+        &self,
+        input: &OwnedProjectInvitationInput,
+    ) -> Result<Uuid, DbError> {
+        database::project_invitations::insert_project_invitation(&self.pool, input)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_project_invitation_by_hash(
+        &self,
+        token_hash: String,
+    ) -> Result<ProjectInvitation, DbError> {
+        database::project_invitations::get_project_invitation_by_hash(&self.pool, token_hash)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_pending_project_invitations(
+        &self,
+        project_id: Uuid,
+    ) -> Result<Vec<ProjectInvitation>, DbError> {
+        database::project_invitations::get_pending_project_invitations(&self.pool, project_id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn delete_project_invitation(&self, project_id: Uuid, id: Uuid) -> Result<u64, DbError> {
+        database::project_invitations::delete_project_invitation(&self.pool, project_id, id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn accept_project_invitation(
+        &self,
+        id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<Uuid>, DbError> {
+        database::project_invitations::accept_project_invitation(&self.pool, id, user_id)
+            .await
+            .map_err(Into::into)
+    }
 }
@@ -1,5 +1,16 @@
 use crate::models::errors::DbError;
-use database::users::{User, UserInput};
+use database::access::{ChatAccess, CompanyAccess, ProjectAccess};
+use database::api_keys::{ApiKey, ApiKeyInput};
+use database::email_change_tokens::{EmailChangeToken, EmailChangeTokenInput};
+use database::oidc_login_states::{OidcLoginState, OidcLoginStateInput};
+use database::password_reset_tokens::{PasswordResetToken, PasswordResetTokenInput};
+use database::refresh_tokens::{RefreshToken, RefreshTokenInput};
+use database::revoked_tokens::RevokedTokenInput;
+use database::sessions::{Session, SessionInput};
+use database::user_data::{DeletedUserData, UserData};
+use database::user_identities::UserIdentityInput;
+use database::users::{User, UserInput, UserProfileUpdate};
+use sqlx::types::time::PrimitiveDateTime;
 use sqlx::PgPool;
 use uuid::Uuid;
 
@@ -14,19 +25,15 @@ impl PgUserDb {
     }
 }
 
-pub type OwnedUser = UserInput<
-    String,
-    String,
-    String,
-    String,
-    String,
-    String,
-    String,
-    String,
-    String,
-    String,
-    String,
->;
+pub type OwnedSessionInput = SessionInput<String, String, String>;
+
+pub type OwnedUserIdentityInput = UserIdentityInput<String, String, String>;
+
+pub type OwnedOidcLoginStateInput = OidcLoginStateInput<String, String, String, String, String>;
+
+pub type OwnedApiKeyInput = ApiKeyInput<String, String, String>;
+
+pub type OwnedEmailChangeTokenInput = EmailChangeTokenInput<String, String>;
 
 #[async_trait::async_trait]
 pub trait UserDb: Clone + Send + Sync + 'static {
@@ -35,18 +42,157 @@ pub trait UserDb: Clone + Send + Sync + 'static {
         email: impl AsRef<str> + std::fmt::Debug + Send,
     ) -> Result<User, DbError>;
 
-    async fn insert_user(&self, user_input: &OwnedUser) -> Result<Uuid, DbError>;
+    async fn insert_user(&self, user_input: &UserInput) -> Result<Uuid, DbError>;
 
     async fn get_user(&self, id: &Uuid) -> Result<User, DbError>;
 
-    async fn get_access_token(&self, id: &Uuid) -> Result<String, DbError>;
+    async fn update_password_hash(&self, id: &Uuid, password_hash: String) -> Result<u64, DbError>;
 
-    async fn update_user_token(
+    async fn update_user_profile(
         &self,
         id: &Uuid,
-        token: impl AsRef<str> + std::fmt::Debug + Send,
-        refresh_token: impl AsRef<str> + std::fmt::Debug + Send,
+        update: &UserProfileUpdate,
+    ) -> Result<u64, DbError>;
+
+    async fn update_user_avatar(
+        &self,
+        id: &Uuid,
+        avatar: Option<String>,
+    ) -> Result<Option<String>, DbError>;
+
+    async fn update_email(&self, id: &Uuid, email: String) -> Result<u64, DbError>;
+
+    async fn verify_email(&self, id: &Uuid, email: String) -> Result<u64, DbError>;
+
+    async fn insert_refresh_token(
+        &self,
+        input: &RefreshTokenInput<String>,
+    ) -> Result<Uuid, DbError>;
+
+    async fn get_refresh_token_by_hash(
+        &self,
+        token_hash: impl AsRef<str> + std::fmt::Debug + Send,
+    ) -> Result<RefreshToken, DbError>;
+
+    async fn use_refresh_token(&self, id: Uuid) -> Result<u64, DbError>;
+
+    async fn insert_session(&self, input: &OwnedSessionInput) -> Result<Uuid, DbError>;
+
+    async fn get_active_sessions(&self, user_id: Uuid) -> Result<Vec<Session>, DbError>;
+
+    async fn touch_session(&self, id: Uuid) -> Result<u64, DbError>;
+
+    async fn revoke_session(&self, id: Uuid, user_id: Uuid) -> Result<u64, DbError>;
+
+    async fn revoke_user_sessions(&self, user_id: Uuid) -> Result<u64, DbError>;
+
+    async fn revoke_other_user_sessions(
+        &self,
+        user_id: Uuid,
+        session_id: Uuid,
+    ) -> Result<u64, DbError>;
+
+    async fn revoke_access_token(&self, input: &RevokedTokenInput) -> Result<u64, DbError>;
+
+    async fn restore_access_token(&self, id: Uuid) -> Result<u64, DbError>;
+
+    async fn is_access_token_revoked(
+        &self,
+        token_id: Uuid,
+        session_id: Uuid,
+    ) -> Result<bool, DbError>;
+
+    async fn insert_password_reset_token(
+        &self,
+        input: &PasswordResetTokenInput<String>,
+    ) -> Result<Uuid, DbError>;
+
+    async fn get_password_reset_token_by_hash(
+        &self,
+        token_hash: impl AsRef<str> + std::fmt::Debug + Send,
+    ) -> Result<PasswordResetToken, DbError>;
+
+    async fn reset_password(&self, id: Uuid, password_hash: String) -> Result<u64, DbError>;
+
+    async fn count_password_reset_tokens_since(
+        &self,
+        user_id: Uuid,
+        since: PrimitiveDateTime,
+    ) -> Result<i64, DbError>;
+
+    async fn insert_email_change_token(
+        &self,
+        input: &OwnedEmailChangeTokenInput,
+    ) -> Result<Uuid, DbError>;
+
+    async fn get_email_change_token_by_hash(
+        &self,
+        token_hash: impl AsRef<str> + std::fmt::Debug + Send,
+    ) -> Result<EmailChangeToken, DbError>;
+
+    async fn use_email_change_token(&self, id: Uuid) -> Result<u64, DbError>;
+
+    async fn set_totp_secret(&self, user_id: Uuid, secret: String) -> Result<u64, DbError>;
+
+    async fn enable_totp(
+        &self,
+        user_id: Uuid,
+        step: i64,
+        recovery_code_hashes: &[String],
     ) -> Result<u64, DbError>;
+
+    async fn use_totp_step(&self, user_id: Uuid, step: i64) -> Result<u64, DbError>;
+
+    async fn use_recovery_code(&self, user_id: Uuid, code_hash: String) -> Result<u64, DbError>;
+
+    async fn get_user_by_identity(&self, issuer: String, subject: String) -> Result<User, DbError>;
+
+    async fn insert_user_identity(&self, input: &OwnedUserIdentityInput) -> Result<Uuid, DbError>;
+
+    async fn insert_user_with_identity(
+        &self,
+        user_input: &UserInput,
+        issuer: String,
+        subject: String,
+        email_verified: bool,
+    ) -> Result<Uuid, DbError>;
+
+    async fn insert_oidc_login_state(
+        &self,
+        input: &OwnedOidcLoginStateInput,
+    ) -> Result<Uuid, DbError>;
+
+    async fn take_oidc_login_state(&self, state_hash: String) -> Result<OidcLoginState, DbError>;
+
+    async fn insert_api_key(&self, input: &OwnedApiKeyInput) -> Result<Uuid, DbError>;
+
+    async fn get_active_api_keys(&self, user_id: Uuid) -> Result<Vec<ApiKey>, DbError>;
+
+    async fn use_api_key(&self, key_hash: String) -> Result<ApiKey, DbError>;
+
+    async fn revoke_api_key(&self, id: Uuid, user_id: Uuid) -> Result<u64, DbError>;
+
+    async fn is_api_key_revoked(&self, id: Uuid) -> Result<bool, DbError>;
+
+    async fn get_project_access(
+        &self,
+        project_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<ProjectAccess, DbError>;
+
+    async fn get_company_access(
+        &self,
+        company_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<CompanyAccess, DbError>;
+
+    async fn get_chat_access(&self, chat_id: Uuid, user_id: Uuid) -> Result<ChatAccess, DbError>;
+
+    async fn is_admin(&self, user_id: Uuid) -> Result<bool, DbError>;
+
+    async fn get_user_data(&self, user_id: Uuid) -> Result<UserData, DbError>;
+
+    async fn delete_user(&self, user_id: Uuid) -> Result<DeletedUserData, DbError>;
 }
 
 #[async_trait::async_trait]
@@ -62,7 +208,7 @@ impl UserDb for PgUserDb {
     }
 
     #[tracing::instrument(skip(self))]
-    async fn insert_user(&self, user_input: &OwnedUser) -> Result<Uuid, DbError> {
+    async fn insert_user(&self, user_input: &UserInput) -> Result<Uuid, DbError> {
         database::users::insert_user(&self.pool, user_input)
             .await
             .map_err(Into::into)
@@ -75,21 +221,378 @@ impl UserDb for PgUserDb {
             .map_err(Into::into)
     }
 
+    #[tracing::instrument(skip(self, password_hash))]
+    async fn update_password_hash(&self, id: &Uuid, password_hash: String) -> Result<u64, DbError> {
+        database::users::update_password_hash(&self.pool, id, password_hash)
+            .await
+            .map_err(Into::into)
+    }
+
     #[tracing::instrument(skip(self))]
-    async fn get_access_token(&self, id: &Uuid) -> Result<String, DbError> {
-        database::users::get_access_token(&self.pool, id)
+    async fn update_user_profile(
+        &self,
+        id: &Uuid,
+        update: &UserProfileUpdate,
+    ) -> Result<u64, DbError> {
+        database::users::update_user_profile(&self.pool, id, update)
             .await
             .map_err(Into::into)
     }
 
     #[tracing::instrument(skip(self))]
-    async fn update_user_token(
+    async fn update_user_avatar(
         &self,
         id: &Uuid,
-        token: impl AsRef<str> + std::fmt::Debug + Send,
-        refresh_token: impl AsRef<str> + std::fmt::Debug + Send,
+        avatar: Option<String>,
+    ) -> Result<Option<String>, DbError> {
+        database::users::update_user_avatar(&self.pool, id, avatar.as_deref())
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn update_email(&self, id: &Uuid, email: String) -> Result<u64, DbError> {
+        database::users::update_email(&self.pool, id, email)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn verify_email(&self, id: &Uuid, email: String) -> Result<u64, DbError> {
+        database::users::verify_email(&self.pool, id, email)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn insert_refresh_token(
+        &self,
+        input: &RefreshTokenInput<String>,
+    ) -> Result<Uuid, DbError> {
+        database::refresh_tokens::insert_refresh_token(&self.pool, input)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_refresh_token_by_hash(
+        &self,
+        token_hash: impl AsRef<str> + std::fmt::Debug + Send,
+    ) -> Result<RefreshToken, DbError> {
+        database::refresh_tokens::get_refresh_token_by_hash(&self.pool, token_hash)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn use_refresh_token(&self, id: Uuid) -> Result<u64, DbError> {
+        database::refresh_tokens::use_refresh_token(&self.pool, id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn insert_session(&self, input: &OwnedSessionInput) -> Result<Uuid, DbError> {
+        database::sessions::insert_session(&self.pool, input)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_active_sessions(&self, user_id: Uuid) -> Result<Vec<Session>, DbError> {
+        database::sessions::get_active_sessions(&self.pool, user_id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn touch_session(&self, id: Uuid) -> Result<u64, DbError> {
+        database::sessions::touch_session(&self.pool, id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn revoke_session(&self, id: Uuid, user_id: Uuid) -> Result<u64, DbError> {
+        database::sessions::revoke_session(&self.pool, id, user_id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn revoke_user_sessions(&self, user_id: Uuid) -> Result<u64, DbError> {
+        database::sessions::revoke_user_sessions(&self.pool, user_id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn revoke_other_user_sessions(
+        &self,
+        user_id: Uuid,
+        session_id: Uuid,
+    ) -> Result<u64, DbError> {
+        database::sessions::revoke_other_user_sessions(&self.pool, user_id, session_id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn revoke_access_token(&self, input: &RevokedTokenInput) -> Result<u64, DbError> {
+        database::revoked_tokens::insert_revoked_token(&self.pool, input)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn restore_access_token(&self, id: Uuid) -> Result<u64, DbError> {
+        database::revoked_tokens::delete_revoked_token(&self.pool, id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn is_access_token_revoked(
+        &self,
+        token_id: Uuid,
+        session_id: Uuid,
+    ) -> Result<bool, DbError> {
+        database::revoked_tokens::is_token_revoked(&self.pool, token_id, session_id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn insert_password_reset_token(
+        &self,
+        input: &PasswordResetTokenInput<String>,
+    ) -> Result<Uuid, DbError> {
+        database::password_reset_tokens::insert_password_reset_token(&self.pool, input)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_password_reset_token_by_hash(
+        &self,
+        token_hash: impl AsRef<str> + std::fmt::Debug + Send,
+    ) -> Result<PasswordResetToken, DbError> {
+        database::password_reset_tokens::get_password_reset_token_by_hash(&self.pool, token_hash)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self, password_hash))]
+    async fn reset_password(&self, id: Uuid, password_hash: String) -> Result<u64, DbError> {
+        database::password_reset_tokens::reset_password(&self.pool, id, password_hash)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn count_password_reset_tokens_since(
+        &self,
+        user_id: Uuid,
+        since: PrimitiveDateTime,
+    ) -> Result<i64, DbError> {
+        database::password_reset_tokens::count_password_reset_tokens_since(
+            &self.pool, user_id, since,
+        )
+        .await
+        .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn insert_email_change_token(
+        &self,
+        input: &OwnedEmailChangeTokenInput,
+    ) -> Result<Uuid, DbError> {
+        database::email_change_tokens::insert_email_change_token(&self.pool, input)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_email_change_token_by_hash(
+        &self,
+        token_hash: impl AsRef<str> + std::fmt::Debug + Send,
+    ) -> Result<EmailChangeToken, DbError> {
+        database::email_change_tokens::get_email_change_token_by_hash(&self.pool, token_hash)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn use_email_change_token(&self, id: Uuid) -> Result<u64, DbError> {
+        database::email_change_tokens::use_email_change_token(&self.pool, id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self, secret))]
+    async fn set_totp_secret(&self, user_id: Uuid, secret: String) -> Result<u64, DbError> {
+        database::mfa::set_totp_secret(&self.pool, user_id, secret)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self, recovery_code_hashes))]
+    async fn enable_totp(
+        &self,
+        user_id: Uuid,
+        step: i64,
+        recovery_code_hashes: &[String],
     ) -> Result<u64, DbError> {
-        database::users::update_user_token(&self.pool, id, token, refresh_token)
+        database::mfa::enable_totp(&self.pool, user_id, step, recovery_code_hashes)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn use_totp_step(&self, user_id: Uuid, step: i64) -> Result<u64, DbError> {
+        database::mfa::use_totp_step(&self.pool, user_id, step)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self, code_hash))]
+    async fn use_recovery_code(&self, user_id: Uuid, code_hash: String) -> Result<u64, DbError> {
+        database::mfa::use_recovery_code(&self.pool, user_id, code_hash)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_user_by_identity(&self, issuer: String, subject: String) -> Result<User, DbError> {
+        database::user_identities::get_user_by_identity(&self.pool, issuer, subject)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn insert_user_identity(&self, input: &OwnedUserIdentityInput) -> Result<Uuid, DbError> {
+        database::user_identities::insert_user_identity(&self.pool, input)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn insert_user_with_identity(
+        &self,
+        user_input: &UserInput,
+        issuer: String,
+        subject: String,
+        email_verified: bool,
+    ) -> Result<Uuid, DbError> {
+        database::user_identities::insert_user_with_identity(
+            &self.pool,
+            user_input,
+            issuer,
+            subject,
+            email_verified,
+        )
+        .await
+        .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self, input))]
+    async fn insert_oidc_login_state(
+        &self,
+        input: &OwnedOidcLoginStateInput,
+    ) -> Result<Uuid, DbError> {
+        database::oidc_login_states::insert_oidc_login_state(&self.pool, input)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn take_oidc_login_state(&self, state_hash: String) -> Result<OidcLoginState, DbError> {
+        database::oidc_login_states::take_oidc_login_state(&self.pool, state_hash)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self, input))]
+    async fn insert_api_key(&self, input: &OwnedApiKeyInput) -> Result<Uuid, DbError> {
+        database::api_keys::insert_api_key(&self.pool, input)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_active_api_keys(&self, user_id: Uuid) -> Result<Vec<ApiKey>, DbError> {
+        database::api_keys::get_active_api_keys(&self.pool, user_id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self, key_hash))]
+    async fn use_api_key(&self, key_hash: String) -> Result<ApiKey, DbError> {
+        database::api_keys::use_api_key(&self.pool, key_hash)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn revoke_api_key(&self, id: Uuid, user_id: Uuid) -> Result<u64, DbError> {
+        database::api_keys::revoke_api_key(&self.pool, id, user_id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn is_api_key_revoked(&self, id: Uuid) -> Result<bool, DbError> {
+        database::api_keys::is_api_key_revoked(&self.pool, id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_project_access(
+        &self,
+        project_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<ProjectAccess, DbError> {
+        database::access::get_project_access(&self.pool, project_id, user_id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_company_access(
+        &self,
+        company_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<CompanyAccess, DbError> {
+        database::access::get_company_access(&self.pool, company_id, user_id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_chat_access(&self, chat_id: Uuid, user_id: Uuid) -> Result<ChatAccess, DbError> {
+        database::access::get_chat_access(&self.pool, chat_id, user_id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn is_admin(&self, user_id: Uuid) -> Result<bool, DbError> {
+        database::admins::is_admin(&self.pool, user_id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_user_data(&self, user_id: Uuid) -> Result<UserData, DbError> {
+        database::user_data::get_user_data(&self.pool, user_id)
+            .await
+            .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn delete_user(&self, user_id: Uuid) -> Result<DeletedUserData, DbError> {
+        database::user_data::delete_user(&self.pool, user_id)
             .await
             .map_err(Into::into)
     }
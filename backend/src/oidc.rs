@@ -0,0 +1,564 @@
+use base64::engine::{general_purpose, GeneralPurpose};
+use base64::Engine;
+use jsonwebtoken::jwk::{Jwk, JwkSet};
+use jsonwebtoken::{DecodingKey, Validation};
+use reqwest::Url;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock, RwLock};
+
+const BASE_64_URL: GeneralPurpose = general_purpose::URL_SAFE_NO_PAD;
+
+const SCOPES: &str = "openid email profile";
+
+#[derive(Debug)]
+pub enum OidcError {
+    Http(reqwest::Error),
+    InvalidUrl(String),
+    IssuerMismatch(String),
+    TokenRequest(String),
+    Jwt(jsonwebtoken::errors::Error),
+    UnknownKey,
+    NonceMismatch,
+}
+
+/// A provider as configured with `OIDC_<NAME>_*` variables
+#[derive(Debug, Clone)]
+pub struct OidcProviderConfig {
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    /// Public clients rely on PKCE alone
+    pub client_secret: Option<String>,
+}
+
+/// The part of a discovery document we use
+#[derive(Debug, Clone, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// Claims of a validated ID token
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub given_name: Option<String>,
+    pub family_name: Option<String>,
+    pub locale: Option<String>,
+    nonce: Option<String>,
+}
+
+/// `code_challenge` of a PKCE verifier for the `S256` method
+pub fn pkce_challenge(code_verifier: impl AsRef<str>) -> String {
+    BASE_64_URL.encode(Sha256::digest(code_verifier.as_ref().as_bytes()))
+}
+
+pub struct OidcProvider {
+    config: OidcProviderConfig,
+    /// Fetched on the first login through the provider
+    metadata: OnceLock<ProviderMetadata>,
+    /// Refetched when a token is signed with a key it does not have
+    jwk_set: RwLock<Option<JwkSet>>,
+    http: reqwest::Client,
+}
+
+impl OidcProvider {
+    pub fn new(config: OidcProviderConfig) -> Self {
+        Self {
+            config,
+            metadata: OnceLock::new(),
+            jwk_set: RwLock::new(None),
+            http: reqwest::Client::new(),
+        }
+    }
+
+    async fn metadata(&self) -> Result<&ProviderMetadata, OidcError> {
+        if let Some(metadata) = self.metadata.get() {
+            return Ok(metadata);
+        }
+
+        let url = std::format!(
+            "{}/.well-known/openid-configuration",
+            self.config.issuer.trim_end_matches('/')
+        );
+        let metadata = self
+            .http
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(OidcError::Http)?
+            .json::<ProviderMetadata>()
+            .await
+            .map_err(OidcError::Http)?;
+        // A document served for another issuer would let it mint our ID tokens
+        if metadata.issuer != self.config.issuer {
+            return Err(OidcError::IssuerMismatch(metadata.issuer));
+        }
+
+        // Concurrent first logins may both fetch it, either copy is fine
+        Ok(self.metadata.get_or_init(|| metadata))
+    }
+
+    /// Where to send a user to sign in, the authorization code flow with PKCE
+    pub async fn authorization_url(
+        &self,
+        redirect_url: impl AsRef<str>,
+        state: impl AsRef<str>,
+        nonce: impl AsRef<str>,
+        code_challenge: impl AsRef<str>,
+    ) -> Result<String, OidcError> {
+        let metadata = self.metadata().await?;
+
+        Url::parse_with_params(
+            &metadata.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", &self.config.client_id),
+                ("redirect_uri", redirect_url.as_ref()),
+                ("scope", SCOPES),
+                ("state", state.as_ref()),
+                ("nonce", nonce.as_ref()),
+                ("code_challenge", code_challenge.as_ref()),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map(String::from)
+        .map_err(|error| OidcError::InvalidUrl(error.to_string()))
+    }
+
+    /// Redeems an authorization code and validates the ID token it is exchanged for
+    pub async fn exchange_code(
+        &self,
+        code: impl AsRef<str>,
+        code_verifier: impl AsRef<str>,
+        redirect_url: impl AsRef<str>,
+        nonce: impl AsRef<str>,
+    ) -> Result<IdTokenClaims, OidcError> {
+        let metadata = self.metadata().await?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code.as_ref()),
+            ("redirect_uri", redirect_url.as_ref()),
+            ("client_id", &self.config.client_id),
+            ("code_verifier", code_verifier.as_ref()),
+        ];
+        if let Some(client_secret) = &self.config.client_secret {
+            form.push(("client_secret", client_secret));
+        }
+
+        let response = self
+            .http
+            .post(&metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .map_err(OidcError::Http)?;
+        if !response.status().is_success() {
+            let error = response.text().await.map_err(OidcError::Http)?;
+            return Err(OidcError::TokenRequest(error));
+        }
+        let token_response = response
+            .json::<TokenResponse>()
+            .await
+            .map_err(OidcError::Http)?;
+
+        self.validate_id_token(metadata, &token_response.id_token, nonce)
+            .await
+    }
+
+    /// The key a token is signed with, the JWKS is fetched again on a miss as keys rotate
+    async fn jwk(&self, metadata: &ProviderMetadata, kid: Option<&str>) -> Result<Jwk, OidcError> {
+        let find = |jwk_set: &JwkSet| {
+            match kid {
+                Some(kid) => jwk_set.find(kid),
+                None if jwk_set.keys.len() == 1 => jwk_set.keys.first(),
+                None => None,
+            }
+            .cloned()
+        };
+
+        let cached = self
+            .jwk_set
+            .read()
+            .expect("jwk set lock")
+            .as_ref()
+            .and_then(find);
+        if let Some(jwk) = cached {
+            return Ok(jwk);
+        }
+
+        let jwk_set = self
+            .http
+            .get(&metadata.jwks_uri)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(OidcError::Http)?
+            .json::<JwkSet>()
+            .await
+            .map_err(OidcError::Http)?;
+        let jwk = find(&jwk_set);
+        *self.jwk_set.write().expect("jwk set lock") = Some(jwk_set);
+
+        jwk.ok_or(OidcError::UnknownKey)
+    }
+
+    /// Checks the signature against the provider's JWKS, then the issuer, audience and nonce
+    async fn validate_id_token(
+        &self,
+        metadata: &ProviderMetadata,
+        id_token: &str,
+        nonce: impl AsRef<str>,
+    ) -> Result<IdTokenClaims, OidcError> {
+        let header = jsonwebtoken::decode_header(id_token).map_err(OidcError::Jwt)?;
+        let jwk = self.jwk(metadata, header.kid.as_deref()).await?;
+        let decoding_key = DecodingKey::from_jwk(&jwk).map_err(OidcError::Jwt)?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&metadata.issuer]);
+        validation.set_audience(&[&self.config.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "sub", "aud"]);
+
+        let claims = jsonwebtoken::decode::<IdTokenClaims>(id_token, &decoding_key, &validation)
+            .map_err(OidcError::Jwt)?
+            .claims;
+        if claims.nonce.as_deref() != Some(nonce.as_ref()) {
+            return Err(OidcError::NonceMismatch);
+        }
+
+        Ok(claims)
+    }
+}
+
+/// Identity providers users can sign in with, by name
+pub struct OidcProviders {
+    providers: HashMap<String, OidcProvider>,
+    redirect_url: String,
+}
+
+impl OidcProviders {
+    pub fn new(configs: Vec<OidcProviderConfig>, redirect_url: impl Into<String>) -> Self {
+        Self {
+            providers: configs
+                .into_iter()
+                .map(|config| (config.name.clone(), OidcProvider::new(config)))
+                .collect(),
+            redirect_url: redirect_url.into(),
+        }
+    }
+
+    /// Reads names from `OIDC_PROVIDERS`, then `OIDC_<NAME>_ISSUER`, `OIDC_<NAME>_CLIENT_ID`
+    /// and optional `OIDC_<NAME>_CLIENT_SECRET` of each
+    pub fn from_env() -> Arc<Self> {
+        let env = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+
+        let configs = env("OIDC_PROVIDERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                let prefix = std::format!("OIDC_{}", name.to_uppercase());
+                let required = |suffix: &str| {
+                    env(&std::format!("{prefix}_{suffix}"))
+                        .unwrap_or_else(|| panic!("{prefix}_{suffix} must be in environment"))
+                };
+
+                OidcProviderConfig {
+                    name: name.to_owned(),
+                    issuer: required("ISSUER"),
+                    client_id: required("CLIENT_ID"),
+                    client_secret: env(&std::format!("{prefix}_CLIENT_SECRET")),
+                }
+            })
+            .collect();
+
+        Arc::new(Self::new(
+            configs,
+            env("OIDC_REDIRECT_URL").expect("OIDC_REDIRECT_URL must be in environment"),
+        ))
+    }
+
+    pub fn get(&self, name: impl AsRef<str>) -> Option<&OidcProvider> {
+        self.providers.get(name.as_ref())
+    }
+
+    /// The frontend page providers send users back to
+    pub fn redirect_url(&self) -> &str {
+        &self.redirect_url
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::utils::tokens::{encode_claims, generate_opaque_token, TokenKey, TokenKeys};
+    use axum::extract::State;
+    use axum::http::StatusCode;
+    use axum::routing::{get, post};
+    use axum::{Form, Json, Router};
+    use lazy_static::lazy_static;
+    use ring::rand::SystemRandom;
+    use ring::signature::Ed25519KeyPair;
+    use serde::Serialize;
+    use std::sync::Mutex;
+    use time::OffsetDateTime;
+
+    pub const MOCK_PROVIDER: &str = "mock";
+    const MOCK_CLIENT_ID: &str = "exchange";
+    const MOCK_CLIENT_SECRET: &str = "mock-secret";
+
+    lazy_static! {
+        /// Runs on its own thread, so it outlives the runtime of any single test
+        pub static ref MOCK_ISSUER: MockIssuer = MockIssuer::start();
+    }
+
+    /// An account at the mock provider
+    #[derive(Debug, Clone, Serialize)]
+    pub struct MockIdentity {
+        pub sub: String,
+        pub email: String,
+        pub email_verified: bool,
+        pub given_name: Option<String>,
+        pub family_name: Option<String>,
+        pub locale: Option<String>,
+    }
+
+    struct PendingAuthorization {
+        identity: MockIdentity,
+        client_id: String,
+        redirect_uri: String,
+        nonce: String,
+        code_challenge: String,
+    }
+
+    struct MockState {
+        issuer: String,
+        keys: TokenKeys,
+        pending: Mutex<HashMap<String, PendingAuthorization>>,
+    }
+
+    #[derive(Serialize)]
+    struct MockIdTokenClaims {
+        iss: String,
+        aud: String,
+        iat: i64,
+        exp: i64,
+        nonce: String,
+        #[serde(flatten)]
+        identity: MockIdentity,
+    }
+
+    /// A local OpenID Connect issuer, it signs everybody in without asking
+    pub struct MockIssuer {
+        state: Arc<MockState>,
+    }
+
+    impl MockIssuer {
+        fn start() -> Self {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("free port");
+            listener
+                .set_nonblocking(true)
+                .expect("non blocking listener");
+            let issuer = std::format!("http://{}", listener.local_addr().expect("bound"));
+
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).expect("key pair");
+            let key = TokenKey::parse(std::format!(
+                "mock:EdDSA:{}",
+                general_purpose::STANDARD.encode(pkcs8.as_ref())
+            ))
+            .expect("valid key");
+
+            let state = Arc::new(MockState {
+                issuer,
+                keys: TokenKeys::new(vec![key]).expect("a key"),
+                pending: Mutex::new(HashMap::new()),
+            });
+
+            let router = Router::new()
+                .route("/.well-known/openid-configuration", get(discovery))
+                .route("/jwks", get(jwks))
+                .route("/token", post(token))
+                .with_state(state.clone());
+            std::thread::spawn(move || {
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .expect("runtime")
+                    .block_on(async move {
+                        axum::Server::from_tcp(listener)
+                            .expect("server")
+                            .serve(router.into_make_service())
+                            .await
+                            .expect("mock issuer is serving")
+                    })
+            });
+
+            Self { state }
+        }
+
+        pub fn provider_config(&self) -> OidcProviderConfig {
+            OidcProviderConfig {
+                name: MOCK_PROVIDER.to_owned(),
+                issuer: self.state.issuer.clone(),
+                client_id: MOCK_CLIENT_ID.to_owned(),
+                client_secret: Some(MOCK_CLIENT_SECRET.to_owned()),
+            }
+        }
+
+        /// Signs an identity in as a browser sent to `authorization_url` would,
+        /// returns the `code` and `state` it is redirected back with
+        pub fn authorize(
+            &self,
+            authorization_url: &str,
+            identity: MockIdentity,
+        ) -> (String, String) {
+            let url = Url::parse(authorization_url).expect("valid url");
+            let params = url.query_pairs().into_owned().collect::<HashMap<_, _>>();
+            let param = |name: &str| params.get(name).cloned().expect("a parameter");
+            assert_eq!(param("code_challenge_method"), "S256");
+
+            let code = generate_opaque_token();
+            self.state.pending.lock().expect("lock").insert(
+                code.clone(),
+                PendingAuthorization {
+                    identity,
+                    client_id: param("client_id"),
+                    redirect_uri: param("redirect_uri"),
+                    nonce: param("nonce"),
+                    code_challenge: param("code_challenge"),
+                },
+            );
+
+            (code, param("state"))
+        }
+    }
+
+    async fn discovery(State(state): State<Arc<MockState>>) -> Json<serde_json::Value> {
+        Json(serde_json::json!({
+            "issuer": state.issuer,
+            "authorization_endpoint": std::format!("{}/authorize", state.issuer),
+            "token_endpoint": std::format!("{}/token", state.issuer),
+            "jwks_uri": std::format!("{}/jwks", state.issuer),
+        }))
+    }
+
+    async fn jwks(State(state): State<Arc<MockState>>) -> Json<JwkSet> {
+        Json(state.keys.jwk_set())
+    }
+
+    async fn token(
+        State(state): State<Arc<MockState>>,
+        Form(form): Form<HashMap<String, String>>,
+    ) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+        let invalid_grant = || {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({ "error": "invalid_grant" })),
+            )
+        };
+        let field = |name: &str| form.get(name).map(String::as_str).unwrap_or_default();
+
+        let pending = state
+            .pending
+            .lock()
+            .expect("lock")
+            .remove(field("code"))
+            .ok_or_else(invalid_grant)?;
+        if field("grant_type") != "authorization_code"
+            || field("client_id") != pending.client_id
+            || field("client_secret") != MOCK_CLIENT_SECRET
+            || field("redirect_uri") != pending.redirect_uri
+            || pkce_challenge(field("code_verifier")) != pending.code_challenge
+        {
+            return Err(invalid_grant());
+        }
+
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let claims = MockIdTokenClaims {
+            iss: state.issuer.clone(),
+            aud: pending.client_id,
+            iat: now,
+            exp: now + 60,
+            nonce: pending.nonce,
+            identity: pending.identity,
+        };
+        let id_token = encode_claims(&claims, state.keys.signing_key()).expect("signed");
+
+        Ok(Json(serde_json::json!({
+            "access_token": "mock",
+            "token_type": "Bearer",
+            "id_token": id_token,
+        })))
+    }
+
+    async fn sign_in(provider: &OidcProvider) -> Result<IdTokenClaims, OidcError> {
+        let code_verifier = generate_opaque_token();
+        let redirect_url = "http://localhost/oidc/callback";
+        let authorization_url = provider
+            .authorization_url(
+                redirect_url,
+                "state",
+                "nonce",
+                pkce_challenge(&code_verifier),
+            )
+            .await?;
+        let identity = MockIdentity {
+            sub: generate_opaque_token(),
+            email: "ada@test.test".to_owned(),
+            email_verified: true,
+            given_name: None,
+            family_name: None,
+            locale: None,
+        };
+        let (code, _) = MOCK_ISSUER.authorize(&authorization_url, identity);
+
+        provider
+            .exchange_code(code, code_verifier, redirect_url, "nonce")
+            .await
+    }
+
+    #[tokio::test]
+    async fn should_cache_keys_and_refetch_them_on_a_miss() {
+        let provider = OidcProvider::new(MOCK_ISSUER.provider_config());
+        sign_in(&provider).await.expect("signed in");
+        let cached_keys = |provider: &OidcProvider| {
+            provider
+                .jwk_set
+                .read()
+                .expect("jwk set lock")
+                .as_ref()
+                .map(|jwk_set| jwk_set.keys.len())
+        };
+        assert_eq!(cached_keys(&provider), Some(1));
+
+        // As if the provider rotated its keys since
+        *provider.jwk_set.write().expect("jwk set lock") = Some(JwkSet { keys: vec![] });
+        sign_in(&provider).await.expect("signed in");
+        assert_eq!(cached_keys(&provider), Some(1));
+    }
+
+    #[test]
+    fn test_pkce_challenge() {
+        // RFC 7636, appendix B
+        assert_eq!(
+            pkce_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use crate::models::errors::DbError;
+use crate::models::user::UserDb;
+use database::access::{ChatAccess, CompanyAccess, ProjectAccess};
+use database::chats::{ChatMemberRole, ChatType};
+use database::companies::CompanyMemberRole;
+use database::projects::ProjectMemberRole;
+use uuid::Uuid;
+
+/// Something a user wants to do with a resource
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Read,
+    Update,
+    Delete,
+    ManageMembers,
+    PostMessage,
+    /// Delete messages of other members
+    Moderate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    Project(Uuid),
+    Company(Uuid),
+    Chat(Uuid),
+}
+
+/// Follows `ProjectMemberRole`, only the owner deletes a project and members of its companies only read it
+pub fn project_allows(access: &ProjectAccess, action: Action) -> bool {
+    let role = access.role;
+    match action {
+        Action::Read => access.is_owner || role.is_some() || access.is_company_member,
+        Action::Update => {
+            access.is_owner
+                || matches!(
+                    role,
+                    Some(ProjectMemberRole::Owner | ProjectMemberRole::Editor)
+                )
+        }
+        Action::ManageMembers => access.is_owner || role == Some(ProjectMemberRole::Owner),
+        Action::Delete => access.is_owner,
+        Action::PostMessage | Action::Moderate => false,
+    }
+}
+
+/// Follows `CompanyMemberRole`, admins run a company and only owners delete it
+pub fn company_allows(access: &CompanyAccess, action: Action) -> bool {
+    let Some(role) = access.role else {
+        return false;
+    };
+
+    match action {
+        Action::Read => true,
+        Action::Update | Action::ManageMembers => {
+            matches!(role, CompanyMemberRole::Owner | CompanyMemberRole::Admin)
+        }
+        Action::Delete => role == CompanyMemberRole::Owner,
+        Action::PostMessage | Action::Moderate => false,
+    }
+}
+
+/// Follows `ChatMemberRole`, only admins post to channels and nobody who left or is banned gets in
+pub fn chat_allows(access: &ChatAccess, action: Action) -> bool {
+    let Some(role) = access.role else {
+        return false;
+    };
+
+    match (role, action) {
+        (ChatMemberRole::Left | ChatMemberRole::Banned, _) => false,
+        (ChatMemberRole::Creator, _) => true,
+        (ChatMemberRole::Admin, Action::Delete) => false,
+        (ChatMemberRole::Admin, _) => true,
+        (ChatMemberRole::Member, Action::Read) => true,
+        (ChatMemberRole::Member, Action::PostMessage) => access.chat_type != ChatType::Channel,
+        (ChatMemberRole::Member, _) => false,
+    }
+}
+
+/// Answers whether a user may do an action on a resource
+///
+/// Fails with `DbError::NotFoundError` when the resource does not exist.
+pub async fn is_allowed(
+    user_db: &impl UserDb,
+    user_id: Uuid,
+    action: Action,
+    resource: Resource,
+) -> Result<bool, DbError> {
+    match resource {
+        Resource::Project(project_id) => user_db
+            .get_project_access(project_id, user_id)
+            .await
+            .map(|access| project_allows(&access, action)),
+        Resource::Company(company_id) => user_db
+            .get_company_access(company_id, user_id)
+            .await
+            .map(|access| company_allows(&access, action)),
+        Resource::Chat(chat_id) => user_db
+            .get_chat_access(chat_id, user_id)
+            .await
+            .map(|access| chat_allows(&access, action)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ACTIONS: [Action; 6] = [
+        Action::Read,
+        Action::Update,
+        Action::Delete,
+        Action::ManageMembers,
+        Action::PostMessage,
+        Action::Moderate,
+    ];
+
+    fn allowed(allows: impl Fn(Action) -> bool) -> Vec<Action> {
+        ACTIONS
+            .into_iter()
+            .filter(|action| allows(*action))
+            .collect()
+    }
+
+    #[test]
+    fn test_project_roles() {
+        let access = |is_owner, role, is_company_member| ProjectAccess {
+            is_owner,
+            role,
+            is_company_member,
+        };
+
+        assert_eq!(
+            allowed(|action| project_allows(&access(true, None, false), action)),
+            vec![
+                Action::Read,
+                Action::Update,
+                Action::Delete,
+                Action::ManageMembers
+            ]
+        );
+        assert_eq!(
+            allowed(|action| project_allows(
+                &access(false, Some(ProjectMemberRole::Owner), false),
+                action
+            )),
+            vec![Action::Read, Action::Update, Action::ManageMembers]
+        );
+        assert_eq!(
+            allowed(|action| project_allows(
+                &access(false, Some(ProjectMemberRole::Editor), false),
+                action
+            )),
+            vec![Action::Read, Action::Update]
+        );
+        assert_eq!(
+            allowed(|action| project_allows(
+                &access(false, Some(ProjectMemberRole::Viewer), false),
+                action
+            )),
+            vec![Action::Read]
+        );
+        assert_eq!(
+            allowed(|action| project_allows(&access(false, None, true), action)),
+            vec![Action::Read]
+        );
+        assert!(allowed(|action| project_allows(&access(false, None, false), action)).is_empty());
+    }
+
+    #[test]
+    fn test_company_roles() {
+        let access = |role| CompanyAccess { role };
+
+        assert_eq!(
+            allowed(|action| company_allows(&access(Some(CompanyMemberRole::Owner)), action)),
+            vec![
+                Action::Read,
+                Action::Update,
+                Action::Delete,
+                Action::ManageMembers
+            ]
+        );
+        assert_eq!(
+            allowed(|action| company_allows(&access(Some(CompanyMemberRole::Admin)), action)),
+            vec![Action::Read, Action::Update, Action::ManageMembers]
+        );
+        assert_eq!(
+            allowed(|action| company_allows(&access(Some(CompanyMemberRole::Member)), action)),
+            vec![Action::Read]
+        );
+        assert!(allowed(|action| company_allows(&access(None), action)).is_empty());
+    }
+
+    #[test]
+    fn test_chat_roles() {
+        let access = |chat_type, role| ChatAccess { chat_type, role };
+
+        assert_eq!(
+            allowed(|action| chat_allows(
+                &access(ChatType::Group, Some(ChatMemberRole::Creator)),
+                action
+            )),
+            ACTIONS.to_vec()
+        );
+        assert_eq!(
+            allowed(|action| chat_allows(
+                &access(ChatType::Group, Some(ChatMemberRole::Admin)),
+                action
+            )),
+            vec![
+                Action::Read,
+                Action::Update,
+                Action::ManageMembers,
+                Action::PostMessage,
+                Action::Moderate
+            ]
+        );
+        assert_eq!(
+            allowed(|action| chat_allows(
+                &access(ChatType::Group, Some(ChatMemberRole::Member)),
+                action
+            )),
+            vec![Action::Read, Action::PostMessage]
+        );
+        assert_eq!(
+            allowed(|action| chat_allows(
+                &access(ChatType::Channel, Some(ChatMemberRole::Member)),
+                action
+            )),
+            vec![Action::Read]
+        );
+
+        for role in [
+            None,
+            Some(ChatMemberRole::Left),
+            Some(ChatMemberRole::Banned),
+        ] {
+            assert!(
+                allowed(|action| chat_allows(&access(ChatType::Group, role), action)).is_empty()
+            );
+        }
+    }
+}
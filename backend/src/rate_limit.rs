@@ -0,0 +1,459 @@
+use crate::models::errors::DbError;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use time::{Duration, OffsetDateTime};
+
+/// A token bucket, `capacity` requests at once and `refill_per_minute` more every minute
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BucketLimit {
+    pub capacity: u32,
+    pub refill_per_minute: u32,
+}
+
+impl BucketLimit {
+    /// Panics unless a bucket holds a request and refills, an empty one would never let anyone in
+    pub fn new(capacity: u32, refill_per_minute: u32) -> Self {
+        assert!(capacity >= 1, "a rate limit capacity must be at least 1");
+        assert!(
+            refill_per_minute >= 1,
+            "a rate limit must refill at least 1 request per minute"
+        );
+
+        Self {
+            capacity,
+            refill_per_minute,
+        }
+    }
+
+    fn refill_per_second(&self) -> f64 {
+        f64::from(self.refill_per_minute) / 60.0
+    }
+}
+
+/// Locks an account after `threshold` failed logins in a row
+///
+/// The lock lasts `base` and twice as long after every further failure, up to `max`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LockoutPolicy {
+    pub threshold: i32,
+    pub base: Duration,
+    pub max: Duration,
+}
+
+impl LockoutPolicy {
+    /// How long to lock an account after a given number of failed logins
+    pub fn lockout(&self, failures: i32) -> Option<Duration> {
+        if failures < self.threshold {
+            return None;
+        }
+
+        let doublings = (failures - self.threshold).min(30) as u32;
+        let lockout = self
+            .base
+            .checked_mul(2i32.saturating_pow(doublings))
+            .unwrap_or(self.max);
+
+        Some(lockout.min(self.max))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimits {
+    pub per_ip: BucketLimit,
+    pub per_account: BucketLimit,
+    pub lockout: LockoutPolicy,
+}
+
+fn env_number<T: std::str::FromStr>(name: &str) -> T {
+    std::env::var(name)
+        .unwrap_or_else(|_| panic!("{name} must be in environment"))
+        .parse::<T>()
+        .unwrap_or_else(|_| panic!("{name} must be a number"))
+}
+
+impl RateLimits {
+    pub fn from_env() -> Self {
+        Self {
+            per_ip: BucketLimit::new(
+                env_number("RATE_LIMIT_IP_CAPACITY"),
+                env_number("RATE_LIMIT_IP_PER_MINUTE"),
+            ),
+            per_account: BucketLimit::new(
+                env_number("RATE_LIMIT_ACCOUNT_CAPACITY"),
+                env_number("RATE_LIMIT_ACCOUNT_PER_MINUTE"),
+            ),
+            lockout: LockoutPolicy {
+                threshold: env_number("LOGIN_LOCKOUT_THRESHOLD"),
+                base: Duration::seconds(env_number("LOGIN_LOCKOUT_BASE_IN_SECS")),
+                max: Duration::seconds(env_number("LOGIN_LOCKOUT_MAX_IN_SECS")),
+            },
+        }
+    }
+}
+
+/// Limits of login routes and the store keeping their counters
+pub struct RateLimiter {
+    pub store: Arc<dyn RateLimitStore>,
+    pub limits: RateLimits,
+}
+
+impl RateLimiter {
+    pub fn new(store: Arc<dyn RateLimitStore>, limits: RateLimits) -> Self {
+        Self { store, limits }
+    }
+
+    pub fn from_env(pool: PgPool) -> Arc<Self> {
+        Arc::new(Self::new(
+            rate_limit_store_from_env(pool),
+            RateLimits::from_env(),
+        ))
+    }
+}
+
+/// Where rate limit counters live, instances behind a load balancer need a shared store
+#[async_trait::async_trait]
+pub trait RateLimitStore: Send + Sync + 'static {
+    /// Takes a token from a bucket, returns how long to wait when there is none left
+    async fn take_token(&self, key: &str, limit: BucketLimit) -> Result<Option<Duration>, DbError>;
+
+    /// How long logins into an account stay locked, `None` when they are not
+    async fn get_lockout(&self, account: &str) -> Result<Option<Duration>, DbError>;
+
+    /// Counts a failed login, returns a lockout when the policy calls for one
+    async fn record_failed_login(
+        &self,
+        account: &str,
+        policy: LockoutPolicy,
+    ) -> Result<Option<Duration>, DbError>;
+
+    async fn reset_failed_logins(&self, account: &str) -> Result<(), DbError>;
+}
+
+/// Picks Postgres when `RATE_LIMIT_STORE` is `postgres`, otherwise keeps counters in memory
+pub fn rate_limit_store_from_env(pool: PgPool) -> Arc<dyn RateLimitStore> {
+    match std::env::var("RATE_LIMIT_STORE").as_deref() {
+        Ok("postgres") => Arc::new(PgRateLimitStore::new(pool)),
+        _ => Arc::new(InMemoryRateLimitStore::default()),
+    }
+}
+
+/// How often the in memory store drops counters which have nothing to limit any more
+const SWEEP_INTERVAL: Duration = Duration::minutes(1);
+
+struct Bucket {
+    tokens: f64,
+    updated_at: OffsetDateTime,
+    limit: BucketLimit,
+}
+
+impl Bucket {
+    fn tokens_at(&self, now: OffsetDateTime) -> f64 {
+        let elapsed = (now - self.updated_at).as_seconds_f64();
+
+        f64::from(self.limit.capacity).min(self.tokens + elapsed * self.limit.refill_per_second())
+    }
+}
+
+struct LoginFailures {
+    failures: i32,
+    locked_until: Option<OffsetDateTime>,
+    /// Failures are forgotten once a lockout has been over for as long as the longest one
+    forget_at: OffsetDateTime,
+}
+
+/// Counters of a single instance, lost on restart
+///
+/// Full buckets and forgotten failures are dropped every `SWEEP_INTERVAL`, they behave
+/// like missing ones.
+#[derive(Default)]
+pub struct InMemoryRateLimitStore {
+    buckets: Mutex<HashMap<String, Bucket>>,
+    login_failures: Mutex<HashMap<String, LoginFailures>>,
+    swept_at: Mutex<Option<OffsetDateTime>>,
+}
+
+impl InMemoryRateLimitStore {
+    fn sweep(&self, now: OffsetDateTime) {
+        self.buckets
+            .lock()
+            .expect("rate limit buckets lock")
+            .retain(|_, bucket| bucket.tokens_at(now) < f64::from(bucket.limit.capacity));
+        self.login_failures
+            .lock()
+            .expect("login failures lock")
+            .retain(|_, failures| failures.forget_at > now);
+    }
+
+    fn sweep_if_due(&self, now: OffsetDateTime) {
+        let mut swept_at = self.swept_at.lock().expect("sweep lock");
+        match *swept_at {
+            Some(swept_at) if now - swept_at < SWEEP_INTERVAL => {}
+            _ => {
+                *swept_at = Some(now);
+                self.sweep(now);
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimitStore for InMemoryRateLimitStore {
+    async fn take_token(&self, key: &str, limit: BucketLimit) -> Result<Option<Duration>, DbError> {
+        let now = OffsetDateTime::now_utc();
+        self.sweep_if_due(now);
+        let mut buckets = self.buckets.lock().expect("rate limit buckets lock");
+
+        let bucket = buckets.entry(key.to_owned()).or_insert(Bucket {
+            tokens: f64::from(limit.capacity),
+            updated_at: now,
+            limit,
+        });
+        bucket.limit = limit;
+        bucket.tokens = bucket.tokens_at(now);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(None)
+        } else {
+            Ok(Some(Duration::seconds_f64(
+                (1.0 - bucket.tokens) / limit.refill_per_second(),
+            )))
+        }
+    }
+
+    async fn get_lockout(&self, account: &str) -> Result<Option<Duration>, DbError> {
+        let now = OffsetDateTime::now_utc();
+        let login_failures = self.login_failures.lock().expect("login failures lock");
+
+        Ok(login_failures
+            .get(account)
+            .and_then(|failures| failures.locked_until)
+            .filter(|locked_until| *locked_until > now)
+            .map(|locked_until| locked_until - now))
+    }
+
+    async fn record_failed_login(
+        &self,
+        account: &str,
+        policy: LockoutPolicy,
+    ) -> Result<Option<Duration>, DbError> {
+        let now = OffsetDateTime::now_utc();
+        let mut login_failures = self.login_failures.lock().expect("login failures lock");
+
+        let failures = login_failures
+            .entry(account.to_owned())
+            .or_insert(LoginFailures {
+                failures: 0,
+                locked_until: None,
+                forget_at: now,
+            });
+        failures.failures += 1;
+
+        let lockout = policy.lockout(failures.failures);
+        if let Some(lockout) = lockout {
+            failures.locked_until = Some(now + lockout);
+        }
+        failures.forget_at = failures.locked_until.unwrap_or(now).max(now) + policy.max;
+
+        Ok(lockout)
+    }
+
+    async fn reset_failed_logins(&self, account: &str) -> Result<(), DbError> {
+        self.login_failures
+            .lock()
+            .expect("login failures lock")
+            .remove(account);
+
+        Ok(())
+    }
+}
+
+/// Counters shared by every instance through Postgres
+pub struct PgRateLimitStore {
+    pool: PgPool,
+}
+
+impl PgRateLimitStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimitStore for PgRateLimitStore {
+    #[tracing::instrument(skip(self))]
+    async fn take_token(&self, key: &str, limit: BucketLimit) -> Result<Option<Duration>, DbError> {
+        database::rate_limits::take_bucket_token(
+            &self.pool,
+            key,
+            f64::from(limit.capacity),
+            limit.refill_per_second(),
+        )
+        .await
+        .map(|wait| wait.map(Duration::seconds_f64))
+        .map_err(Into::into)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn get_lockout(&self, account: &str) -> Result<Option<Duration>, DbError> {
+        let locked_until = database::rate_limits::get_login_lockout(&self.pool, account).await?;
+
+        Ok(locked_until.map(|locked_until| locked_until.assume_utc() - OffsetDateTime::now_utc()))
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn record_failed_login(
+        &self,
+        account: &str,
+        policy: LockoutPolicy,
+    ) -> Result<Option<Duration>, DbError> {
+        let failures = database::rate_limits::record_login_failure(&self.pool, account).await?;
+
+        let Some(lockout) = policy.lockout(failures) else {
+            return Ok(None);
+        };
+        database::rate_limits::lock_login(&self.pool, account, lockout.whole_seconds()).await?;
+
+        Ok(Some(lockout))
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn reset_failed_logins(&self, account: &str) -> Result<(), DbError> {
+        database::rate_limits::reset_login_failures(&self.pool, account)
+            .await
+            .map(|_| ())
+            .map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMIT: BucketLimit = BucketLimit {
+        capacity: 2,
+        refill_per_minute: 1,
+    };
+
+    #[test]
+    fn test_lockout_doubles_up_to_the_max() {
+        let policy = LockoutPolicy {
+            threshold: 3,
+            base: Duration::seconds(30),
+            max: Duration::minutes(5),
+        };
+
+        assert_eq!(policy.lockout(2), None);
+        assert_eq!(policy.lockout(3), Some(Duration::seconds(30)));
+        assert_eq!(policy.lockout(4), Some(Duration::seconds(60)));
+        assert_eq!(policy.lockout(6), Some(Duration::seconds(240)));
+        assert_eq!(policy.lockout(7), Some(Duration::minutes(5)));
+        assert_eq!(policy.lockout(i32::MAX), Some(Duration::minutes(5)));
+    }
+
+    #[test]
+    #[should_panic(expected = "refill at least 1 request per minute")]
+    fn test_bucket_limit_must_refill() {
+        BucketLimit::new(5, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity must be at least 1")]
+    fn test_bucket_limit_must_hold_a_request() {
+        BucketLimit::new(0, 5);
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_bucket_runs_out_of_tokens() {
+        let store = InMemoryRateLimitStore::default();
+
+        assert_eq!(store.take_token("ip:a", LIMIT).await.ok(), Some(None));
+        assert_eq!(store.take_token("ip:a", LIMIT).await.ok(), Some(None));
+
+        let wait = store
+            .take_token("ip:a", LIMIT)
+            .await
+            .expect("in memory store")
+            .expect("bucket is empty");
+        assert!(wait > Duration::seconds(59) && wait <= Duration::minutes(1));
+
+        assert_eq!(store.take_token("ip:b", LIMIT).await.ok(), Some(None));
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_lockout() {
+        let store = InMemoryRateLimitStore::default();
+        let policy = LockoutPolicy {
+            threshold: 2,
+            base: Duration::minutes(1),
+            max: Duration::minutes(10),
+        };
+
+        assert_eq!(
+            store.record_failed_login("a", policy).await.ok(),
+            Some(None)
+        );
+        assert_eq!(store.get_lockout("a").await.ok(), Some(None));
+        assert_eq!(
+            store.record_failed_login("a", policy).await.ok(),
+            Some(Some(Duration::minutes(1)))
+        );
+        assert!(store
+            .get_lockout("a")
+            .await
+            .expect("in memory store")
+            .is_some());
+
+        store
+            .reset_failed_logins("a")
+            .await
+            .expect("in memory store");
+        assert_eq!(store.get_lockout("a").await.ok(), Some(None));
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_store_drops_counters_with_nothing_to_limit() {
+        let store = InMemoryRateLimitStore::default();
+        let policy = LockoutPolicy {
+            threshold: 1,
+            base: Duration::minutes(1),
+            max: Duration::minutes(2),
+        };
+
+        store
+            .take_token("ip:a", LIMIT)
+            .await
+            .expect("in memory store");
+        store
+            .take_token("ip:b", LIMIT)
+            .await
+            .expect("in memory store");
+        store
+            .take_token("ip:b", LIMIT)
+            .await
+            .expect("in memory store");
+        store
+            .record_failed_login("a", policy)
+            .await
+            .expect("in memory store");
+
+        // One more token is in both buckets, only the first one is full
+        store.sweep(OffsetDateTime::now_utc() + Duration::seconds(61));
+        assert_eq!(
+            store
+                .buckets
+                .lock()
+                .expect("rate limit buckets lock")
+                .keys()
+                .collect::<Vec<_>>(),
+            vec!["ip:b"]
+        );
+        assert_eq!(store.login_failures.lock().expect("lock").len(), 1);
+
+        store.sweep(OffsetDateTime::now_utc() + Duration::minutes(4));
+        assert!(store.buckets.lock().expect("lock").is_empty());
+        assert!(store.login_failures.lock().expect("lock").is_empty());
+    }
+}
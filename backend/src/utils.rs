@@ -1,3 +1,6 @@
+pub mod api_keys;
+pub mod images;
+pub mod mfa;
 pub mod modify_builder;
-pub mod salted_hashes;
+pub mod passwords;
 pub mod tokens;
@@ -0,0 +1,119 @@
+use crate::utils::tokens::generate_opaque_token;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Every API key starts with it, so a leaked key is easy to spot and tell from a bearer token
+pub const API_KEY_PREFIX: &str = "exk_";
+
+/// Characters of a key kept in clear text, enough to tell keys of a user apart
+const API_KEY_DISPLAY_PREFIX_LENGTH: usize = 12;
+
+/// What an API key is allowed to do, access tokens of a signed in user may do everything
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Scope {
+    #[serde(rename = "projects:read")]
+    ProjectsRead,
+    #[serde(rename = "projects:write")]
+    ProjectsWrite,
+    #[serde(rename = "companies:read")]
+    CompaniesRead,
+    #[serde(rename = "companies:write")]
+    CompaniesWrite,
+    #[serde(rename = "chats:read")]
+    ChatsRead,
+    #[serde(rename = "chats:write")]
+    ChatsWrite,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::ProjectsRead => "projects:read",
+            Scope::ProjectsWrite => "projects:write",
+            Scope::CompaniesRead => "companies:read",
+            Scope::CompaniesWrite => "companies:write",
+            Scope::ChatsRead => "chats:read",
+            Scope::ChatsWrite => "chats:write",
+        }
+    }
+}
+
+impl Display for Scope {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownScope(pub String);
+
+impl FromStr for Scope {
+    type Err = UnknownScope;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        [
+            Scope::ProjectsRead,
+            Scope::ProjectsWrite,
+            Scope::CompaniesRead,
+            Scope::CompaniesWrite,
+            Scope::ChatsRead,
+            Scope::ChatsWrite,
+        ]
+        .into_iter()
+        .find(|scope| scope.as_str() == value)
+        .ok_or_else(|| UnknownScope(value.to_owned()))
+    }
+}
+
+/// A new random key, shown to its owner once and stored only as a hash
+pub fn generate_api_key() -> String {
+    std::format!("{API_KEY_PREFIX}{}", generate_opaque_token())
+}
+
+pub fn is_api_key(token: impl AsRef<str>) -> bool {
+    token.as_ref().starts_with(API_KEY_PREFIX)
+}
+
+/// The clear text part of a key stored next to its hash
+pub fn api_key_prefix(key: impl AsRef<str>) -> String {
+    key.as_ref()
+        .chars()
+        .take(API_KEY_DISPLAY_PREFIX_LENGTH)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generated_keys_are_recognised() {
+        let key = generate_api_key();
+
+        assert!(is_api_key(&key));
+        assert!(key.starts_with(&api_key_prefix(&key)));
+        assert_eq!(api_key_prefix(&key).len(), API_KEY_DISPLAY_PREFIX_LENGTH);
+        assert_ne!(key, generate_api_key());
+    }
+
+    #[test]
+    fn test_scopes_round_trip() {
+        for scope in [
+            Scope::ProjectsRead,
+            Scope::ProjectsWrite,
+            Scope::CompaniesRead,
+            Scope::CompaniesWrite,
+            Scope::ChatsRead,
+            Scope::ChatsWrite,
+        ] {
+            assert_eq!(scope.as_str().parse::<Scope>().expect("known scope"), scope);
+            assert_eq!(
+                serde_json::to_string(&scope).expect("serializable"),
+                std::format!("\"{scope}\"")
+            );
+        }
+
+        assert!("projects:delete".parse::<Scope>().is_err());
+    }
+}
@@ -0,0 +1,136 @@
+use image::io::{Limits, Reader};
+use image::{ImageFormat, ImageOutputFormat};
+use std::io::Cursor;
+
+/// Thumbnails fit into a square of this many pixels
+pub const THUMBNAIL_SIZE: u32 = 256;
+
+/// Larger images are refused before they are decoded
+const MAX_IMAGE_DIMENSION: u32 = 8192;
+
+#[derive(Debug)]
+pub enum ImageError {
+    /// The bytes are not the type the client claimed
+    ContentMismatch,
+    Decode(image::ImageError),
+    Encode(image::ImageError),
+}
+
+/// Image types accepted as uploads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageType {
+    Png,
+    Jpeg,
+    Gif,
+    Webp,
+}
+
+impl ImageType {
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        match content_type {
+            "image/png" => Some(ImageType::Png),
+            "image/jpeg" => Some(ImageType::Jpeg),
+            "image/gif" => Some(ImageType::Gif),
+            "image/webp" => Some(ImageType::Webp),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ImageType::Png => "image/png",
+            ImageType::Jpeg => "image/jpeg",
+            ImageType::Gif => "image/gif",
+            ImageType::Webp => "image/webp",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageType::Png => "png",
+            ImageType::Jpeg => "jpg",
+            ImageType::Gif => "gif",
+            ImageType::Webp => "webp",
+        }
+    }
+
+    fn format(self) -> ImageFormat {
+        match self {
+            ImageType::Png => ImageFormat::Png,
+            ImageType::Jpeg => ImageFormat::Jpeg,
+            ImageType::Gif => ImageFormat::Gif,
+            ImageType::Webp => ImageFormat::WebP,
+        }
+    }
+}
+
+/// Checks the bytes against their claimed type and scales them down into a PNG
+///
+/// Decoding is CPU bound, call it from a blocking task.
+pub fn create_thumbnail(bytes: &[u8], image_type: ImageType) -> Result<Vec<u8>, ImageError> {
+    match image::guess_format(bytes) {
+        Ok(format) if format == image_type.format() => {}
+        _ => return Err(ImageError::ContentMismatch),
+    }
+
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_IMAGE_DIMENSION);
+    limits.max_image_height = Some(MAX_IMAGE_DIMENSION);
+
+    let mut reader = Reader::with_format(Cursor::new(bytes), image_type.format());
+    reader.limits(limits);
+    let image = reader.decode().map_err(ImageError::Decode)?;
+
+    let mut thumbnail = Cursor::new(Vec::new());
+    image
+        .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+        .write_to(&mut thumbnail, ImageOutputFormat::Png)
+        .map_err(ImageError::Encode)?;
+
+    Ok(thumbnail.into_inner())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use image::{DynamicImage, GenericImageView, RgbImage};
+
+    /// A PNG of the given size, to upload in tests
+    pub fn create_png(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(RgbImage::new(width, height))
+            .write_to(&mut bytes, ImageOutputFormat::Png)
+            .expect("PNG is encoded");
+        bytes.into_inner()
+    }
+
+    #[test]
+    fn test_create_thumbnail() {
+        let thumbnail =
+            create_thumbnail(&create_png(600, 300), ImageType::Png).expect("thumbnail is created");
+
+        let image = image::load_from_memory_with_format(&thumbnail, ImageFormat::Png)
+            .expect("thumbnail is a PNG");
+        assert_eq!(image.dimensions(), (THUMBNAIL_SIZE, THUMBNAIL_SIZE / 2));
+    }
+
+    #[test]
+    fn test_create_thumbnail_refuses_mismatching_content() {
+        assert!(matches!(
+            create_thumbnail(&create_png(10, 10), ImageType::Jpeg),
+            Err(ImageError::ContentMismatch)
+        ));
+        assert!(matches!(
+            create_thumbnail(b"<svg></svg>", ImageType::Png),
+            Err(ImageError::ContentMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_create_thumbnail_refuses_huge_images() {
+        assert!(matches!(
+            create_thumbnail(&create_png(MAX_IMAGE_DIMENSION + 1, 1), ImageType::Png),
+            Err(ImageError::Decode(_))
+        ));
+    }
+}
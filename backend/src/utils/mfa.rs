@@ -0,0 +1,195 @@
+use data_encoding::{DecodeError, BASE32_NOPAD};
+use lazy_static::lazy_static;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use rand::{thread_rng, RngCore};
+use ring::constant_time::verify_slices_are_equal;
+use ring::hmac;
+use time::OffsetDateTime;
+
+/// RFC 6238 defaults, the ones every authenticator app supports
+const TOTP_STEP_IN_SECS: i64 = 30;
+const TOTP_DIGITS: u32 = 6;
+
+const TOTP_SECRET_LENGTH: usize = 20;
+
+/// Codes of neighbouring steps are accepted too, phones are not always in sync
+const TOTP_ALLOWED_DRIFT_STEPS: i64 = 1;
+
+const RECOVERY_CODE_LENGTH: usize = 10;
+const RECOVERY_CODES_COUNT: usize = 10;
+
+lazy_static! {
+    static ref TOTP_ISSUER: String =
+        std::env::var("TOTP_ISSUER").expect("TOTP_ISSUER must be in environment");
+}
+
+#[derive(Debug)]
+pub enum TotpError {
+    InvalidSecret(DecodeError),
+}
+
+/// A random Base32 secret shared with an authenticator app
+pub fn generate_totp_secret() -> String {
+    let mut secret_bytes = [0u8; TOTP_SECRET_LENGTH];
+    thread_rng().fill_bytes(&mut secret_bytes);
+
+    BASE32_NOPAD.encode(&secret_bytes)
+}
+
+/// `otpauth://` URI of a secret, authenticator apps scan it as a QR code
+pub fn totp_uri(secret: impl AsRef<str>, account_name: impl AsRef<str>) -> String {
+    let issuer = utf8_percent_encode(&TOTP_ISSUER, NON_ALPHANUMERIC);
+    let account_name = utf8_percent_encode(account_name.as_ref(), NON_ALPHANUMERIC);
+
+    std::format!(
+        "otpauth://totp/{issuer}:{account_name}?secret={}&issuer={issuer}\
+        &algorithm=SHA1&digits={TOTP_DIGITS}&period={TOTP_STEP_IN_SECS}",
+        secret.as_ref()
+    )
+}
+
+/// HOTP value of RFC 4226, truncated to `TOTP_DIGITS`
+fn hotp(key: &[u8], counter: u64) -> u32 {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, key);
+    let digest = hmac::sign(&key, &counter.to_be_bytes());
+    let digest = digest.as_ref();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+
+    binary % 10u32.pow(TOTP_DIGITS)
+}
+
+fn totp_step(time: OffsetDateTime) -> i64 {
+    time.unix_timestamp() / TOTP_STEP_IN_SECS
+}
+
+/// Checks a code at a given time, returns the time step it belongs to
+///
+/// Callers keep the step to refuse the same code twice.
+pub fn verify_totp_code(
+    secret: impl AsRef<str>,
+    code: impl AsRef<str>,
+    time: OffsetDateTime,
+) -> Result<Option<i64>, TotpError> {
+    let key = BASE32_NOPAD
+        .decode(secret.as_ref().as_bytes())
+        .map_err(TotpError::InvalidSecret)?;
+    let code = code.as_ref().trim();
+
+    let current_step = totp_step(time);
+    let step = (current_step - TOTP_ALLOWED_DRIFT_STEPS..=current_step + TOTP_ALLOWED_DRIFT_STEPS)
+        .find(|step| {
+            let expected = std::format!(
+                "{:0width$}",
+                hotp(&key, *step as u64),
+                width = TOTP_DIGITS as usize
+            );
+            verify_slices_are_equal(expected.as_bytes(), code.as_bytes()).is_ok()
+        });
+
+    Ok(step)
+}
+
+/// One-time codes to sign in without the authenticator, shown to a user once
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODES_COUNT)
+        .map(|_| {
+            let mut code_bytes = [0u8; RECOVERY_CODE_LENGTH];
+            thread_rng().fill_bytes(&mut code_bytes);
+
+            let code = BASE32_NOPAD.encode(&code_bytes)[..RECOVERY_CODE_LENGTH].to_lowercase();
+            let (first, second) = code.split_at(RECOVERY_CODE_LENGTH / 2);
+            std::format!("{first}-{second}")
+        })
+        .collect()
+}
+
+/// Recovery codes are compared without dashes, spaces and case
+pub fn normalize_recovery_code(code: impl AsRef<str>) -> String {
+    code.as_ref()
+        .chars()
+        .filter(|char| char.is_ascii_alphanumeric())
+        .map(|char| char.to_ascii_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use dotenvy::dotenv;
+
+    /// The current code of a secret
+    pub fn create_totp_code(secret: impl AsRef<str>, time: OffsetDateTime) -> String {
+        let key = BASE32_NOPAD
+            .decode(secret.as_ref().as_bytes())
+            .expect("valid secret");
+
+        std::format!(
+            "{:0width$}",
+            hotp(&key, totp_step(time) as u64),
+            width = TOTP_DIGITS as usize
+        )
+    }
+
+    #[test]
+    fn test_rfc_6238_vectors() {
+        // The SHA1 secret of RFC 6238, last six digits of its eight digit codes
+        let secret = BASE32_NOPAD.encode(b"12345678901234567890");
+
+        for (timestamp, code) in [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+        ] {
+            let time = OffsetDateTime::from_unix_timestamp(timestamp).expect("valid timestamp");
+            assert_eq!(
+                verify_totp_code(&secret, code, time).expect("valid secret"),
+                Some(timestamp / TOTP_STEP_IN_SECS)
+            );
+        }
+    }
+
+    #[test]
+    fn test_codes_of_neighbouring_steps_are_accepted() {
+        let secret = generate_totp_secret();
+        let now = OffsetDateTime::now_utc();
+        let code = create_totp_code(&secret, now);
+
+        let step = verify_totp_code(&secret, &code, now + time::Duration::seconds(30))
+            .expect("valid secret");
+        assert_eq!(step, Some(totp_step(now)));
+
+        let step = verify_totp_code(&secret, &code, now + time::Duration::seconds(90))
+            .expect("valid secret");
+        assert_eq!(step, None);
+    }
+
+    #[test]
+    fn test_totp_uri() {
+        dotenv().expect("failed to load .env");
+
+        let uri = totp_uri("SECRET", "user@test.test");
+        assert!(uri.starts_with("otpauth://totp/"));
+        assert!(uri.contains(":user%40test%2Etest?secret=SECRET&issuer="));
+    }
+
+    #[test]
+    fn test_recovery_codes() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODES_COUNT);
+
+        let code = &codes[0];
+        assert_eq!(code.len(), RECOVERY_CODE_LENGTH + 1);
+        assert_eq!(
+            normalize_recovery_code(code.to_uppercase()),
+            code.replace('-', "")
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::engine::{general_purpose, GeneralPurpose};
+use base64::{DecodeError, Engine};
+use hmac::Hmac;
+use hmac::Mac;
+use lazy_static::lazy_static;
+use rand::thread_rng;
+use ring::constant_time::verify_slices_are_equal;
+use sha2::Sha512;
+
+const BASE_64: GeneralPurpose = general_purpose::STANDARD;
+
+/// Prefix of hashes made before Argon2id, `$hmac-sha512$<b64 salt>$<b64 hash>`
+const LEGACY_HASH_PREFIX: &str = "$hmac-sha512$";
+
+/// Stored for users signed up through an identity provider, no password matches it
+pub const NO_PASSWORD_HASH: &str = "!";
+
+lazy_static! {
+    static ref ARGON2_PARAMS: Params =
+        argon2_params_from_env().expect("ARGON2_* must contain valid Argon2 parameters");
+}
+
+#[derive(Debug)]
+pub enum PasswordHashError {
+    Argon2(argon2::Error),
+    PasswordHash(argon2::password_hash::Error),
+    InvalidLegacyHashFormat,
+    Base64DecodeError(DecodeError),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PasswordVerification {
+    Invalid,
+    Valid,
+    /// The password is right, but its hash is legacy or made with outdated parameters
+    ValidNeedsRehash,
+}
+
+fn env_cost(name: &str) -> u32 {
+    std::env::var(name)
+        .unwrap_or_else(|_| panic!("{name} must be in environment"))
+        .parse::<u32>()
+        .unwrap_or_else(|_| panic!("{name} must be an integer"))
+}
+
+/// Reads `ARGON2_MEMORY_COST_KIB`, `ARGON2_TIME_COST` and `ARGON2_PARALLELISM`
+fn argon2_params_from_env() -> Result<Params, PasswordHashError> {
+    Params::new(
+        env_cost("ARGON2_MEMORY_COST_KIB"),
+        env_cost("ARGON2_TIME_COST"),
+        env_cost("ARGON2_PARALLELISM"),
+        None,
+    )
+    .map_err(PasswordHashError::Argon2)
+}
+
+fn argon2(params: Params) -> Argon2<'static> {
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+}
+
+fn hash_password_with_params(
+    password: impl AsRef<str>,
+    params: Params,
+) -> Result<String, PasswordHashError> {
+    let salt = SaltString::generate(&mut thread_rng());
+
+    argon2(params)
+        .hash_password(password.as_ref().as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(PasswordHashError::PasswordHash)
+}
+
+/// Hashes a password with Argon2id into a PHC string
+pub fn hash_password(password: impl AsRef<str>) -> Result<String, PasswordHashError> {
+    hash_password_with_params(password, ARGON2_PARAMS.clone())
+}
+
+fn verify_password_with_params(
+    password: impl AsRef<str>,
+    password_hash: impl AsRef<str>,
+    params: &Params,
+) -> Result<PasswordVerification, PasswordHashError> {
+    if password_hash.as_ref() == NO_PASSWORD_HASH {
+        return Ok(PasswordVerification::Invalid);
+    }
+
+    if let Some(legacy_hash) = password_hash.as_ref().strip_prefix(LEGACY_HASH_PREFIX) {
+        return verify_legacy_password(password, legacy_hash);
+    }
+
+    let parsed_hash =
+        PasswordHash::new(password_hash.as_ref()).map_err(PasswordHashError::PasswordHash)?;
+
+    match argon2(params.clone()).verify_password(password.as_ref().as_bytes(), &parsed_hash) {
+        Ok(()) => {}
+        Err(argon2::password_hash::Error::Password) => return Ok(PasswordVerification::Invalid),
+        Err(error) => return Err(PasswordHashError::PasswordHash(error)),
+    }
+
+    let hash_params = Params::try_from(&parsed_hash).map_err(PasswordHashError::PasswordHash)?;
+    let is_outdated = parsed_hash.algorithm != Algorithm::Argon2id.ident()
+        || hash_params.m_cost() != params.m_cost()
+        || hash_params.t_cost() != params.t_cost()
+        || hash_params.p_cost() != params.p_cost();
+
+    if is_outdated {
+        Ok(PasswordVerification::ValidNeedsRehash)
+    } else {
+        Ok(PasswordVerification::Valid)
+    }
+}
+
+/// Checks a password against a stored hash, either a PHC string or a legacy one
+pub fn verify_password(
+    password: impl AsRef<str>,
+    password_hash: impl AsRef<str>,
+) -> Result<PasswordVerification, PasswordHashError> {
+    verify_password_with_params(password, password_hash, &ARGON2_PARAMS)
+}
+
+/// Single HMAC-SHA512 pass keyed with the salt, only used to verify old hashes
+fn legacy_hash(password: impl AsRef<str>, salt_bytes: &[u8]) -> Vec<u8> {
+    let salted_password = [salt_bytes, password.as_ref().as_bytes()].concat();
+
+    let mut mac = Hmac::<Sha512>::new_from_slice(salt_bytes).expect("HMAC takes a key of any size");
+    mac.update(&salted_password);
+
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn verify_legacy_password(
+    password: impl AsRef<str>,
+    legacy_hash_with_salt: &str,
+) -> Result<PasswordVerification, PasswordHashError> {
+    let (salt_b64, hash_b64) = legacy_hash_with_salt
+        .split_once('$')
+        .ok_or(PasswordHashError::InvalidLegacyHashFormat)?;
+
+    let salt_bytes = BASE_64
+        .decode(salt_b64)
+        .map_err(PasswordHashError::Base64DecodeError)?;
+    let existing_hash = BASE_64
+        .decode(hash_b64)
+        .map_err(PasswordHashError::Base64DecodeError)?;
+
+    let input_hash = legacy_hash(password, &salt_bytes);
+
+    match verify_slices_are_equal(&input_hash, &existing_hash) {
+        Ok(()) => Ok(PasswordVerification::ValidNeedsRehash),
+        Err(_) => Ok(PasswordVerification::Invalid),
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use database::utils::random_samples::RandomSample;
+    use dotenvy::dotenv;
+    use rand::RngCore;
+
+    pub fn create_legacy_password_hash(password: impl AsRef<str>) -> String {
+        let mut salt_bytes = [0u8; 64];
+        thread_rng().fill_bytes(&mut salt_bytes);
+
+        std::format!(
+            "{LEGACY_HASH_PREFIX}{}${}",
+            BASE_64.encode(salt_bytes),
+            BASE_64.encode(legacy_hash(password, &salt_bytes))
+        )
+    }
+
+    #[test]
+    fn test_passwords() {
+        dotenv().expect("failed to load .env");
+
+        let password = String::new_random(1024);
+
+        let hash = hash_password(&password).expect("password is hashed");
+        assert!(hash.starts_with("$argon2id$"));
+
+        assert_eq!(
+            verify_password(&password, &hash).expect("valid hash"),
+            PasswordVerification::Valid
+        );
+        assert_eq!(
+            verify_password(String::new_random(1024), &hash).expect("valid hash"),
+            PasswordVerification::Invalid
+        );
+    }
+
+    #[test]
+    fn test_legacy_passwords_need_rehash() {
+        dotenv().expect("failed to load .env");
+
+        let password = String::new_random(1024);
+        let hash = create_legacy_password_hash(&password);
+
+        assert_eq!(
+            verify_password(&password, &hash).expect("valid hash"),
+            PasswordVerification::ValidNeedsRehash
+        );
+        assert_eq!(
+            verify_password(String::new_random(1024), &hash).expect("valid hash"),
+            PasswordVerification::Invalid
+        );
+        assert!(verify_password(&password, "$hmac-sha512$no-hash").is_err());
+    }
+
+    #[test]
+    fn test_no_password_matches_no_password_hash() {
+        dotenv().expect("failed to load .env");
+
+        assert_eq!(
+            verify_password("", NO_PASSWORD_HASH).expect("valid hash"),
+            PasswordVerification::Invalid
+        );
+        assert_eq!(
+            verify_password(NO_PASSWORD_HASH, NO_PASSWORD_HASH).expect("valid hash"),
+            PasswordVerification::Invalid
+        );
+    }
+
+    #[test]
+    fn test_outdated_params_need_rehash() {
+        let password = String::new_random(64);
+        let old_params = Params::new(Params::MIN_M_COST, 1, 1, None).expect("valid params");
+        let new_params = Params::new(Params::MIN_M_COST * 2, 1, 1, None).expect("valid params");
+
+        let hash = hash_password_with_params(&password, old_params.clone()).expect("hashed");
+
+        assert_eq!(
+            verify_password_with_params(&password, &hash, &old_params).expect("valid hash"),
+            PasswordVerification::Valid
+        );
+        assert_eq!(
+            verify_password_with_params(&password, &hash, &new_params).expect("valid hash"),
+            PasswordVerification::ValidNeedsRehash
+        );
+    }
+}
@@ -1,27 +1,59 @@
-use crate::utils::salted_hashes::generate_b64_hash_for_text_and_salt;
 use base64::engine::{general_purpose, GeneralPurpose};
 use base64::{DecodeError, Engine};
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, OctetKeyPairParameters,
+    OctetKeyPairType, PublicKeyUse,
+};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use lazy_static::lazy_static;
+use rand::{thread_rng, RngCore};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
 use std::ops::Add;
-use std::string::FromUtf8Error;
+use std::str::FromStr;
 use time::{Duration, OffsetDateTime};
 use uuid::Uuid;
 
 const BASE_64: GeneralPurpose = general_purpose::STANDARD;
+const BASE_64_URL: GeneralPurpose = general_purpose::URL_SAFE_NO_PAD;
+
+/// Value of the `iss` claim of every token minted by the backend
+pub const TOKEN_ISSUER: &str = "exchange-backend";
+
+/// Value of the `aud` claim of tokens mailed to confirm an email address
+const EMAIL_VERIFICATION_AUDIENCE: &str = "email-verification";
+
+/// Value of the `aud` claim of tokens standing for a login waiting for a second factor
+const MFA_PENDING_AUDIENCE: &str = "mfa-pending";
+
+const OPAQUE_TOKEN_LENGTH: usize = 32;
+
+lazy_static! {
+    static ref TOKEN_KEYS: TokenKeys =
+        TokenKeys::from_env().expect("JWT_KEYS must contain valid signing keys");
+}
 
 #[derive(Debug)]
 pub enum CreateAccessTokenError {
-    JsonError(serde_json::Error),
-    DecodeError(DecodeError),
+    JwtError(jsonwebtoken::errors::Error),
 }
 
 #[derive(Debug)]
 pub enum ParseAccessTokenError {
-    ParseJsonError(serde_json::Error),
-    CreateAccessTokenError(CreateAccessTokenError),
-    InvalidDigest,
+    JwtError(jsonwebtoken::errors::Error),
+    UnknownKey,
+    InvalidTimestamp,
+}
+
+#[derive(Debug)]
+pub enum TokenKeysError {
+    InvalidFormat(String),
+    UnsupportedAlgorithm(String),
     Base64DecodeError(DecodeError),
-    StringDecodingError(FromUtf8Error),
+    InvalidEd25519Key,
+    NoKeys,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -29,98 +61,294 @@ pub struct UserInfo {
     pub first_name: Option<String>,
     pub last_name: Option<String>,
     pub user_id: Uuid,
+    pub email_verified: bool,
 }
 
 #[derive(Debug, Clone)]
 pub struct AccessTokenResponse {
     pub token: String,
     pub expires_at: OffsetDateTime,
-    pub refresh_at: OffsetDateTime,
 }
 
 impl AccessTokenResponse {
-    pub fn new(user: UserInfo) -> Result<Self, CreateAccessTokenError> {
-        let access_token = AccessToken::new_with_user(user);
+    pub fn new(user: UserInfo, session_id: Uuid) -> Result<Self, CreateAccessTokenError> {
+        AccessToken::new_with_user(user, session_id).encode(TOKEN_KEYS.signing_key())
+    }
+}
 
-        let digest_access_token: DigestAccessToken = access_token.try_into()?;
+/// An opaque refresh token, only its hash is stored in a database
+#[derive(Debug, Clone)]
+pub struct RefreshTokenResponse {
+    pub token: String,
+    pub expires_at: OffsetDateTime,
+}
 
-        digest_access_token.try_into()
+impl RefreshTokenResponse {
+    pub fn generate() -> Self {
+        let duration_in_secs = std::env::var("REFRESH_TOKEN_DURATION_IN_SECS")
+            .expect("REFRESH_TOKEN_DURATION_IN_SECS must be in environment")
+            .parse::<i64>()
+            .expect("integer duration");
+
+        let expires_at = OffsetDateTime::now_utc()
+            .replace_nanosecond(0)
+            .expect("zero is a valid nanosecond")
+            .add(Duration::seconds(duration_in_secs));
+
+        Self {
+            token: generate_opaque_token(),
+            expires_at,
+        }
+    }
+
+    pub fn hash(&self) -> String {
+        hash_opaque_token(&self.token)
     }
 }
 
-impl TryFrom<DigestAccessToken> for AccessTokenResponse {
-    type Error = CreateAccessTokenError;
+/// A random URL safe token, meant to be stored only as a hash
+pub fn generate_opaque_token() -> String {
+    let mut token_bytes = [0u8; OPAQUE_TOKEN_LENGTH];
+    thread_rng().fill_bytes(&mut token_bytes);
 
-    fn try_from(value: DigestAccessToken) -> Result<Self, Self::Error> {
-        let token = serde_json::to_string(&value).map_err(CreateAccessTokenError::JsonError)?;
+    BASE_64_URL.encode(token_bytes)
+}
 
-        Ok(AccessTokenResponse {
-            token: BASE_64.encode(token),
-            expires_at: value.expires_at,
-            refresh_at: value.refresh_at,
-        })
+pub fn hash_opaque_token(token: impl AsRef<str>) -> String {
+    BASE_64.encode(Sha512::digest(token.as_ref().as_bytes()))
+}
+
+/// A key used to sign and verify access tokens
+///
+/// `kid` ends up in the JWS header, so verification can pick the right key
+/// while several of them are configured during a rotation.
+pub struct TokenKey {
+    kid: String,
+    algorithm: Algorithm,
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+    public_jwk: Option<Jwk>,
+}
+
+impl TokenKey {
+    /// Parses a key in the `kid:algorithm:base64_key` format
+    ///
+    /// HMAC keys (`HS256`, `HS512`) take the raw secret, `EdDSA` keys take a PKCS#8 DER document.
+    pub fn parse(value: impl AsRef<str>) -> Result<Self, TokenKeysError> {
+        let mut parts = value.as_ref().trim().splitn(3, ':');
+        let (kid, algorithm, key) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(kid), Some(algorithm), Some(key)) if !kid.is_empty() => (kid, algorithm, key),
+            _ => return Err(TokenKeysError::InvalidFormat(value.as_ref().to_owned())),
+        };
+
+        let algorithm = Algorithm::from_str(algorithm)
+            .map_err(|_| TokenKeysError::UnsupportedAlgorithm(algorithm.to_owned()))?;
+        let key_bytes = BASE_64
+            .decode(key)
+            .map_err(TokenKeysError::Base64DecodeError)?;
+
+        match algorithm {
+            Algorithm::HS256 | Algorithm::HS512 => Ok(TokenKey {
+                kid: kid.to_owned(),
+                algorithm,
+                encoding_key: EncodingKey::from_secret(&key_bytes),
+                decoding_key: DecodingKey::from_secret(&key_bytes),
+                public_jwk: None,
+            }),
+            Algorithm::EdDSA => {
+                let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&key_bytes)
+                    .map_err(|_| TokenKeysError::InvalidEd25519Key)?;
+                let public_key = key_pair.public_key().as_ref();
+
+                let public_jwk = Jwk {
+                    common: CommonParameters {
+                        public_key_use: Some(PublicKeyUse::Signature),
+                        key_operations: None,
+                        algorithm: Some(Algorithm::EdDSA),
+                        key_id: Some(kid.to_owned()),
+                        x509_url: None,
+                        x509_chain: None,
+                        x509_sha1_fingerprint: None,
+                        x509_sha256_fingerprint: None,
+                    },
+                    algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                        key_type: OctetKeyPairType::OctetKeyPair,
+                        curve: EllipticCurve::Ed25519,
+                        x: BASE_64_URL.encode(public_key),
+                    }),
+                };
+
+                Ok(TokenKey {
+                    kid: kid.to_owned(),
+                    algorithm,
+                    encoding_key: EncodingKey::from_ed_der(&key_bytes),
+                    decoding_key: DecodingKey::from_ed_der(public_key),
+                    public_jwk: Some(public_jwk),
+                })
+            }
+            _ => Err(TokenKeysError::UnsupportedAlgorithm(std::format!(
+                "{algorithm:?}"
+            ))),
+        }
+    }
+}
+
+/// Keys for signing and verifying access tokens
+///
+/// The first key signs new tokens, all of them are accepted on verification.
+/// To rotate a secret put a new key in front and drop the old one once the tokens
+/// it has signed are expired.
+pub struct TokenKeys {
+    keys: Vec<TokenKey>,
+}
+
+impl TokenKeys {
+    pub fn new(keys: Vec<TokenKey>) -> Result<Self, TokenKeysError> {
+        if keys.is_empty() {
+            return Err(TokenKeysError::NoKeys);
+        }
+
+        Ok(Self { keys })
+    }
+
+    /// Reads comma separated keys from `JWT_KEYS`, see [`TokenKey::parse`]
+    pub fn from_env() -> Result<Self, TokenKeysError> {
+        let keys = std::env::var("JWT_KEYS").map_err(|_| TokenKeysError::NoKeys)?;
+
+        keys.split(',')
+            .filter(|x| !x.trim().is_empty())
+            .map(TokenKey::parse)
+            .collect::<Result<Vec<_>, _>>()
+            .and_then(Self::new)
+    }
+
+    pub fn signing_key(&self) -> &TokenKey {
+        &self.keys[0]
+    }
+
+    /// Keys to try for a token, the one matching `kid` goes first
+    fn verification_keys<'a>(&'a self, header: &'a Header) -> impl Iterator<Item = &'a TokenKey> {
+        let by_kid = self
+            .keys
+            .iter()
+            .filter(move |key| header.kid.as_deref() == Some(key.kid.as_str()));
+        let others = self.keys.iter().filter(move |key| {
+            key.algorithm == header.alg && header.kid.as_deref() != Some(key.kid.as_str())
+        });
+
+        by_kid.chain(others)
+    }
+
+    /// Public keys which other services can use to verify tokens
+    ///
+    /// HMAC secrets are never published.
+    pub fn jwk_set(&self) -> JwkSet {
+        JwkSet {
+            keys: self
+                .keys
+                .iter()
+                .flat_map(|key| key.public_jwk.clone())
+                .collect(),
+        }
     }
 }
 
+pub fn token_keys() -> &'static TokenKeys {
+    &TOKEN_KEYS
+}
+
 #[derive(Serialize, Deserialize)]
+struct AccessTokenClaims {
+    iss: String,
+    sub: Uuid,
+    sid: Uuid,
+    jti: Uuid,
+    iat: i64,
+    exp: i64,
+    first_name: Option<String>,
+    last_name: Option<String>,
+    email_verified: bool,
+}
+
 pub struct AccessToken {
     user: UserInfo,
+    session_id: Uuid,
+    token_id: Uuid,
     expires_at: OffsetDateTime,
-    refresh_at: OffsetDateTime,
 }
 
 impl AccessToken {
-    pub fn new_with_user(user: UserInfo) -> Self {
+    pub fn new_with_user(user: UserInfo, session_id: Uuid) -> Self {
         let duration_in_secs = std::env::var("TOKEN_DURATION_IN_SECS")
-            .expect("SECRET_SALT must be in environment")
+            .expect("TOKEN_DURATION_IN_SECS must be in environment")
             .parse::<i64>()
             .expect("integer duration");
         let duration = Duration::seconds(duration_in_secs);
 
-        Self::new_with_user_and_duration(user, duration)
+        Self::new_with_user_and_duration(user, session_id, duration)
     }
 
-    fn new_with_user_and_duration(user: UserInfo, duration: Duration) -> Self {
-        let expires_at = OffsetDateTime::now_utc().add(duration);
+    fn new_with_user_and_duration(user: UserInfo, session_id: Uuid, duration: Duration) -> Self {
+        // JWT timestamps have a second precision
+        let now = OffsetDateTime::now_utc()
+            .replace_nanosecond(0)
+            .expect("zero is a valid nanosecond");
 
-        let refresh_at: OffsetDateTime = OffsetDateTime::now_utc().add(duration * 2);
+        let expires_at = now.add(duration);
 
         Self {
             user,
+            session_id,
+            token_id: Uuid::new_v4(),
             expires_at,
-            refresh_at,
         }
     }
 
-    pub fn from_token(token_b64: impl AsRef<str>) -> Result<Self, ParseAccessTokenError> {
-        let token_bytes = BASE_64
-            .decode(token_b64.as_ref())
-            .map_err(ParseAccessTokenError::Base64DecodeError)?;
+    fn encode(self, key: &TokenKey) -> Result<AccessTokenResponse, CreateAccessTokenError> {
+        let claims = AccessTokenClaims {
+            iss: TOKEN_ISSUER.to_owned(),
+            sub: self.user.user_id,
+            sid: self.session_id,
+            jti: self.token_id,
+            iat: OffsetDateTime::now_utc().unix_timestamp(),
+            exp: self.expires_at.unix_timestamp(),
+            first_name: self.user.first_name,
+            last_name: self.user.last_name,
+            email_verified: self.user.email_verified,
+        };
 
-        let token =
-            String::from_utf8(token_bytes).map_err(ParseAccessTokenError::StringDecodingError)?;
+        let token = encode_claims(&claims, key)?;
 
-        let digest_token: DigestAccessToken =
-            serde_json::from_str(&token).map_err(ParseAccessTokenError::ParseJsonError)?;
+        Ok(AccessTokenResponse {
+            token,
+            expires_at: self.expires_at,
+        })
+    }
 
-        let access_token: AccessToken = serde_json::from_str(digest_token.token.as_ref())
-            .map_err(ParseAccessTokenError::ParseJsonError)?;
-        let user = access_token.user.clone();
-        let expires_at = access_token.expires_at;
-        let refresh_at = access_token.refresh_at;
+    /// Verifies the token signature against the configured keys and checks that it is not expired
+    pub fn from_token(token: impl AsRef<str>) -> Result<Self, ParseAccessTokenError> {
+        Self::from_token_with_keys(token, &TOKEN_KEYS)
+    }
 
-        let given_digest_access_token: DigestAccessToken = access_token
-            .try_into()
-            .map_err(ParseAccessTokenError::CreateAccessTokenError)?;
+    fn from_token_with_keys(
+        token: impl AsRef<str>,
+        keys: &TokenKeys,
+    ) -> Result<Self, ParseAccessTokenError> {
+        let claims = decode_claims::<AccessTokenClaims>(token, keys, None)?;
 
-        if given_digest_access_token.digest != digest_token.digest {
-            return Err(ParseAccessTokenError::InvalidDigest);
-        }
+        let expires_at = OffsetDateTime::from_unix_timestamp(claims.exp)
+            .map_err(|_| ParseAccessTokenError::InvalidTimestamp)?;
 
         Ok(AccessToken {
-            user,
+            user: UserInfo {
+                first_name: claims.first_name,
+                last_name: claims.last_name,
+                user_id: claims.sub,
+                email_verified: claims.email_verified,
+            },
+            session_id: claims.sid,
+            token_id: claims.jti,
             expires_at,
-            refresh_at,
         })
     }
 
@@ -128,43 +356,199 @@ impl AccessToken {
         &self.user
     }
 
-    pub fn get_expires_at(&self) -> &OffsetDateTime {
-        &self.expires_at
+    /// Session the token was issued for
+    pub fn get_session_id(&self) -> Uuid {
+        self.session_id
+    }
+
+    /// Unique id of the token, used to revoke it before it expires
+    pub fn get_token_id(&self) -> Uuid {
+        self.token_id
     }
 
-    pub fn get_refresh_at(&self) -> &OffsetDateTime {
-        &self.refresh_at
+    pub fn get_expires_at(&self) -> OffsetDateTime {
+        self.expires_at
     }
 }
 
+pub fn encode_claims(
+    claims: &impl Serialize,
+    key: &TokenKey,
+) -> Result<String, CreateAccessTokenError> {
+    let mut header = Header::new(key.algorithm);
+    header.kid = Some(key.kid.clone());
+
+    jsonwebtoken::encode(&header, claims, &key.encoding_key)
+        .map_err(CreateAccessTokenError::JwtError)
+}
+
+/// Verifies a token against the configured keys, the one matching `kid` is tried first
+///
+/// Access tokens have no audience, other tokens we sign must name theirs,
+/// so one kind can not be passed off as another.
+fn decode_claims<T: DeserializeOwned>(
+    token: impl AsRef<str>,
+    keys: &TokenKeys,
+    audience: Option<&str>,
+) -> Result<T, ParseAccessTokenError> {
+    let header =
+        jsonwebtoken::decode_header(token.as_ref()).map_err(ParseAccessTokenError::JwtError)?;
+
+    let mut result = Err(ParseAccessTokenError::UnknownKey);
+    for key in keys.verification_keys(&header) {
+        let mut validation = Validation::new(key.algorithm);
+        validation.set_issuer(&[TOKEN_ISSUER]);
+        validation.leeway = 0;
+        match audience {
+            Some(audience) => {
+                validation.set_audience(&[audience]);
+                validation.set_required_spec_claims(&["exp", "iss", "sub", "aud"]);
+            }
+            None => validation.set_required_spec_claims(&["exp", "iss", "sub", "jti"]),
+        }
+
+        result = jsonwebtoken::decode::<T>(token.as_ref(), &key.decoding_key, &validation)
+            .map_err(ParseAccessTokenError::JwtError);
+        if result.is_ok() {
+            break;
+        }
+    }
+
+    result.map(|data| data.claims)
+}
+
 #[derive(Serialize, Deserialize)]
-pub struct DigestAccessToken {
-    digest: String,
-    token: String,
-    expires_at: OffsetDateTime,
-    refresh_at: OffsetDateTime,
+struct EmailVerificationClaims {
+    iss: String,
+    aud: String,
+    sub: Uuid,
+    email: String,
+    iat: i64,
+    exp: i64,
 }
 
-impl TryFrom<AccessToken> for DigestAccessToken {
-    type Error = CreateAccessTokenError;
+/// A mailed proof that a user owns an email address
+///
+/// It is bound to the address, so it stops working once the email is verified or changed.
+#[derive(Debug, PartialEq)]
+pub struct EmailVerificationToken {
+    pub user_id: Uuid,
+    pub email: String,
+}
 
-    fn try_from(value: AccessToken) -> Result<Self, Self::Error> {
-        let token = serde_json::to_string(&value).map_err(CreateAccessTokenError::JsonError)?;
+impl EmailVerificationToken {
+    pub fn encode(&self) -> Result<String, CreateAccessTokenError> {
+        let duration_in_secs = std::env::var("EMAIL_VERIFICATION_TOKEN_DURATION_IN_SECS")
+            .expect("EMAIL_VERIFICATION_TOKEN_DURATION_IN_SECS must be in environment")
+            .parse::<i64>()
+            .expect("integer duration");
 
-        let salt_b64 = std::env::var("SECRET_SALT").expect("SECRET_SALT must be in environment");
+        let now = OffsetDateTime::now_utc();
+        let claims = EmailVerificationClaims {
+            iss: TOKEN_ISSUER.to_owned(),
+            aud: EMAIL_VERIFICATION_AUDIENCE.to_owned(),
+            sub: self.user_id,
+            email: self.email.clone(),
+            iat: now.unix_timestamp(),
+            exp: now
+                .add(Duration::seconds(duration_in_secs))
+                .unix_timestamp(),
+        };
 
-        let digest = generate_b64_hash_for_text_and_salt(&token, salt_b64)
-            .map_err(CreateAccessTokenError::DecodeError)?;
+        encode_claims(&claims, TOKEN_KEYS.signing_key())
+    }
 
-        Ok(DigestAccessToken {
-            digest,
+    pub fn from_token(token: impl AsRef<str>) -> Result<Self, ParseAccessTokenError> {
+        let claims = decode_claims::<EmailVerificationClaims>(
             token,
-            expires_at: value.expires_at,
-            refresh_at: value.refresh_at,
+            &TOKEN_KEYS,
+            Some(EMAIL_VERIFICATION_AUDIENCE),
+        )?;
+
+        Ok(Self {
+            user_id: claims.sub,
+            email: claims.email,
         })
     }
 }
 
+#[derive(Serialize, Deserialize)]
+struct MfaPendingClaims {
+    iss: String,
+    aud: String,
+    sub: Uuid,
+    jti: Uuid,
+    device_name: Option<String>,
+    iat: i64,
+    exp: i64,
+}
+
+/// Proof that a user has passed the password check and still owes a second factor
+///
+/// A token is spent by a successful login, its `token_id` is denylisted like a revoked access token.
+#[derive(Debug, PartialEq)]
+pub struct MfaPendingToken {
+    pub user_id: Uuid,
+    pub device_name: Option<String>,
+    token_id: Uuid,
+    expires_at: OffsetDateTime,
+}
+
+impl MfaPendingToken {
+    pub fn new(user_id: Uuid, device_name: Option<String>) -> Self {
+        let duration_in_secs = std::env::var("MFA_TOKEN_DURATION_IN_SECS")
+            .expect("MFA_TOKEN_DURATION_IN_SECS must be in environment")
+            .parse::<i64>()
+            .expect("integer duration");
+        // JWT timestamps have a second precision
+        let now = OffsetDateTime::now_utc()
+            .replace_nanosecond(0)
+            .expect("zero is a valid nanosecond");
+
+        Self {
+            user_id,
+            device_name,
+            token_id: Uuid::new_v4(),
+            expires_at: now.add(Duration::seconds(duration_in_secs)),
+        }
+    }
+
+    pub fn encode(&self) -> Result<String, CreateAccessTokenError> {
+        let claims = MfaPendingClaims {
+            iss: TOKEN_ISSUER.to_owned(),
+            aud: MFA_PENDING_AUDIENCE.to_owned(),
+            sub: self.user_id,
+            jti: self.token_id,
+            device_name: self.device_name.clone(),
+            iat: OffsetDateTime::now_utc().unix_timestamp(),
+            exp: self.expires_at.unix_timestamp(),
+        };
+
+        encode_claims(&claims, TOKEN_KEYS.signing_key())
+    }
+
+    pub fn from_token(token: impl AsRef<str>) -> Result<Self, ParseAccessTokenError> {
+        let claims =
+            decode_claims::<MfaPendingClaims>(token, &TOKEN_KEYS, Some(MFA_PENDING_AUDIENCE))?;
+
+        Ok(Self {
+            user_id: claims.sub,
+            device_name: claims.device_name,
+            token_id: claims.jti,
+            expires_at: OffsetDateTime::from_unix_timestamp(claims.exp)
+                .map_err(|_| ParseAccessTokenError::InvalidTimestamp)?,
+        })
+    }
+
+    pub fn get_token_id(&self) -> Uuid {
+        self.token_id
+    }
+
+    pub fn get_expires_at(&self) -> OffsetDateTime {
+        self.expires_at
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
@@ -172,22 +556,32 @@ pub mod tests {
     use database::utils::random_samples::RandomSample;
     use dotenvy::dotenv;
 
-    pub fn create_token() -> (UserInfo, AccessTokenResponse) {
-        dotenv().expect("failed to load .env");
-
+    fn create_user_info() -> UserInfo {
         let user_id = Uuid::new_v4();
         let first_name = Some(String::new_random(124));
         let last_name = Some(String::new_random(124));
 
-        let user = UserInfo {
+        UserInfo {
             user_id,
             first_name,
             last_name,
-        };
+            email_verified: true,
+        }
+    }
+
+    fn create_hmac_key(kid: &str) -> TokenKey {
+        let secret = BASE_64.encode(String::new_random(64));
+        TokenKey::parse(std::format!("{kid}:HS256:{secret}")).expect("valid key")
+    }
+
+    pub fn create_token() -> (UserInfo, AccessTokenResponse) {
+        dotenv().expect("failed to load .env");
+
+        let user = create_user_info();
 
         (
             user.clone(),
-            AccessTokenResponse::new(user).expect("valid token"),
+            AccessTokenResponse::new(user, Uuid::new_v4()).expect("valid token"),
         )
     }
 
@@ -195,19 +589,16 @@ pub mod tests {
         // past
         let expires_at = OffsetDateTime::now_utc().add(-minus_duration);
 
-        // distant future
-        let refresh_at: OffsetDateTime = OffsetDateTime::now_utc().add(Duration::days(1000));
-
         let access_token = AccessToken {
             user,
+            session_id: Uuid::new_v4(),
+            token_id: Uuid::new_v4(),
             expires_at,
-            refresh_at,
         };
 
-        let digest_access_token: DigestAccessToken =
-            access_token.try_into().expect("valid digest token");
-
-        digest_access_token.try_into().expect("valid token")
+        access_token
+            .encode(TOKEN_KEYS.signing_key())
+            .expect("valid token")
     }
 
     #[test]
@@ -220,42 +611,133 @@ pub mod tests {
 
         assert_eq!(parsed_access_token.user, user);
         assert_eq!(parsed_access_token.expires_at, token_response.expires_at);
-        assert_eq!(parsed_access_token.refresh_at, token_response.refresh_at);
     }
 
     #[test]
     fn test_parse_invalid_token() {
         dotenv().expect("failed to load .env");
 
-        let user_id = Uuid::new_v4();
-        let first_name = Some(String::new_random(124));
-        let last_name = Some(String::new_random(124));
+        let (_, token_response) = create_token();
 
-        let user = UserInfo {
-            user_id,
-            first_name,
-            last_name,
-        };
+        let mut parts = token_response.token.split('.').collect::<Vec<_>>();
+        let signature = BASE_64_URL.encode(String::new_random(64));
+        parts[2] = signature.as_str();
+        let token = parts.join(".");
 
-        let access_token = AccessToken::new_with_user(user);
-        let expires_at = access_token.expires_at;
-        let refresh_at = access_token.refresh_at;
+        let parsed_access_token_or_error = AccessToken::from_token(token);
+        assert!(parsed_access_token_or_error.is_err())
+    }
 
-        let digest_access_token = DigestAccessToken {
-            digest: String::new_random(88),
-            token: serde_json::to_string(&access_token)
-                .map_err(CreateAccessTokenError::JsonError)
-                .expect("valid access token"),
-            expires_at,
-            refresh_at,
+    #[test]
+    fn test_parse_expired_token() {
+        let (user, _) = create_token();
+
+        let token_response = make_expired_token(user, Duration::seconds(1));
+
+        assert!(AccessToken::from_token(token_response.token).is_err());
+    }
+
+    #[test]
+    fn test_generate_refresh_tokens() {
+        dotenv().expect("failed to load .env");
+
+        let first = RefreshTokenResponse::generate();
+        let second = RefreshTokenResponse::generate();
+
+        assert_ne!(first.token, second.token);
+        assert_ne!(first.hash(), second.hash());
+        assert_eq!(first.hash(), hash_opaque_token(&first.token));
+        assert_eq!(first.hash().len(), 88);
+    }
+
+    #[test]
+    fn test_parse_token_signed_with_a_rotated_out_key() {
+        let old_key = create_hmac_key("old");
+        let token = AccessToken::new_with_user_and_duration(
+            create_user_info(),
+            Uuid::new_v4(),
+            Duration::HOUR,
+        )
+        .encode(&old_key)
+        .expect("valid token")
+        .token;
+
+        let keys = TokenKeys::new(vec![create_hmac_key("new"), old_key]).expect("valid keys");
+        assert!(AccessToken::from_token_with_keys(&token, &keys).is_ok());
+
+        let keys = TokenKeys::new(vec![create_hmac_key("new")]).expect("valid keys");
+        assert!(AccessToken::from_token_with_keys(&token, &keys).is_err());
+    }
+
+    #[test]
+    fn test_ed25519_key_is_published_and_verifies_tokens() {
+        dotenv().expect("failed to load .env");
+
+        let ed_key = TOKEN_KEYS
+            .keys
+            .iter()
+            .find(|key| key.algorithm == Algorithm::EdDSA)
+            .expect("EdDSA key in JWT_KEYS");
+
+        let token = AccessToken::new_with_user_and_duration(
+            create_user_info(),
+            Uuid::new_v4(),
+            Duration::HOUR,
+        )
+        .encode(ed_key)
+        .expect("valid token")
+        .token;
+
+        let jwk_set = TOKEN_KEYS.jwk_set();
+        assert!(jwk_set
+            .keys
+            .iter()
+            .all(|jwk| jwk.common.algorithm == Some(Algorithm::EdDSA)));
+
+        let jwk = jwk_set
+            .find(&ed_key.kid)
+            .expect("EdDSA key is in the key set");
+        let mut validation = Validation::new(Algorithm::EdDSA);
+        validation.set_issuer(&[TOKEN_ISSUER]);
+        let decoding_key = DecodingKey::from_jwk(jwk).expect("valid jwk");
+
+        assert!(
+            jsonwebtoken::decode::<AccessTokenClaims>(&token, &decoding_key, &validation).is_ok()
+        );
+    }
+
+    #[test]
+    fn test_email_verification_token_is_not_an_access_token() {
+        dotenv().expect("failed to load .env");
+
+        let token = EmailVerificationToken {
+            user_id: Uuid::new_v4(),
+            email: "test@test.test".to_owned(),
         };
+        let encoded = token.encode().expect("valid token");
 
-        let token_response: AccessTokenResponse =
-            digest_access_token.try_into().expect("valid token");
+        assert_eq!(
+            EmailVerificationToken::from_token(&encoded).expect("valid token"),
+            token
+        );
+        assert!(AccessToken::from_token(&encoded).is_err());
 
-        let token = token_response.token;
+        let (_, access_token) = create_token();
+        assert!(EmailVerificationToken::from_token(access_token.token).is_err());
+    }
 
-        let parsed_access_token_or_error = AccessToken::from_token(token);
-        assert!(parsed_access_token_or_error.is_err())
+    #[test]
+    fn test_mfa_pending_token_is_neither_an_access_nor_a_verification_token() {
+        dotenv().expect("failed to load .env");
+
+        let token = MfaPendingToken::new(Uuid::new_v4(), Some("laptop".to_owned()));
+        let encoded = token.encode().expect("valid token");
+
+        assert_eq!(
+            MfaPendingToken::from_token(&encoded).expect("valid token"),
+            token
+        );
+        assert!(AccessToken::from_token(&encoded).is_err());
+        assert!(EmailVerificationToken::from_token(&encoded).is_err());
     }
 }
@@ -1,5 +1,27 @@
+pub mod accounts;
+pub mod api_keys;
+pub mod audit;
 pub mod authentication;
+pub mod authorization;
+pub mod chat_members;
+pub mod chat_messages;
+pub mod chats;
+pub mod companies;
+pub mod company_members;
+pub mod credentials;
+pub mod email_verification;
 pub mod errors;
+pub mod events;
 mod formats;
+pub mod jwks;
+pub mod media;
+pub mod mfa;
+pub mod oidc;
+pub mod password_reset;
+pub mod profiles;
+pub mod project_members;
 pub mod projects;
+pub mod rate_limiting;
+pub mod sessions;
+pub mod tokens;
 pub mod users;
@@ -0,0 +1,374 @@
+use crate::models::chat_members::ChatMemberDb;
+use crate::models::chat_message::ChatMessageDb;
+use crate::models::chats::ChatDb;
+use crate::models::company::CompanyDb;
+use crate::models::errors::DbError;
+use crate::models::project::ProjectDb;
+use crate::models::user::UserDb;
+use crate::web::audit::{record_audit_event, AuditEventResponseData};
+use crate::web::authentication::{Authenticated, ScopeError};
+use crate::web::chat_members::chat_member_role_name;
+use crate::web::company_members::company_member_role_name;
+use crate::web::credentials::{get_user_with_password, CredentialsErrorResponse};
+use crate::web::errors::create_bad_request_error;
+use crate::web::formats::JsonDateTime;
+use crate::web::profiles::ProfileResponseData;
+use crate::web::project_members::project_member_role_name;
+use crate::web::sessions::ClientInfo;
+use crate::web_service::WebService;
+use axum::extract::rejection::JsonRejection;
+use axum::extract::State;
+use axum::http::header::CONTENT_DISPOSITION;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use database::audit_events::AuditEventType;
+use database::chats::{ChatMember, ChatMessage};
+use database::companies::CompanyMember;
+use database::projects::{Project, ProjectMember};
+use database::user_data::DeletedUserData;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+#[derive(Debug)]
+pub enum AccountErrorResponse {
+    Forbidden(ScopeError),
+    DbError(DbError),
+    Credentials(CredentialsErrorResponse),
+    JsonRejection(JsonRejection),
+}
+
+impl IntoResponse for AccountErrorResponse {
+    fn into_response(self) -> Response {
+        match self {
+            AccountErrorResponse::Forbidden(error) => error.into_response(),
+            AccountErrorResponse::DbError(db_error) => db_error.into_response(),
+            AccountErrorResponse::Credentials(error) => error.into_response(),
+            AccountErrorResponse::JsonRejection(error) => {
+                create_bad_request_error(error.to_string()).into_response()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ExportedProject {
+    id: Uuid,
+    name: String,
+    description: String,
+    created_at: JsonDateTime,
+    updated_at: JsonDateTime,
+}
+
+impl From<Project> for ExportedProject {
+    fn from(value: Project) -> Self {
+        ExportedProject {
+            id: value.id,
+            name: value.name,
+            description: value.description,
+            created_at: value.created_at.into(),
+            updated_at: value.updated_at.into(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ExportedMembership {
+    /// A project, a company or a chat
+    id: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    role: Option<String>,
+    created_at: JsonDateTime,
+}
+
+impl From<ProjectMember> for ExportedMembership {
+    fn from(value: ProjectMember) -> Self {
+        ExportedMembership {
+            id: value.project_id,
+            role: Some(project_member_role_name(value.role).to_owned()),
+            created_at: value.created_at.into(),
+        }
+    }
+}
+
+impl From<CompanyMember> for ExportedMembership {
+    fn from(value: CompanyMember) -> Self {
+        ExportedMembership {
+            id: value.company_id,
+            role: Some(company_member_role_name(value.role).to_owned()),
+            created_at: value.created_at.into(),
+        }
+    }
+}
+
+impl From<ChatMember> for ExportedMembership {
+    fn from(value: ChatMember) -> Self {
+        ExportedMembership {
+            id: value.chat_id,
+            role: Some(chat_member_role_name(value.role).to_owned()),
+            created_at: value.created_at.into(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ExportedChatMessage {
+    id: Uuid,
+    chat_id: Uuid,
+    parent_id: Option<Uuid>,
+    message: String,
+    created_at: JsonDateTime,
+    updated_at: JsonDateTime,
+    deleted_at: Option<JsonDateTime>,
+}
+
+impl From<ChatMessage> for ExportedChatMessage {
+    fn from(value: ChatMessage) -> Self {
+        ExportedChatMessage {
+            id: value.id,
+            chat_id: value.chat_id,
+            parent_id: value.parent_id,
+            message: value.message,
+            created_at: value.created_at.into(),
+            updated_at: value.updated_at.into(),
+            deleted_at: value.deleted_at.map(Into::into),
+        }
+    }
+}
+
+/// A portable copy of everything stored about a user
+#[derive(Debug, Deserialize, Serialize)]
+pub struct UserDataExport {
+    exported_at: JsonDateTime,
+    profile: ProfileResponseData,
+    projects: Vec<ExportedProject>,
+    project_memberships: Vec<ExportedMembership>,
+    company_memberships: Vec<ExportedMembership>,
+    chat_memberships: Vec<ExportedMembership>,
+    chat_messages: Vec<ExportedChatMessage>,
+    audit_events: Vec<AuditEventResponseData>,
+}
+
+fn collect<T, R: From<T>>(values: Vec<T>) -> Vec<R> {
+    values.into_iter().map(Into::into).collect()
+}
+
+/// Returns a JSON archive of the current user's data as a file download
+#[tracing::instrument(skip(web_service))]
+pub async fn export<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authenticated: Authenticated,
+) -> Result<impl IntoResponse, AccountErrorResponse> {
+    let user_info = authenticated
+        .require_user()
+        .map_err(AccountErrorResponse::Forbidden)?;
+
+    let user = web_service
+        .user_db
+        .get_user(&user_info.user_id)
+        .await
+        .map_err(AccountErrorResponse::DbError)?;
+    let data = web_service
+        .user_db
+        .get_user_data(user_info.user_id)
+        .await
+        .map_err(AccountErrorResponse::DbError)?;
+
+    let now = OffsetDateTime::now_utc();
+    let export = UserDataExport {
+        exported_at: JsonDateTime::from(time::PrimitiveDateTime::new(now.date(), now.time())),
+        profile: user.into(),
+        projects: collect(data.projects),
+        project_memberships: collect(data.project_memberships),
+        company_memberships: collect(data.company_memberships),
+        chat_memberships: collect(data.chat_memberships),
+        chat_messages: collect(data.chat_messages),
+        audit_events: collect(data.audit_events),
+    };
+
+    let disposition = std::format!(
+        "attachment; filename=\"user-data-{}.json\"",
+        user_info.user_id
+    );
+    Ok((
+        StatusCode::OK,
+        [(CONTENT_DISPOSITION, disposition)],
+        Json(export),
+    ))
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DeleteAccountData {
+    password: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DeleteAccountRequestBody {
+    data: DeleteAccountData,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DeleteAccountResponseBody {
+    transferred_projects: u64,
+    deleted_projects: u64,
+    redacted_messages: u64,
+}
+
+impl From<DeletedUserData> for DeleteAccountResponseBody {
+    fn from(value: DeletedUserData) -> Self {
+        DeleteAccountResponseBody {
+            transferred_projects: value.transferred_projects,
+            deleted_projects: value.deleted_projects,
+            redacted_messages: value.redacted_messages,
+        }
+    }
+}
+
+/// Erases the current user, confirmed with their password
+///
+/// Projects with other members go to the longest standing one, the rest are deleted.
+/// Every session ends, so the current access token stops working at once.
+#[tracing::instrument(skip(web_service, body_or_error))]
+pub async fn delete<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authenticated: Authenticated,
+    client_info: ClientInfo,
+    body_or_error: Result<Json<DeleteAccountRequestBody>, JsonRejection>,
+) -> Result<(StatusCode, Json<DeleteAccountResponseBody>), AccountErrorResponse> {
+    let Json(body) = body_or_error.map_err(AccountErrorResponse::JsonRejection)?;
+
+    let user = get_user_with_password(&web_service.user_db, &authenticated, &body.data.password)
+        .await
+        .map_err(AccountErrorResponse::Credentials)?;
+
+    let deleted = web_service
+        .user_db
+        .delete_user(user.id)
+        .await
+        .map_err(AccountErrorResponse::DbError)?;
+
+    record_audit_event(
+        web_service.audit_log.as_ref(),
+        AuditEventType::AccountDeleted,
+        user.id,
+        None,
+        &client_info,
+    )
+    .await;
+
+    Ok((StatusCode::OK, Json(deleted.into())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web::email_verification::tests::register_verified_user;
+    use crate::web::projects::tests::create_project_with_token;
+    use crate::web::users::tests::{create_test_router, login_with_email_and_password, user_id};
+    use crate::web_service::tests::{
+        delete_json_with_auth_header, deserialize_response_body, get_with_auth_header,
+    };
+    use axum::body::Bytes;
+    use axum::Router;
+    use http_body::combinators::UnsyncBoxBody;
+
+    async fn delete_account(
+        router: &Router,
+        token: &str,
+        password: impl Into<String>,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        let request_body = DeleteAccountRequestBody {
+            data: DeleteAccountData {
+                password: password.into(),
+            },
+        };
+
+        delete_json_with_auth_header(router, "/api/user/me", &request_body, Some(token)).await
+    }
+
+    #[tokio::test]
+    async fn should_export_user_data() {
+        let router = create_test_router().await;
+        let (request, token) = register_verified_user().await;
+        let (_, project) = create_project_with_token(&router, &token).await;
+
+        let response = get_with_auth_header(&router, "/api/user/me/export", Some(&token)).await;
+        assert_eq!(response.status(), 200);
+        let disposition = response
+            .headers()
+            .get(CONTENT_DISPOSITION)
+            .and_then(|x| x.to_str().ok())
+            .map(String::from);
+        assert!(disposition.is_some_and(|x| x.starts_with("attachment")));
+
+        let export = deserialize_response_body::<UserDataExport>(response).await;
+        let user_id = user_id(&token);
+        assert_eq!(
+            serde_json::to_value(&export.profile).expect("profile")["email"],
+            request.email()
+        );
+        assert_eq!(
+            export.projects.iter().map(|x| x.id).collect::<Vec<_>>(),
+            vec![project.project_id()]
+        );
+        assert!(export
+            .audit_events
+            .iter()
+            .all(
+                |event| serde_json::to_value(event).expect("event")["user_id"]
+                    == user_id.to_string()
+            ));
+        assert!(!export.audit_events.is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_delete_an_account() {
+        let router = create_test_router().await;
+        let (request, token) = register_verified_user().await;
+        let user_id = user_id(&token);
+        create_project_with_token(&router, &token).await;
+
+        let response = delete_account(&router, &token, "wrong password").await;
+        assert_eq!(response.status(), 403);
+
+        let response = delete_account(&router, &token, request.password()).await;
+        assert_eq!(response.status(), 200);
+        let response_body = deserialize_response_body::<DeleteAccountResponseBody>(response).await;
+        assert_eq!(response_body.deleted_projects, 1);
+        assert_eq!(response_body.transferred_projects, 0);
+
+        let response = get_with_auth_header(&router, "/api/user/me", Some(&token)).await;
+        assert_eq!(response.status(), 401);
+
+        let response = login_with_email_and_password(
+            request.email().to_owned(),
+            request.password().to_owned(),
+        )
+        .await;
+        assert_eq!(response.status(), 404);
+
+        let (_, other_token) = register_verified_user().await;
+        let response = get_with_auth_header(
+            &router,
+            std::format!("/api/user/{user_id}"),
+            Some(&other_token),
+        )
+        .await;
+        assert_eq!(response.status(), 404);
+    }
+}
@@ -0,0 +1,383 @@
+use crate::models::chat_members::ChatMemberDb;
+use crate::models::chat_message::ChatMessageDb;
+use crate::models::chats::ChatDb;
+use crate::models::company::CompanyDb;
+use crate::models::errors::DbError;
+use crate::models::project::ProjectDb;
+use crate::models::user::{OwnedApiKeyInput, UserDb};
+use crate::utils::api_keys::{api_key_prefix, generate_api_key, Scope};
+use crate::utils::tokens::hash_opaque_token;
+use crate::web::authentication::{Authenticated, ScopeError};
+use crate::web::errors::create_invalid_response;
+use crate::web::formats::JsonDateTime;
+use crate::web_service::WebService;
+use axum::extract::rejection::{JsonRejection, PathRejection};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use database::api_keys::ApiKey;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+const API_KEY_NAME_MAX_LENGTH: usize = 255;
+
+#[derive(Debug)]
+pub enum ApiKeyErrorResponse {
+    Forbidden(ScopeError),
+    DbError(DbError),
+    InvalidInputDataFormat(String),
+}
+
+impl IntoResponse for ApiKeyErrorResponse {
+    fn into_response(self) -> Response {
+        match self {
+            ApiKeyErrorResponse::Forbidden(error) => error.into_response(),
+            ApiKeyErrorResponse::DbError(db_error) => db_error.into_response(),
+            ApiKeyErrorResponse::InvalidInputDataFormat(error) => {
+                create_invalid_response(error).into_response()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CreateApiKeyData {
+    name: String,
+    scopes: Vec<Scope>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CreateApiKeyRequestBody {
+    data: CreateApiKeyData,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CreateApiKeyResponseBody {
+    id: Uuid,
+    /// The only time the key is shown
+    key: String,
+    prefix: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ApiKeyResponseData {
+    id: Uuid,
+    name: String,
+    prefix: String,
+    scopes: Vec<String>,
+    created_at: JsonDateTime,
+    last_used_at: Option<JsonDateTime>,
+}
+
+impl From<ApiKey> for ApiKeyResponseData {
+    fn from(value: ApiKey) -> Self {
+        ApiKeyResponseData {
+            id: value.id,
+            name: value.name,
+            prefix: value.prefix,
+            scopes: value.scopes,
+            created_at: value.created_at.into(),
+            last_used_at: value.last_used_at.map(Into::into),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ApiKeysResponseBody {
+    api_keys: Vec<ApiKeyResponseData>,
+}
+
+/// Creates an API key of the current user with a given set of scopes
+///
+/// Only a signed in user creates keys, a key can not be used to create another one.
+#[tracing::instrument(skip(web_service, body_or_error))]
+pub async fn post<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authenticated: Authenticated,
+    body_or_error: Result<Json<CreateApiKeyRequestBody>, JsonRejection>,
+) -> Result<(StatusCode, Json<CreateApiKeyResponseBody>), ApiKeyErrorResponse> {
+    let user_info = authenticated
+        .require_user()
+        .map_err(ApiKeyErrorResponse::Forbidden)?;
+
+    let Json(body) = body_or_error
+        .map_err(|x| x.to_string())
+        .map_err(ApiKeyErrorResponse::InvalidInputDataFormat)?;
+    let name = body.data.name.trim().to_owned();
+    if name.is_empty() || name.len() > API_KEY_NAME_MAX_LENGTH {
+        return Err(ApiKeyErrorResponse::InvalidInputDataFormat(std::format!(
+            "API key name must be 1 to {API_KEY_NAME_MAX_LENGTH} characters long"
+        )));
+    }
+    if body.data.scopes.is_empty() {
+        return Err(ApiKeyErrorResponse::InvalidInputDataFormat(
+            "API key needs at least one scope".to_owned(),
+        ));
+    }
+
+    let mut scopes = body
+        .data
+        .scopes
+        .iter()
+        .map(|scope| scope.as_str().to_owned())
+        .collect::<Vec<_>>();
+    scopes.sort();
+    scopes.dedup();
+
+    let key = generate_api_key();
+    let prefix = api_key_prefix(&key);
+
+    let id = web_service
+        .user_db
+        .insert_api_key(&OwnedApiKeyInput {
+            user_id: user_info.user_id,
+            name,
+            prefix: prefix.clone(),
+            key_hash: hash_opaque_token(&key),
+            scopes,
+        })
+        .await
+        .map_err(ApiKeyErrorResponse::DbError)?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreateApiKeyResponseBody { id, key, prefix }),
+    ))
+}
+
+/// Lists not revoked API keys of the current user, the most recently created first
+///
+#[tracing::instrument(skip(web_service))]
+pub async fn get_all<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authenticated: Authenticated,
+) -> Result<(StatusCode, Json<ApiKeysResponseBody>), ApiKeyErrorResponse> {
+    let user_info = authenticated
+        .require_user()
+        .map_err(ApiKeyErrorResponse::Forbidden)?;
+
+    let api_keys = web_service
+        .user_db
+        .get_active_api_keys(user_info.user_id)
+        .await
+        .map_err(ApiKeyErrorResponse::DbError)?;
+
+    Ok((
+        StatusCode::OK,
+        Json(ApiKeysResponseBody {
+            api_keys: api_keys.into_iter().map(Into::into).collect(),
+        }),
+    ))
+}
+
+/// Revokes an API key of the current user, it stops working at once
+///
+#[tracing::instrument(skip(web_service))]
+pub async fn delete<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authenticated: Authenticated,
+    api_key_id_or_error: Result<Path<Uuid>, PathRejection>,
+) -> Result<StatusCode, ApiKeyErrorResponse> {
+    let user_info = authenticated
+        .require_user()
+        .map_err(ApiKeyErrorResponse::Forbidden)?;
+
+    let Path(api_key_id) = api_key_id_or_error
+        .map_err(|x| x.to_string())
+        .map_err(ApiKeyErrorResponse::InvalidInputDataFormat)?;
+
+    let revoked = web_service
+        .user_db
+        .revoke_api_key(api_key_id, user_info.user_id)
+        .await
+        .map_err(ApiKeyErrorResponse::DbError)?;
+
+    if revoked == 0 {
+        return Err(ApiKeyErrorResponse::DbError(DbError::NotFoundError));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::utils::api_keys::Scope;
+    use crate::web::api_keys::{
+        ApiKeysResponseBody, CreateApiKeyData, CreateApiKeyRequestBody, CreateApiKeyResponseBody,
+    };
+    use crate::web::email_verification::tests::register_verified_user;
+    use crate::web::errors::{INVALID_API_KEY_ERROR_MSG, MISSING_SCOPE_ERROR_MSG};
+    use crate::web::projects::tests::{create_project_request, create_project_with_token};
+    use crate::web::users::tests::create_test_router;
+    use crate::web_service::tests::{
+        delete_with_auth_header, deserialize_response_body, get_with_auth_header,
+        post_with_auth_header,
+    };
+    use crate::web_service::ErrorResponseBody;
+    use axum::Router;
+    use uuid::Uuid;
+
+    impl CreateApiKeyResponseBody {
+        pub fn id(&self) -> Uuid {
+            self.id
+        }
+
+        pub fn key(&self) -> &str {
+            &self.key
+        }
+    }
+
+    /// A new key of a user the access token belongs to
+    pub async fn create_api_key(
+        router: &Router,
+        access_token: &str,
+        scopes: Vec<Scope>,
+    ) -> CreateApiKeyResponseBody {
+        let request_body = CreateApiKeyRequestBody {
+            data: CreateApiKeyData {
+                name: "lab robot".to_owned(),
+                scopes,
+            },
+        };
+
+        let response = post_with_auth_header(
+            router,
+            "/api/user/api-keys",
+            &request_body,
+            Some(access_token),
+        )
+        .await;
+        assert_eq!(response.status(), 201);
+
+        deserialize_response_body::<CreateApiKeyResponseBody>(response).await
+    }
+
+    #[tokio::test]
+    async fn should_use_an_api_key_within_its_scopes() {
+        let router = create_test_router().await;
+        let (_, access_token) = register_verified_user().await;
+
+        let api_key = create_api_key(
+            &router,
+            &access_token,
+            vec![Scope::ProjectsRead, Scope::ProjectsWrite],
+        )
+        .await;
+        assert!(api_key.key.starts_with(&api_key.prefix));
+
+        let (_, project) = create_project_with_token(&router, &api_key.key).await;
+
+        let uri = std::format!("/api/project/{}", project.project_id());
+        let response = get_with_auth_header(&router, uri, Some(&api_key.key)).await;
+        assert_eq!(response.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn should_reject_an_api_key_without_a_scope() {
+        let router = create_test_router().await;
+        let (_, access_token) = register_verified_user().await;
+
+        let api_key = create_api_key(&router, &access_token, vec![Scope::ProjectsRead]).await;
+
+        let response = post_with_auth_header(
+            &router,
+            "/api/project/new",
+            &create_project_request(),
+            Some(&api_key.key),
+        )
+        .await;
+        assert_eq!(response.status(), 403);
+
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(
+            response_body.error,
+            std::format!("{MISSING_SCOPE_ERROR_MSG}: projects:write")
+        );
+    }
+
+    #[tokio::test]
+    async fn should_not_manage_api_keys_with_an_api_key() {
+        let router = create_test_router().await;
+        let (_, access_token) = register_verified_user().await;
+
+        let api_key = create_api_key(&router, &access_token, vec![Scope::ProjectsRead]).await;
+
+        let response =
+            get_with_auth_header(&router, "/api/user/api-keys", Some(&api_key.key)).await;
+        assert_eq!(response.status(), 403);
+    }
+
+    #[tokio::test]
+    async fn should_list_and_revoke_api_keys() {
+        let router = create_test_router().await;
+        let (_, access_token) = register_verified_user().await;
+
+        let api_key = create_api_key(&router, &access_token, vec![Scope::ProjectsRead]).await;
+
+        let response =
+            get_with_auth_header(&router, "/api/user/api-keys", Some(&access_token)).await;
+        assert_eq!(response.status(), 200);
+
+        let response_body = deserialize_response_body::<ApiKeysResponseBody>(response).await;
+        assert_eq!(response_body.api_keys.len(), 1);
+        assert_eq!(response_body.api_keys[0].id, api_key.id);
+        assert_eq!(response_body.api_keys[0].scopes, vec!["projects:read"]);
+
+        let uri = std::format!("/api/user/api-keys/{}", api_key.id);
+        let response = delete_with_auth_header(&router, &uri, Some(&access_token)).await;
+        assert_eq!(response.status(), 204);
+
+        let response = delete_with_auth_header(&router, &uri, Some(&access_token)).await;
+        assert_eq!(response.status(), 404);
+
+        let response =
+            get_with_auth_header(&router, "/api/user/api-keys", Some(&api_key.key)).await;
+        assert_eq!(response.status(), 401);
+
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.error, INVALID_API_KEY_ERROR_MSG);
+    }
+
+    #[tokio::test]
+    async fn should_reject_an_api_key_without_scopes() {
+        let router = create_test_router().await;
+        let (_, access_token) = register_verified_user().await;
+
+        let request_body = CreateApiKeyRequestBody {
+            data: CreateApiKeyData {
+                name: "lab robot".to_owned(),
+                scopes: vec![],
+            },
+        };
+        let response = post_with_auth_header(
+            &router,
+            "/api/user/api-keys",
+            &request_body,
+            Some(&access_token),
+        )
+        .await;
+        assert_eq!(response.status(), 400);
+    }
+}
@@ -0,0 +1,352 @@
+use crate::models::audit::{AuditLog, OwnedAuditEventInput};
+use crate::models::chat_members::ChatMemberDb;
+use crate::models::chat_message::ChatMessageDb;
+use crate::models::chats::ChatDb;
+use crate::models::company::CompanyDb;
+use crate::models::errors::DbError;
+use crate::models::project::ProjectDb;
+use crate::models::user::UserDb;
+use crate::web::authentication::{Authenticated, ScopeError};
+use crate::web::authorization::create_forbidden_response;
+use crate::web::errors::{create_invalid_response, FORBIDDEN_ERROR_MSG};
+use crate::web::formats::{JsonDateTime, DATE_TIME_FORMAT};
+use crate::web::sessions::ClientInfo;
+use crate::web_service::WebService;
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use database::audit_events::{AuditEvent, AuditEventFilter, AuditEventType};
+use serde::{Deserialize, Serialize};
+use time::{OffsetDateTime, PrimitiveDateTime, UtcOffset};
+use uuid::Uuid;
+
+const DEFAULT_AUDIT_EVENTS_LIMIT: i64 = 100;
+const MAX_AUDIT_EVENTS_LIMIT: i64 = 1000;
+
+const EVENT_TYPE_NAMES: [(AuditEventType, &str); 12] = [
+    (AuditEventType::Registered, "registered"),
+    (AuditEventType::LoginSucceeded, "login_succeeded"),
+    (AuditEventType::LoginFailed, "login_failed"),
+    (AuditEventType::TokenRefreshed, "token_refreshed"),
+    (AuditEventType::PasswordReset, "password_reset"),
+    (AuditEventType::PasswordChanged, "password_changed"),
+    (AuditEventType::EmailChanged, "email_changed"),
+    (AuditEventType::AccountDeleted, "account_deleted"),
+    (AuditEventType::ProjectCreated, "project_created"),
+    (AuditEventType::ProjectRead, "project_read"),
+    (AuditEventType::ProjectUpdated, "project_updated"),
+    (AuditEventType::ProjectDeleted, "project_deleted"),
+];
+
+fn event_type_name(event_type: AuditEventType) -> &'static str {
+    EVENT_TYPE_NAMES
+        .iter()
+        .find(|(x, _)| *x == event_type)
+        .map(|(_, name)| *name)
+        .unwrap_or_default()
+}
+
+fn parse_event_type(name: &str) -> Option<AuditEventType> {
+    EVENT_TYPE_NAMES
+        .iter()
+        .find(|(_, x)| *x == name)
+        .map(|(event_type, _)| *event_type)
+}
+
+/// Records an event, a failure is logged and does not fail the request
+pub async fn record_audit_event(
+    audit_log: &dyn AuditLog,
+    event_type: AuditEventType,
+    user_id: Uuid,
+    resource_id: Option<Uuid>,
+    client_info: &ClientInfo,
+) {
+    let input = OwnedAuditEventInput {
+        event_type,
+        user_id,
+        resource_id,
+        ip_address: client_info.ip_address().map(String::from),
+        user_agent: client_info.user_agent().map(String::from),
+    };
+
+    if let Err(error) = audit_log.record(&input).await {
+        tracing::error!("Can not record an audit event {:?}: {:?}", input, error);
+    }
+}
+
+#[derive(Debug)]
+pub enum AuditErrorResponse {
+    Forbidden(ScopeError),
+    NotAdmin,
+    DbError(DbError),
+    InvalidInputDataFormat(String),
+}
+
+impl IntoResponse for AuditErrorResponse {
+    fn into_response(self) -> Response {
+        match self {
+            AuditErrorResponse::Forbidden(error) => error.into_response(),
+            AuditErrorResponse::NotAdmin => create_forbidden_response(FORBIDDEN_ERROR_MSG),
+            AuditErrorResponse::DbError(db_error) => db_error.into_response(),
+            AuditErrorResponse::InvalidInputDataFormat(error) => {
+                create_invalid_response(error).into_response()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct AuditQuery {
+    user_id: Option<Uuid>,
+    event_type: Option<String>,
+    /// ISO 8601, inclusive
+    from: Option<String>,
+    /// ISO 8601, exclusive
+    to: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AuditEventResponseData {
+    id: Uuid,
+    event_type: String,
+    user_id: Uuid,
+    resource_id: Option<Uuid>,
+    ip_address: Option<String>,
+    user_agent: Option<String>,
+    created_at: JsonDateTime,
+}
+
+impl From<AuditEvent> for AuditEventResponseData {
+    fn from(value: AuditEvent) -> Self {
+        AuditEventResponseData {
+            id: value.id,
+            event_type: event_type_name(value.event_type).to_owned(),
+            user_id: value.user_id,
+            resource_id: value.resource_id,
+            ip_address: value.ip_address,
+            user_agent: value.user_agent,
+            created_at: value.created_at.into(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AuditEventsResponseBody {
+    events: Vec<AuditEventResponseData>,
+}
+
+fn parse_date_time(name: &str, value: &str) -> Result<PrimitiveDateTime, AuditErrorResponse> {
+    OffsetDateTime::parse(value, &DATE_TIME_FORMAT)
+        .map(|date| {
+            let date = date.to_offset(UtcOffset::UTC);
+            PrimitiveDateTime::new(date.date(), date.time())
+        })
+        .map_err(|_| {
+            AuditErrorResponse::InvalidInputDataFormat(std::format!(
+                "{name} must be an ISO 8601 date with an offset"
+            ))
+        })
+}
+
+/// Queries the audit log, the most recent events first, only admins are allowed
+///
+#[tracing::instrument(skip(web_service))]
+pub async fn get<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authenticated: Authenticated,
+    query_or_error: Result<Query<AuditQuery>, QueryRejection>,
+) -> Result<(StatusCode, Json<AuditEventsResponseBody>), AuditErrorResponse> {
+    let user_info = authenticated
+        .require_user()
+        .map_err(AuditErrorResponse::Forbidden)?;
+    let is_admin = web_service
+        .user_db
+        .is_admin(user_info.user_id)
+        .await
+        .map_err(AuditErrorResponse::DbError)?;
+    if !is_admin {
+        return Err(AuditErrorResponse::NotAdmin);
+    }
+
+    let Query(query) = query_or_error
+        .map_err(|x| x.to_string())
+        .map_err(AuditErrorResponse::InvalidInputDataFormat)?;
+    let event_type = query
+        .event_type
+        .as_deref()
+        .map(|name| {
+            parse_event_type(name).ok_or_else(|| {
+                AuditErrorResponse::InvalidInputDataFormat(std::format!(
+                    "Unknown event type: {name}"
+                ))
+            })
+        })
+        .transpose()?;
+    let limit = query.limit.unwrap_or(DEFAULT_AUDIT_EVENTS_LIMIT);
+    if !(1..=MAX_AUDIT_EVENTS_LIMIT).contains(&limit) {
+        return Err(AuditErrorResponse::InvalidInputDataFormat(std::format!(
+            "limit must be 1 to {MAX_AUDIT_EVENTS_LIMIT}"
+        )));
+    }
+
+    let filter = AuditEventFilter {
+        user_id: query.user_id,
+        event_type,
+        from: query
+            .from
+            .as_deref()
+            .map(|from| parse_date_time("from", from))
+            .transpose()?,
+        to: query
+            .to
+            .as_deref()
+            .map(|to| parse_date_time("to", to))
+            .transpose()?,
+    };
+
+    let events = web_service
+        .audit_log
+        .get_events(&filter, limit)
+        .await
+        .map_err(AuditErrorResponse::DbError)?;
+
+    Ok((
+        StatusCode::OK,
+        Json(AuditEventsResponseBody {
+            events: events.into_iter().map(Into::into).collect(),
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web::email_verification::tests::register_verified_user;
+    use crate::web::projects::tests::create_project_with_token;
+    use crate::web::users::tests::{create_test_router, login_with_email_and_password, user_id};
+    use crate::web_service::tests::{deserialize_response_body, get_with_auth_header};
+    use crate::web_service::{ErrorCode, ErrorResponseBody};
+    use axum::Router;
+
+    async fn get_audit_events(
+        router: &Router,
+        token: &str,
+        query: &str,
+    ) -> AuditEventsResponseBody {
+        let uri = std::format!("/api/admin/audit?{query}");
+        let response = get_with_auth_header(router, uri, Some(token)).await;
+        assert_eq!(response.status(), 200);
+
+        deserialize_response_body::<AuditEventsResponseBody>(response).await
+    }
+
+    fn event_types(body: &AuditEventsResponseBody) -> Vec<&str> {
+        body.events
+            .iter()
+            .map(|event| event.event_type.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_event_type_names() {
+        for (event_type, name) in EVENT_TYPE_NAMES {
+            assert_eq!(event_type_name(event_type), name);
+            assert_eq!(parse_event_type(name), Some(event_type));
+        }
+        assert_eq!(parse_event_type("unknown"), None);
+    }
+
+    #[tokio::test]
+    async fn should_record_and_filter_audit_events() {
+        let router = create_test_router().await;
+        let (user, token) = register_verified_user().await;
+        let user_id = user_id(&token);
+
+        let response =
+            login_with_email_and_password(user.email().to_owned(), "wrong password".to_owned())
+                .await;
+        assert_eq!(response.status(), 401);
+        let (_, project) = create_project_with_token(&router, &token).await;
+        let response = get_with_auth_header(
+            &router,
+            std::format!("/api/project/{}", project.project_id()),
+            Some(&token),
+        )
+        .await;
+        assert!(response.status().is_success());
+
+        let pool = crate::pg_pool().await.expect("pool is expected");
+        database::admins::insert_admin(&pool, user_id)
+            .await
+            .expect("admin is created");
+
+        let body = get_audit_events(&router, &token, &std::format!("user_id={user_id}")).await;
+        let mut types = event_types(&body);
+        types.sort();
+        assert_eq!(
+            types,
+            vec![
+                "login_failed",
+                "project_created",
+                "project_read",
+                "registered",
+                "token_refreshed"
+            ]
+        );
+        let project_read = body
+            .events
+            .iter()
+            .find(|event| event.event_type == "project_read")
+            .expect("project read event");
+        assert_eq!(project_read.resource_id, Some(project.project_id()));
+
+        let body = get_audit_events(
+            &router,
+            &token,
+            &std::format!("user_id={user_id}&event_type=registered"),
+        )
+        .await;
+        assert_eq!(event_types(&body), vec!["registered"]);
+
+        let body = get_audit_events(
+            &router,
+            &token,
+            &std::format!("user_id={user_id}&to=2000-01-01T00:00:00Z"),
+        )
+        .await;
+        assert!(body.events.is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_reject_invalid_audit_queries() {
+        let router = create_test_router().await;
+        let (_, token) = register_verified_user().await;
+        let user_id = user_id(&token);
+
+        let response = get_with_auth_header(&router, "/api/admin/audit", Some(&token)).await;
+        assert_eq!(response.status(), 403);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code, Some(ErrorCode::Forbidden));
+
+        let pool = crate::pg_pool().await.expect("pool is expected");
+        database::admins::insert_admin(&pool, user_id)
+            .await
+            .expect("admin is created");
+
+        for query in ["event_type=unknown", "from=yesterday", "limit=0"] {
+            let uri = std::format!("/api/admin/audit?{query}");
+            let response = get_with_auth_header(&router, uri, Some(&token)).await;
+            assert_eq!(response.status(), 400, "{query}");
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use crate::models::chat_members::ChatMemberDb;
+use crate::models::chat_message::ChatMessageDb;
+use crate::models::chats::ChatDb;
+use crate::models::company::CompanyDb;
+use crate::models::errors::DbError;
+use crate::models::project::ProjectDb;
+use crate::models::user::UserDb;
+use crate::policy::{is_allowed, Action, Resource};
+use crate::utils::api_keys::Scope;
+use crate::utils::tokens::UserInfo;
+use crate::web::authentication::Authenticated;
+use crate::web::errors::{create_invalid_response, FORBIDDEN_ERROR_MSG};
+use crate::web_service::{ErrorCode, ErrorResponseBody, WebService};
+use axum::extract::{FromRequestParts, Path};
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::marker::PhantomData;
+use uuid::Uuid;
+
+/// What a handler needs to be allowed, checked by the `Authorized` extractor
+pub trait Permission: Send + Sync + 'static {
+    /// Route path parameter with an id of the resource
+    const PATH_PARAMETER: &'static str;
+    /// API keys need the scope on top of the policy
+    const SCOPE: Scope;
+    const ACTION: Action;
+
+    fn resource(id: Uuid) -> Resource;
+}
+
+pub struct ReadProject;
+
+impl Permission for ReadProject {
+    const PATH_PARAMETER: &'static str = "project_id";
+    const SCOPE: Scope = Scope::ProjectsRead;
+    const ACTION: Action = Action::Read;
+
+    fn resource(id: Uuid) -> Resource {
+        Resource::Project(id)
+    }
+}
+
+pub struct UpdateProject;
+
+impl Permission for UpdateProject {
+    const PATH_PARAMETER: &'static str = "project_id";
+    const SCOPE: Scope = Scope::ProjectsWrite;
+    const ACTION: Action = Action::Update;
+
+    fn resource(id: Uuid) -> Resource {
+        Resource::Project(id)
+    }
+}
+
+pub struct DeleteProject;
+
+impl Permission for DeleteProject {
+    const PATH_PARAMETER: &'static str = "project_id";
+    const SCOPE: Scope = Scope::ProjectsWrite;
+    const ACTION: Action = Action::Delete;
+
+    fn resource(id: Uuid) -> Resource {
+        Resource::Project(id)
+    }
+}
+
+pub struct ManageProjectMembers;
+
+impl Permission for ManageProjectMembers {
+    const PATH_PARAMETER: &'static str = "project_id";
+    const SCOPE: Scope = Scope::ProjectsWrite;
+    const ACTION: Action = Action::ManageMembers;
+
+    fn resource(id: Uuid) -> Resource {
+        Resource::Project(id)
+    }
+}
+
+pub struct ReadCompany;
+
+impl Permission for ReadCompany {
+    const PATH_PARAMETER: &'static str = "company_id";
+    const SCOPE: Scope = Scope::CompaniesRead;
+    const ACTION: Action = Action::Read;
+
+    fn resource(id: Uuid) -> Resource {
+        Resource::Company(id)
+    }
+}
+
+pub struct UpdateCompany;
+
+impl Permission for UpdateCompany {
+    const PATH_PARAMETER: &'static str = "company_id";
+    const SCOPE: Scope = Scope::CompaniesWrite;
+    const ACTION: Action = Action::Update;
+
+    fn resource(id: Uuid) -> Resource {
+        Resource::Company(id)
+    }
+}
+
+pub struct ManageCompanyMembers;
+
+impl Permission for ManageCompanyMembers {
+    const PATH_PARAMETER: &'static str = "company_id";
+    const SCOPE: Scope = Scope::CompaniesWrite;
+    const ACTION: Action = Action::ManageMembers;
+
+    fn resource(id: Uuid) -> Resource {
+        Resource::Company(id)
+    }
+}
+
+pub struct ReadChat;
+
+impl Permission for ReadChat {
+    const PATH_PARAMETER: &'static str = "chat_id";
+    const SCOPE: Scope = Scope::ChatsRead;
+    const ACTION: Action = Action::Read;
+
+    fn resource(id: Uuid) -> Resource {
+        Resource::Chat(id)
+    }
+}
+
+pub struct PostChatMessage;
+
+impl Permission for PostChatMessage {
+    const PATH_PARAMETER: &'static str = "chat_id";
+    const SCOPE: Scope = Scope::ChatsWrite;
+    const ACTION: Action = Action::PostMessage;
+
+    fn resource(id: Uuid) -> Resource {
+        Resource::Chat(id)
+    }
+}
+
+pub struct ManageChatMembers;
+
+impl Permission for ManageChatMembers {
+    const PATH_PARAMETER: &'static str = "chat_id";
+    const SCOPE: Scope = Scope::ChatsWrite;
+    const ACTION: Action = Action::ManageMembers;
+
+    fn resource(id: Uuid) -> Resource {
+        Resource::Chat(id)
+    }
+}
+
+/// The caller and the resource of a request, only extracted when the policy allows `P`
+pub struct Authorized<P: Permission> {
+    user: UserInfo,
+    resource_id: Uuid,
+    permission: PhantomData<P>,
+}
+
+impl<P: Permission> Debug for Authorized<P> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Authorized")
+            .field("user", &self.user)
+            .field("resource_id", &self.resource_id)
+            .finish()
+    }
+}
+
+impl<P: Permission> Authorized<P> {
+    pub fn user(&self) -> &UserInfo {
+        &self.user
+    }
+
+    pub fn resource_id(&self) -> Uuid {
+        self.resource_id
+    }
+}
+
+pub fn create_forbidden_response(error: impl Into<String>) -> Response {
+    (
+        StatusCode::FORBIDDEN,
+        Json(ErrorResponseBody {
+            code: Some(ErrorCode::Forbidden),
+            error: error.into(),
+        }),
+    )
+        .into_response()
+}
+
+#[async_trait::async_trait]
+impl<
+        UDB: UserDb,
+        PDB: ProjectDb,
+        CDB: CompanyDb,
+        CHDB: ChatDb,
+        CMDB: ChatMemberDb,
+        MDB: ChatMessageDb,
+        P: Permission,
+    > FromRequestParts<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>> for Authorized<P>
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>,
+    ) -> Result<Self, Self::Rejection> {
+        let authenticated = Authenticated::from_request_parts(parts, state).await?;
+        let user = authenticated
+            .require_scope(P::SCOPE)
+            .map_err(IntoResponse::into_response)?
+            .clone();
+
+        let Path(parameters) = Path::<HashMap<String, String>>::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let resource_id = parameters
+            .get(P::PATH_PARAMETER)
+            .and_then(|id| Uuid::parse_str(id).ok())
+            .ok_or_else(|| {
+                create_invalid_response(std::format!("Invalid {}", P::PATH_PARAMETER))
+                    .into_response()
+            })?;
+
+        let allowed = is_allowed(
+            &state.user_db,
+            user.user_id,
+            P::ACTION,
+            P::resource(resource_id),
+        )
+        .await
+        .map_err(|db_error: DbError| db_error.into_response())?;
+        if !allowed {
+            return Err(create_forbidden_response(FORBIDDEN_ERROR_MSG));
+        }
+
+        Ok(Authorized {
+            user,
+            resource_id,
+            permission: PhantomData,
+        })
+    }
+}
@@ -0,0 +1,467 @@
+use crate::models::chat_members::ChatMemberDb;
+use crate::models::chat_message::ChatMessageDb;
+use crate::models::chats::ChatDb;
+use crate::models::company::CompanyDb;
+use crate::models::errors::DbError;
+use crate::models::project::ProjectDb;
+use crate::models::user::UserDb;
+use crate::policy::{is_allowed, Action, Resource};
+use crate::web::authorization::{Authorized, ManageChatMembers, ReadChat};
+use crate::web::chats::{get_member_alias, ChatErrorResponse};
+use crate::web::formats::JsonDateTime;
+use crate::web_service::WebService;
+use axum::extract::rejection::JsonRejection;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::Json;
+use database::chats::{ChatMemberProfile, ChatMemberRole, ChatType};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatMemberRoleParameter {
+    Creator,
+    Admin,
+    Member,
+    Left,
+    Banned,
+}
+
+impl From<ChatMemberRole> for ChatMemberRoleParameter {
+    fn from(value: ChatMemberRole) -> Self {
+        match value {
+            ChatMemberRole::Creator => ChatMemberRoleParameter::Creator,
+            ChatMemberRole::Admin => ChatMemberRoleParameter::Admin,
+            ChatMemberRole::Member => ChatMemberRoleParameter::Member,
+            ChatMemberRole::Left => ChatMemberRoleParameter::Left,
+            ChatMemberRole::Banned => ChatMemberRoleParameter::Banned,
+        }
+    }
+}
+
+/// Roles given by admins, a chat has one creator and people leave on their own
+fn grantable_role(role: ChatMemberRoleParameter) -> Result<ChatMemberRole, ChatErrorResponse> {
+    match role {
+        ChatMemberRoleParameter::Admin => Ok(ChatMemberRole::Admin),
+        ChatMemberRoleParameter::Member => Ok(ChatMemberRole::Member),
+        _ => Err(ChatErrorResponse::InvalidInputDataFormat(
+            "role must be admin or member".to_owned(),
+        )),
+    }
+}
+
+pub fn chat_member_role_name(role: ChatMemberRole) -> &'static str {
+    match role {
+        ChatMemberRole::Creator => "creator",
+        ChatMemberRole::Admin => "admin",
+        ChatMemberRole::Member => "member",
+        ChatMemberRole::Left => "left",
+        ChatMemberRole::Banned => "banned",
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ChatMemberResponseData {
+    user_id: Uuid,
+    alias: String,
+    first_name: Option<String>,
+    last_name: Option<String>,
+    role: ChatMemberRoleParameter,
+    created_at: JsonDateTime,
+}
+
+impl From<ChatMemberProfile> for ChatMemberResponseData {
+    fn from(value: ChatMemberProfile) -> Self {
+        ChatMemberResponseData {
+            user_id: value.user_id,
+            alias: value.alias,
+            first_name: value.first_name,
+            last_name: value.last_name,
+            role: value.role.into(),
+            created_at: value.created_at.into(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ChatMembersResponseBody {
+    members: Vec<ChatMemberResponseData>,
+}
+
+/// Lists current members of a chat, its creator and admins first
+///
+#[tracing::instrument(skip(web_service))]
+pub async fn get_all<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authorized: Authorized<ReadChat>,
+) -> Result<Json<ChatMembersResponseBody>, DbError> {
+    let members = web_service
+        .chat_member_db
+        .get_chat_members(authorized.resource_id())
+        .await?;
+
+    Ok(Json(ChatMembersResponseBody {
+        members: members.into_iter().map(Into::into).collect(),
+    }))
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AddChatMemberData {
+    user_id: Uuid,
+    role: ChatMemberRoleParameter,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AddChatMemberRequestBody {
+    data: AddChatMemberData,
+}
+
+/// Adds a user to a group or a channel, people who have left may be added again
+///
+#[tracing::instrument(skip(web_service, body_or_error))]
+pub async fn post<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authorized: Authorized<ManageChatMembers>,
+    body_or_error: Result<Json<AddChatMemberRequestBody>, JsonRejection>,
+) -> Result<StatusCode, ChatErrorResponse> {
+    let Json(body) = body_or_error
+        .map_err(|x| x.to_string())
+        .map_err(ChatErrorResponse::InvalidInputDataFormat)?;
+    let role = grantable_role(body.data.role)?;
+
+    let chat = web_service
+        .chat_db
+        .get_chat(authorized.resource_id())
+        .await
+        .map_err(ChatErrorResponse::DbError)?;
+    if chat.r#type == ChatType::Private {
+        return Err(ChatErrorResponse::PrivateChatMembers);
+    }
+
+    let alias = get_member_alias(&web_service.user_db, body.data.user_id).await?;
+    web_service
+        .chat_member_db
+        .add_chat_member(chat.id, alias, role)
+        .await
+        .map_err(ChatErrorResponse::DbError)?
+        .ok_or(ChatErrorResponse::AlreadyMember)?;
+
+    Ok(StatusCode::CREATED)
+}
+
+/// Fails with `ChatCreator` for the creator and `NotFoundError` for somebody who is not in the chat
+async fn member_not_updated(
+    user_db: &impl UserDb,
+    chat_id: Uuid,
+    user_id: Uuid,
+) -> ChatErrorResponse {
+    match user_db.get_chat_access(chat_id, user_id).await {
+        Ok(access) if access.role == Some(ChatMemberRole::Creator) => {
+            ChatErrorResponse::ChatCreator
+        }
+        Ok(_) => ChatErrorResponse::DbError(DbError::NotFoundError),
+        Err(db_error) => ChatErrorResponse::DbError(db_error),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UpdateChatMemberData {
+    role: ChatMemberRoleParameter,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UpdateChatMemberRequestBody {
+    data: UpdateChatMemberData,
+}
+
+/// Makes a member an admin or back, the creator keeps their role
+///
+#[tracing::instrument(skip(web_service, body_or_error))]
+pub async fn patch<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authorized: Authorized<ManageChatMembers>,
+    Path((_, user_id)): Path<(Uuid, Uuid)>,
+    body_or_error: Result<Json<UpdateChatMemberRequestBody>, JsonRejection>,
+) -> Result<StatusCode, ChatErrorResponse> {
+    let Json(body) = body_or_error
+        .map_err(|x| x.to_string())
+        .map_err(ChatErrorResponse::InvalidInputDataFormat)?;
+    let role = grantable_role(body.data.role)?;
+
+    let alias = get_member_alias(&web_service.user_db, user_id).await?;
+    let updated = web_service
+        .chat_member_db
+        .update_chat_member_role(authorized.resource_id(), alias, role)
+        .await
+        .map_err(ChatErrorResponse::DbError)?;
+    if updated == 0 {
+        return Err(
+            member_not_updated(&web_service.user_db, authorized.resource_id(), user_id).await,
+        );
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Removes a member from a chat, they keep their messages
+///
+/// Members leave a chat on their own, removing others needs the right to manage members.
+#[tracing::instrument(skip(web_service))]
+pub async fn delete<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authorized: Authorized<ReadChat>,
+    Path((_, user_id)): Path<(Uuid, Uuid)>,
+) -> Result<StatusCode, ChatErrorResponse> {
+    let chat_id = authorized.resource_id();
+    let caller_id = authorized.user().user_id;
+    if caller_id != user_id {
+        let allowed = is_allowed(
+            &web_service.user_db,
+            caller_id,
+            Action::ManageMembers,
+            Resource::Chat(chat_id),
+        )
+        .await
+        .map_err(ChatErrorResponse::DbError)?;
+        if !allowed {
+            return Err(ChatErrorResponse::NotAllowed);
+        }
+    }
+
+    let alias = get_member_alias(&web_service.user_db, user_id).await?;
+    let updated = web_service
+        .chat_member_db
+        .update_chat_member_role(chat_id, alias, ChatMemberRole::Left)
+        .await
+        .map_err(ChatErrorResponse::DbError)?;
+    if updated == 0 {
+        return Err(member_not_updated(&web_service.user_db, chat_id, user_id).await);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web::chats::tests::{create_chat_with_token, register_chat_user};
+    use crate::web::chats::ChatTypeParameter;
+    use crate::web::users::tests::create_test_router;
+    use crate::web_service::tests::{
+        delete_with_auth_header, deserialize_response_body, get_with_auth_header,
+        patch_with_auth_header, post_with_auth_header,
+    };
+    use axum::Router;
+
+    async fn get_members(
+        router: &Router,
+        chat_id: Uuid,
+        token: &str,
+    ) -> Vec<(Uuid, ChatMemberRoleParameter)> {
+        let response = get_with_auth_header(
+            router,
+            std::format!("/api/chat/{chat_id}/members"),
+            Some(token),
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        deserialize_response_body::<ChatMembersResponseBody>(response)
+            .await
+            .members
+            .into_iter()
+            .map(|member| (member.user_id, member.role))
+            .collect()
+    }
+
+    async fn add_member(router: &Router, chat_id: Uuid, user_id: Uuid, token: &str) -> StatusCode {
+        let request_body = AddChatMemberRequestBody {
+            data: AddChatMemberData {
+                user_id,
+                role: ChatMemberRoleParameter::Member,
+            },
+        };
+        post_with_auth_header(
+            router,
+            std::format!("/api/chat/{chat_id}/members"),
+            &request_body,
+            Some(token),
+        )
+        .await
+        .status()
+    }
+
+    async fn set_role(
+        router: &Router,
+        chat_id: Uuid,
+        user_id: Uuid,
+        role: ChatMemberRoleParameter,
+        token: &str,
+    ) -> StatusCode {
+        let request_body = UpdateChatMemberRequestBody {
+            data: UpdateChatMemberData { role },
+        };
+        patch_with_auth_header(
+            router,
+            std::format!("/api/chat/{chat_id}/members/{user_id}"),
+            &request_body,
+            Some(token),
+        )
+        .await
+        .status()
+    }
+
+    #[tokio::test]
+    async fn should_manage_group_members() {
+        let router = create_test_router().await;
+        let (creator_id, creator_token) = register_chat_user(&router).await;
+        let (admin_id, admin_token) = register_chat_user(&router).await;
+        let (member_id, member_token) = register_chat_user(&router).await;
+
+        let chat_id = create_chat_with_token(
+            &router,
+            &creator_token,
+            ChatTypeParameter::Group,
+            vec![admin_id],
+        )
+        .await;
+
+        assert_eq!(
+            add_member(&router, chat_id, member_id, &admin_token).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            set_role(
+                &router,
+                chat_id,
+                admin_id,
+                ChatMemberRoleParameter::Admin,
+                &creator_token
+            )
+            .await,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            add_member(&router, chat_id, member_id, &admin_token).await,
+            StatusCode::CREATED
+        );
+        assert_eq!(
+            add_member(&router, chat_id, member_id, &admin_token).await,
+            StatusCode::CONFLICT
+        );
+        assert_eq!(
+            get_members(&router, chat_id, &member_token).await,
+            vec![
+                (creator_id, ChatMemberRoleParameter::Creator),
+                (admin_id, ChatMemberRoleParameter::Admin),
+                (member_id, ChatMemberRoleParameter::Member),
+            ]
+        );
+
+        assert_eq!(
+            set_role(
+                &router,
+                chat_id,
+                creator_id,
+                ChatMemberRoleParameter::Member,
+                &admin_token
+            )
+            .await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            set_role(
+                &router,
+                chat_id,
+                member_id,
+                ChatMemberRoleParameter::Creator,
+                &admin_token
+            )
+            .await,
+            StatusCode::BAD_REQUEST
+        );
+        let response = delete_with_auth_header(
+            &router,
+            std::format!("/api/chat/{chat_id}/members/{admin_id}"),
+            Some(&member_token),
+        )
+        .await;
+        assert_eq!(response.status(), 403);
+
+        let response = delete_with_auth_header(
+            &router,
+            std::format!("/api/chat/{chat_id}/members/{member_id}"),
+            Some(&member_token),
+        )
+        .await;
+        assert_eq!(response.status(), 204);
+        let response = get_with_auth_header(
+            &router,
+            std::format!("/api/chat/{chat_id}"),
+            Some(&member_token),
+        )
+        .await;
+        assert_eq!(response.status(), 403);
+
+        assert_eq!(
+            add_member(&router, chat_id, member_id, &admin_token).await,
+            StatusCode::CREATED
+        );
+        assert_eq!(get_members(&router, chat_id, &member_token).await.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn should_keep_private_chat_members() {
+        let router = create_test_router().await;
+        let (_, token) = register_chat_user(&router).await;
+        let (other_id, _) = register_chat_user(&router).await;
+        let (stranger_id, _) = register_chat_user(&router).await;
+
+        let chat_id =
+            create_chat_with_token(&router, &token, ChatTypeParameter::Private, vec![other_id])
+                .await;
+        assert_eq!(
+            add_member(&router, chat_id, stranger_id, &token).await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            set_role(
+                &router,
+                chat_id,
+                stranger_id,
+                ChatMemberRoleParameter::Admin,
+                &token
+            )
+            .await,
+            StatusCode::NOT_FOUND
+        );
+    }
+}
@@ -0,0 +1,918 @@
+use crate::models::chat_members::ChatMemberDb;
+use crate::models::chat_message::ChatMessageDb;
+use crate::models::chats::ChatDb;
+use crate::models::company::CompanyDb;
+use crate::models::errors::DbError;
+use crate::models::project::ProjectDb;
+use crate::models::user::UserDb;
+use crate::policy::{is_allowed, Action, Resource};
+use crate::web::authorization::{Authorized, PostChatMessage, ReadChat};
+use crate::web::chats::ChatErrorResponse;
+use crate::web::formats::JsonDateTime;
+use crate::web_service::WebService;
+use axum::extract::rejection::{JsonRejection, QueryRejection};
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use database::chats::{ChatMessage, ChatMessageRevision};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+const MESSAGE_MAX_LENGTH: usize = 4096;
+const DEFAULT_MESSAGES_LIMIT: i64 = 50;
+const MAX_MESSAGES_LIMIT: i64 = 100;
+/// How many messages are shown on each side of the one jumped to
+const DEFAULT_CONTEXT_LIMIT: i64 = 20;
+
+/// A message, deleted ones are tombstones without their text
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ChatMessageResponseData {
+    id: Uuid,
+    sender_id: Uuid,
+    message: String,
+    parent_id: Option<Uuid>,
+    created_at: JsonDateTime,
+    updated_at: JsonDateTime,
+    edited_at: Option<JsonDateTime>,
+    deleted_at: Option<JsonDateTime>,
+}
+
+impl From<ChatMessage> for ChatMessageResponseData {
+    fn from(value: ChatMessage) -> Self {
+        let message = match value.deleted_at {
+            Some(_) => String::new(),
+            None => value.message,
+        };
+
+        ChatMessageResponseData {
+            id: value.id,
+            sender_id: value.sender_id,
+            message,
+            parent_id: value.parent_id,
+            created_at: value.created_at.into(),
+            updated_at: value.updated_at.into(),
+            edited_at: value.edited_at.map(Into::into),
+            deleted_at: value.deleted_at.map(Into::into),
+        }
+    }
+}
+
+fn validate_message(message: &str) -> Result<(), ChatErrorResponse> {
+    if message.trim().is_empty() || message.chars().count() > MESSAGE_MAX_LENGTH {
+        return Err(ChatErrorResponse::InvalidInputDataFormat(std::format!(
+            "message must be 1 to {MESSAGE_MAX_LENGTH} characters"
+        )));
+    }
+
+    Ok(())
+}
+
+/// A message of a chat that is not deleted, `DbError::NotFoundError` otherwise
+async fn get_live_message(
+    chat_message_db: &impl ChatMessageDb,
+    chat_id: Uuid,
+    id: Uuid,
+) -> Result<ChatMessage, ChatErrorResponse> {
+    match chat_message_db.get_chat_message(id).await {
+        Ok(message) if message.chat_id == chat_id && message.deleted_at.is_none() => Ok(message),
+        Ok(_) => Err(ChatErrorResponse::DbError(DbError::NotFoundError)),
+        Err(db_error) => Err(ChatErrorResponse::DbError(db_error)),
+    }
+}
+
+fn validate_limit(limit: Option<i64>, default: i64) -> Result<i64, ChatErrorResponse> {
+    let limit = limit.unwrap_or(default);
+    if !(1..=MAX_MESSAGES_LIMIT).contains(&limit) {
+        return Err(ChatErrorResponse::InvalidInputDataFormat(std::format!(
+            "limit must be 1 to {MAX_MESSAGES_LIMIT}"
+        )));
+    }
+
+    Ok(limit)
+}
+
+/// Cuts a page fetched with one message more than asked for, which tells whether there is a next page
+///
+/// Returns the last message of the page when there is one.
+fn truncate_page(messages: &mut Vec<ChatMessage>, limit: i64) -> Option<Uuid> {
+    if messages.len() as i64 > limit {
+        messages.truncate(limit as usize);
+        messages.last().map(|message| message.id)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ChatMessagesQuery {
+    /// `next_before` of the previous page, to go back in history
+    before: Option<Uuid>,
+    /// `next_after` of the previous page, to go forward in history
+    after: Option<Uuid>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ChatMessagesResponseBody {
+    messages: Vec<ChatMessageResponseData>,
+    /// `before` of the page of older messages, `None` when there are none
+    next_before: Option<Uuid>,
+    /// `after` of the page of newer messages, `None` when there are none
+    next_after: Option<Uuid>,
+}
+
+/// Pages through the history of a chat, deleted messages are tombstones
+///
+/// Pages go back from the newest message or a `before` one, the newest first.
+/// Pages after an `after` message go forward, the oldest first.
+///
+#[tracing::instrument(skip(web_service))]
+pub async fn get_all<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authorized: Authorized<ReadChat>,
+    query_or_error: Result<Query<ChatMessagesQuery>, QueryRejection>,
+) -> Result<Json<ChatMessagesResponseBody>, ChatErrorResponse> {
+    let Query(query) = query_or_error
+        .map_err(|x| x.to_string())
+        .map_err(ChatErrorResponse::InvalidInputDataFormat)?;
+    let limit = validate_limit(query.limit, DEFAULT_MESSAGES_LIMIT)?;
+
+    let chat_id = authorized.resource_id();
+    let (messages, next_before, next_after) = match (query.before, query.after) {
+        (Some(_), Some(_)) => {
+            return Err(ChatErrorResponse::InvalidInputDataFormat(
+                "before and after can not be combined".to_owned(),
+            ))
+        }
+        (None, Some(after)) => {
+            let mut messages = web_service
+                .chat_message_db
+                .get_chat_messages_after(chat_id, after, limit + 1)
+                .await
+                .map_err(ChatErrorResponse::DbError)?;
+            let next_after = truncate_page(&mut messages, limit);
+            (messages, None, next_after)
+        }
+        (before, None) => {
+            let mut messages = web_service
+                .chat_message_db
+                .get_chat_messages(chat_id, before, limit + 1)
+                .await
+                .map_err(ChatErrorResponse::DbError)?;
+            let next_before = truncate_page(&mut messages, limit);
+            (messages, next_before, None)
+        }
+    };
+
+    Ok(Json(ChatMessagesResponseBody {
+        messages: messages.into_iter().map(Into::into).collect(),
+        next_before,
+        next_after,
+    }))
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ChatMessageContextQuery {
+    /// Messages on each side of the one jumped to
+    limit: Option<i64>,
+}
+
+/// Jumps to a message, it comes with the messages around it, the oldest first
+///
+/// `next_before` and `next_after` page further back and forward from there.
+#[tracing::instrument(skip(web_service))]
+pub async fn get_context<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authorized: Authorized<ReadChat>,
+    Path((_, message_id)): Path<(Uuid, Uuid)>,
+    query_or_error: Result<Query<ChatMessageContextQuery>, QueryRejection>,
+) -> Result<Json<ChatMessagesResponseBody>, ChatErrorResponse> {
+    let Query(query) = query_or_error
+        .map_err(|x| x.to_string())
+        .map_err(ChatErrorResponse::InvalidInputDataFormat)?;
+    let limit = validate_limit(query.limit, DEFAULT_CONTEXT_LIMIT)?;
+
+    // One more than asked for on each side tells whether there are more messages there
+    let mut messages = web_service
+        .chat_message_db
+        .get_chat_message_context(authorized.resource_id(), message_id, limit + 1)
+        .await
+        .map_err(ChatErrorResponse::DbError)?;
+    let position = messages
+        .iter()
+        .position(|message| message.id == message_id)
+        .ok_or(ChatErrorResponse::DbError(DbError::NotFoundError))?;
+
+    let next_before = if position as i64 > limit {
+        messages.remove(0);
+        messages.first().map(|message| message.id)
+    } else {
+        None
+    };
+    let newer = messages.len() - position.min(limit as usize) - 1;
+    let next_after = if newer as i64 > limit {
+        messages.pop();
+        messages.last().map(|message| message.id)
+    } else {
+        None
+    };
+
+    Ok(Json(ChatMessagesResponseBody {
+        messages: messages.into_iter().map(Into::into).collect(),
+        next_before,
+        next_after,
+    }))
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ChatThreadQuery {
+    /// `next_after` of the previous page
+    after: Option<Uuid>,
+    limit: Option<i64>,
+}
+
+/// Pages through the replies to a message and the replies to them, the oldest first
+///
+#[tracing::instrument(skip(web_service))]
+pub async fn get_thread<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authorized: Authorized<ReadChat>,
+    Path((_, message_id)): Path<(Uuid, Uuid)>,
+    query_or_error: Result<Query<ChatThreadQuery>, QueryRejection>,
+) -> Result<Json<ChatMessagesResponseBody>, ChatErrorResponse> {
+    let Query(query) = query_or_error
+        .map_err(|x| x.to_string())
+        .map_err(ChatErrorResponse::InvalidInputDataFormat)?;
+    let limit = validate_limit(query.limit, DEFAULT_MESSAGES_LIMIT)?;
+
+    // Deleted messages start threads too, their replies stay
+    let chat_id = authorized.resource_id();
+    let parent = web_service
+        .chat_message_db
+        .get_chat_message(message_id)
+        .await
+        .map_err(ChatErrorResponse::DbError)?;
+    if parent.chat_id != chat_id {
+        return Err(ChatErrorResponse::DbError(DbError::NotFoundError));
+    }
+
+    let mut messages = web_service
+        .chat_message_db
+        .get_chat_thread(chat_id, parent.id, query.after, limit + 1)
+        .await
+        .map_err(ChatErrorResponse::DbError)?;
+    let next_after = truncate_page(&mut messages, limit);
+
+    Ok(Json(ChatMessagesResponseBody {
+        messages: messages.into_iter().map(Into::into).collect(),
+        next_before: None,
+        next_after,
+    }))
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PostChatMessageData {
+    message: String,
+    /// A message of the same chat this one replies to
+    parent_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PostChatMessageRequestBody {
+    data: PostChatMessageData,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PostChatMessageResponseBody {
+    message_id: Uuid,
+}
+
+/// Posts a message to a chat, in channels only admins do
+///
+#[tracing::instrument(skip(web_service, body_or_error))]
+pub async fn post<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authorized: Authorized<PostChatMessage>,
+    body_or_error: Result<Json<PostChatMessageRequestBody>, JsonRejection>,
+) -> Result<(StatusCode, Json<PostChatMessageResponseBody>), ChatErrorResponse> {
+    let Json(body) = body_or_error
+        .map_err(|x| x.to_string())
+        .map_err(ChatErrorResponse::InvalidInputDataFormat)?;
+    let message = body.data.message;
+    validate_message(&message)?;
+
+    let chat_id = authorized.resource_id();
+    if let Some(parent_id) = body.data.parent_id {
+        match web_service
+            .chat_message_db
+            .get_chat_message(parent_id)
+            .await
+        {
+            Ok(parent) if parent.chat_id == chat_id && parent.deleted_at.is_none() => {}
+            Ok(_) | Err(DbError::NotFoundError) => {
+                return Err(ChatErrorResponse::InvalidInputDataFormat(
+                    "Invalid parent_id".to_owned(),
+                ))
+            }
+            Err(db_error) => return Err(ChatErrorResponse::DbError(db_error)),
+        }
+    }
+
+    let message_id = web_service
+        .chat_message_db
+        .insert_chat_message(
+            chat_id,
+            authorized.user().user_id,
+            message,
+            body.data.parent_id,
+        )
+        .await
+        .map_err(ChatErrorResponse::DbError)?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(PostChatMessageResponseBody { message_id }),
+    ))
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PatchChatMessageData {
+    message: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PatchChatMessageRequestBody {
+    data: PatchChatMessageData,
+}
+
+/// Edits a message, only its sender does and the previous text is kept as a revision
+///
+#[tracing::instrument(skip(web_service, body_or_error))]
+pub async fn patch<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authorized: Authorized<PostChatMessage>,
+    Path((_, message_id)): Path<(Uuid, Uuid)>,
+    body_or_error: Result<Json<PatchChatMessageRequestBody>, JsonRejection>,
+) -> Result<Json<ChatMessageResponseData>, ChatErrorResponse> {
+    let Json(body) = body_or_error
+        .map_err(|x| x.to_string())
+        .map_err(ChatErrorResponse::InvalidInputDataFormat)?;
+    validate_message(&body.data.message)?;
+
+    let chat_id = authorized.resource_id();
+    let user_id = authorized.user().user_id;
+    let message = get_live_message(&web_service.chat_message_db, chat_id, message_id).await?;
+    if message.sender_id != user_id {
+        return Err(ChatErrorResponse::NotAllowed);
+    }
+
+    let message = web_service
+        .chat_message_db
+        .update_chat_message(chat_id, message_id, user_id, body.data.message)
+        .await
+        .map_err(ChatErrorResponse::DbError)?
+        .ok_or(ChatErrorResponse::DbError(DbError::NotFoundError))?;
+
+    Ok(Json(message.into()))
+}
+
+/// Deletes a message, senders delete their own ones and admins or the creator any
+///
+/// The message stays in the history as a tombstone.
+#[tracing::instrument(skip(web_service))]
+pub async fn delete<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authorized: Authorized<PostChatMessage>,
+    Path((_, message_id)): Path<(Uuid, Uuid)>,
+) -> Result<StatusCode, ChatErrorResponse> {
+    let chat_id = authorized.resource_id();
+    let user_id = authorized.user().user_id;
+    let message = get_live_message(&web_service.chat_message_db, chat_id, message_id).await?;
+    if message.sender_id != user_id {
+        let allowed = is_allowed(
+            &web_service.user_db,
+            user_id,
+            Action::Moderate,
+            Resource::Chat(chat_id),
+        )
+        .await
+        .map_err(ChatErrorResponse::DbError)?;
+        if !allowed {
+            return Err(ChatErrorResponse::NotAllowed);
+        }
+    }
+
+    let deleted = web_service
+        .chat_message_db
+        .delete_chat_message(chat_id, message_id, user_id)
+        .await
+        .map_err(ChatErrorResponse::DbError)?;
+    if deleted == 0 {
+        return Err(ChatErrorResponse::DbError(DbError::NotFoundError));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ChatMessageRevisionResponseData {
+    message: String,
+    /// When the text was written
+    created_at: JsonDateTime,
+}
+
+impl From<ChatMessageRevision> for ChatMessageRevisionResponseData {
+    fn from(value: ChatMessageRevision) -> Self {
+        ChatMessageRevisionResponseData {
+            message: value.message,
+            created_at: value.created_at.into(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ChatMessageRevisionsResponseBody {
+    /// The oldest first, the current text is the message itself
+    revisions: Vec<ChatMessageRevisionResponseData>,
+}
+
+/// Earlier texts of an edited message, there are none for a deleted one
+///
+#[tracing::instrument(skip(web_service))]
+pub async fn get_revisions<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authorized: Authorized<ReadChat>,
+    Path((_, message_id)): Path<(Uuid, Uuid)>,
+) -> Result<Json<ChatMessageRevisionsResponseBody>, ChatErrorResponse> {
+    let message = get_live_message(
+        &web_service.chat_message_db,
+        authorized.resource_id(),
+        message_id,
+    )
+    .await?;
+
+    let revisions = web_service
+        .chat_message_db
+        .get_chat_message_revisions(message.id)
+        .await
+        .map_err(ChatErrorResponse::DbError)?;
+
+    Ok(Json(ChatMessageRevisionsResponseBody {
+        revisions: revisions.into_iter().map(Into::into).collect(),
+    }))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::web::chats::tests::{create_chat_with_token, register_chat_user};
+    use crate::web::chats::ChatTypeParameter;
+    use crate::web::users::tests::create_test_router;
+    use crate::web_service::tests::{
+        delete_with_auth_header, deserialize_response_body, get_with_auth_header,
+        patch_with_auth_header, post_with_auth_header,
+    };
+    use axum::body::Bytes;
+    use axum::Router;
+    use http_body::combinators::UnsyncBoxBody;
+
+    async fn post_message(
+        router: &Router,
+        chat_id: Uuid,
+        message: &str,
+        parent_id: Option<Uuid>,
+        token: &str,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        let request_body = PostChatMessageRequestBody {
+            data: PostChatMessageData {
+                message: message.to_owned(),
+                parent_id,
+            },
+        };
+        post_with_auth_header(
+            router,
+            std::format!("/api/chat/{chat_id}/messages"),
+            &request_body,
+            Some(token),
+        )
+        .await
+    }
+
+    pub async fn post_message_with_token(
+        router: &Router,
+        chat_id: Uuid,
+        message: &str,
+        token: &str,
+    ) -> Uuid {
+        let response = post_message(router, chat_id, message, None, token).await;
+        assert_eq!(response.status(), 201);
+
+        deserialize_response_body::<PostChatMessageResponseBody>(response)
+            .await
+            .message_id
+    }
+
+    async fn get_page(
+        router: &Router,
+        chat_id: Uuid,
+        query: &str,
+        token: &str,
+    ) -> ChatMessagesResponseBody {
+        let response = get_with_auth_header(
+            router,
+            std::format!("/api/chat/{chat_id}/messages?{query}"),
+            Some(token),
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        deserialize_response_body::<ChatMessagesResponseBody>(response).await
+    }
+
+    async fn edit_message(
+        router: &Router,
+        chat_id: Uuid,
+        message_id: Uuid,
+        message: &str,
+        token: &str,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        let request_body = PatchChatMessageRequestBody {
+            data: PatchChatMessageData {
+                message: message.to_owned(),
+            },
+        };
+        patch_with_auth_header(
+            router,
+            std::format!("/api/chat/{chat_id}/messages/{message_id}"),
+            &request_body,
+            Some(token),
+        )
+        .await
+    }
+
+    async fn delete_message(
+        router: &Router,
+        chat_id: Uuid,
+        message_id: Uuid,
+        token: &str,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        delete_with_auth_header(
+            router,
+            std::format!("/api/chat/{chat_id}/messages/{message_id}"),
+            Some(token),
+        )
+        .await
+    }
+
+    async fn get_revisions(
+        router: &Router,
+        chat_id: Uuid,
+        message_id: Uuid,
+        token: &str,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        get_with_auth_header(
+            router,
+            std::format!("/api/chat/{chat_id}/messages/{message_id}/revisions"),
+            Some(token),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn should_post_replies_and_page_through_history() {
+        let router = create_test_router().await;
+        let (_, token) = register_chat_user(&router).await;
+        let (other_id, other_token) = register_chat_user(&router).await;
+
+        let chat_id =
+            create_chat_with_token(&router, &token, ChatTypeParameter::Group, vec![other_id]).await;
+        let mut message_ids = vec![];
+        for i in 0..5 {
+            let response = post_message(&router, chat_id, &i.to_string(), None, &token).await;
+            assert_eq!(response.status(), 201);
+            message_ids.push(
+                deserialize_response_body::<PostChatMessageResponseBody>(response)
+                    .await
+                    .message_id,
+            );
+        }
+        let parent_id = message_ids[0];
+        let response = post_message(&router, chat_id, "reply", Some(parent_id), &other_token).await;
+        assert_eq!(response.status(), 201);
+        let reply_id = deserialize_response_body::<PostChatMessageResponseBody>(response)
+            .await
+            .message_id;
+        message_ids.push(reply_id);
+
+        let first_page = get_page(&router, chat_id, "limit=4", &other_token).await;
+        assert_eq!(first_page.messages.len(), 4);
+        let next_before = first_page.next_before.expect("a next page");
+        assert_eq!(next_before, first_page.messages[3].id);
+
+        let last_page = get_page(
+            &router,
+            chat_id,
+            &std::format!("limit=4&before={next_before}"),
+            &other_token,
+        )
+        .await;
+        assert_eq!(last_page.messages.len(), 2);
+        assert_eq!(last_page.next_before, None);
+
+        let messages = first_page
+            .messages
+            .into_iter()
+            .chain(last_page.messages)
+            .collect::<Vec<_>>();
+        let mut ids = messages
+            .iter()
+            .map(|message| message.id)
+            .collect::<Vec<_>>();
+        ids.sort();
+        message_ids.sort();
+        assert_eq!(ids, message_ids);
+
+        let reply = messages
+            .iter()
+            .find(|message| message.id == reply_id)
+            .expect("the reply");
+        assert_eq!(reply.sender_id, other_id);
+        assert_eq!(reply.parent_id, Some(parent_id));
+    }
+
+    #[tokio::test]
+    async fn should_reject_invalid_messages() {
+        let router = create_test_router().await;
+        let (_, token) = register_chat_user(&router).await;
+        let (other_id, other_token) = register_chat_user(&router).await;
+
+        let channel_id =
+            create_chat_with_token(&router, &token, ChatTypeParameter::Channel, vec![other_id])
+                .await;
+        let response = post_message(&router, channel_id, "hello", None, &other_token).await;
+        assert_eq!(response.status(), 403);
+        let response = post_message(&router, channel_id, "hello", None, &token).await;
+        assert_eq!(response.status(), 201);
+        let channel_message_id = deserialize_response_body::<PostChatMessageResponseBody>(response)
+            .await
+            .message_id;
+
+        let group_id =
+            create_chat_with_token(&router, &token, ChatTypeParameter::Group, vec![other_id]).await;
+        let response = post_message(&router, group_id, " ", None, &other_token).await;
+        assert_eq!(response.status(), 400);
+        let response = post_message(
+            &router,
+            group_id,
+            "reply",
+            Some(channel_message_id),
+            &other_token,
+        )
+        .await;
+        assert_eq!(response.status(), 400);
+
+        let response = get_with_auth_header(
+            &router,
+            std::format!("/api/chat/{group_id}/messages?limit=0"),
+            Some(&token),
+        )
+        .await;
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn should_edit_messages_of_their_senders_only() {
+        let router = create_test_router().await;
+        let (_, token) = register_chat_user(&router).await;
+        let (other_id, other_token) = register_chat_user(&router).await;
+
+        let chat_id =
+            create_chat_with_token(&router, &token, ChatTypeParameter::Group, vec![other_id]).await;
+        let message_id = post_message_with_token(&router, chat_id, "helo", &other_token).await;
+
+        let response = edit_message(&router, chat_id, message_id, "hijacked", &token).await;
+        assert_eq!(response.status(), 403);
+        let response = edit_message(&router, chat_id, message_id, " ", &other_token).await;
+        assert_eq!(response.status(), 400);
+
+        let response = edit_message(&router, chat_id, message_id, "hello", &other_token).await;
+        assert_eq!(response.status(), 200);
+        let edited = deserialize_response_body::<ChatMessageResponseData>(response).await;
+        assert_eq!(edited.message, "hello");
+        assert!(edited.edited_at.is_some());
+
+        let response = get_revisions(&router, chat_id, message_id, &token).await;
+        assert_eq!(response.status(), 200);
+        let revisions = deserialize_response_body::<ChatMessageRevisionsResponseBody>(response)
+            .await
+            .revisions;
+        assert_eq!(
+            revisions
+                .iter()
+                .map(|x| x.message.as_str())
+                .collect::<Vec<_>>(),
+            vec!["helo"]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_leave_tombstones_of_deleted_messages() {
+        let router = create_test_router().await;
+        let (_, token) = register_chat_user(&router).await;
+        let (other_id, other_token) = register_chat_user(&router).await;
+
+        let chat_id =
+            create_chat_with_token(&router, &token, ChatTypeParameter::Group, vec![other_id]).await;
+        let creator_message_id = post_message_with_token(&router, chat_id, "rules", &token).await;
+        let message_id = post_message_with_token(&router, chat_id, "spam", &other_token).await;
+
+        let response = delete_message(&router, chat_id, creator_message_id, &other_token).await;
+        assert_eq!(response.status(), 403);
+        let response = delete_message(&router, chat_id, message_id, &token).await;
+        assert_eq!(response.status(), 204);
+        let response = delete_message(&router, chat_id, message_id, &token).await;
+        assert_eq!(response.status(), 404);
+
+        let response = edit_message(&router, chat_id, message_id, "not spam", &other_token).await;
+        assert_eq!(response.status(), 404);
+        let response = get_revisions(&router, chat_id, message_id, &token).await;
+        assert_eq!(response.status(), 404);
+
+        let page = get_page(&router, chat_id, "", &other_token).await;
+        assert_eq!(page.messages.len(), 2);
+        let tombstone = page
+            .messages
+            .iter()
+            .find(|message| message.id == message_id)
+            .expect("the deleted message");
+        assert_eq!(tombstone.message, "");
+        assert!(tombstone.deleted_at.is_some());
+
+        let response = delete_message(&router, chat_id, creator_message_id, &token).await;
+        assert_eq!(response.status(), 204);
+    }
+
+    #[tokio::test]
+    async fn should_page_forward_jump_to_messages_and_follow_threads() {
+        let router = create_test_router().await;
+        let (_, token) = register_chat_user(&router).await;
+
+        let chat_id =
+            create_chat_with_token(&router, &token, ChatTypeParameter::Group, vec![]).await;
+        for i in 0..5 {
+            post_message_with_token(&router, chat_id, &i.to_string(), &token).await;
+        }
+        // Messages posted within a second are ordered by their ids, so take the order the chat has
+        let mut timeline = get_page(&router, chat_id, "", &token)
+            .await
+            .messages
+            .into_iter()
+            .map(|message| message.id)
+            .collect::<Vec<_>>();
+        timeline.reverse();
+        let ids = |page: &ChatMessagesResponseBody| {
+            page.messages
+                .iter()
+                .map(|message| message.id)
+                .collect::<Vec<_>>()
+        };
+
+        let page = get_page(
+            &router,
+            chat_id,
+            &std::format!("after={}&limit=2", timeline[0]),
+            &token,
+        )
+        .await;
+        assert_eq!(ids(&page), timeline[1..3].to_vec());
+        assert_eq!(page.next_after, Some(timeline[2]));
+        let page = get_page(
+            &router,
+            chat_id,
+            &std::format!("after={}&limit=2", timeline[2]),
+            &token,
+        )
+        .await;
+        assert_eq!(ids(&page), timeline[3..5].to_vec());
+        assert_eq!(page.next_after, None);
+
+        let response = get_with_auth_header(
+            &router,
+            std::format!(
+                "/api/chat/{chat_id}/messages?before={}&after={}",
+                timeline[3],
+                timeline[1]
+            ),
+            Some(&token),
+        )
+        .await;
+        assert_eq!(response.status(), 400);
+
+        let context = |message_id: Uuid| {
+            let router = router.clone();
+            let token = token.clone();
+            async move {
+                let response = get_with_auth_header(
+                    &router,
+                    std::format!("/api/chat/{chat_id}/messages/{message_id}/context?limit=1"),
+                    Some(&token),
+                )
+                .await;
+                assert_eq!(response.status(), 200);
+                deserialize_response_body::<ChatMessagesResponseBody>(response).await
+            }
+        };
+        let page = context(timeline[2]).await;
+        assert_eq!(ids(&page), timeline[1..4].to_vec());
+        assert_eq!(page.next_before, Some(timeline[1]));
+        assert_eq!(page.next_after, Some(timeline[3]));
+        let page = context(timeline[0]).await;
+        assert_eq!(ids(&page), timeline[0..2].to_vec());
+        assert_eq!(page.next_before, None);
+        assert_eq!(page.next_after, Some(timeline[1]));
+
+        let reply_id = post_message(&router, chat_id, "reply", Some(timeline[0]), &token).await;
+        let reply_id = deserialize_response_body::<PostChatMessageResponseBody>(reply_id)
+            .await
+            .message_id;
+        let nested = post_message(&router, chat_id, "nested", Some(reply_id), &token).await;
+        let nested_id = deserialize_response_body::<PostChatMessageResponseBody>(nested)
+            .await
+            .message_id;
+
+        let thread_uri = std::format!("/api/chat/{chat_id}/messages/{}/thread", timeline[0]);
+        let response = get_with_auth_header(&router, &thread_uri, Some(&token)).await;
+        assert_eq!(response.status(), 200);
+        let thread = deserialize_response_body::<ChatMessagesResponseBody>(response).await;
+        let mut thread_ids = ids(&thread);
+        thread_ids.sort();
+        let mut expected = vec![reply_id, nested_id];
+        expected.sort();
+        assert_eq!(thread_ids, expected);
+        assert_eq!(thread.next_after, None);
+
+        let response =
+            get_with_auth_header(&router, std::format!("{thread_uri}?limit=1"), Some(&token)).await;
+        let first = deserialize_response_body::<ChatMessagesResponseBody>(response).await;
+        assert_eq!(ids(&first), ids(&thread)[..1].to_vec());
+        assert_eq!(first.next_after, Some(ids(&thread)[0]));
+
+        let other_chat_id =
+            create_chat_with_token(&router, &token, ChatTypeParameter::Group, vec![]).await;
+        for uri in [
+            std::format!("/api/chat/{other_chat_id}/messages/{}/thread", timeline[0]),
+            std::format!("/api/chat/{other_chat_id}/messages/{}/context", timeline[0]),
+        ] {
+            let response = get_with_auth_header(&router, uri, Some(&token)).await;
+            assert_eq!(response.status(), 404);
+        }
+    }
+}
@@ -0,0 +1,412 @@
+use crate::models::chat_members::ChatMemberDb;
+use crate::models::chat_message::ChatMessageDb;
+use crate::models::chats::ChatDb;
+use crate::models::company::CompanyDb;
+use crate::models::errors::DbError;
+use crate::models::project::ProjectDb;
+use crate::models::user::UserDb;
+use crate::utils::api_keys::Scope;
+use crate::web::authentication::{Authenticated, ScopeError};
+use crate::web::authorization::{create_forbidden_response, Authorized, ReadChat};
+use crate::web::errors::{
+    create_invalid_response, ALREADY_CHAT_MEMBER_ERROR_MSG, CHAT_ALIAS_REQUIRED_ERROR_MSG,
+    CHAT_CREATOR_ERROR_MSG, FORBIDDEN_ERROR_MSG, PRIVATE_CHAT_MEMBERS_ERROR_MSG,
+};
+use crate::web::formats::JsonDateTime;
+use crate::web_service::{ErrorCode, ErrorResponseBody, WebService};
+use axum::extract::rejection::JsonRejection;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use database::chats::{Chat, ChatType};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+const TITLE_MAX_LENGTH: usize = 255;
+
+/// Shared by the chat, chat member and chat message handlers
+#[derive(Debug)]
+pub enum ChatErrorResponse {
+    Forbidden(ScopeError),
+    NotAllowed,
+    DbError(DbError),
+    InvalidInputDataFormat(String),
+    AliasRequired,
+    AlreadyMember,
+    ChatCreator,
+    PrivateChatMembers,
+}
+
+impl IntoResponse for ChatErrorResponse {
+    fn into_response(self) -> Response {
+        match self {
+            ChatErrorResponse::Forbidden(error) => error.into_response(),
+            ChatErrorResponse::NotAllowed => create_forbidden_response(FORBIDDEN_ERROR_MSG),
+            ChatErrorResponse::DbError(db_error) => db_error.into_response(),
+            ChatErrorResponse::InvalidInputDataFormat(error) => {
+                create_invalid_response(error).into_response()
+            }
+            ChatErrorResponse::AliasRequired => {
+                create_invalid_response(CHAT_ALIAS_REQUIRED_ERROR_MSG.to_owned()).into_response()
+            }
+            ChatErrorResponse::AlreadyMember => (
+                StatusCode::CONFLICT,
+                Json(ErrorResponseBody {
+                    code: Some(ErrorCode::AlreadyTaken),
+                    error: ALREADY_CHAT_MEMBER_ERROR_MSG.into(),
+                }),
+            )
+                .into_response(),
+            ChatErrorResponse::ChatCreator => create_forbidden_response(CHAT_CREATOR_ERROR_MSG),
+            ChatErrorResponse::PrivateChatMembers => {
+                create_invalid_response(PRIVATE_CHAT_MEMBERS_ERROR_MSG.to_owned()).into_response()
+            }
+        }
+    }
+}
+
+/// Chat members are stored by their aliases, so users without one can not join chats
+pub async fn get_member_alias(
+    user_db: &impl UserDb,
+    user_id: Uuid,
+) -> Result<String, ChatErrorResponse> {
+    user_db
+        .get_user(&user_id)
+        .await
+        .map_err(ChatErrorResponse::DbError)?
+        .alias
+        .ok_or(ChatErrorResponse::AliasRequired)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatTypeParameter {
+    Private,
+    Group,
+    Channel,
+}
+
+impl From<ChatTypeParameter> for ChatType {
+    fn from(value: ChatTypeParameter) -> Self {
+        match value {
+            ChatTypeParameter::Private => ChatType::Private,
+            ChatTypeParameter::Group => ChatType::Group,
+            ChatTypeParameter::Channel => ChatType::Channel,
+        }
+    }
+}
+
+impl From<ChatType> for ChatTypeParameter {
+    fn from(value: ChatType) -> Self {
+        match value {
+            ChatType::Private => ChatTypeParameter::Private,
+            ChatType::Group => ChatTypeParameter::Group,
+            ChatType::Channel => ChatTypeParameter::Channel,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CreateChatData {
+    r#type: ChatTypeParameter,
+    title: String,
+    description: Option<String>,
+    /// Everybody but the creator, exactly one user for a private chat
+    #[serde(default)]
+    member_ids: Vec<Uuid>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CreateChatRequestBody {
+    data: CreateChatData,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CreateChatResponseBody {
+    chat_id: Uuid,
+}
+
+/// Creates a chat, the user becomes its creator and the others its members
+///
+#[tracing::instrument(skip(web_service, body_or_error))]
+pub async fn post<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authenticated: Authenticated,
+    body_or_error: Result<Json<CreateChatRequestBody>, JsonRejection>,
+) -> Result<(StatusCode, Json<CreateChatResponseBody>), ChatErrorResponse> {
+    let user_info = authenticated
+        .require_scope(Scope::ChatsWrite)
+        .map_err(ChatErrorResponse::Forbidden)?;
+
+    let Json(body) = body_or_error
+        .map_err(|x| x.to_string())
+        .map_err(ChatErrorResponse::InvalidInputDataFormat)?;
+    let title = body.data.title;
+    if title.trim().is_empty() || title.chars().count() > TITLE_MAX_LENGTH {
+        return Err(ChatErrorResponse::InvalidInputDataFormat(std::format!(
+            "title must be 1 to {TITLE_MAX_LENGTH} characters"
+        )));
+    }
+
+    let mut member_ids = body.data.member_ids;
+    member_ids.sort();
+    member_ids.dedup();
+    member_ids.retain(|id| *id != user_info.user_id);
+    if body.data.r#type == ChatTypeParameter::Private && member_ids.len() != 1 {
+        return Err(ChatErrorResponse::PrivateChatMembers);
+    }
+
+    let creator = get_member_alias(&web_service.user_db, user_info.user_id).await?;
+    let mut members = Vec::with_capacity(member_ids.len());
+    for member_id in member_ids {
+        members.push(get_member_alias(&web_service.user_db, member_id).await?);
+    }
+
+    let chat_id = web_service
+        .chat_db
+        .insert_chat_with_members(
+            body.data.r#type.into(),
+            title,
+            body.data.description,
+            creator,
+            members,
+        )
+        .await
+        .map_err(ChatErrorResponse::DbError)?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreateChatResponseBody { chat_id }),
+    ))
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ChatResponseData {
+    id: Uuid,
+    r#type: ChatTypeParameter,
+    title: String,
+    description: Option<String>,
+    avatar: Option<String>,
+    created_at: JsonDateTime,
+    updated_at: JsonDateTime,
+}
+
+impl From<Chat> for ChatResponseData {
+    fn from(value: Chat) -> Self {
+        ChatResponseData {
+            id: value.id,
+            r#type: value.r#type.into(),
+            title: value.title,
+            description: value.description,
+            avatar: value.avatar,
+            created_at: value.created_at.into(),
+            updated_at: value.updated_at.into(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ChatsResponseBody {
+    chats: Vec<ChatResponseData>,
+}
+
+/// Lists chats the user is a member of, the newest first
+///
+#[tracing::instrument(skip(web_service))]
+pub async fn get_all<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authenticated: Authenticated,
+) -> Result<Json<ChatsResponseBody>, ChatErrorResponse> {
+    let user_info = authenticated
+        .require_scope(Scope::ChatsRead)
+        .map_err(ChatErrorResponse::Forbidden)?;
+
+    let chats = web_service
+        .chat_db
+        .get_user_chats(user_info.user_id)
+        .await
+        .map_err(ChatErrorResponse::DbError)?;
+
+    Ok(Json(ChatsResponseBody {
+        chats: chats.into_iter().map(Into::into).collect(),
+    }))
+}
+
+#[tracing::instrument(skip(web_service))]
+pub async fn get<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authorized: Authorized<ReadChat>,
+) -> Result<Json<ChatResponseData>, DbError> {
+    let chat = web_service
+        .chat_db
+        .get_chat(authorized.resource_id())
+        .await?;
+
+    Ok(Json(chat.into()))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::utils::tokens::AccessToken;
+    use crate::web::email_verification::tests::register_verified_user;
+    use crate::web::users::tests::create_test_router;
+    use crate::web_service::tests::{
+        deserialize_response_body, get_with_auth_header, patch_with_auth_header,
+        post_with_auth_header,
+    };
+    use axum::Router;
+    use database::utils::random_samples::RandomSample;
+
+    /// Registers a verified user with an alias, so that they can join chats
+    pub async fn register_chat_user(router: &Router) -> (Uuid, String) {
+        let (_, token) = register_verified_user().await;
+        let response = patch_with_auth_header(
+            router,
+            "/api/user/me",
+            &serde_json::json!({ "data": { "alias": std::format!("alias:{}", String::new_random(22)) } }),
+            Some(&token),
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+
+        let user_id = AccessToken::from_token(&token)
+            .expect("valid token")
+            .get_user()
+            .user_id;
+        (user_id, token)
+    }
+
+    pub async fn create_chat_with_token(
+        router: &Router,
+        token: &str,
+        r#type: ChatTypeParameter,
+        member_ids: Vec<Uuid>,
+    ) -> Uuid {
+        let request_body = CreateChatRequestBody {
+            data: CreateChatData {
+                r#type,
+                title: String::new_random(30),
+                description: None,
+                member_ids,
+            },
+        };
+
+        let response = post_with_auth_header(router, "/api/chat", &request_body, Some(token)).await;
+        assert_eq!(response.status(), 201);
+
+        deserialize_response_body::<CreateChatResponseBody>(response)
+            .await
+            .chat_id
+    }
+
+    #[tokio::test]
+    async fn should_create_and_list_chats() {
+        let router = create_test_router().await;
+        let (_, token) = register_chat_user(&router).await;
+        let (other_id, other_token) = register_chat_user(&router).await;
+        let (_, stranger_token) = register_chat_user(&router).await;
+
+        let private_id =
+            create_chat_with_token(&router, &token, ChatTypeParameter::Private, vec![other_id])
+                .await;
+        let group_id =
+            create_chat_with_token(&router, &token, ChatTypeParameter::Group, vec![]).await;
+
+        let response = get_with_auth_header(&router, "/api/chats", Some(&token)).await;
+        assert_eq!(response.status(), 200);
+        let chats = deserialize_response_body::<ChatsResponseBody>(response)
+            .await
+            .chats;
+        let mut chat_ids = chats.iter().map(|chat| chat.id).collect::<Vec<_>>();
+        chat_ids.sort();
+        let mut expected_ids = vec![group_id, private_id];
+        expected_ids.sort();
+        assert_eq!(chat_ids, expected_ids);
+
+        let response = get_with_auth_header(&router, "/api/chats", Some(&other_token)).await;
+        let chats = deserialize_response_body::<ChatsResponseBody>(response)
+            .await
+            .chats;
+        assert_eq!(chats.len(), 1);
+        assert_eq!(chats[0].r#type, ChatTypeParameter::Private);
+
+        let response = get_with_auth_header(
+            &router,
+            std::format!("/api/chat/{private_id}"),
+            Some(&other_token),
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        let chat = deserialize_response_body::<ChatResponseData>(response).await;
+        assert_eq!(chat.id, private_id);
+
+        let response = get_with_auth_header(
+            &router,
+            std::format!("/api/chat/{private_id}"),
+            Some(&stranger_token),
+        )
+        .await;
+        assert_eq!(response.status(), 403);
+    }
+
+    #[tokio::test]
+    async fn should_reject_invalid_chats() {
+        let router = create_test_router().await;
+        let (user_id, token) = register_chat_user(&router).await;
+        let (other_id, _) = register_chat_user(&router).await;
+        let (_, no_alias_token) = register_verified_user().await;
+
+        let create_chat = |r#type, title: &str, member_ids: Vec<Uuid>| CreateChatRequestBody {
+            data: CreateChatData {
+                r#type,
+                title: title.to_owned(),
+                description: None,
+                member_ids,
+            },
+        };
+
+        for request_body in [
+            create_chat(ChatTypeParameter::Private, "chat", vec![]),
+            create_chat(ChatTypeParameter::Private, "chat", vec![user_id]),
+            create_chat(ChatTypeParameter::Group, " ", vec![other_id]),
+        ] {
+            let response =
+                post_with_auth_header(&router, "/api/chat", &request_body, Some(&token)).await;
+            assert_eq!(response.status(), 400);
+        }
+
+        let response = post_with_auth_header(
+            &router,
+            "/api/chat",
+            &create_chat(ChatTypeParameter::Group, "chat", vec![other_id]),
+            Some(&no_alias_token),
+        )
+        .await;
+        assert_eq!(response.status(), 400);
+        let body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(body.error, CHAT_ALIAS_REQUIRED_ERROR_MSG);
+    }
+}
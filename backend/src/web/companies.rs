@@ -0,0 +1,622 @@
+use crate::models::chat_members::ChatMemberDb;
+use crate::models::chat_message::ChatMessageDb;
+use crate::models::chats::ChatDb;
+use crate::models::company::{CompanyDb, OwnedAddressInput};
+use crate::models::errors::DbError;
+use crate::models::project::ProjectDb;
+use crate::models::user::UserDb;
+use crate::policy::{is_allowed, Action, Resource};
+use crate::utils::api_keys::Scope;
+use crate::web::authentication::{Authenticated, ScopeError};
+use crate::web::authorization::{
+    create_forbidden_response, Authorized, ReadCompany, UpdateCompany,
+};
+use crate::web::errors::{create_invalid_response, FORBIDDEN_ERROR_MSG};
+use crate::web::formats::JsonDateTime;
+use crate::web::projects::{
+    list_projects, ListProjectsErrorResponse, ProjectsQuery, ProjectsResponseBody,
+};
+use crate::web_service::WebService;
+use axum::extract::rejection::{JsonRejection, QueryRejection};
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use database::addresses::Address;
+use database::companies::Company;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+const NAME_MAX_LENGTH: usize = 255;
+
+#[derive(Debug)]
+pub enum CompanyErrorResponse {
+    Forbidden(ScopeError),
+    NotAllowed,
+    DbError(DbError),
+    InvalidInputDataFormat(String),
+}
+
+impl IntoResponse for CompanyErrorResponse {
+    fn into_response(self) -> Response {
+        match self {
+            CompanyErrorResponse::Forbidden(error) => error.into_response(),
+            CompanyErrorResponse::NotAllowed => create_forbidden_response(FORBIDDEN_ERROR_MSG),
+            CompanyErrorResponse::DbError(db_error) => db_error.into_response(),
+            CompanyErrorResponse::InvalidInputDataFormat(error) => {
+                create_invalid_response(error).into_response()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AddressData {
+    zip_code: i32,
+    country: String,
+    region: String,
+    city: String,
+    district: Option<String>,
+    street: String,
+    building: String,
+    apartment: String,
+}
+
+impl From<AddressData> for OwnedAddressInput {
+    fn from(value: AddressData) -> Self {
+        OwnedAddressInput {
+            zip_code: value.zip_code,
+            country: value.country,
+            region: value.region,
+            city: value.city,
+            district: value.district,
+            street: value.street,
+            building: value.building,
+            apartment: value.apartment,
+        }
+    }
+}
+
+impl From<Address> for AddressData {
+    fn from(value: Address) -> Self {
+        AddressData {
+            zip_code: value.zip_code,
+            country: value.country,
+            region: value.region,
+            city: value.city,
+            district: value.district,
+            street: value.street,
+            building: value.building,
+            apartment: value.apartment,
+        }
+    }
+}
+
+fn validate_company_name(name: &str) -> Result<(), CompanyErrorResponse> {
+    if name.trim().is_empty() || name.chars().count() > NAME_MAX_LENGTH {
+        return Err(CompanyErrorResponse::InvalidInputDataFormat(std::format!(
+            "name must be 1 to {NAME_MAX_LENGTH} characters"
+        )));
+    }
+    Ok(())
+}
+
+/// A country is an ISO 3166-1 alpha-2 code, the rest is free text
+fn validate_address(address: &AddressData) -> Result<(), CompanyErrorResponse> {
+    if address.country.len() != 2 || !address.country.chars().all(|x| x.is_ascii_uppercase()) {
+        return Err(CompanyErrorResponse::InvalidInputDataFormat(
+            "country must be a two letter code".to_owned(),
+        ));
+    }
+
+    let fields = [
+        &address.region,
+        &address.city,
+        &address.street,
+        &address.building,
+        &address.apartment,
+    ];
+    if fields
+        .into_iter()
+        .chain(&address.district)
+        .any(|x| x.chars().count() > NAME_MAX_LENGTH)
+    {
+        return Err(CompanyErrorResponse::InvalidInputDataFormat(std::format!(
+            "address fields must be up to {NAME_MAX_LENGTH} characters"
+        )));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CreateCompanyData {
+    name: String,
+    address: AddressData,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CreateCompanyRequestBody {
+    data: CreateCompanyData,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CreateCompanyResponseBody {
+    company_id: Uuid,
+}
+
+/// Creates a company with its address, the user becomes its owner
+///
+#[tracing::instrument(skip(web_service, body_or_error))]
+pub async fn post<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authenticated: Authenticated,
+    body_or_error: Result<Json<CreateCompanyRequestBody>, JsonRejection>,
+) -> Result<(StatusCode, Json<CreateCompanyResponseBody>), CompanyErrorResponse> {
+    let user_info = authenticated
+        .require_scope(Scope::CompaniesWrite)
+        .map_err(CompanyErrorResponse::Forbidden)?;
+
+    let Json(body) = body_or_error
+        .map_err(|x| x.to_string())
+        .map_err(CompanyErrorResponse::InvalidInputDataFormat)?;
+    validate_company_name(&body.data.name)?;
+    validate_address(&body.data.address)?;
+
+    let company_id = web_service
+        .company_db
+        .insert_company(body.data.name, &body.data.address.into(), user_info.user_id)
+        .await
+        .map_err(CompanyErrorResponse::DbError)?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreateCompanyResponseBody { company_id }),
+    ))
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CompanyResponseData {
+    id: Uuid,
+    name: String,
+    /// Only a single company comes with its address
+    #[serde(skip_serializing_if = "Option::is_none")]
+    address: Option<AddressData>,
+    created_at: JsonDateTime,
+    updated_at: JsonDateTime,
+}
+
+impl From<Company> for CompanyResponseData {
+    fn from(value: Company) -> Self {
+        CompanyResponseData {
+            id: value.id,
+            name: value.name,
+            address: None,
+            created_at: value.created_at.into(),
+            updated_at: value.updated_at.into(),
+        }
+    }
+}
+
+async fn company_response(
+    company_db: &impl CompanyDb,
+    company_id: Uuid,
+) -> Result<Json<CompanyResponseData>, DbError> {
+    let company = company_db.get_company(company_id).await?;
+    let address = company_db.get_address(company.address_id).await?;
+
+    Ok(Json(CompanyResponseData {
+        address: Some(address.into()),
+        ..company.into()
+    }))
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CompaniesResponseBody {
+    companies: Vec<CompanyResponseData>,
+}
+
+/// Lists companies the user is a member of by name
+///
+#[tracing::instrument(skip(web_service))]
+pub async fn get_all<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authenticated: Authenticated,
+) -> Result<Json<CompaniesResponseBody>, CompanyErrorResponse> {
+    let user_info = authenticated
+        .require_scope(Scope::CompaniesRead)
+        .map_err(CompanyErrorResponse::Forbidden)?;
+
+    let companies = web_service
+        .company_db
+        .get_user_companies(user_info.user_id)
+        .await
+        .map_err(CompanyErrorResponse::DbError)?;
+
+    Ok(Json(CompaniesResponseBody {
+        companies: companies.into_iter().map(Into::into).collect(),
+    }))
+}
+
+#[tracing::instrument(skip(web_service))]
+pub async fn get<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authorized: Authorized<ReadCompany>,
+) -> Result<Json<CompanyResponseData>, DbError> {
+    company_response(&web_service.company_db, authorized.resource_id()).await
+}
+
+/// Fields left out keep their values, an address is replaced as a whole
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct UpdateCompanyData {
+    name: Option<String>,
+    address: Option<AddressData>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct UpdateCompanyRequestBody {
+    data: UpdateCompanyData,
+}
+
+/// Renames a company or moves it to another address
+///
+#[tracing::instrument(skip(web_service, body_or_error))]
+pub async fn patch<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authorized: Authorized<UpdateCompany>,
+    body_or_error: Result<Json<UpdateCompanyRequestBody>, JsonRejection>,
+) -> Result<Json<CompanyResponseData>, CompanyErrorResponse> {
+    let Json(body) = body_or_error
+        .map_err(|x| x.to_string())
+        .map_err(CompanyErrorResponse::InvalidInputDataFormat)?;
+    if let Some(name) = &body.data.name {
+        validate_company_name(name)?;
+    }
+    if let Some(address) = &body.data.address {
+        validate_address(address)?;
+    }
+
+    let company = web_service
+        .company_db
+        .get_company(authorized.resource_id())
+        .await
+        .map_err(CompanyErrorResponse::DbError)?;
+    if let Some(name) = body.data.name {
+        web_service
+            .company_db
+            .update_company_name(company.id, name)
+            .await
+            .map_err(CompanyErrorResponse::DbError)?;
+    }
+    if let Some(address) = body.data.address {
+        web_service
+            .company_db
+            .update_address(company.address_id, &address.into())
+            .await
+            .map_err(CompanyErrorResponse::DbError)?;
+    }
+
+    company_response(&web_service.company_db, company.id)
+        .await
+        .map_err(CompanyErrorResponse::DbError)
+}
+
+/// Lists projects of a company a page at a time, the same way as `/api/projects`
+///
+#[tracing::instrument(skip(web_service))]
+pub async fn get_projects<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authorized: Authorized<ReadCompany>,
+    query_or_error: Result<Query<ProjectsQuery>, QueryRejection>,
+) -> Result<Json<ProjectsResponseBody>, ListProjectsErrorResponse> {
+    let Query(mut query) = query_or_error
+        .map_err(|x| x.to_string())
+        .map_err(ListProjectsErrorResponse::InvalidInputDataFormat)?;
+
+    query.company_id = Some(authorized.resource_id());
+    list_projects(&web_service.project_db, authorized.user().user_id, query)
+        .await
+        .map(Json)
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AttachProjectData {
+    project_id: Uuid,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AttachProjectRequestBody {
+    data: AttachProjectData,
+}
+
+/// Shares a project with every member of a company
+///
+/// Needs the right to manage members of the project as well, its members grow by the company.
+#[tracing::instrument(skip(web_service, body_or_error))]
+pub async fn post_project<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authorized: Authorized<UpdateCompany>,
+    body_or_error: Result<Json<AttachProjectRequestBody>, JsonRejection>,
+) -> Result<StatusCode, CompanyErrorResponse> {
+    let Json(body) = body_or_error
+        .map_err(|x| x.to_string())
+        .map_err(CompanyErrorResponse::InvalidInputDataFormat)?;
+
+    let allowed = is_allowed(
+        &web_service.user_db,
+        authorized.user().user_id,
+        Action::ManageMembers,
+        Resource::Project(body.data.project_id),
+    )
+    .await
+    .map_err(CompanyErrorResponse::DbError)?;
+    if !allowed {
+        return Err(CompanyErrorResponse::NotAllowed);
+    }
+
+    web_service
+        .company_db
+        .insert_company_project(authorized.resource_id(), body.data.project_id)
+        .await
+        .map_err(CompanyErrorResponse::DbError)?;
+
+    Ok(StatusCode::CREATED)
+}
+
+/// Stops sharing a project with a company, the project itself stays
+///
+#[tracing::instrument(skip(web_service))]
+pub async fn delete_project<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authorized: Authorized<UpdateCompany>,
+    Path((_, project_id)): Path<(Uuid, Uuid)>,
+) -> Result<StatusCode, DbError> {
+    let deleted = web_service
+        .company_db
+        .delete_company_project(authorized.resource_id(), project_id)
+        .await?;
+    if deleted == 0 {
+        return Err(DbError::NotFoundError);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::web::email_verification::tests::register_verified_user;
+    use crate::web::projects::tests::create_project_with_token;
+    use crate::web::users::tests::create_test_router;
+    use crate::web_service::tests::{
+        delete_with_auth_header, deserialize_response_body, get_with_auth_header,
+        patch_with_auth_header, post_with_auth_header,
+    };
+    use axum::Router;
+    use database::utils::random_samples::RandomSample;
+
+    fn create_address_data() -> AddressData {
+        AddressData {
+            zip_code: 10115,
+            country: "DE".to_owned(),
+            region: String::new_random(20),
+            city: String::new_random(20),
+            district: None,
+            street: String::new_random(20),
+            building: "1".to_owned(),
+            apartment: "2".to_owned(),
+        }
+    }
+
+    /// Creates a company with a random name and address, the token's user owns it
+    pub async fn create_company_with_token(router: &Router, token: &str) -> Uuid {
+        let request_body = CreateCompanyRequestBody {
+            data: CreateCompanyData {
+                name: String::new_random(30),
+                address: create_address_data(),
+            },
+        };
+
+        let response =
+            post_with_auth_header(router, "/api/company", &request_body, Some(token)).await;
+        assert_eq!(response.status(), 201);
+
+        deserialize_response_body::<CreateCompanyResponseBody>(response)
+            .await
+            .company_id
+    }
+
+    async fn get_company(router: &Router, company_id: Uuid, token: &str) -> CompanyResponseData {
+        let response = get_with_auth_header(
+            router,
+            std::format!("/api/company/{company_id}"),
+            Some(token),
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+
+        deserialize_response_body::<CompanyResponseData>(response).await
+    }
+
+    async fn get_company_project_ids(router: &Router, company_id: Uuid, token: &str) -> Vec<Uuid> {
+        let response = get_with_auth_header(
+            router,
+            std::format!("/api/company/{company_id}/projects"),
+            Some(token),
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+
+        let body = deserialize_response_body::<ProjectsResponseBody>(response).await;
+        serde_json::to_value(body).expect("projects")["projects"]
+            .as_array()
+            .expect("a list of projects")
+            .iter()
+            .map(|x| x["id"].as_str().expect("an id").parse().expect("a uuid"))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn should_create_and_update_a_company() {
+        let router = create_test_router().await;
+        let (_, owner_token) = register_verified_user().await;
+        let (_, other_token) = register_verified_user().await;
+
+        let invalid_requests = [
+            CreateCompanyData {
+                name: " ".to_owned(),
+                address: create_address_data(),
+            },
+            CreateCompanyData {
+                name: String::new_random(30),
+                address: AddressData {
+                    country: "Germany".to_owned(),
+                    ..create_address_data()
+                },
+            },
+        ];
+        for data in invalid_requests {
+            let request_body = CreateCompanyRequestBody { data };
+            let response =
+                post_with_auth_header(&router, "/api/company", &request_body, Some(&owner_token))
+                    .await;
+            assert_eq!(response.status(), 400);
+        }
+
+        let company_id = create_company_with_token(&router, &owner_token).await;
+        let company = get_company(&router, company_id, &owner_token).await;
+        assert_eq!(company.id, company_id);
+        assert_eq!(company.address.expect("an address").zip_code, 10115);
+
+        let response = get_with_auth_header(&router, "/api/companies", Some(&owner_token)).await;
+        assert_eq!(response.status(), 200);
+        let companies = deserialize_response_body::<CompaniesResponseBody>(response).await;
+        assert_eq!(
+            companies.companies.iter().map(|x| x.id).collect::<Vec<_>>(),
+            vec![company_id]
+        );
+
+        let uri = std::format!("/api/company/{company_id}");
+        let response = get_with_auth_header(&router, &uri, Some(&other_token)).await;
+        assert_eq!(response.status(), 403);
+
+        let address = AddressData {
+            district: Some(String::new_random(20)),
+            ..create_address_data()
+        };
+        let request_body = UpdateCompanyRequestBody {
+            data: UpdateCompanyData {
+                name: Some("Renamed".to_owned()),
+                address: Some(address.clone()),
+            },
+        };
+        let response =
+            patch_with_auth_header(&router, &uri, &request_body, Some(&other_token)).await;
+        assert_eq!(response.status(), 403);
+        let response =
+            patch_with_auth_header(&router, &uri, &request_body, Some(&owner_token)).await;
+        assert_eq!(response.status(), 200);
+
+        let company = get_company(&router, company_id, &owner_token).await;
+        assert_eq!(company.name, "Renamed");
+        let updated_address = company.address.expect("an address");
+        assert_eq!(updated_address.district, address.district);
+        assert_eq!(updated_address.street, address.street);
+    }
+
+    #[tokio::test]
+    async fn should_share_projects_with_a_company() {
+        let router = create_test_router().await;
+        let (_, owner_token) = register_verified_user().await;
+        let (_, other_token) = register_verified_user().await;
+        let company_id = create_company_with_token(&router, &owner_token).await;
+        let (_, project) = create_project_with_token(&router, &owner_token).await;
+        let project_id = project.project_id();
+        let (_, other_project) = create_project_with_token(&router, &other_token).await;
+
+        let uri = std::format!("/api/company/{company_id}/projects");
+        let attach = |project_id| AttachProjectRequestBody {
+            data: AttachProjectData { project_id },
+        };
+
+        let response =
+            post_with_auth_header(&router, &uri, &attach(project_id), Some(&owner_token)).await;
+        assert_eq!(response.status(), 201);
+        let response =
+            post_with_auth_header(&router, &uri, &attach(project_id), Some(&owner_token)).await;
+        assert_eq!(response.status(), 409);
+        // Somebody else's project is not theirs to share
+        let response = post_with_auth_header(
+            &router,
+            &uri,
+            &attach(other_project.project_id()),
+            Some(&owner_token),
+        )
+        .await;
+        assert_eq!(response.status(), 403);
+
+        assert_eq!(
+            get_company_project_ids(&router, company_id, &owner_token).await,
+            vec![project_id]
+        );
+        let response = get_with_auth_header(&router, &uri, Some(&other_token)).await;
+        assert_eq!(response.status(), 403);
+
+        let uri = std::format!("/api/company/{company_id}/projects/{project_id}");
+        let response = delete_with_auth_header(&router, &uri, Some(&owner_token)).await;
+        assert_eq!(response.status(), 204);
+        let response = delete_with_auth_header(&router, &uri, Some(&owner_token)).await;
+        assert_eq!(response.status(), 404);
+        assert!(get_company_project_ids(&router, company_id, &owner_token)
+            .await
+            .is_empty());
+    }
+}
@@ -0,0 +1,928 @@
+use crate::mailer::{Email, MailerError};
+use crate::models::chat_members::ChatMemberDb;
+use crate::models::chat_message::ChatMessageDb;
+use crate::models::chats::ChatDb;
+use crate::models::company::{CompanyDb, OwnedCompanyInvitationInput};
+use crate::models::errors::DbError;
+use crate::models::project::ProjectDb;
+use crate::models::user::UserDb;
+use crate::utils::tokens::{generate_opaque_token, hash_opaque_token};
+use crate::web::authentication::{Authenticated, ScopeError};
+use crate::web::authorization::{
+    create_forbidden_response, Authorized, ManageCompanyMembers, ReadCompany,
+};
+use crate::web::errors::{
+    create_bad_request_error, create_internal_server_error, ALREADY_COMPANY_MEMBER_ERROR_MSG,
+    COMPANY_LAST_OWNER_ERROR_MSG, FORBIDDEN_ERROR_MSG, INVALID_COMPANY_INVITATION_ERROR_MSG,
+    INVALID_MAIL_MSG,
+};
+use crate::web::formats::JsonDateTime;
+use crate::web_service::{ErrorCode, ErrorResponseBody, WebService};
+use axum::extract::rejection::JsonRejection;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use database::companies::{CompanyMemberProfile, CompanyMemberRole};
+use database::company_invitations::CompanyInvitation;
+use email_address::EmailAddress;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime, PrimitiveDateTime};
+use uuid::Uuid;
+
+lazy_static! {
+    static ref COMPANY_INVITATION_TOKEN_DURATION: Duration = Duration::seconds(
+        std::env::var("COMPANY_INVITATION_TOKEN_DURATION_IN_SECS")
+            .expect("COMPANY_INVITATION_TOKEN_DURATION_IN_SECS must be in environment")
+            .parse::<i64>()
+            .expect("integer duration")
+    );
+    static ref COMPANY_INVITATION_URL: String = std::env::var("COMPANY_INVITATION_URL")
+        .expect("COMPANY_INVITATION_URL must be in environment");
+}
+
+#[derive(Debug)]
+pub enum CompanyMemberErrorResponse {
+    Forbidden(ScopeError),
+    NotAllowed,
+    DbError(DbError),
+    InvalidEmailFormat,
+    InvalidToken,
+    AlreadyMember,
+    LastOwner,
+    JsonRejection(JsonRejection),
+    Mailer(MailerError),
+}
+
+impl IntoResponse for CompanyMemberErrorResponse {
+    fn into_response(self) -> Response {
+        match self {
+            CompanyMemberErrorResponse::Forbidden(error) => error.into_response(),
+            CompanyMemberErrorResponse::NotAllowed => {
+                create_forbidden_response(FORBIDDEN_ERROR_MSG)
+            }
+            CompanyMemberErrorResponse::DbError(db_error) => db_error.into_response(),
+            CompanyMemberErrorResponse::InvalidEmailFormat => (
+                StatusCode::NOT_ACCEPTABLE,
+                Json(ErrorResponseBody {
+                    code: Some(ErrorCode::InvalidEmailFormat),
+                    error: INVALID_MAIL_MSG.into(),
+                }),
+            )
+                .into_response(),
+            CompanyMemberErrorResponse::InvalidToken => {
+                create_bad_request_error(INVALID_COMPANY_INVITATION_ERROR_MSG.into())
+                    .into_response()
+            }
+            CompanyMemberErrorResponse::AlreadyMember => (
+                StatusCode::CONFLICT,
+                Json(ErrorResponseBody {
+                    code: Some(ErrorCode::AlreadyTaken),
+                    error: ALREADY_COMPANY_MEMBER_ERROR_MSG.into(),
+                }),
+            )
+                .into_response(),
+            CompanyMemberErrorResponse::LastOwner => (
+                StatusCode::CONFLICT,
+                Json(ErrorResponseBody {
+                    code: None,
+                    error: COMPANY_LAST_OWNER_ERROR_MSG.into(),
+                }),
+            )
+                .into_response(),
+            CompanyMemberErrorResponse::JsonRejection(error) => {
+                create_bad_request_error(error.to_string()).into_response()
+            }
+            CompanyMemberErrorResponse::Mailer(error) => {
+                create_internal_server_error(std::format!("Can not send an email: {:?}", error))
+                    .into_response()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompanyMemberRoleParameter {
+    Owner,
+    Admin,
+    Member,
+}
+
+impl From<CompanyMemberRoleParameter> for CompanyMemberRole {
+    fn from(value: CompanyMemberRoleParameter) -> Self {
+        match value {
+            CompanyMemberRoleParameter::Owner => CompanyMemberRole::Owner,
+            CompanyMemberRoleParameter::Admin => CompanyMemberRole::Admin,
+            CompanyMemberRoleParameter::Member => CompanyMemberRole::Member,
+        }
+    }
+}
+
+impl From<CompanyMemberRole> for CompanyMemberRoleParameter {
+    fn from(value: CompanyMemberRole) -> Self {
+        match value {
+            CompanyMemberRole::Owner => CompanyMemberRoleParameter::Owner,
+            CompanyMemberRole::Admin => CompanyMemberRoleParameter::Admin,
+            CompanyMemberRole::Member => CompanyMemberRoleParameter::Member,
+        }
+    }
+}
+
+pub fn company_member_role_name(role: CompanyMemberRole) -> &'static str {
+    match role {
+        CompanyMemberRole::Owner => "owner",
+        CompanyMemberRole::Admin => "admin",
+        CompanyMemberRole::Member => "member",
+    }
+}
+
+/// Admins manage members and admins, owners are managed by owners only
+async fn require_owner_for<UDB: UserDb>(
+    user_db: &UDB,
+    company_id: Uuid,
+    caller_id: Uuid,
+    role: CompanyMemberRole,
+) -> Result<(), CompanyMemberErrorResponse> {
+    if role != CompanyMemberRole::Owner {
+        return Ok(());
+    }
+
+    let access = user_db
+        .get_company_access(company_id, caller_id)
+        .await
+        .map_err(CompanyMemberErrorResponse::DbError)?;
+    if access.role != Some(CompanyMemberRole::Owner) {
+        return Err(CompanyMemberErrorResponse::NotAllowed);
+    }
+    Ok(())
+}
+
+async fn get_member_role<CDB: CompanyDb>(
+    company_db: &CDB,
+    company_id: Uuid,
+    user_id: Uuid,
+) -> Result<CompanyMemberRole, CompanyMemberErrorResponse> {
+    company_db
+        .get_company_members(company_id)
+        .await
+        .map_err(CompanyMemberErrorResponse::DbError)?
+        .into_iter()
+        .find(|member| member.user_id == user_id)
+        .map(|member| member.role)
+        .ok_or(CompanyMemberErrorResponse::DbError(DbError::NotFoundError))
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CompanyMemberResponseData {
+    user_id: Uuid,
+    email: String,
+    alias: Option<String>,
+    first_name: Option<String>,
+    last_name: Option<String>,
+    role: CompanyMemberRoleParameter,
+    created_at: JsonDateTime,
+}
+
+impl From<CompanyMemberProfile> for CompanyMemberResponseData {
+    fn from(value: CompanyMemberProfile) -> Self {
+        CompanyMemberResponseData {
+            user_id: value.user_id,
+            email: value.email,
+            alias: value.alias,
+            first_name: value.first_name,
+            last_name: value.last_name,
+            role: value.role.into(),
+            created_at: value.created_at.into(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CompanyMembersResponseBody {
+    members: Vec<CompanyMemberResponseData>,
+}
+
+/// Lists members of a company, owners first
+///
+#[tracing::instrument(skip(web_service))]
+pub async fn get_all<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authorized: Authorized<ReadCompany>,
+) -> Result<Json<CompanyMembersResponseBody>, DbError> {
+    let members = web_service
+        .company_db
+        .get_company_members(authorized.resource_id())
+        .await?;
+
+    Ok(Json(CompanyMembersResponseBody {
+        members: members.into_iter().map(Into::into).collect(),
+    }))
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct InviteCompanyMemberData {
+    email: String,
+    role: CompanyMemberRoleParameter,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct InviteCompanyMemberRequestBody {
+    data: InviteCompanyMemberData,
+}
+
+/// Either a user who has become a member or an invitation mailed to somebody without an account
+#[derive(Debug, Deserialize, Serialize)]
+pub struct InviteCompanyMemberResponseBody {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    invitation_id: Option<Uuid>,
+}
+
+/// Adds a user with a given email to a company
+///
+/// Registered users become members at once and are told by email (201).
+/// Anybody else is mailed a single-use invitation link, see `accept_invitation` (202).
+#[tracing::instrument(skip(web_service, body_or_error))]
+pub async fn post<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authorized: Authorized<ManageCompanyMembers>,
+    body_or_error: Result<Json<InviteCompanyMemberRequestBody>, JsonRejection>,
+) -> Result<(StatusCode, Json<InviteCompanyMemberResponseBody>), CompanyMemberErrorResponse> {
+    let Json(body) = body_or_error.map_err(CompanyMemberErrorResponse::JsonRejection)?;
+
+    if !EmailAddress::is_valid(&body.data.email) {
+        return Err(CompanyMemberErrorResponse::InvalidEmailFormat);
+    }
+
+    let role = body.data.role.into();
+    require_owner_for(
+        &web_service.user_db,
+        authorized.resource_id(),
+        authorized.user().user_id,
+        role,
+    )
+    .await?;
+    let company = web_service
+        .company_db
+        .get_company(authorized.resource_id())
+        .await
+        .map_err(CompanyMemberErrorResponse::DbError)?;
+
+    let user = match web_service
+        .user_db
+        .get_user_by_email(&body.data.email)
+        .await
+    {
+        Ok(user) => Some(user),
+        Err(DbError::NotFoundError) => None,
+        Err(db_error) => return Err(CompanyMemberErrorResponse::DbError(db_error)),
+    };
+
+    if let Some(user) = user {
+        web_service
+            .company_db
+            .insert_company_member(company.id, user.id, role)
+            .await
+            .map_err(|error| match error {
+                DbError::UniqueViolation(_) => CompanyMemberErrorResponse::AlreadyMember,
+                error => CompanyMemberErrorResponse::DbError(error),
+            })?;
+
+        // The membership is made already, so a failed notice does not fail the request
+        let notice = web_service
+            .mailer
+            .send(Email {
+                to: user.email,
+                subject: std::format!("You have been added to {}", company.name),
+                body: std::format!(
+                    "You are now a member of the company {} as {}.",
+                    company.name,
+                    company_member_role_name(role)
+                ),
+            })
+            .await;
+        if let Err(error) = notice {
+            tracing::warn!("can not notify {} of a membership: {:?}", user.id, error);
+        }
+
+        return Ok((
+            StatusCode::CREATED,
+            Json(InviteCompanyMemberResponseBody {
+                user_id: Some(user.id),
+                invitation_id: None,
+            }),
+        ));
+    }
+
+    let token = generate_opaque_token();
+    let expires_at = OffsetDateTime::now_utc()
+        .replace_nanosecond(0)
+        .expect("zero is a valid nanosecond")
+        + *COMPANY_INVITATION_TOKEN_DURATION;
+    let invitation_id = web_service
+        .company_db
+        .insert_company_invitation(&OwnedCompanyInvitationInput {
+            company_id: company.id,
+            email: body.data.email.clone(),
+            role,
+            invited_by: authorized.user().user_id,
+            token_hash: hash_opaque_token(&token),
+            expires_at: PrimitiveDateTime::new(expires_at.date(), expires_at.time()),
+        })
+        .await
+        .map_err(CompanyMemberErrorResponse::DbError)?;
+
+    web_service
+        .mailer
+        .send(Email {
+            to: body.data.email,
+            subject: std::format!("You are invited to {}", company.name),
+            body: std::format!(
+                "You are invited to the company {}, please sign up and accept the invitation by following the link: {}{token}",
+                company.name,
+                *COMPANY_INVITATION_URL
+            ),
+        })
+        .await
+        .map_err(CompanyMemberErrorResponse::Mailer)?;
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(InviteCompanyMemberResponseBody {
+            user_id: None,
+            invitation_id: Some(invitation_id),
+        }),
+    ))
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UpdateCompanyMemberData {
+    role: CompanyMemberRoleParameter,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UpdateCompanyMemberRequestBody {
+    data: UpdateCompanyMemberData,
+}
+
+/// Changes a role of a member
+///
+/// Only owners make or unmake owners, and the last owner keeps their role.
+#[tracing::instrument(skip(web_service, body_or_error))]
+pub async fn patch<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authorized: Authorized<ManageCompanyMembers>,
+    Path((_, user_id)): Path<(Uuid, Uuid)>,
+    body_or_error: Result<Json<UpdateCompanyMemberRequestBody>, JsonRejection>,
+) -> Result<StatusCode, CompanyMemberErrorResponse> {
+    let Json(body) = body_or_error.map_err(CompanyMemberErrorResponse::JsonRejection)?;
+
+    let company_id = authorized.resource_id();
+    let current_role = get_member_role(&web_service.company_db, company_id, user_id).await?;
+    let role = body.data.role.into();
+    for role in [current_role, role] {
+        require_owner_for(
+            &web_service.user_db,
+            company_id,
+            authorized.user().user_id,
+            role,
+        )
+        .await?;
+    }
+
+    let updated = web_service
+        .company_db
+        .update_company_member_role(company_id, user_id, role)
+        .await
+        .map_err(CompanyMemberErrorResponse::DbError)?;
+    if updated == 0 {
+        return Err(CompanyMemberErrorResponse::LastOwner);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Removes a member from a company
+///
+/// Members leave a company on their own, removing others needs the right to manage members
+/// and only owners remove owners. The last owner can not leave.
+#[tracing::instrument(skip(web_service))]
+pub async fn delete<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authorized: Authorized<ReadCompany>,
+    Path((_, user_id)): Path<(Uuid, Uuid)>,
+) -> Result<StatusCode, CompanyMemberErrorResponse> {
+    let company_id = authorized.resource_id();
+    let caller_id = authorized.user().user_id;
+    if caller_id != user_id {
+        let access = web_service
+            .user_db
+            .get_company_access(company_id, caller_id)
+            .await
+            .map_err(CompanyMemberErrorResponse::DbError)?;
+        if !matches!(
+            access.role,
+            Some(CompanyMemberRole::Owner | CompanyMemberRole::Admin)
+        ) {
+            return Err(CompanyMemberErrorResponse::NotAllowed);
+        }
+
+        let role = get_member_role(&web_service.company_db, company_id, user_id).await?;
+        if role == CompanyMemberRole::Owner && access.role != Some(CompanyMemberRole::Owner) {
+            return Err(CompanyMemberErrorResponse::NotAllowed);
+        }
+    }
+
+    let deleted = web_service
+        .company_db
+        .delete_company_member(company_id, user_id)
+        .await
+        .map_err(CompanyMemberErrorResponse::DbError)?;
+    if deleted == 0 {
+        // Members are known by now, so nothing deleted means the last owner
+        get_member_role(&web_service.company_db, company_id, user_id).await?;
+        return Err(CompanyMemberErrorResponse::LastOwner);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CompanyInvitationResponseData {
+    id: Uuid,
+    email: String,
+    role: CompanyMemberRoleParameter,
+    invited_by: Uuid,
+    created_at: JsonDateTime,
+    expires_at: JsonDateTime,
+}
+
+impl From<CompanyInvitation> for CompanyInvitationResponseData {
+    fn from(value: CompanyInvitation) -> Self {
+        CompanyInvitationResponseData {
+            id: value.id,
+            email: value.email,
+            role: value.role.into(),
+            invited_by: value.invited_by,
+            created_at: value.created_at.into(),
+            expires_at: value.expires_at.into(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CompanyInvitationsResponseBody {
+    invitations: Vec<CompanyInvitationResponseData>,
+}
+
+/// Lists invitations of a company which are neither accepted nor expired
+///
+#[tracing::instrument(skip(web_service))]
+pub async fn get_invitations<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authorized: Authorized<ManageCompanyMembers>,
+) -> Result<Json<CompanyInvitationsResponseBody>, DbError> {
+    let invitations = web_service
+        .company_db
+        .get_pending_company_invitations(authorized.resource_id())
+        .await?;
+
+    Ok(Json(CompanyInvitationsResponseBody {
+        invitations: invitations.into_iter().map(Into::into).collect(),
+    }))
+}
+
+/// Withdraws an invitation, its link stops working
+///
+#[tracing::instrument(skip(web_service))]
+pub async fn delete_invitation<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authorized: Authorized<ManageCompanyMembers>,
+    Path((_, invitation_id)): Path<(Uuid, Uuid)>,
+) -> Result<StatusCode, DbError> {
+    let deleted = web_service
+        .company_db
+        .delete_company_invitation(authorized.resource_id(), invitation_id)
+        .await?;
+    if deleted == 0 {
+        return Err(DbError::NotFoundError);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AcceptCompanyInvitationData {
+    token: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AcceptCompanyInvitationRequestBody {
+    data: AcceptCompanyInvitationData,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AcceptCompanyInvitationResponseBody {
+    company_id: Uuid,
+}
+
+/// Makes the current user a member of a company with a mailed invitation token
+///
+/// The token is all it takes, people may sign up with another email than the invited one.
+#[tracing::instrument(skip(web_service, body_or_error))]
+pub async fn accept_invitation<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authenticated: Authenticated,
+    body_or_error: Result<Json<AcceptCompanyInvitationRequestBody>, JsonRejection>,
+) -> Result<Json<AcceptCompanyInvitationResponseBody>, CompanyMemberErrorResponse> {
+    let user_info = authenticated
+        .require_user()
+        .map_err(CompanyMemberErrorResponse::Forbidden)?;
+    let Json(body) = body_or_error.map_err(CompanyMemberErrorResponse::JsonRejection)?;
+
+    let invitation = match web_service
+        .company_db
+        .get_company_invitation_by_hash(hash_opaque_token(&body.data.token))
+        .await
+    {
+        Ok(invitation) => invitation,
+        Err(DbError::NotFoundError) => return Err(CompanyMemberErrorResponse::InvalidToken),
+        Err(db_error) => return Err(CompanyMemberErrorResponse::DbError(db_error)),
+    };
+
+    let company_id = web_service
+        .company_db
+        .accept_company_invitation(invitation.id, user_info.user_id)
+        .await
+        .map_err(CompanyMemberErrorResponse::DbError)?
+        .ok_or(CompanyMemberErrorResponse::InvalidToken)?;
+
+    Ok(Json(AcceptCompanyInvitationResponseBody { company_id }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web::companies::tests::create_company_with_token;
+    use crate::web::email_verification::tests::{get_mailed_token, register_verified_user};
+    use crate::web::users::tests::{create_test_router, user_id};
+    use crate::web_service::tests::{
+        delete_with_auth_header, deserialize_response_body, get_with_auth_header,
+        patch_with_auth_header, post_with_auth_header, TEST_MAILER,
+    };
+    use axum::body::Bytes;
+    use axum::Router;
+    use database::utils::random_samples::RandomSample;
+    use http_body::combinators::UnsyncBoxBody;
+
+    async fn invite(
+        router: &Router,
+        company_id: Uuid,
+        email: &str,
+        role: CompanyMemberRoleParameter,
+        token: &str,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        let request_body = InviteCompanyMemberRequestBody {
+            data: InviteCompanyMemberData {
+                email: email.to_owned(),
+                role,
+            },
+        };
+
+        post_with_auth_header(
+            router,
+            std::format!("/api/company/{company_id}/members"),
+            &request_body,
+            Some(token),
+        )
+        .await
+    }
+
+    async fn get_members(
+        router: &Router,
+        company_id: Uuid,
+        token: &str,
+    ) -> Vec<(Uuid, CompanyMemberRoleParameter)> {
+        let response = get_with_auth_header(
+            router,
+            std::format!("/api/company/{company_id}/members"),
+            Some(token),
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+
+        deserialize_response_body::<CompanyMembersResponseBody>(response)
+            .await
+            .members
+            .into_iter()
+            .map(|x| (x.user_id, x.role))
+            .collect()
+    }
+
+    async fn update_role(
+        router: &Router,
+        company_id: Uuid,
+        user_id: Uuid,
+        role: CompanyMemberRoleParameter,
+        token: &str,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        let request_body = UpdateCompanyMemberRequestBody {
+            data: UpdateCompanyMemberData { role },
+        };
+
+        patch_with_auth_header(
+            router,
+            std::format!("/api/company/{company_id}/members/{user_id}"),
+            &request_body,
+            Some(token),
+        )
+        .await
+    }
+
+    async fn remove(
+        router: &Router,
+        company_id: Uuid,
+        user_id: Uuid,
+        token: &str,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        delete_with_auth_header(
+            router,
+            std::format!("/api/company/{company_id}/members/{user_id}"),
+            Some(token),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn should_manage_members_of_a_company() {
+        let router = create_test_router().await;
+        let (_, owner_token) = register_verified_user().await;
+        let (admin, admin_token) = register_verified_user().await;
+        let (member, member_token) = register_verified_user().await;
+        let company_id = create_company_with_token(&router, &owner_token).await;
+        let (owner_id, admin_id, member_id) = (
+            user_id(&owner_token),
+            user_id(&admin_token),
+            user_id(&member_token),
+        );
+
+        let response = invite(
+            &router,
+            company_id,
+            admin.email(),
+            CompanyMemberRoleParameter::Admin,
+            &owner_token,
+        )
+        .await;
+        assert_eq!(response.status(), 201);
+        assert!(TEST_MAILER
+            .emails_to(admin.email())
+            .pop()
+            .is_some_and(|x| x.body.contains("as admin")));
+        // Admins add members but do not make owners
+        let response = invite(
+            &router,
+            company_id,
+            member.email(),
+            CompanyMemberRoleParameter::Owner,
+            &admin_token,
+        )
+        .await;
+        assert_eq!(response.status(), 403);
+        let response = invite(
+            &router,
+            company_id,
+            member.email(),
+            CompanyMemberRoleParameter::Member,
+            &admin_token,
+        )
+        .await;
+        assert_eq!(response.status(), 201);
+        let response = invite(
+            &router,
+            company_id,
+            member.email(),
+            CompanyMemberRoleParameter::Member,
+            &owner_token,
+        )
+        .await;
+        assert_eq!(response.status(), 409);
+
+        assert_eq!(
+            get_members(&router, company_id, &member_token).await,
+            vec![
+                (owner_id, CompanyMemberRoleParameter::Owner),
+                (admin_id, CompanyMemberRoleParameter::Admin),
+                (member_id, CompanyMemberRoleParameter::Member),
+            ]
+        );
+
+        let response = update_role(
+            &router,
+            company_id,
+            admin_id,
+            CompanyMemberRoleParameter::Member,
+            &member_token,
+        )
+        .await;
+        assert_eq!(response.status(), 403);
+        let response = update_role(
+            &router,
+            company_id,
+            owner_id,
+            CompanyMemberRoleParameter::Member,
+            &admin_token,
+        )
+        .await;
+        assert_eq!(response.status(), 403);
+        let response = remove(&router, company_id, owner_id, &admin_token).await;
+        assert_eq!(response.status(), 403);
+
+        // The only owner keeps the company
+        let response = update_role(
+            &router,
+            company_id,
+            owner_id,
+            CompanyMemberRoleParameter::Admin,
+            &owner_token,
+        )
+        .await;
+        assert_eq!(response.status(), 409);
+        let response = remove(&router, company_id, owner_id, &owner_token).await;
+        assert_eq!(response.status(), 409);
+
+        let response = update_role(
+            &router,
+            company_id,
+            admin_id,
+            CompanyMemberRoleParameter::Owner,
+            &owner_token,
+        )
+        .await;
+        assert_eq!(response.status(), 204);
+        let response = remove(&router, company_id, owner_id, &owner_token).await;
+        assert_eq!(response.status(), 204);
+
+        let response = remove(&router, company_id, member_id, &member_token).await;
+        assert_eq!(response.status(), 204);
+        let response = get_with_auth_header(
+            &router,
+            std::format!("/api/company/{company_id}/members"),
+            Some(&member_token),
+        )
+        .await;
+        assert_eq!(response.status(), 403);
+        assert_eq!(
+            get_members(&router, company_id, &admin_token).await,
+            vec![(admin_id, CompanyMemberRoleParameter::Owner)]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_let_admins_invite_somebody_without_an_account() {
+        let router = create_test_router().await;
+        let (_, owner_token) = register_verified_user().await;
+        let (admin, admin_token) = register_verified_user().await;
+        let (_, invited_token) = register_verified_user().await;
+        let company_id = create_company_with_token(&router, &owner_token).await;
+        let email = std::format!("{}@test.test", String::new_random(16));
+
+        let response = invite(
+            &router,
+            company_id,
+            admin.email(),
+            CompanyMemberRoleParameter::Admin,
+            &owner_token,
+        )
+        .await;
+        assert_eq!(response.status(), 201);
+
+        // Admins invite members but do not make owners, not even of people without an account
+        let response = invite(
+            &router,
+            company_id,
+            &email,
+            CompanyMemberRoleParameter::Owner,
+            &admin_token,
+        )
+        .await;
+        assert_eq!(response.status(), 403);
+        let response = invite(
+            &router,
+            company_id,
+            &email,
+            CompanyMemberRoleParameter::Member,
+            &admin_token,
+        )
+        .await;
+        assert_eq!(response.status(), 202);
+        let invitation = deserialize_response_body::<InviteCompanyMemberResponseBody>(response)
+            .await
+            .invitation_id
+            .expect("an invitation");
+
+        let response = get_with_auth_header(
+            &router,
+            std::format!("/api/company/{company_id}/invitations"),
+            Some(&admin_token),
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        let invitations =
+            deserialize_response_body::<CompanyInvitationsResponseBody>(response).await;
+        assert_eq!(
+            invitations
+                .invitations
+                .iter()
+                .map(|x| x.id)
+                .collect::<Vec<_>>(),
+            vec![invitation]
+        );
+
+        let response = post_with_auth_header(
+            &router,
+            "/api/user/company-invitations/accept",
+            &AcceptCompanyInvitationRequestBody {
+                data: AcceptCompanyInvitationData {
+                    token: get_mailed_token(&email),
+                },
+            },
+            Some(&invited_token),
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        let response_body =
+            deserialize_response_body::<AcceptCompanyInvitationResponseBody>(response).await;
+        assert_eq!(response_body.company_id, company_id);
+
+        assert_eq!(
+            get_members(&router, company_id, &invited_token).await,
+            vec![
+                (user_id(&owner_token), CompanyMemberRoleParameter::Owner),
+                (user_id(&admin_token), CompanyMemberRoleParameter::Admin),
+                (user_id(&invited_token), CompanyMemberRoleParameter::Member),
+            ]
+        );
+
+        // Members do not see invitations
+        let response = get_with_auth_header(
+            &router,
+            std::format!("/api/company/{company_id}/invitations"),
+            Some(&invited_token),
+        )
+        .await;
+        assert_eq!(response.status(), 403);
+    }
+}
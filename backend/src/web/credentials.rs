@@ -0,0 +1,544 @@
+use crate::mailer::{Email, MailerError};
+use crate::models::chat_members::ChatMemberDb;
+use crate::models::chat_message::ChatMessageDb;
+use crate::models::chats::ChatDb;
+use crate::models::company::CompanyDb;
+use crate::models::errors::DbError;
+use crate::models::project::ProjectDb;
+use crate::models::user::{OwnedEmailChangeTokenInput, UserDb};
+use crate::utils::passwords::{
+    hash_password, verify_password, PasswordHashError, PasswordVerification,
+};
+use crate::utils::tokens::{generate_opaque_token, hash_opaque_token};
+use crate::web::audit::record_audit_event;
+use crate::web::authentication::{Authenticated, ScopeError};
+use crate::web::authorization::create_forbidden_response;
+use crate::web::errors::{
+    create_bad_request_error, create_internal_server_error, INVALID_EMAIL_CHANGE_TOKEN_ERROR_MSG,
+    INVALID_MAIL_MSG, WRONG_PASSWORD_ERROR_MSG,
+};
+use crate::web::rate_limiting::FailedLogin;
+use crate::web::sessions::ClientInfo;
+use crate::web_service::{ErrorCode, ErrorResponseBody, WebService};
+use axum::extract::rejection::JsonRejection;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use database::audit_events::AuditEventType;
+use database::users::User;
+use email_address::EmailAddress;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime, PrimitiveDateTime};
+
+lazy_static! {
+    static ref EMAIL_CHANGE_TOKEN_DURATION: Duration = Duration::seconds(
+        std::env::var("EMAIL_CHANGE_TOKEN_DURATION_IN_SECS")
+            .expect("EMAIL_CHANGE_TOKEN_DURATION_IN_SECS must be in environment")
+            .parse::<i64>()
+            .expect("integer duration")
+    );
+    static ref EMAIL_CHANGE_URL: String =
+        std::env::var("EMAIL_CHANGE_URL").expect("EMAIL_CHANGE_URL must be in environment");
+}
+
+#[derive(Debug)]
+pub enum CredentialsErrorResponse {
+    Forbidden(ScopeError),
+    DbError(DbError),
+    WrongPassword,
+    InvalidEmailFormat,
+    InvalidToken,
+    EmailAlreadyRegistered,
+    JsonRejection(JsonRejection),
+    PasswordHashError(PasswordHashError),
+    Mailer(MailerError),
+}
+
+impl IntoResponse for CredentialsErrorResponse {
+    fn into_response(self) -> Response {
+        match self {
+            CredentialsErrorResponse::Forbidden(error) => error.into_response(),
+            CredentialsErrorResponse::DbError(db_error) => db_error.into_response(),
+            CredentialsErrorResponse::WrongPassword => (
+                Extension(FailedLogin),
+                create_forbidden_response(WRONG_PASSWORD_ERROR_MSG),
+            )
+                .into_response(),
+            CredentialsErrorResponse::InvalidEmailFormat => (
+                StatusCode::NOT_ACCEPTABLE,
+                Json(ErrorResponseBody {
+                    code: Some(ErrorCode::InvalidEmailFormat),
+                    error: INVALID_MAIL_MSG.into(),
+                }),
+            )
+                .into_response(),
+            CredentialsErrorResponse::InvalidToken => {
+                create_bad_request_error(INVALID_EMAIL_CHANGE_TOKEN_ERROR_MSG.into())
+                    .into_response()
+            }
+            CredentialsErrorResponse::EmailAlreadyRegistered => (
+                StatusCode::CONFLICT,
+                Json(ErrorResponseBody {
+                    code: Some(ErrorCode::AlreadyRegistered),
+                    error: "user for given email already exists".to_owned(),
+                }),
+            )
+                .into_response(),
+            CredentialsErrorResponse::JsonRejection(error) => {
+                create_bad_request_error(error.to_string()).into_response()
+            }
+            CredentialsErrorResponse::PasswordHashError(error) => {
+                create_internal_server_error(std::format!("Can not hash a password: {:?}", error))
+                    .into_response()
+            }
+            CredentialsErrorResponse::Mailer(error) => {
+                create_internal_server_error(std::format!("Can not send an email: {:?}", error))
+                    .into_response()
+            }
+        }
+    }
+}
+
+/// Re-authenticates the current user, credentials change only with the current password
+pub async fn get_user_with_password(
+    user_db: &impl UserDb,
+    authenticated: &Authenticated,
+    password: impl AsRef<str>,
+) -> Result<User, CredentialsErrorResponse> {
+    let user_info = authenticated
+        .require_user()
+        .map_err(CredentialsErrorResponse::Forbidden)?;
+    let user = user_db
+        .get_user(&user_info.user_id)
+        .await
+        .map_err(CredentialsErrorResponse::DbError)?;
+
+    match verify_password(password, &user.password_hash)
+        .map_err(CredentialsErrorResponse::PasswordHashError)?
+    {
+        PasswordVerification::Invalid => Err(CredentialsErrorResponse::WrongPassword),
+        PasswordVerification::Valid | PasswordVerification::ValidNeedsRehash => Ok(user),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChangePasswordData {
+    current_password: String,
+    new_password: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChangePasswordRequestBody {
+    data: ChangePasswordData,
+}
+
+/// Sets a new password of the current user
+///
+/// Every other session is revoked, the current one stays signed in. API keys are kept,
+/// the user can see and revoke them while signed in.
+#[tracing::instrument(skip(web_service, body_or_error))]
+pub async fn change_password<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authenticated: Authenticated,
+    client_info: ClientInfo,
+    body_or_error: Result<Json<ChangePasswordRequestBody>, JsonRejection>,
+) -> Result<StatusCode, CredentialsErrorResponse> {
+    let Json(body) = body_or_error.map_err(CredentialsErrorResponse::JsonRejection)?;
+    let session_id = authenticated
+        .session_id()
+        .ok_or(CredentialsErrorResponse::Forbidden(
+            ScopeError::ApiKeyNotAllowed,
+        ))?;
+
+    let user = get_user_with_password(
+        &web_service.user_db,
+        &authenticated,
+        &body.data.current_password,
+    )
+    .await?;
+
+    let password_hash = hash_password(&body.data.new_password)
+        .map_err(CredentialsErrorResponse::PasswordHashError)?;
+    web_service
+        .user_db
+        .update_password_hash(&user.id, password_hash)
+        .await
+        .map_err(CredentialsErrorResponse::DbError)?;
+
+    web_service
+        .user_db
+        .revoke_other_user_sessions(user.id, session_id)
+        .await
+        .map_err(CredentialsErrorResponse::DbError)?;
+
+    record_audit_event(
+        web_service.audit_log.as_ref(),
+        AuditEventType::PasswordChanged,
+        user.id,
+        None,
+        &client_info,
+    )
+    .await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChangeEmailData {
+    password: String,
+    new_email: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChangeEmailRequestBody {
+    data: ChangeEmailData,
+}
+
+/// Mails a single-use confirmation link to a new email of the current user
+///
+/// The email is swapped only after the link is followed, see `confirm_email_change`.
+/// The current address is told about the request.
+#[tracing::instrument(skip(web_service, body_or_error))]
+pub async fn change_email<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authenticated: Authenticated,
+    body_or_error: Result<Json<ChangeEmailRequestBody>, JsonRejection>,
+) -> Result<StatusCode, CredentialsErrorResponse> {
+    let Json(body) = body_or_error.map_err(CredentialsErrorResponse::JsonRejection)?;
+
+    if !EmailAddress::is_valid(&body.data.new_email) {
+        return Err(CredentialsErrorResponse::InvalidEmailFormat);
+    }
+
+    let user =
+        get_user_with_password(&web_service.user_db, &authenticated, &body.data.password).await?;
+
+    let token = generate_opaque_token();
+    let expires_at = OffsetDateTime::now_utc()
+        .replace_nanosecond(0)
+        .expect("zero is a valid nanosecond")
+        + *EMAIL_CHANGE_TOKEN_DURATION;
+    web_service
+        .user_db
+        .insert_email_change_token(&OwnedEmailChangeTokenInput {
+            user_id: user.id,
+            new_email: body.data.new_email.clone(),
+            token_hash: hash_opaque_token(&token),
+            expires_at: PrimitiveDateTime::new(expires_at.date(), expires_at.time()),
+        })
+        .await
+        .map_err(CredentialsErrorResponse::DbError)?;
+
+    web_service
+        .mailer
+        .send(Email {
+            to: body.data.new_email,
+            subject: "Confirm your new email".to_owned(),
+            body: std::format!(
+                "Please confirm your new email by following the link: {}{token}",
+                *EMAIL_CHANGE_URL
+            ),
+        })
+        .await
+        .map_err(CredentialsErrorResponse::Mailer)?;
+
+    // The change is not made yet, so a failed notice does not fail the request
+    let notice = web_service
+        .mailer
+        .send(Email {
+            to: user.email,
+            subject: "Your email is being changed".to_owned(),
+            body: "Somebody asked to change the email of your account. \
+                If it was not you, please reset your password."
+                .to_owned(),
+        })
+        .await;
+    if let Err(error) = notice {
+        tracing::warn!("can not notify {} of an email change: {:?}", user.id, error);
+    }
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ConfirmEmailChangeData {
+    token: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ConfirmEmailChangeRequestBody {
+    data: ConfirmEmailChangeData,
+}
+
+/// Swaps an email for a new one with a mailed token
+///
+/// The new email counts as verified. Access tokens are not re-issued, they carry no email.
+#[tracing::instrument(skip(web_service, body_or_error))]
+pub async fn confirm_email_change<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    client_info: ClientInfo,
+    body_or_error: Result<Json<ConfirmEmailChangeRequestBody>, JsonRejection>,
+) -> Result<StatusCode, CredentialsErrorResponse> {
+    let Json(body) = body_or_error.map_err(CredentialsErrorResponse::JsonRejection)?;
+
+    let token = match web_service
+        .user_db
+        .get_email_change_token_by_hash(hash_opaque_token(&body.data.token))
+        .await
+    {
+        Ok(token) => token,
+        Err(DbError::NotFoundError) => return Err(CredentialsErrorResponse::InvalidToken),
+        Err(db_error) => return Err(CredentialsErrorResponse::DbError(db_error)),
+    };
+
+    // Expired and already used tokens are not updated
+    let used = web_service
+        .user_db
+        .use_email_change_token(token.id)
+        .await
+        .map_err(CredentialsErrorResponse::DbError)?;
+    if used == 0 {
+        return Err(CredentialsErrorResponse::InvalidToken);
+    }
+
+    web_service
+        .user_db
+        .update_email(&token.user_id, token.new_email)
+        .await
+        .map_err(|error| match error {
+            DbError::UniqueViolation(_) => CredentialsErrorResponse::EmailAlreadyRegistered,
+            error => CredentialsErrorResponse::DbError(error),
+        })?;
+
+    record_audit_event(
+        web_service.audit_log.as_ref(),
+        AuditEventType::EmailChanged,
+        token.user_id,
+        None,
+        &client_info,
+    )
+    .await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rate_limit::RateLimits;
+    use crate::utils::api_keys::Scope;
+    use crate::web::api_keys::tests::create_api_key;
+    use crate::web::email_verification::tests::{get_mailed_token, register_verified_user};
+    use crate::web::errors::ACCOUNT_LOCKED_ERROR_MSG;
+    use crate::web::tokens::tests::refresh_tokens;
+    use crate::web::users::tests::{
+        create_test_router, get_refresh_token_header, login_from_device,
+        login_with_email_and_password, register_new_user,
+    };
+    use crate::web::users::{LoginUserData, LoginUserDataBody};
+    use crate::web_service::tests::{
+        deserialize_response_body, get_with_auth_header, post, post_with_auth_header, TEST_MAILER,
+    };
+    use axum::body::Bytes;
+    use axum::Router;
+    use database::utils::random_samples::RandomSample;
+    use http_body::combinators::UnsyncBoxBody;
+
+    async fn change_password_request(
+        router: &Router,
+        token: &str,
+        current_password: impl Into<String>,
+        new_password: impl Into<String>,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        let request_body = ChangePasswordRequestBody {
+            data: ChangePasswordData {
+                current_password: current_password.into(),
+                new_password: new_password.into(),
+            },
+        };
+
+        post_with_auth_header(router, "/api/user/password", &request_body, Some(token)).await
+    }
+
+    async fn change_email_request(
+        router: &Router,
+        token: &str,
+        password: impl Into<String>,
+        new_email: impl Into<String>,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        let request_body = ChangeEmailRequestBody {
+            data: ChangeEmailData {
+                password: password.into(),
+                new_email: new_email.into(),
+            },
+        };
+
+        post_with_auth_header(router, "/api/user/email", &request_body, Some(token)).await
+    }
+
+    async fn confirm_email_change_request(
+        router: &Router,
+        token: impl Into<String>,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        let request_body = ConfirmEmailChangeRequestBody {
+            data: ConfirmEmailChangeData {
+                token: token.into(),
+            },
+        };
+
+        post(router, "/api/user/email/confirm", &request_body).await
+    }
+
+    #[tokio::test]
+    async fn should_lock_an_account_after_wrong_current_passwords() {
+        let router = create_test_router().await;
+        let (request, access_token) = register_verified_user().await;
+        let threshold = RateLimits::from_env().lockout.threshold;
+
+        for _ in 0..threshold {
+            let response =
+                change_password_request(&router, &access_token, "wrong password", "password").await;
+            assert_eq!(response.status(), 403);
+        }
+
+        let response =
+            change_password_request(&router, &access_token, request.password(), "password").await;
+        assert_eq!(response.status(), 429);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.error, ACCOUNT_LOCKED_ERROR_MSG);
+
+        let login_body =
+            LoginUserDataBody::new(LoginUserData::new(request.email(), request.password()));
+        let response = post(&router, "/api/user/login", &login_body).await;
+        assert_eq!(response.status(), 429);
+    }
+
+    #[tokio::test]
+    async fn should_change_password_and_revoke_other_sessions() {
+        let router = create_test_router().await;
+        let (request, access_token) = register_verified_user().await;
+        let response = login_from_device(&request, None).await;
+        let other_refresh_token = get_refresh_token_header(&response);
+        let api_key = create_api_key(&router, &access_token, vec![Scope::ProjectsRead]).await;
+
+        let response =
+            change_password_request(&router, &access_token, "wrong password", "password").await;
+        assert_eq!(response.status(), 403);
+
+        let password = String::new_random(32);
+        let response =
+            change_password_request(&router, &access_token, request.password(), &password).await;
+        assert_eq!(response.status(), 204);
+
+        let response =
+            get_with_auth_header(&router, "/api/user/sessions", Some(&access_token)).await;
+        assert_eq!(response.status(), 200);
+
+        let response = refresh_tokens(&router, other_refresh_token).await;
+        assert_eq!(response.status(), 401);
+
+        let response = get_with_auth_header(&router, "/api/projects", Some(api_key.key())).await;
+        assert_eq!(response.status(), 200);
+
+        let response = login_with_email_and_password(
+            request.email().to_owned(),
+            request.password().to_owned(),
+        )
+        .await;
+        assert_eq!(response.status(), 401);
+
+        let response = login_with_email_and_password(request.email().to_owned(), password).await;
+        assert_eq!(response.status(), 202);
+    }
+
+    #[tokio::test]
+    async fn should_change_email_after_confirmation() {
+        let router = create_test_router().await;
+        let (request, access_token) = register_verified_user().await;
+
+        let new_email = std::format!("{}@test.test", String::new_random(16));
+        let response =
+            change_email_request(&router, &access_token, "wrong password", &new_email).await;
+        assert_eq!(response.status(), 403);
+        let response =
+            change_email_request(&router, &access_token, request.password(), "not an email").await;
+        assert_eq!(response.status(), 406);
+
+        let response =
+            change_email_request(&router, &access_token, request.password(), &new_email).await;
+        assert_eq!(response.status(), 202);
+
+        // Nothing changes until the link is followed
+        let response = login_with_email_and_password(
+            request.email().to_owned(),
+            request.password().to_owned(),
+        )
+        .await;
+        assert_eq!(response.status(), 202);
+
+        let token = get_mailed_token(&new_email);
+        let response = confirm_email_change_request(&router, &token).await;
+        assert_eq!(response.status(), 204);
+
+        let response = confirm_email_change_request(&router, &token).await;
+        assert_eq!(response.status(), 400);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.error, INVALID_EMAIL_CHANGE_TOKEN_ERROR_MSG);
+
+        let response =
+            login_with_email_and_password(new_email.clone(), request.password().to_owned()).await;
+        assert_eq!(response.status(), 202);
+        let response = login_with_email_and_password(
+            request.email().to_owned(),
+            request.password().to_owned(),
+        )
+        .await;
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn should_not_change_email_to_a_registered_one() {
+        let router = create_test_router().await;
+        let (request, access_token) = register_verified_user().await;
+        let (other_request, _) = register_new_user(None).await;
+
+        let response = change_email_request(
+            &router,
+            &access_token,
+            request.password(),
+            other_request.email(),
+        )
+        .await;
+        assert_eq!(response.status(), 202);
+
+        let token = get_mailed_token(other_request.email());
+        let response = confirm_email_change_request(&router, &token).await;
+        assert_eq!(response.status(), 409);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code, Some(ErrorCode::AlreadyRegistered));
+        assert!(!TEST_MAILER.emails_to(request.email()).is_empty());
+    }
+}
@@ -0,0 +1,319 @@
+use crate::mailer::{Email, Mailer, MailerError};
+use crate::models::chat_members::ChatMemberDb;
+use crate::models::chat_message::ChatMessageDb;
+use crate::models::chats::ChatDb;
+use crate::models::company::CompanyDb;
+use crate::models::errors::DbError;
+use crate::models::project::ProjectDb;
+use crate::models::user::UserDb;
+use crate::utils::tokens::{CreateAccessTokenError, EmailVerificationToken};
+use crate::web::authentication::{Authenticated, ScopeError};
+use crate::web::errors::{
+    create_bad_request_error, create_internal_server_error, INVALID_VERIFICATION_TOKEN_ERROR_MSG,
+};
+use crate::web_service::WebService;
+use axum::extract::rejection::JsonRejection;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug)]
+pub enum SendVerificationEmailError {
+    CreateToken(CreateAccessTokenError),
+    Mailer(MailerError),
+}
+
+/// Mails a link with a signed verification token to a given address
+pub async fn send_verification_email(
+    mailer: &dyn Mailer,
+    user_id: Uuid,
+    email: impl Into<String>,
+) -> Result<(), SendVerificationEmailError> {
+    let email = email.into();
+    let token = EmailVerificationToken {
+        user_id,
+        email: email.clone(),
+    }
+    .encode()
+    .map_err(SendVerificationEmailError::CreateToken)?;
+
+    let url = std::env::var("EMAIL_VERIFICATION_URL")
+        .expect("EMAIL_VERIFICATION_URL must be in environment");
+
+    mailer
+        .send(Email {
+            to: email,
+            subject: "Confirm your email".to_owned(),
+            body: std::format!("Please confirm your email by following the link: {url}{token}"),
+        })
+        .await
+        .map_err(SendVerificationEmailError::Mailer)
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct VerifyEmailData {
+    token: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct VerifyEmailRequestBody {
+    data: VerifyEmailData,
+}
+
+#[derive(Debug)]
+pub enum VerifyEmailErrorResponse {
+    DbError(DbError),
+    InvalidToken,
+    JsonRejection(JsonRejection),
+}
+
+impl IntoResponse for VerifyEmailErrorResponse {
+    fn into_response(self) -> Response {
+        match self {
+            VerifyEmailErrorResponse::DbError(db_error) => db_error.into_response(),
+            VerifyEmailErrorResponse::InvalidToken => {
+                create_bad_request_error(INVALID_VERIFICATION_TOKEN_ERROR_MSG.into())
+                    .into_response()
+            }
+            VerifyEmailErrorResponse::JsonRejection(error) => {
+                create_bad_request_error(error.to_string()).into_response()
+            }
+        }
+    }
+}
+
+/// Confirms an email with a mailed token
+///
+/// A token works once and only for the address it was sent to. Access tokens minted
+/// before the verification keep their claim, clients refresh them afterwards.
+#[tracing::instrument(skip(web_service, body_or_error))]
+pub async fn verify<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    body_or_error: Result<Json<VerifyEmailRequestBody>, JsonRejection>,
+) -> Result<StatusCode, VerifyEmailErrorResponse> {
+    let Json(body) = body_or_error.map_err(VerifyEmailErrorResponse::JsonRejection)?;
+
+    let token = EmailVerificationToken::from_token(&body.data.token)
+        .map_err(|_| VerifyEmailErrorResponse::InvalidToken)?;
+
+    let verified = web_service
+        .user_db
+        .verify_email(&token.user_id, token.email)
+        .await
+        .map_err(VerifyEmailErrorResponse::DbError)?;
+    if verified == 0 {
+        return Err(VerifyEmailErrorResponse::InvalidToken);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug)]
+pub enum ResendVerificationEmailErrorResponse {
+    Forbidden(ScopeError),
+    DbError(DbError),
+    AlreadyVerified,
+    SendVerificationEmail(SendVerificationEmailError),
+}
+
+impl IntoResponse for ResendVerificationEmailErrorResponse {
+    fn into_response(self) -> Response {
+        match self {
+            ResendVerificationEmailErrorResponse::Forbidden(error) => error.into_response(),
+            ResendVerificationEmailErrorResponse::DbError(db_error) => db_error.into_response(),
+            ResendVerificationEmailErrorResponse::AlreadyVerified => {
+                create_bad_request_error("Email is already verified".to_owned()).into_response()
+            }
+            ResendVerificationEmailErrorResponse::SendVerificationEmail(error) => {
+                create_internal_server_error(std::format!(
+                    "Can not send a verification email: {:?}",
+                    error
+                ))
+                .into_response()
+            }
+        }
+    }
+}
+
+/// Mails another verification link to the current user
+///
+#[tracing::instrument(skip(web_service))]
+pub async fn resend<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authenticated: Authenticated,
+) -> Result<StatusCode, ResendVerificationEmailErrorResponse> {
+    let user_info = authenticated
+        .require_user()
+        .map_err(ResendVerificationEmailErrorResponse::Forbidden)?;
+
+    let user = web_service
+        .user_db
+        .get_user(&user_info.user_id)
+        .await
+        .map_err(ResendVerificationEmailErrorResponse::DbError)?;
+    if user.email_verified_at.is_some() {
+        return Err(ResendVerificationEmailErrorResponse::AlreadyVerified);
+    }
+
+    send_verification_email(web_service.mailer.as_ref(), user.id, user.email)
+        .await
+        .map_err(ResendVerificationEmailErrorResponse::SendVerificationEmail)?;
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::utils::tokens::AccessToken;
+    use crate::web::email_verification::{VerifyEmailData, VerifyEmailRequestBody};
+    use crate::web::errors::{EMAIL_NOT_VERIFIED_ERROR_MSG, INVALID_VERIFICATION_TOKEN_ERROR_MSG};
+    use crate::web::tokens::tests::refresh_tokens;
+    use crate::web::users::tests::{
+        create_test_router, get_auth_header_for_name, get_refresh_token_header, register_new_user,
+    };
+    use crate::web::users::RegisterUserData;
+    use crate::web_service::tests::{
+        deserialize_response_body, get_with_auth_header, patch_with_auth_header, post,
+        post_with_auth_header, TEST_MAILER,
+    };
+    use crate::web_service::{ErrorCode, ErrorResponseBody};
+    use axum::body::Bytes;
+    use axum::Router;
+    use http_body::combinators::UnsyncBoxBody;
+
+    /// Token from the last link mailed to a given address
+    pub fn get_mailed_token(email: &str) -> String {
+        let email = TEST_MAILER
+            .emails_to(email)
+            .pop()
+            .expect("an email is sent");
+
+        email
+            .body
+            .rsplit("token=")
+            .next()
+            .expect("a link with a token")
+            .to_owned()
+    }
+
+    pub async fn verify_email(
+        router: &Router,
+        token: impl Into<String>,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        let request_body = VerifyEmailRequestBody {
+            data: VerifyEmailData {
+                token: token.into(),
+            },
+        };
+
+        post(router, "/api/user/verify-email", &request_body).await
+    }
+
+    /// Registers a user and verifies its email, returns a fresh access token
+    pub async fn register_verified_user() -> (RegisterUserData, String) {
+        let router = create_test_router().await;
+
+        let (request, response) = register_new_user(None).await;
+        let refresh_token = get_refresh_token_header(&response);
+
+        let response = verify_email(&router, get_mailed_token(request.email())).await;
+        assert_eq!(response.status(), 204);
+
+        let response = refresh_tokens(&router, refresh_token).await;
+        let access_token = get_auth_header_for_name(&response);
+
+        (request, access_token)
+    }
+
+    #[tokio::test]
+    async fn should_verify_an_email_once() {
+        let router = create_test_router().await;
+
+        let (request, response) = register_new_user(None).await;
+        let access_token = get_auth_header_for_name(&response);
+        let refresh_token = get_refresh_token_header(&response);
+
+        let access_token = AccessToken::from_token(access_token).expect("valid token");
+        assert!(!access_token.get_user().email_verified);
+
+        let token = get_mailed_token(request.email());
+
+        let response = verify_email(&router, &token).await;
+        assert_eq!(response.status(), 204);
+
+        let response = verify_email(&router, &token).await;
+        assert_eq!(response.status(), 400);
+
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.error, INVALID_VERIFICATION_TOKEN_ERROR_MSG);
+
+        // Refreshed tokens carry the new state
+        let response = refresh_tokens(&router, refresh_token).await;
+        let access_token = get_auth_header_for_name(&response);
+        let access_token = AccessToken::from_token(access_token).expect("valid token");
+        assert!(access_token.get_user().email_verified);
+    }
+
+    #[tokio::test]
+    async fn should_reject_an_access_token_as_a_verification_token() {
+        let router = create_test_router().await;
+
+        let (_, response) = register_new_user(None).await;
+        let access_token = get_auth_header_for_name(&response);
+
+        let response = verify_email(&router, access_token).await;
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn should_limit_unverified_users_to_allowed_routes() {
+        let router = create_test_router().await;
+
+        let (request, response) = register_new_user(None).await;
+        let access_token = get_auth_header_for_name(&response);
+
+        let response =
+            get_with_auth_header(&router, "/api/user/sessions", Some(&access_token)).await;
+        assert_eq!(response.status(), 200);
+
+        let uri = "/api/project/new";
+        let response = post_with_auth_header(&router, uri, &(), Some(&access_token)).await;
+        assert_eq!(response.status(), 403);
+
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.error, EMAIL_NOT_VERIFIED_ERROR_MSG);
+        assert_eq!(response_body.code, Some(ErrorCode::EmailNotVerified));
+
+        let uri = "/api/user/verify-email/resend";
+        let response = post_with_auth_header(&router, uri, &(), Some(&access_token)).await;
+        assert_eq!(response.status(), 202);
+        assert_eq!(TEST_MAILER.emails_to(request.email()).len(), 2);
+
+        let response = get_with_auth_header(&router, "/api/user/me", Some(&access_token)).await;
+        assert_eq!(response.status(), 200);
+
+        let response =
+            patch_with_auth_header(&router, "/api/user/me", &(), Some(&access_token)).await;
+        assert_eq!(response.status(), 403);
+
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code, Some(ErrorCode::EmailNotVerified));
+    }
+}
@@ -10,7 +10,45 @@ pub const INTERNAL_SERVER_ERROR_MSG: &str = "Internal server error";
 pub const SERVICE_UNAVAILABLE_MSG: &str = "Service unavailable";
 const NOT_FOUND_ERROR_MSG: &str = "Not found";
 pub const INVALID_TOKEN_FORMAT_ERROR_MSG: &str = "Invalid token format";
+pub const TOKEN_EXPIRED_ERROR_MSG: &str = "Access token is expired, please refresh it";
+pub const TOKEN_REVOKED_ERROR_MSG: &str = "Access token is revoked, please login again";
+pub const INVALID_REFRESH_TOKEN_ERROR_MSG: &str = "Invalid refresh token, please login again";
 pub const UNAUTHORIZED_ERROR_MSG: &str = "Unauthorized, please try to login again";
+pub const INVALID_VERIFICATION_TOKEN_ERROR_MSG: &str = "Invalid or expired verification token";
+pub const INVALID_PASSWORD_RESET_TOKEN_ERROR_MSG: &str = "Invalid or expired password reset token";
+pub const INVALID_EMAIL_CHANGE_TOKEN_ERROR_MSG: &str = "Invalid or expired email change token";
+pub const WRONG_PASSWORD_ERROR_MSG: &str = "The current password is wrong";
+pub const INVALID_MFA_TOKEN_ERROR_MSG: &str = "Invalid or expired MFA token, please login again";
+pub const INVALID_MFA_CODE_ERROR_MSG: &str = "Invalid authentication code";
+pub const INVALID_OIDC_STATE_ERROR_MSG: &str = "Invalid or expired sign in, please try again";
+pub const INVALID_API_KEY_ERROR_MSG: &str = "Invalid or revoked API key";
+pub const MISSING_SCOPE_ERROR_MSG: &str = "The API key is missing a scope";
+pub const API_KEY_NOT_ALLOWED_ERROR_MSG: &str = "Please sign in, API keys can not be used here";
+pub const FORBIDDEN_ERROR_MSG: &str = "You are not allowed to do this";
+pub const TOO_MANY_REQUESTS_ERROR_MSG: &str = "Too many requests, please try again later";
+pub const ACCOUNT_LOCKED_ERROR_MSG: &str =
+    "Too many failed logins, the account is locked for a while";
+pub const ALREADY_TAKEN_ERROR_MSG: &str = "The value is already taken";
+pub const EMAIL_NOT_VERIFIED_ERROR_MSG: &str = "Please verify your email first";
+pub const MISSING_FILE_ERROR_MSG: &str = "Please send the image as a multipart `file` field";
+pub const FILE_TOO_LARGE_ERROR_MSG: &str = "The file is too large";
+pub const BODY_TOO_LARGE_ERROR_MSG: &str = "The request body is too large";
+pub const UNSUPPORTED_MEDIA_TYPE_ERROR_MSG: &str =
+    "Only PNG, JPEG, GIF and WebP images are accepted";
+pub const INVALID_IMAGE_ERROR_MSG: &str = "The file is not a valid image of its type";
+pub const PRECONDITION_REQUIRED_ERROR_MSG: &str = "Please send the ETag you have as If-Match";
+pub const ALREADY_PROJECT_MEMBER_ERROR_MSG: &str = "The user is already a member of the project";
+pub const PROJECT_OWNER_ERROR_MSG: &str = "The owner of the project can not be changed or removed";
+pub const INVALID_PROJECT_INVITATION_ERROR_MSG: &str = "Invalid or expired project invitation";
+pub const ALREADY_COMPANY_MEMBER_ERROR_MSG: &str = "The user is already a member of the company";
+pub const COMPANY_LAST_OWNER_ERROR_MSG: &str =
+    "The last owner of the company can not leave it or give up the role";
+pub const INVALID_COMPANY_INVITATION_ERROR_MSG: &str = "Invalid or expired company invitation";
+pub const CHAT_ALIAS_REQUIRED_ERROR_MSG: &str = "Chat members need an alias, please set one first";
+pub const ALREADY_CHAT_MEMBER_ERROR_MSG: &str = "The user is already a member of the chat";
+pub const CHAT_CREATOR_ERROR_MSG: &str = "The creator of the chat can not be changed or removed";
+pub const PRIVATE_CHAT_MEMBERS_ERROR_MSG: &str = "A private chat is for exactly two people";
+pub const PRECONDITION_FAILED_ERROR_MSG: &str = "It has changed in the meantime, please reload it";
 
 type ErrorResponseType = (StatusCode, Json<ErrorResponseBody>);
 
@@ -78,6 +116,13 @@ impl IntoResponse for DbError {
                     error: SERVICE_UNAVAILABLE_MSG.into(),
                 }),
             ),
+            DbError::UniqueViolation(constraint) => (
+                StatusCode::CONFLICT,
+                Json(ErrorResponseBody {
+                    code: Some(ErrorCode::AlreadyTaken),
+                    error: std::format!("{ALREADY_TAKEN_ERROR_MSG}: {constraint}"),
+                }),
+            ),
             DbError::UnexpectedError(error) => {
                 tracing::error!(error);
                 INTERNAL_SERVER_ERROR_RESPONSE.to_owned()
@@ -0,0 +1,770 @@
+use crate::event_hub::{EventHub, HubEvent};
+use crate::models::chat_members::ChatMemberDb;
+use crate::models::chat_message::ChatMessageDb;
+use crate::models::chats::ChatDb;
+use crate::models::company::CompanyDb;
+use crate::models::errors::DbError;
+use crate::models::project::ProjectDb;
+use crate::models::user::UserDb;
+use crate::utils::api_keys::Scope;
+use crate::web::authentication::Authenticated;
+use crate::web::chat_messages::ChatMessageResponseData;
+use crate::web::chats::ChatErrorResponse;
+use crate::web_service::WebService;
+use axum::extract::rejection::QueryRejection;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, State};
+use axum::http::HeaderMap;
+use axum::response::sse::{self, KeepAlive, Sse};
+use axum::response::Response;
+use database::events::{Event, EventType};
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Receiver;
+use tokio::time::{Instant, Interval, MissedTickBehavior};
+use uuid::Uuid;
+
+const REPLAY_PAGE_SIZE: i64 = 100;
+/// How often an idle event stream gets a comment, so that proxies do not close it
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+const LAST_EVENT_ID: &str = "last-event-id";
+/// How often a stream checks that its credential has not expired, revocations are announced
+const CREDENTIAL_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventTypeParameter {
+    MessageCreated,
+    MessageUpdated,
+    MessageDeleted,
+    Read,
+    MemberAdded,
+    MemberUpdated,
+    MemberRemoved,
+    Typing,
+    ProjectCreated,
+    ProjectUpdated,
+    ProjectDeleted,
+    ProjectMemberAdded,
+    ProjectMemberUpdated,
+    ProjectMemberRemoved,
+}
+
+impl From<EventType> for EventTypeParameter {
+    fn from(value: EventType) -> Self {
+        match value {
+            EventType::MessageCreated => EventTypeParameter::MessageCreated,
+            EventType::MessageUpdated => EventTypeParameter::MessageUpdated,
+            EventType::MessageDeleted => EventTypeParameter::MessageDeleted,
+            EventType::Read => EventTypeParameter::Read,
+            EventType::MemberAdded => EventTypeParameter::MemberAdded,
+            EventType::MemberUpdated => EventTypeParameter::MemberUpdated,
+            EventType::MemberRemoved => EventTypeParameter::MemberRemoved,
+            EventType::ProjectCreated => EventTypeParameter::ProjectCreated,
+            EventType::ProjectUpdated => EventTypeParameter::ProjectUpdated,
+            EventType::ProjectDeleted => EventTypeParameter::ProjectDeleted,
+            EventType::ProjectMemberAdded => EventTypeParameter::ProjectMemberAdded,
+            EventType::ProjectMemberUpdated => EventTypeParameter::ProjectMemberUpdated,
+            EventType::ProjectMemberRemoved => EventTypeParameter::ProjectMemberRemoved,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct EventData {
+    /// Cursor to resume from, typing indicators have none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    pub r#type: EventTypeParameter,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chat_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub project_id: Option<Uuid>,
+    /// Sender of a message, reader, member the event is about or who changed a project
+    pub user_id: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<Uuid>,
+    /// The message as it is now, for events about one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<ChatMessageResponseData>,
+}
+
+impl From<&Event> for EventData {
+    fn from(value: &Event) -> Self {
+        EventData {
+            id: Some(value.id),
+            r#type: value.event_type.into(),
+            chat_id: value.chat_id,
+            project_id: value.project_id,
+            user_id: value.user_id,
+            message_id: value.message_id,
+            message: value.message.clone().map(Into::into),
+        }
+    }
+}
+
+/// Which events a request gets, API keys only get the ones of their read scopes
+#[derive(Debug, Clone, Copy)]
+pub struct EventScopes {
+    chats: bool,
+    projects: bool,
+}
+
+impl EventScopes {
+    /// Scopes of the user, at least one of the read scopes is required
+    fn authorize(authenticated: &Authenticated) -> Result<Self, ChatErrorResponse> {
+        let chats = authenticated.require_scope(Scope::ChatsRead);
+        let projects = authenticated.require_scope(Scope::ProjectsRead);
+        let scopes = EventScopes {
+            chats: chats.is_ok(),
+            projects: projects.is_ok(),
+        };
+        chats
+            .or_else(|error| projects.map_err(|_| error))
+            .map_err(ChatErrorResponse::Forbidden)?;
+
+        Ok(scopes)
+    }
+}
+
+/// Events of the user's chats and projects, the ones after a cursor are replayed first
+///
+/// A connection that falls behind the hub replays what it has missed from the database too.
+/// It ends once the credential it was opened with expires or is revoked.
+pub struct EventSubscription {
+    hub: Arc<EventHub>,
+    receiver: Receiver<HubEvent>,
+    authenticated: Authenticated,
+    credential_check: Interval,
+    user_id: Uuid,
+    scopes: EventScopes,
+    chat_ids: HashSet<Uuid>,
+    /// Id of the last event delivered
+    cursor: i64,
+    /// Events up to this id were delivered from the database, the hub may send them again
+    replayed_until: i64,
+    replaying: bool,
+    backlog: VecDeque<EventData>,
+}
+
+impl EventSubscription {
+    pub async fn new(
+        hub: Arc<EventHub>,
+        chat_db: &impl ChatDb,
+        authenticated: Authenticated,
+        scopes: EventScopes,
+        cursor: Option<i64>,
+    ) -> Result<Self, DbError> {
+        // Subscribed first, so that nothing is missed while the rest is loaded
+        let receiver = hub.subscribe();
+        let user_id = authenticated.user().user_id;
+        let mut credential_check = tokio::time::interval_at(
+            Instant::now() + CREDENTIAL_CHECK_INTERVAL,
+            CREDENTIAL_CHECK_INTERVAL,
+        );
+        credential_check.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let chat_ids = chat_db
+            .get_user_chats(user_id)
+            .await?
+            .into_iter()
+            .map(|chat| chat.id)
+            .collect();
+        let (cursor, replaying) = match cursor {
+            Some(cursor) => (cursor, true),
+            None => (hub.get_last_event_id().await?, false),
+        };
+
+        Ok(Self {
+            hub,
+            receiver,
+            authenticated,
+            credential_check,
+            user_id,
+            scopes,
+            chat_ids,
+            cursor,
+            replayed_until: cursor,
+            replaying,
+            backlog: VecDeque::new(),
+        })
+    }
+
+    pub fn user_id(&self) -> Uuid {
+        self.user_id
+    }
+
+    pub fn is_member(&self, chat_id: Uuid) -> bool {
+        self.scopes.chats && self.chat_ids.contains(&chat_id)
+    }
+
+    /// The next event for the user, `None` once the hub has stopped or the credential is revoked
+    ///
+    /// Nothing is lost when the returned future is dropped before it completes.
+    pub async fn next(&mut self, user_db: &impl UserDb) -> Result<Option<EventData>, DbError> {
+        loop {
+            if let Some(event) = self.backlog.pop_front() {
+                return Ok(Some(event));
+            }
+            if self.replaying {
+                self.replay_page().await?;
+                continue;
+            }
+
+            let user_id = self.user_id;
+            let received = tokio::select! {
+                received = self.receiver.recv() => received,
+                _ = self.credential_check.tick() => Ok(HubEvent::Revoked { user_id }),
+            };
+            match received {
+                Ok(HubEvent::Chat(event)) => {
+                    if event.id > self.replayed_until && self.accept(&event, None) {
+                        return Ok(Some(event.as_ref().into()));
+                    }
+                }
+                Ok(HubEvent::Project { event, audience }) => {
+                    if event.id > self.replayed_until && self.accept(&event, Some(&audience)) {
+                        return Ok(Some(event.as_ref().into()));
+                    }
+                }
+                Ok(HubEvent::Typing { chat_id, user_id }) => {
+                    if user_id != self.user_id && self.is_member(chat_id) {
+                        return Ok(Some(EventData {
+                            id: None,
+                            r#type: EventTypeParameter::Typing,
+                            chat_id: Some(chat_id),
+                            project_id: None,
+                            user_id,
+                            message_id: None,
+                            message: None,
+                        }));
+                    }
+                }
+                Ok(HubEvent::Revoked { user_id }) => {
+                    if user_id == self.user_id && self.authenticated.is_revoked(user_db).await? {
+                        return Ok(None);
+                    }
+                }
+                Err(RecvError::Lagged(_)) => self.replaying = true,
+                Err(RecvError::Closed) => return Ok(None),
+            }
+        }
+    }
+
+    async fn replay_page(&mut self) -> Result<(), DbError> {
+        // Pages the user gets nothing from still move the replay forward
+        let after = self.cursor.max(self.replayed_until);
+        let events = self
+            .hub
+            .get_user_events(self.user_id, after, REPLAY_PAGE_SIZE)
+            .await?;
+
+        self.replaying = events.len() as i64 == REPLAY_PAGE_SIZE;
+        for event in events {
+            self.replayed_until = self.replayed_until.max(event.id);
+            if self.accept(&event, None) {
+                self.backlog.push_back((&event).into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether an event is for the user, keeps track of the chats they join and leave
+    ///
+    /// Project events of the database are the user's already, the hub sends them with
+    /// the users who see them.
+    fn accept(&mut self, event: &Event, audience: Option<&HashSet<Uuid>>) -> bool {
+        let about_user = event.user_id == self.user_id;
+        let accepted = match (event.chat_id, event.project_id) {
+            (Some(chat_id), _) => {
+                let about_membership = about_user && event.event_type.is_member_event();
+                if about_membership {
+                    if event.event_type == EventType::MemberRemoved {
+                        self.chat_ids.remove(&chat_id);
+                    } else {
+                        self.chat_ids.insert(chat_id);
+                    }
+                }
+                self.is_member(chat_id) || (self.scopes.chats && about_membership)
+            }
+            (None, Some(_)) => {
+                let in_audience = !matches!(audience, Some(x) if !x.contains(&self.user_id));
+                self.scopes.projects
+                    && (in_audience || (about_user && event.event_type.is_project_member_event()))
+            }
+            (None, None) => false,
+        };
+        if !accepted {
+            return false;
+        }
+
+        self.cursor = self.cursor.max(event.id);
+        true
+    }
+}
+
+/// What clients send over a socket, anything else is ignored
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChatClientEvent {
+    Typing { chat_id: Uuid },
+    Read { chat_id: Uuid, message_id: Uuid },
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct EventsQuery {
+    /// Id of the last event a client has got, the ones after it are replayed
+    cursor: Option<i64>,
+}
+
+/// Streams events of the user's chats and projects as server-sent events
+///
+/// A fallback for clients that can not open a WebSocket. Every event has its id, so a
+/// reconnecting `EventSource` resumes after the one in its `Last-Event-ID` header.
+/// Idle streams get a heartbeat comment.
+#[tracing::instrument(skip(web_service))]
+pub async fn get<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authenticated: Authenticated,
+    headers: HeaderMap,
+    query_or_error: Result<Query<EventsQuery>, QueryRejection>,
+) -> Result<Sse<impl Stream<Item = Result<sse::Event, serde_json::Error>>>, ChatErrorResponse> {
+    let scopes = EventScopes::authorize(&authenticated)?;
+    let Query(query) = query_or_error
+        .map_err(|x| x.to_string())
+        .map_err(ChatErrorResponse::InvalidInputDataFormat)?;
+    let last_event_id = headers
+        .get(LAST_EVENT_ID)
+        .map(|x| x.to_str().ok().and_then(|x| x.parse().ok()))
+        .map(|x| {
+            x.ok_or_else(|| {
+                ChatErrorResponse::InvalidInputDataFormat("Invalid Last-Event-ID".to_string())
+            })
+        })
+        .transpose()?;
+
+    let subscription = EventSubscription::new(
+        web_service.event_hub.clone(),
+        &web_service.chat_db,
+        authenticated,
+        scopes,
+        last_event_id.or(query.cursor),
+    )
+    .await
+    .map_err(ChatErrorResponse::DbError)?;
+
+    let state = (subscription, web_service.user_db);
+    let stream = futures::stream::unfold(state, |(mut subscription, user_db)| async move {
+        let event = match subscription.next(&user_db).await {
+            Ok(Some(event)) => event,
+            Ok(None) => return None,
+            Err(error) => {
+                tracing::error!("events error: {error:?}");
+                return None;
+            }
+        };
+        let sse_event = sse::Event::default()
+            .json_data(&event)
+            .map(|x| match event.id {
+                Some(id) => x.id(id.to_string()),
+                None => x,
+            });
+        Some((sse_event, (subscription, user_db)))
+    });
+
+    Ok(Sse::new(stream).keep_alive(
+        KeepAlive::new()
+            .interval(HEARTBEAT_INTERVAL)
+            .text("heartbeat"),
+    ))
+}
+
+/// Streams events of the user's chats and projects over a WebSocket
+///
+/// New, edited and deleted messages, typing indicators, read receipts, membership
+/// changes and project changes are sent as JSON text messages. Clients send typing
+/// indicators and read receipts the same way.
+#[tracing::instrument(skip(web_service, upgrade))]
+pub async fn get_socket<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authenticated: Authenticated,
+    query_or_error: Result<Query<EventsQuery>, QueryRejection>,
+    upgrade: WebSocketUpgrade,
+) -> Result<Response, ChatErrorResponse> {
+    let scopes = EventScopes::authorize(&authenticated)?;
+    let Query(query) = query_or_error
+        .map_err(|x| x.to_string())
+        .map_err(ChatErrorResponse::InvalidInputDataFormat)?;
+
+    let hub = web_service.event_hub.clone();
+    let subscription = EventSubscription::new(
+        hub.clone(),
+        &web_service.chat_db,
+        authenticated,
+        scopes,
+        query.cursor,
+    )
+    .await
+    .map_err(ChatErrorResponse::DbError)?;
+
+    let user_db = web_service.user_db;
+    Ok(upgrade.on_upgrade(move |socket| stream_events(socket, hub, subscription, user_db)))
+}
+
+async fn stream_events(
+    mut socket: WebSocket,
+    hub: Arc<EventHub>,
+    mut subscription: EventSubscription,
+    user_db: impl UserDb,
+) {
+    loop {
+        tokio::select! {
+            event = subscription.next(&user_db) => {
+                let event = match event {
+                    Ok(Some(event)) => event,
+                    Ok(None) => break,
+                    Err(error) => {
+                        tracing::error!("events error: {error:?}");
+                        break;
+                    }
+                };
+                let text = serde_json::to_string(&event).expect("event is serialized");
+                if socket.send(Message::Text(text)).await.is_err() {
+                    break;
+                }
+            }
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    receive_client_event(&hub, &subscription, &text).await;
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            }
+        }
+    }
+}
+
+async fn receive_client_event(hub: &EventHub, subscription: &EventSubscription, text: &str) {
+    let user_id = subscription.user_id();
+    let result = match serde_json::from_str(text) {
+        Ok(ChatClientEvent::Typing { chat_id }) if subscription.is_member(chat_id) => {
+            hub.send_typing(chat_id, user_id).await
+        }
+        Ok(ChatClientEvent::Read {
+            chat_id,
+            message_id,
+        }) if subscription.is_member(chat_id) => hub
+            .mark_read(chat_id, user_id, message_id)
+            .await
+            .map(|_| ()),
+        _ => Ok(()),
+    };
+
+    if let Err(error) = result {
+        tracing::error!("chat client event error: {error:?}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web::api_keys::tests::create_api_key;
+    use crate::web::chat_messages::tests::post_message_with_token;
+    use crate::web::chats::tests::{create_chat_with_token, register_chat_user};
+    use crate::web::chats::ChatTypeParameter;
+    use crate::web::projects::tests::create_project_with_token;
+    use crate::web::users::tests::create_test_router;
+    use crate::web_service::tests::{delete_with_auth_header, post_with_auth_header, send_request};
+    use axum::body::{Bytes, HttpBody};
+    use axum::http::{Method, Request};
+    use axum::Router;
+    use futures::{SinkExt, StreamExt};
+    use http_body::combinators::UnsyncBoxBody;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+    use tokio::net::TcpStream;
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+    use tokio_tungstenite::tungstenite::Message as ClientMessage;
+    use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+    type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    async fn serve(router: Router) -> SocketAddr {
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(router.into_make_service_with_connect_info::<SocketAddr>());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    async fn connect(addr: SocketAddr, token: &str, cursor: Option<i64>) -> Socket {
+        let query = cursor
+            .map(|x| std::format!("?cursor={x}"))
+            .unwrap_or_default();
+        let mut request = std::format!("ws://{addr}/api/ws{query}")
+            .into_client_request()
+            .expect("valid request");
+        request.headers_mut().insert(
+            "authorization",
+            std::format!("Bearer {token}")
+                .parse()
+                .expect("valid header"),
+        );
+
+        tokio_tungstenite::connect_async(request)
+            .await
+            .expect("socket is connected")
+            .0
+    }
+
+    async fn next_event(socket: &mut Socket) -> EventData {
+        loop {
+            let message = tokio::time::timeout(Duration::from_secs(5), socket.next())
+                .await
+                .expect("an event in time")
+                .expect("open socket")
+                .expect("valid message");
+            if let ClientMessage::Text(text) = message {
+                return serde_json::from_str(&text).expect("event");
+            }
+        }
+    }
+
+    async fn send(socket: &mut Socket, event: ChatClientEvent) {
+        let text = serde_json::to_string(&event).expect("client event is serialized");
+        socket
+            .send(ClientMessage::Text(text))
+            .await
+            .expect("client event is sent");
+    }
+
+    type EventStream = UnsyncBoxBody<Bytes, axum::Error>;
+
+    async fn open_event_stream(
+        router: &Router,
+        token: &str,
+        last_event_id: Option<&str>,
+    ) -> hyper::Response<EventStream> {
+        let mut request = Request::builder()
+            .method(Method::GET)
+            .uri("/api/events")
+            .header("Authorization", std::format!("Bearer {token}"));
+        if let Some(last_event_id) = last_event_id {
+            request = request.header(LAST_EVENT_ID, last_event_id);
+        }
+        let request = request
+            .body(hyper::Body::empty())
+            .expect("failed to build GET request");
+
+        send_request(router, request).await
+    }
+
+    /// Reads the next event of a stream, skipping comments
+    async fn next_server_sent_event(stream: &mut EventStream) -> (Option<i64>, EventData) {
+        let mut buffer = String::new();
+        loop {
+            while let Some(end) = buffer.find("\n\n") {
+                let block = buffer[..end].to_string();
+                buffer.drain(..end + 2);
+
+                let mut id = None;
+                let mut data = None;
+                for line in block.lines() {
+                    if let Some(value) = line.strip_prefix("id:") {
+                        id = Some(value.trim().parse().expect("numeric id"));
+                    } else if let Some(value) = line.strip_prefix("data:") {
+                        data = Some(serde_json::from_str(value.trim()).expect("event"));
+                    }
+                }
+                if let Some(data) = data {
+                    return (id, data);
+                }
+            }
+
+            let chunk = tokio::time::timeout(Duration::from_secs(5), stream.data())
+                .await
+                .expect("an event in time")
+                .expect("open stream")
+                .expect("valid chunk");
+            buffer.push_str(std::str::from_utf8(&chunk).expect("utf-8 text"));
+        }
+    }
+
+    #[tokio::test]
+    async fn should_push_chat_events_and_replay_missed_ones() {
+        let router = create_test_router().await;
+        let addr = serve(router.clone()).await;
+        let (user_id, token) = register_chat_user(&router).await;
+        let (other_id, other_token) = register_chat_user(&router).await;
+
+        let chat_id =
+            create_chat_with_token(&router, &token, ChatTypeParameter::Group, vec![other_id]).await;
+        let mut socket = connect(addr, &token, None).await;
+        let mut other_socket = connect(addr, &other_token, None).await;
+
+        let message_id = post_message_with_token(&router, chat_id, "hello", &token).await;
+        for socket in [&mut socket, &mut other_socket] {
+            let event = next_event(socket).await;
+            assert_eq!(event.r#type, EventTypeParameter::MessageCreated);
+            assert_eq!(event.chat_id, Some(chat_id));
+            assert_eq!(event.user_id, user_id);
+            assert_eq!(event.message_id, Some(message_id));
+            assert!(event.message.is_some());
+        }
+
+        send(&mut other_socket, ChatClientEvent::Typing { chat_id }).await;
+        let event = next_event(&mut socket).await;
+        assert_eq!(event.r#type, EventTypeParameter::Typing);
+        assert_eq!((event.id, event.user_id), (None, other_id));
+
+        send(
+            &mut other_socket,
+            ChatClientEvent::Read {
+                chat_id,
+                message_id,
+            },
+        )
+        .await;
+        let event = next_event(&mut socket).await;
+        assert_eq!(event.r#type, EventTypeParameter::Read);
+        assert_eq!(event.user_id, other_id);
+        let cursor = next_event(&mut other_socket).await.id;
+        assert_eq!(cursor, event.id);
+
+        other_socket.close(None).await.expect("socket is closed");
+        let missed_id = post_message_with_token(&router, chat_id, "missed", &token).await;
+
+        let mut other_socket = connect(addr, &other_token, cursor).await;
+        let event = next_event(&mut other_socket).await;
+        assert_eq!(event.r#type, EventTypeParameter::MessageCreated);
+        assert_eq!(event.message_id, Some(missed_id));
+    }
+
+    #[tokio::test]
+    async fn should_follow_membership_changes() {
+        let router = create_test_router().await;
+        let addr = serve(router.clone()).await;
+        let (_, token) = register_chat_user(&router).await;
+        let (other_id, other_token) = register_chat_user(&router).await;
+
+        let mut other_socket = connect(addr, &other_token, None).await;
+        let chat_id =
+            create_chat_with_token(&router, &token, ChatTypeParameter::Group, vec![other_id]).await;
+        let event = next_event(&mut other_socket).await;
+        assert_eq!(event.r#type, EventTypeParameter::MemberAdded);
+        assert_eq!((event.chat_id, event.user_id), (Some(chat_id), other_id));
+
+        let message_id = post_message_with_token(&router, chat_id, "welcome", &token).await;
+        let event = next_event(&mut other_socket).await;
+        assert_eq!(event.message_id, Some(message_id));
+    }
+
+    #[tokio::test]
+    async fn should_stream_server_sent_events_and_resume_after_the_last_one() {
+        let router = create_test_router().await;
+        let (user_id, token) = register_chat_user(&router).await;
+        let (other_id, _) = register_chat_user(&router).await;
+
+        let response = open_event_stream(&router, &token, None).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.headers().get("content-type").map(|x| x.as_bytes()),
+            Some("text/event-stream".as_bytes())
+        );
+        let mut stream = response.into_body();
+
+        let (_, project) = create_project_with_token(&router, &token).await;
+        let (id, event) = next_server_sent_event(&mut stream).await;
+        assert_eq!(event.r#type, EventTypeParameter::ProjectCreated);
+        assert_eq!(event.project_id, Some(project.project_id()));
+        assert_eq!(event.user_id, user_id);
+        assert_eq!(id, event.id);
+
+        let chat_id =
+            create_chat_with_token(&router, &token, ChatTypeParameter::Group, vec![other_id]).await;
+        let mut added = Vec::new();
+        for _ in 0..2 {
+            let (_, event) = next_server_sent_event(&mut stream).await;
+            assert_eq!(event.r#type, EventTypeParameter::MemberAdded);
+            assert_eq!(event.chat_id, Some(chat_id));
+            added.push(event.user_id);
+        }
+        added.sort();
+        let mut members = vec![user_id, other_id];
+        members.sort();
+        assert_eq!(added, members);
+
+        let message_id = post_message_with_token(&router, chat_id, "hello", &token).await;
+        let (last_event_id, event) = next_server_sent_event(&mut stream).await;
+        assert_eq!(event.r#type, EventTypeParameter::MessageCreated);
+        assert_eq!(event.message_id, Some(message_id));
+        drop(stream);
+
+        let missed_id = post_message_with_token(&router, chat_id, "missed", &token).await;
+        let last_event_id = last_event_id.expect("event id").to_string();
+        let response = open_event_stream(&router, &token, Some(&last_event_id)).await;
+        assert_eq!(response.status(), 200);
+        let (_, event) = next_server_sent_event(&mut response.into_body()).await;
+        assert_eq!(event.r#type, EventTypeParameter::MessageCreated);
+        assert_eq!(event.message_id, Some(missed_id));
+    }
+
+    #[tokio::test]
+    async fn should_end_streams_once_their_credential_is_revoked() {
+        let router = create_test_router().await;
+        let addr = serve(router.clone()).await;
+        let (_, token) = register_chat_user(&router).await;
+
+        let api_key = create_api_key(&router, &token, vec![Scope::ChatsRead]).await;
+        let response = open_event_stream(&router, api_key.key(), None).await;
+        assert_eq!(response.status(), 200);
+        let mut stream = response.into_body();
+        let uri = std::format!("/api/user/api-keys/{}", api_key.id());
+        let response = delete_with_auth_header(&router, &uri, Some(&token)).await;
+        assert_eq!(response.status(), 204);
+        let chunk = tokio::time::timeout(Duration::from_secs(5), stream.data())
+            .await
+            .expect("the stream ends in time");
+        assert!(chunk.is_none());
+
+        let mut socket = connect(addr, &token, None).await;
+        let response = post_with_auth_header(&router, "/api/user/logout", &(), Some(&token)).await;
+        assert_eq!(response.status(), 204);
+        loop {
+            let message = tokio::time::timeout(Duration::from_secs(5), socket.next())
+                .await
+                .expect("the socket is closed in time");
+            match message {
+                Some(Ok(ClientMessage::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn should_reject_an_invalid_last_event_id() {
+        let router = create_test_router().await;
+        let (_, token) = register_chat_user(&router).await;
+
+        let response = open_event_stream(&router, &token, Some("latest")).await;
+        assert_eq!(response.status(), 400);
+    }
+}
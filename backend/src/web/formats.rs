@@ -57,6 +57,15 @@ impl Serialize for JsonDateTime {
     }
 }
 
+/// Tells a missing field, `None`, from a `null` one, `Some(None)`, use it with `#[serde(default)]`
+pub fn deserialize_nullable<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -72,4 +81,23 @@ mod tests {
 
         assert_eq!(deserialized_now, now);
     }
+
+    #[test]
+    fn test_deserialize_nullable() {
+        #[derive(Deserialize)]
+        struct Nullable {
+            #[serde(default, deserialize_with = "deserialize_nullable")]
+            value: Option<Option<String>>,
+        }
+
+        let parse = |json: &str| {
+            serde_json::from_str::<Nullable>(json)
+                .expect("valid json")
+                .value
+        };
+
+        assert_eq!(parse("{}"), None);
+        assert_eq!(parse(r#"{"value":null}"#), Some(None));
+        assert_eq!(parse(r#"{"value":"x"}"#), Some(Some("x".to_owned())));
+    }
 }
@@ -0,0 +1,30 @@
+use crate::utils::tokens::token_keys;
+use axum::Json;
+use jsonwebtoken::jwk::JwkSet;
+
+/// Publishes public keys for access token verification
+///
+#[tracing::instrument]
+pub async fn get() -> Json<JwkSet> {
+    Json(token_keys().jwk_set())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::web::users::tests::create_test_router;
+    use crate::web_service::tests::{deserialize_response_body, get};
+    use jsonwebtoken::jwk::JwkSet;
+
+    #[tokio::test]
+    async fn should_publish_only_public_keys() {
+        let router = create_test_router().await;
+
+        let response = get(&router, "/.well-known/jwks.json").await;
+        assert_eq!(response.status(), 200);
+
+        let jwk_set = deserialize_response_body::<JwkSet>(response).await;
+
+        assert!(!jwk_set.keys.is_empty());
+        assert!(jwk_set.keys.iter().all(|jwk| jwk.common.key_id.is_some()));
+    }
+}
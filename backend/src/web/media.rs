@@ -0,0 +1,432 @@
+use crate::blob_store::{Blob, BlobStore, BlobStoreError};
+use crate::models::chat_members::ChatMemberDb;
+use crate::models::chat_message::ChatMessageDb;
+use crate::models::chats::ChatDb;
+use crate::models::company::CompanyDb;
+use crate::models::errors::DbError;
+use crate::models::project::ProjectDb;
+use crate::models::user::UserDb;
+use crate::utils::images::{create_thumbnail, ImageError, ImageType};
+use crate::web::authentication::{Authenticated, ScopeError};
+use crate::web::errors::{
+    create_internal_server_error, FILE_TOO_LARGE_ERROR_MSG, INVALID_IMAGE_ERROR_MSG,
+    MISSING_FILE_ERROR_MSG, NOT_FOUND_RESPONSE, UNSUPPORTED_MEDIA_TYPE_ERROR_MSG,
+};
+use crate::web_service::{ErrorCode, ErrorResponseBody, WebService};
+use axum::extract::multipart::{MultipartError, MultipartRejection};
+use axum::extract::{DefaultBodyLimit, Multipart, Path, State};
+use axum::http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Name of the multipart field carrying an upload
+const FILE_FIELD: &str = "file";
+
+/// Room for the multipart boundaries and headers around a file
+const MULTIPART_OVERHEAD_IN_BYTES: usize = 16 * 1024;
+
+/// Keys never get reused, so blobs are cached for good
+const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+lazy_static! {
+    static ref AVATAR_MAX_SIZE_IN_BYTES: usize = std::env::var("AVATAR_MAX_SIZE_IN_BYTES")
+        .expect("AVATAR_MAX_SIZE_IN_BYTES must be in environment")
+        .parse::<usize>()
+        .expect("integer size");
+}
+
+#[derive(Debug)]
+pub enum MediaErrorResponse {
+    Forbidden(ScopeError),
+    DbError(DbError),
+    MultipartRejection(MultipartRejection),
+    Multipart(MultipartError),
+    MissingFile,
+    TooLarge,
+    UnsupportedType,
+    InvalidImage,
+    BlobStore(BlobStoreError),
+}
+
+impl IntoResponse for MediaErrorResponse {
+    fn into_response(self) -> Response {
+        let (status, error) = match self {
+            MediaErrorResponse::Forbidden(error) => return error.into_response(),
+            MediaErrorResponse::DbError(db_error) => return db_error.into_response(),
+            MediaErrorResponse::MultipartRejection(error) => return error.into_response(),
+            MediaErrorResponse::Multipart(error) => (error.status(), error.body_text()),
+            MediaErrorResponse::MissingFile => {
+                (StatusCode::BAD_REQUEST, MISSING_FILE_ERROR_MSG.to_owned())
+            }
+            MediaErrorResponse::TooLarge => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                FILE_TOO_LARGE_ERROR_MSG.to_owned(),
+            ),
+            MediaErrorResponse::UnsupportedType => (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                UNSUPPORTED_MEDIA_TYPE_ERROR_MSG.to_owned(),
+            ),
+            MediaErrorResponse::InvalidImage => {
+                (StatusCode::BAD_REQUEST, INVALID_IMAGE_ERROR_MSG.to_owned())
+            }
+            MediaErrorResponse::BlobStore(BlobStoreError::NotFound)
+            | MediaErrorResponse::BlobStore(BlobStoreError::InvalidKey(_)) => {
+                return NOT_FOUND_RESPONSE.to_owned().into_response()
+            }
+            MediaErrorResponse::BlobStore(error) => {
+                tracing::error!(?error, "blob store failed");
+                return create_internal_server_error(std::format!("{error:?}")).into_response();
+            }
+        };
+
+        (
+            status,
+            Json(ErrorResponseBody {
+                code: Some(ErrorCode::InvalidInput),
+                error,
+            }),
+        )
+            .into_response()
+    }
+}
+
+/// Lets avatar uploads through the default body limit of axum
+pub fn avatar_body_limit() -> DefaultBodyLimit {
+    DefaultBodyLimit::max(*AVATAR_MAX_SIZE_IN_BYTES + MULTIPART_OVERHEAD_IN_BYTES)
+}
+
+/// Where the thumbnail of a stored image lives
+pub fn thumbnail_key(key: &str) -> String {
+    let stem = key.rsplit_once('.').map_or(key, |(stem, _)| stem);
+    std::format!("{stem}.thumbnail.png")
+}
+
+struct Upload {
+    image_type: ImageType,
+    bytes: Vec<u8>,
+}
+
+/// Reads the `file` field, stops as soon as it grows past `max_size`
+async fn read_image_upload(
+    mut multipart: Multipart,
+    max_size: usize,
+) -> Result<Upload, MediaErrorResponse> {
+    while let Some(mut field) = multipart
+        .next_field()
+        .await
+        .map_err(MediaErrorResponse::Multipart)?
+    {
+        if field.name() != Some(FILE_FIELD) {
+            continue;
+        }
+
+        let image_type = field
+            .content_type()
+            .and_then(ImageType::from_content_type)
+            .ok_or(MediaErrorResponse::UnsupportedType)?;
+
+        let mut bytes = Vec::new();
+        while let Some(chunk) = field.chunk().await.map_err(MediaErrorResponse::Multipart)? {
+            if bytes.len() + chunk.len() > max_size {
+                return Err(MediaErrorResponse::TooLarge);
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+
+        return Ok(Upload { image_type, bytes });
+    }
+
+    Err(MediaErrorResponse::MissingFile)
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ImageResponseBody {
+    url: String,
+    thumbnail_url: String,
+}
+
+/// Stores an image and its thumbnail under `<prefix>/<random id>`
+async fn store_image(
+    blob_store: &dyn BlobStore,
+    prefix: String,
+    upload: Upload,
+) -> Result<ImageResponseBody, MediaErrorResponse> {
+    let Upload { image_type, bytes } = upload;
+    let (bytes, thumbnail) = tokio::task::spawn_blocking(move || {
+        let thumbnail = create_thumbnail(&bytes, image_type);
+        (bytes, thumbnail)
+    })
+    .await
+    .map_err(|error| {
+        tracing::error!(?error, "thumbnail task failed");
+        MediaErrorResponse::InvalidImage
+    })?;
+    let thumbnail = thumbnail.map_err(|error| match error {
+        ImageError::ContentMismatch | ImageError::Decode(_) => MediaErrorResponse::InvalidImage,
+        ImageError::Encode(error) => {
+            tracing::error!(?error, "failed to encode a thumbnail");
+            MediaErrorResponse::InvalidImage
+        }
+    })?;
+
+    let key = std::format!("{prefix}/{}.{}", Uuid::new_v4(), image_type.extension());
+    let thumbnail_key = thumbnail_key(&key);
+
+    blob_store
+        .put(
+            &key,
+            Blob {
+                content_type: image_type.content_type().to_owned(),
+                bytes,
+            },
+        )
+        .await
+        .map_err(MediaErrorResponse::BlobStore)?;
+    blob_store
+        .put(
+            &thumbnail_key,
+            Blob {
+                content_type: ImageType::Png.content_type().to_owned(),
+                bytes: thumbnail,
+            },
+        )
+        .await
+        .map_err(MediaErrorResponse::BlobStore)?;
+
+    Ok(ImageResponseBody {
+        url: blob_store.url(&key),
+        thumbnail_url: blob_store.url(&thumbnail_key),
+    })
+}
+
+/// Removes an image this service stored, URLs pointing elsewhere are left alone
+async fn delete_image(blob_store: &dyn BlobStore, url: &str) {
+    let Some(key) = blob_store.key_from_url(url) else {
+        return;
+    };
+
+    for key in [thumbnail_key(&key), key] {
+        if let Err(error) = blob_store.delete(&key).await {
+            tracing::warn!(?error, key, "failed to delete a replaced image");
+        }
+    }
+}
+
+/// Uploads an avatar of the current user as a multipart `file` field
+///
+/// The previous avatar is removed once the new one is saved.
+#[tracing::instrument(skip(web_service, multipart_or_error))]
+pub async fn put_user_avatar<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authenticated: Authenticated,
+    multipart_or_error: Result<Multipart, MultipartRejection>,
+) -> Result<Json<ImageResponseBody>, MediaErrorResponse> {
+    let user_id = authenticated
+        .require_user()
+        .map_err(MediaErrorResponse::Forbidden)?
+        .user_id;
+    let multipart = multipart_or_error.map_err(MediaErrorResponse::MultipartRejection)?;
+
+    let upload = read_image_upload(multipart, *AVATAR_MAX_SIZE_IN_BYTES).await?;
+    let image = store_image(
+        web_service.blob_store.as_ref(),
+        std::format!("avatars/users/{user_id}"),
+        upload,
+    )
+    .await?;
+
+    let replaced = web_service
+        .user_db
+        .update_user_avatar(&user_id, Some(image.url.clone()))
+        .await
+        .map_err(MediaErrorResponse::DbError)?;
+    if let Some(replaced) = replaced {
+        delete_image(web_service.blob_store.as_ref(), &replaced).await;
+    }
+
+    Ok(Json(image))
+}
+
+#[tracing::instrument(skip(web_service))]
+pub async fn delete_user_avatar<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authenticated: Authenticated,
+) -> Result<StatusCode, MediaErrorResponse> {
+    let user_id = authenticated
+        .require_user()
+        .map_err(MediaErrorResponse::Forbidden)?
+        .user_id;
+
+    let replaced = web_service
+        .user_db
+        .update_user_avatar(&user_id, None)
+        .await
+        .map_err(MediaErrorResponse::DbError)?;
+    if let Some(replaced) = replaced {
+        delete_image(web_service.blob_store.as_ref(), &replaced).await;
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Serves stored blobs, needed by the local store, S3 URLs point at the bucket
+#[tracing::instrument(skip(web_service))]
+pub async fn get<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    Path(key): Path<String>,
+) -> Result<impl IntoResponse, MediaErrorResponse> {
+    let key = key.trim_start_matches('/');
+    let blob = web_service
+        .blob_store
+        .get(key)
+        .await
+        .map_err(MediaErrorResponse::BlobStore)?;
+
+    Ok((
+        [
+            (CONTENT_TYPE, blob.content_type),
+            (CACHE_CONTROL, IMMUTABLE_CACHE_CONTROL.to_owned()),
+        ],
+        blob.bytes,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::images::tests::create_png;
+    use crate::web::email_verification::tests::register_verified_user;
+    use crate::web::users::tests::create_test_router;
+    use crate::web_service::tests::{
+        delete_with_auth_header, deserialize_response_body, get_with_auth_header,
+        put_file_with_auth_header,
+    };
+    use image::GenericImageView;
+
+    const AVATAR_URI: &str = "/api/user/me/avatar";
+
+    fn media_uri(url: &str) -> &str {
+        url.split_once("/api/media/")
+            .map(|(_, key)| key)
+            .expect("a local media URL")
+    }
+
+    async fn get_media(router: &axum::Router, url: &str) -> (StatusCode, Vec<u8>) {
+        let response = get_with_auth_header(
+            router,
+            std::format!("/api/media/{}", media_uri(url)),
+            None::<&str>,
+        )
+        .await;
+        let status = response.status();
+        let bytes = hyper::body::to_bytes(response.into_body())
+            .await
+            .expect("failed to read response body into bytes");
+        (status, bytes.to_vec())
+    }
+
+    #[test]
+    fn test_thumbnail_key() {
+        assert_eq!(thumbnail_key("avatars/a1.jpg"), "avatars/a1.thumbnail.png");
+        assert_eq!(thumbnail_key("avatars/a1"), "avatars/a1.thumbnail.png");
+    }
+
+    #[tokio::test]
+    async fn should_upload_and_replace_an_avatar() {
+        let router = create_test_router().await;
+        let (_, token) = register_verified_user().await;
+        let png = create_png(600, 300);
+
+        let response =
+            put_file_with_auth_header(&router, AVATAR_URI, "image/png", &png, Some(&token)).await;
+        assert_eq!(response.status(), 200);
+        let first = deserialize_response_body::<ImageResponseBody>(response).await;
+
+        let (status, bytes) = get_media(&router, &first.url).await;
+        assert_eq!(status, 200);
+        assert_eq!(bytes, png);
+        let (status, bytes) = get_media(&router, &first.thumbnail_url).await;
+        assert_eq!(status, 200);
+        let thumbnail = image::load_from_memory(&bytes).expect("thumbnail is an image");
+        assert_eq!(thumbnail.dimensions(), (256, 128));
+
+        let response = get_with_auth_header(&router, "/api/user/me", Some(&token)).await;
+        let profile = deserialize_response_body::<serde_json::Value>(response).await;
+        assert_eq!(profile["avatar"], first.url.as_str());
+
+        let response =
+            put_file_with_auth_header(&router, AVATAR_URI, "image/png", &png, Some(&token)).await;
+        assert_eq!(response.status(), 200);
+        let second = deserialize_response_body::<ImageResponseBody>(response).await;
+        assert_ne!(first.url, second.url);
+        assert_eq!(get_media(&router, &first.url).await.0, 404);
+        assert_eq!(get_media(&router, &first.thumbnail_url).await.0, 404);
+
+        let response = delete_with_auth_header(&router, AVATAR_URI, Some(&token)).await;
+        assert_eq!(response.status(), 204);
+        assert_eq!(get_media(&router, &second.url).await.0, 404);
+        let response = get_with_auth_header(&router, "/api/user/me", Some(&token)).await;
+        let profile = deserialize_response_body::<serde_json::Value>(response).await;
+        assert_eq!(profile["avatar"], serde_json::Value::Null);
+    }
+
+    #[tokio::test]
+    async fn should_refuse_invalid_avatars() {
+        let router = create_test_router().await;
+        let (_, token) = register_verified_user().await;
+
+        let response = put_file_with_auth_header(
+            &router,
+            AVATAR_URI,
+            "image/svg+xml",
+            b"<svg></svg>",
+            Some(&token),
+        )
+        .await;
+        assert_eq!(response.status(), 415);
+
+        let response = put_file_with_auth_header(
+            &router,
+            AVATAR_URI,
+            "image/jpeg",
+            &create_png(10, 10),
+            Some(&token),
+        )
+        .await;
+        assert_eq!(response.status(), 400);
+
+        let too_large = vec![0; *AVATAR_MAX_SIZE_IN_BYTES + 1];
+        let response =
+            put_file_with_auth_header(&router, AVATAR_URI, "image/png", &too_large, Some(&token))
+                .await;
+        assert_eq!(response.status(), 413);
+
+        let response = get_with_auth_header(&router, "/api/media/../.env", None::<&str>).await;
+        assert_eq!(response.status(), 404);
+    }
+}
@@ -0,0 +1,534 @@
+use crate::models::chat_members::ChatMemberDb;
+use crate::models::chat_message::ChatMessageDb;
+use crate::models::chats::ChatDb;
+use crate::models::company::CompanyDb;
+use crate::models::errors::DbError;
+use crate::models::project::ProjectDb;
+use crate::models::user::UserDb;
+use crate::utils::mfa::{
+    generate_recovery_codes, generate_totp_secret, normalize_recovery_code, totp_uri,
+    verify_totp_code, TotpError,
+};
+use crate::utils::tokens::{hash_opaque_token, MfaPendingToken};
+use crate::web::audit::record_audit_event;
+use crate::web::authentication::{Authenticated, ScopeError};
+use crate::web::errors::{
+    create_bad_request_error, create_internal_server_error, INVALID_MFA_CODE_ERROR_MSG,
+    INVALID_MFA_TOKEN_ERROR_MSG,
+};
+use crate::web::rate_limiting::FailedLogin;
+use crate::web::sessions::ClientInfo;
+use crate::web::tokens::IssueTokensError;
+use crate::web::users::{sign_in, LoginUserResponseBody};
+use crate::web_service::{ErrorResponseBody, WebService};
+use axum::extract::rejection::JsonRejection;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json};
+use database::audit_events::AuditEventType;
+use database::revoked_tokens::RevokedTokenInput;
+use serde::{Deserialize, Serialize};
+use time::{OffsetDateTime, PrimitiveDateTime};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TotpEnrolmentResponseBody {
+    secret: String,
+    otpauth_uri: String,
+}
+
+#[derive(Debug)]
+pub enum TotpErrorResponse {
+    Forbidden(ScopeError),
+    DbError(DbError),
+    AlreadyEnabled,
+    NotEnrolled,
+    InvalidCode,
+    InvalidSecretInDb(TotpError),
+    JsonRejection(JsonRejection),
+}
+
+impl IntoResponse for TotpErrorResponse {
+    fn into_response(self) -> Response {
+        match self {
+            TotpErrorResponse::Forbidden(error) => error.into_response(),
+            TotpErrorResponse::DbError(db_error) => db_error.into_response(),
+            TotpErrorResponse::AlreadyEnabled => {
+                create_bad_request_error("Two-factor authentication is already enabled".to_owned())
+                    .into_response()
+            }
+            TotpErrorResponse::NotEnrolled => {
+                create_bad_request_error("Start two-factor authentication enrolment first".into())
+                    .into_response()
+            }
+            TotpErrorResponse::InvalidCode => {
+                create_bad_request_error(INVALID_MFA_CODE_ERROR_MSG.into()).into_response()
+            }
+            TotpErrorResponse::InvalidSecretInDb(error) => {
+                create_internal_server_error(std::format!("Invalid TOTP secret: {:?}", error))
+                    .into_response()
+            }
+            TotpErrorResponse::JsonRejection(error) => {
+                create_bad_request_error(error.to_string()).into_response()
+            }
+        }
+    }
+}
+
+/// Generates a TOTP secret for the current user
+///
+/// Nothing changes for logins until the secret is confirmed with a code, enrolling again
+/// before that replaces the secret.
+#[tracing::instrument(skip(web_service))]
+pub async fn enrol_totp<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authenticated: Authenticated,
+) -> Result<Json<TotpEnrolmentResponseBody>, TotpErrorResponse> {
+    let user_info = authenticated
+        .require_user()
+        .map_err(TotpErrorResponse::Forbidden)?;
+
+    let user = web_service
+        .user_db
+        .get_user(&user_info.user_id)
+        .await
+        .map_err(TotpErrorResponse::DbError)?;
+
+    let secret = generate_totp_secret();
+    let updated = web_service
+        .user_db
+        .set_totp_secret(user.id, secret.clone())
+        .await
+        .map_err(TotpErrorResponse::DbError)?;
+    if updated == 0 {
+        return Err(TotpErrorResponse::AlreadyEnabled);
+    }
+
+    Ok(Json(TotpEnrolmentResponseBody {
+        otpauth_uri: totp_uri(&secret, &user.email),
+        secret,
+    }))
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ConfirmTotpData {
+    code: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ConfirmTotpRequestBody {
+    data: ConfirmTotpData,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RecoveryCodesResponseBody {
+    recovery_codes: Vec<String>,
+}
+
+/// Enables TOTP with a code of the enrolled secret, returns recovery codes
+///
+/// Recovery codes are shown only here, their hashes are stored.
+#[tracing::instrument(skip(web_service, body_or_error))]
+pub async fn confirm_totp<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authenticated: Authenticated,
+    body_or_error: Result<Json<ConfirmTotpRequestBody>, JsonRejection>,
+) -> Result<Json<RecoveryCodesResponseBody>, TotpErrorResponse> {
+    let user_info = authenticated
+        .require_user()
+        .map_err(TotpErrorResponse::Forbidden)?;
+    let Json(body) = body_or_error.map_err(TotpErrorResponse::JsonRejection)?;
+
+    let user = web_service
+        .user_db
+        .get_user(&user_info.user_id)
+        .await
+        .map_err(TotpErrorResponse::DbError)?;
+    if user.totp_enabled_at.is_some() {
+        return Err(TotpErrorResponse::AlreadyEnabled);
+    }
+    let secret = user.totp_secret.ok_or(TotpErrorResponse::NotEnrolled)?;
+
+    let step = verify_totp_code(&secret, &body.data.code, OffsetDateTime::now_utc())
+        .map_err(TotpErrorResponse::InvalidSecretInDb)?
+        .ok_or(TotpErrorResponse::InvalidCode)?;
+
+    let recovery_codes = generate_recovery_codes();
+    let recovery_code_hashes = recovery_codes
+        .iter()
+        .map(|code| hash_opaque_token(normalize_recovery_code(code)))
+        .collect::<Vec<_>>();
+
+    let enabled = web_service
+        .user_db
+        .enable_totp(user.id, step, &recovery_code_hashes)
+        .await
+        .map_err(TotpErrorResponse::DbError)?;
+    if enabled == 0 {
+        return Err(TotpErrorResponse::AlreadyEnabled);
+    }
+
+    Ok(Json(RecoveryCodesResponseBody { recovery_codes }))
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MfaLoginData {
+    mfa_token: String,
+    /// Either a TOTP code or a recovery code
+    code: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MfaLoginRequestBody {
+    data: MfaLoginData,
+}
+
+#[derive(Debug)]
+pub enum MfaLoginErrorResponse {
+    DbError(DbError),
+    InvalidMfaToken,
+    InvalidCode,
+    InvalidSecretInDb(TotpError),
+    JsonRejection(JsonRejection),
+    IssueTokensError(IssueTokensError),
+}
+
+impl IntoResponse for MfaLoginErrorResponse {
+    fn into_response(self) -> Response {
+        match self {
+            MfaLoginErrorResponse::DbError(db_error) => db_error.into_response(),
+            MfaLoginErrorResponse::InvalidMfaToken => (
+                StatusCode::UNAUTHORIZED,
+                Json(ErrorResponseBody {
+                    code: None,
+                    error: INVALID_MFA_TOKEN_ERROR_MSG.into(),
+                }),
+            )
+                .into_response(),
+            MfaLoginErrorResponse::InvalidCode => (
+                StatusCode::UNAUTHORIZED,
+                Extension(FailedLogin),
+                Json(ErrorResponseBody {
+                    code: None,
+                    error: INVALID_MFA_CODE_ERROR_MSG.into(),
+                }),
+            )
+                .into_response(),
+            MfaLoginErrorResponse::InvalidSecretInDb(error) => {
+                create_internal_server_error(std::format!("Invalid TOTP secret: {:?}", error))
+                    .into_response()
+            }
+            MfaLoginErrorResponse::JsonRejection(error) => {
+                create_bad_request_error(error.to_string()).into_response()
+            }
+            MfaLoginErrorResponse::IssueTokensError(error) => error.into_response(),
+        }
+    }
+}
+
+/// Finishes a login started with a password, a TOTP or a recovery code is spent
+///
+/// The MFA token works for a single login, wrong codes count towards locking the account.
+#[tracing::instrument(skip(web_service, body_or_error))]
+pub async fn login<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    client_info: ClientInfo,
+    body_or_error: Result<Json<MfaLoginRequestBody>, JsonRejection>,
+) -> Result<(StatusCode, HeaderMap, Json<LoginUserResponseBody>), MfaLoginErrorResponse> {
+    let Json(body) = body_or_error.map_err(MfaLoginErrorResponse::JsonRejection)?;
+
+    let mfa_token = MfaPendingToken::from_token(&body.data.mfa_token)
+        .map_err(|_| MfaLoginErrorResponse::InvalidMfaToken)?;
+    // The token is spent before the code is checked, so concurrent logins can not both use it
+    let expires_at = mfa_token.get_expires_at();
+    let spent = web_service
+        .user_db
+        .revoke_access_token(&RevokedTokenInput {
+            id: mfa_token.get_token_id(),
+            user_id: mfa_token.user_id,
+            expires_at: PrimitiveDateTime::new(expires_at.date(), expires_at.time()),
+        })
+        .await
+        .map_err(MfaLoginErrorResponse::DbError)?;
+    if spent == 0 {
+        return Err(MfaLoginErrorResponse::InvalidMfaToken);
+    }
+
+    let user = web_service
+        .user_db
+        .get_user(&mfa_token.user_id)
+        .await
+        .map_err(MfaLoginErrorResponse::DbError)?;
+    let secret = match (user.totp_enabled_at, &user.totp_secret) {
+        (Some(_), Some(secret)) => secret,
+        _ => return Err(MfaLoginErrorResponse::InvalidMfaToken),
+    };
+
+    let step = verify_totp_code(secret, &body.data.code, OffsetDateTime::now_utc())
+        .map_err(MfaLoginErrorResponse::InvalidSecretInDb)?;
+    let used = match step {
+        // A code works once, even within its time step
+        Some(step) => web_service.user_db.use_totp_step(user.id, step).await,
+        None => {
+            web_service
+                .user_db
+                .use_recovery_code(
+                    user.id,
+                    hash_opaque_token(normalize_recovery_code(&body.data.code)),
+                )
+                .await
+        }
+    }
+    .map_err(MfaLoginErrorResponse::DbError)?;
+    if used == 0 {
+        // A wrong code is counted towards locking the account, the token may be tried again
+        web_service
+            .user_db
+            .restore_access_token(mfa_token.get_token_id())
+            .await
+            .map_err(MfaLoginErrorResponse::DbError)?;
+        record_audit_event(
+            web_service.audit_log.as_ref(),
+            AuditEventType::LoginFailed,
+            user.id,
+            None,
+            &client_info,
+        )
+        .await;
+        return Err(MfaLoginErrorResponse::InvalidCode);
+    }
+
+    sign_in(
+        &web_service.user_db,
+        web_service.audit_log.as_ref(),
+        &user,
+        mfa_token.device_name,
+        client_info,
+    )
+    .await
+    .map_err(MfaLoginErrorResponse::IssueTokensError)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rate_limit::RateLimits;
+    use crate::utils::mfa::tests::create_totp_code;
+    use crate::web::email_verification::tests::register_verified_user;
+    use crate::web::errors::{
+        ACCOUNT_LOCKED_ERROR_MSG, INVALID_MFA_CODE_ERROR_MSG, INVALID_MFA_TOKEN_ERROR_MSG,
+    };
+    use crate::web::mfa::{
+        ConfirmTotpData, ConfirmTotpRequestBody, MfaLoginData, MfaLoginRequestBody,
+        RecoveryCodesResponseBody, TotpEnrolmentResponseBody,
+    };
+    use crate::web::users::tests::{
+        create_test_router, get_auth_header_for_name, login_from_device,
+    };
+    use crate::web::users::{LoginUserResponseBody, RegisterUserData};
+    use crate::web_service::tests::{deserialize_response_body, post, post_with_auth_header};
+    use crate::web_service::ErrorResponseBody;
+    use axum::body::Bytes;
+    use axum::http::StatusCode;
+    use axum::Router;
+    use http_body::combinators::UnsyncBoxBody;
+    use time::{Duration, OffsetDateTime};
+
+    /// Registers a user with TOTP enabled, returns its secret and recovery codes
+    async fn register_user_with_totp() -> (RegisterUserData, String, Vec<String>) {
+        let router = create_test_router().await;
+        let (request, access_token) = register_verified_user().await;
+
+        let response =
+            post_with_auth_header(&router, "/api/user/mfa/totp", &(), Some(&access_token)).await;
+        assert_eq!(response.status(), 200);
+        let response_body = deserialize_response_body::<TotpEnrolmentResponseBody>(response).await;
+        assert!(response_body.otpauth_uri.contains(&response_body.secret));
+        let secret = response_body.secret;
+
+        let uri = "/api/user/mfa/totp/confirm";
+        // A code of the previous step, so the current one is still free to log in with
+        let code = create_totp_code(&secret, OffsetDateTime::now_utc() - Duration::seconds(30));
+        let request_body = ConfirmTotpRequestBody {
+            data: ConfirmTotpData { code },
+        };
+        let response =
+            post_with_auth_header(&router, uri, &request_body, Some(&access_token)).await;
+        assert_eq!(response.status(), 200);
+        let response_body = deserialize_response_body::<RecoveryCodesResponseBody>(response).await;
+
+        (request, secret, response_body.recovery_codes)
+    }
+
+    async fn start_mfa_login(request: &RegisterUserData) -> String {
+        let response = login_from_device(request, Some("phone".to_owned())).await;
+        assert_eq!(response.status(), 202);
+        assert!(!response.headers().contains_key("x-auth-token"));
+
+        let response_body = deserialize_response_body::<LoginUserResponseBody>(response).await;
+        response_body
+            .mfa_token()
+            .expect("a login waits for a second factor")
+            .to_owned()
+    }
+
+    async fn finish_mfa_login(
+        router: &Router,
+        mfa_token: impl Into<String>,
+        code: impl Into<String>,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        let request_body = MfaLoginRequestBody {
+            data: MfaLoginData {
+                mfa_token: mfa_token.into(),
+                code: code.into(),
+            },
+        };
+
+        post(router, "/api/user/login/mfa", &request_body).await
+    }
+
+    #[tokio::test]
+    async fn should_require_a_totp_code_to_login() {
+        let router = create_test_router().await;
+        let (request, secret, _) = register_user_with_totp().await;
+
+        let mfa_token = start_mfa_login(&request).await;
+
+        let response = finish_mfa_login(&router, &mfa_token, "not a code").await;
+        assert_eq!(response.status(), 401);
+
+        let code = create_totp_code(&secret, OffsetDateTime::now_utc());
+        let response = finish_mfa_login(&router, &mfa_token, &code).await;
+        assert_eq!(response.status(), 202);
+        get_auth_header_for_name(&response);
+
+        // The same code does not work twice
+        let another_mfa_token = start_mfa_login(&request).await;
+        let response = finish_mfa_login(&router, &another_mfa_token, &code).await;
+        assert_eq!(response.status(), 401);
+
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.error, INVALID_MFA_CODE_ERROR_MSG);
+    }
+
+    #[tokio::test]
+    async fn should_spend_an_mfa_token_on_login() {
+        let router = create_test_router().await;
+        let (request, secret, _) = register_user_with_totp().await;
+
+        let mfa_token = start_mfa_login(&request).await;
+        let code = create_totp_code(&secret, OffsetDateTime::now_utc());
+        let response = finish_mfa_login(&router, &mfa_token, &code).await;
+        assert_eq!(response.status(), 202);
+
+        let code = create_totp_code(&secret, OffsetDateTime::now_utc() + Duration::seconds(30));
+        let response = finish_mfa_login(&router, &mfa_token, &code).await;
+        assert_eq!(response.status(), 401);
+
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.error, INVALID_MFA_TOKEN_ERROR_MSG);
+    }
+
+    #[tokio::test]
+    async fn should_spend_an_mfa_token_on_concurrent_logins_once() {
+        let router = create_test_router().await;
+        let (request, secret, recovery_codes) = register_user_with_totp().await;
+
+        let mfa_token = start_mfa_login(&request).await;
+        let code = create_totp_code(&secret, OffsetDateTime::now_utc());
+        let (totp_response, recovery_response) = tokio::join!(
+            finish_mfa_login(&router, &mfa_token, &code),
+            finish_mfa_login(&router, &mfa_token, &recovery_codes[0]),
+        );
+
+        let mut statuses = vec![totp_response.status(), recovery_response.status()];
+        statuses.sort();
+        assert_eq!(
+            statuses,
+            vec![StatusCode::ACCEPTED, StatusCode::UNAUTHORIZED]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_lock_an_account_after_wrong_codes() {
+        let router = create_test_router().await;
+        let (request, secret, _) = register_user_with_totp().await;
+        let threshold = RateLimits::from_env().lockout.threshold;
+
+        let mfa_token = start_mfa_login(&request).await;
+        for _ in 0..threshold {
+            let response = finish_mfa_login(&router, &mfa_token, "000000").await;
+            assert_eq!(response.status(), 401);
+        }
+
+        let code = create_totp_code(&secret, OffsetDateTime::now_utc());
+        let response = finish_mfa_login(&router, &mfa_token, &code).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.error, ACCOUNT_LOCKED_ERROR_MSG);
+    }
+
+    #[tokio::test]
+    async fn should_login_with_a_recovery_code_once() {
+        let router = create_test_router().await;
+        let (request, _, recovery_codes) = register_user_with_totp().await;
+
+        let mfa_token = start_mfa_login(&request).await;
+
+        let code = recovery_codes[0].to_uppercase();
+        let response = finish_mfa_login(&router, &mfa_token, &code).await;
+        assert_eq!(response.status(), 202);
+
+        let mfa_token = start_mfa_login(&request).await;
+        let response = finish_mfa_login(&router, &mfa_token, &code).await;
+        assert_eq!(response.status(), 401);
+
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.error, INVALID_MFA_CODE_ERROR_MSG);
+    }
+
+    #[tokio::test]
+    async fn should_not_enrol_twice() {
+        let router = create_test_router().await;
+        let (request, secret, _) = register_user_with_totp().await;
+
+        let mfa_token = start_mfa_login(&request).await;
+        let code = create_totp_code(&secret, OffsetDateTime::now_utc());
+        let response = finish_mfa_login(&router, &mfa_token, code).await;
+        let access_token = get_auth_header_for_name(&response);
+
+        let response =
+            post_with_auth_header(&router, "/api/user/mfa/totp", &(), Some(&access_token)).await;
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn should_reject_an_access_token_as_an_mfa_token() {
+        let router = create_test_router().await;
+        let (_, access_token) = register_verified_user().await;
+
+        let response = finish_mfa_login(&router, access_token, "000000").await;
+        assert_eq!(response.status(), 401);
+    }
+}
@@ -0,0 +1,452 @@
+use crate::models::chat_members::ChatMemberDb;
+use crate::models::chat_message::ChatMessageDb;
+use crate::models::chats::ChatDb;
+use crate::models::company::CompanyDb;
+use crate::models::errors::DbError;
+use crate::models::project::ProjectDb;
+use crate::models::user::{OwnedOidcLoginStateInput, UserDb};
+use crate::oidc::{pkce_challenge, OidcError};
+use crate::utils::tokens::{generate_opaque_token, hash_opaque_token};
+use crate::web::errors::{
+    create_bad_request_error, create_internal_server_error, INVALID_OIDC_STATE_ERROR_MSG,
+    NOT_FOUND_RESPONSE,
+};
+use crate::web::profiles::validate_language_code;
+use crate::web::sessions::ClientInfo;
+use crate::web::users::{
+    login_federated, FederatedIdentity, FederatedLoginError, LoginUserResponseBody,
+};
+use crate::web_service::{ErrorResponseBody, WebService};
+use axum::extract::rejection::JsonRejection;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime, PrimitiveDateTime};
+
+lazy_static! {
+    static ref OIDC_STATE_DURATION: Duration = Duration::seconds(
+        std::env::var("OIDC_STATE_DURATION_IN_SECS")
+            .expect("OIDC_STATE_DURATION_IN_SECS must be in environment")
+            .parse::<i64>()
+            .expect("integer duration")
+    );
+}
+
+#[derive(Debug)]
+pub enum OidcErrorResponse {
+    UnknownProvider,
+    DbError(DbError),
+    InvalidState,
+    Provider(OidcError),
+    JsonRejection(JsonRejection),
+    FederatedLogin(FederatedLoginError),
+}
+
+impl IntoResponse for OidcErrorResponse {
+    fn into_response(self) -> Response {
+        match self {
+            OidcErrorResponse::UnknownProvider => NOT_FOUND_RESPONSE.clone().into_response(),
+            OidcErrorResponse::DbError(db_error) => db_error.into_response(),
+            OidcErrorResponse::InvalidState => {
+                create_bad_request_error(INVALID_OIDC_STATE_ERROR_MSG.into()).into_response()
+            }
+            // The provider is down or misconfigured, not the client's fault
+            OidcErrorResponse::Provider(OidcError::Http(error)) => (
+                StatusCode::BAD_GATEWAY,
+                Json(ErrorResponseBody {
+                    code: None,
+                    error: std::format!("Identity provider is unavailable: {}", error),
+                }),
+            )
+                .into_response(),
+            OidcErrorResponse::Provider(OidcError::InvalidUrl(error)) => {
+                create_internal_server_error(std::format!("Invalid provider url: {}", error))
+                    .into_response()
+            }
+            OidcErrorResponse::Provider(error) => {
+                tracing::warn!("identity provider sign in failed: {:?}", error);
+                (
+                    StatusCode::UNAUTHORIZED,
+                    Json(ErrorResponseBody {
+                        code: None,
+                        error: "Can not sign in with the identity provider".to_owned(),
+                    }),
+                )
+                    .into_response()
+            }
+            OidcErrorResponse::JsonRejection(error) => {
+                create_bad_request_error(error.to_string()).into_response()
+            }
+            OidcErrorResponse::FederatedLogin(error) => error.into_response(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct OidcAuthorizeData {
+    #[serde(default)]
+    device_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OidcAuthorizeRequestBody {
+    data: OidcAuthorizeData,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OidcAuthorizeResponseBody {
+    authorization_url: String,
+}
+
+/// Starts a sign in through an identity provider, returns where to send the user
+///
+/// The provider sends the user back to `OIDC_REDIRECT_URL` with `code` and `state`,
+/// the frontend passes both to `callback`.
+#[tracing::instrument(skip(web_service, body_or_error))]
+pub async fn authorize<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    Path(provider_name): Path<String>,
+    body_or_error: Result<Json<OidcAuthorizeRequestBody>, JsonRejection>,
+) -> Result<Json<OidcAuthorizeResponseBody>, OidcErrorResponse> {
+    let Json(body) = body_or_error.map_err(OidcErrorResponse::JsonRejection)?;
+    let provider = web_service
+        .oidc_providers
+        .get(&provider_name)
+        .ok_or(OidcErrorResponse::UnknownProvider)?;
+
+    let state = generate_opaque_token();
+    let nonce = generate_opaque_token();
+    let code_verifier = generate_opaque_token();
+
+    let authorization_url = provider
+        .authorization_url(
+            web_service.oidc_providers.redirect_url(),
+            &state,
+            &nonce,
+            pkce_challenge(&code_verifier),
+        )
+        .await
+        .map_err(OidcErrorResponse::Provider)?;
+
+    let expires_at = OffsetDateTime::now_utc() + *OIDC_STATE_DURATION;
+    web_service
+        .user_db
+        .insert_oidc_login_state(&OwnedOidcLoginStateInput {
+            state_hash: hash_opaque_token(&state),
+            provider: provider_name,
+            nonce,
+            code_verifier,
+            device_name: body.data.device_name,
+            expires_at: PrimitiveDateTime::new(expires_at.date(), expires_at.time()),
+        })
+        .await
+        .map_err(OidcErrorResponse::DbError)?;
+
+    Ok(Json(OidcAuthorizeResponseBody { authorization_url }))
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OidcCallbackData {
+    code: String,
+    state: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OidcCallbackRequestBody {
+    data: OidcCallbackData,
+}
+
+/// Finishes a sign in through an identity provider, logs in or registers the user
+///
+/// Answers like `users::login`, or like `users::post` for a new user.
+#[tracing::instrument(skip(web_service, body_or_error))]
+pub async fn callback<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    Path(provider_name): Path<String>,
+    client_info: ClientInfo,
+    body_or_error: Result<Json<OidcCallbackRequestBody>, JsonRejection>,
+) -> Result<(StatusCode, HeaderMap, Json<LoginUserResponseBody>), OidcErrorResponse> {
+    let Json(body) = body_or_error.map_err(OidcErrorResponse::JsonRejection)?;
+    let provider = web_service
+        .oidc_providers
+        .get(&provider_name)
+        .ok_or(OidcErrorResponse::UnknownProvider)?;
+
+    let login_state = match web_service
+        .user_db
+        .take_oidc_login_state(hash_opaque_token(&body.data.state))
+        .await
+    {
+        Ok(login_state) if login_state.provider == provider_name => login_state,
+        Ok(_) | Err(DbError::NotFoundError) => return Err(OidcErrorResponse::InvalidState),
+        Err(db_error) => return Err(OidcErrorResponse::DbError(db_error)),
+    };
+
+    let claims = provider
+        .exchange_code(
+            &body.data.code,
+            &login_state.code_verifier,
+            web_service.oidc_providers.redirect_url(),
+            &login_state.nonce,
+        )
+        .await
+        .map_err(OidcErrorResponse::Provider)?;
+
+    let identity = FederatedIdentity {
+        issuer: claims.iss,
+        subject: claims.sub,
+        email: claims.email,
+        email_verified: claims.email_verified,
+        first_name: claims.given_name,
+        last_name: claims.family_name,
+        // The rest fall back to the default language
+        language_code: claims
+            .locale
+            .and_then(|locale| validate_language_code(locale).ok()),
+    };
+
+    login_federated(
+        &web_service.user_db,
+        web_service.mailer.as_ref(),
+        web_service.audit_log.as_ref(),
+        identity,
+        login_state.device_name,
+        client_info,
+    )
+    .await
+    .map_err(OidcErrorResponse::FederatedLogin)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::oidc::tests::{MockIdentity, MOCK_ISSUER, MOCK_PROVIDER};
+    use crate::utils::tokens::AccessToken;
+    use crate::web::email_verification::tests::register_verified_user;
+    use crate::web::errors::INVALID_OIDC_STATE_ERROR_MSG;
+    use crate::web::oidc::{
+        OidcAuthorizeData, OidcAuthorizeRequestBody, OidcAuthorizeResponseBody, OidcCallbackData,
+        OidcCallbackRequestBody,
+    };
+    use crate::web::users::tests::{
+        create_test_router, get_auth_header_for_name, register_new_user,
+    };
+    use crate::web_service::tests::{
+        deserialize_response_body, get_with_auth_header, post, TEST_MAILER,
+    };
+    use crate::web_service::ErrorResponseBody;
+    use axum::body::Bytes;
+    use axum::Router;
+    use database::utils::random_samples::RandomSample;
+    use http_body::combinators::UnsyncBoxBody;
+
+    fn create_identity(email: impl Into<String>, email_verified: bool) -> MockIdentity {
+        MockIdentity {
+            sub: String::new_random(32),
+            email: email.into(),
+            email_verified,
+            given_name: Some("Ada".to_owned()),
+            family_name: Some("Lovelace".to_owned()),
+            locale: None,
+        }
+    }
+
+    async fn authorize(router: &Router) -> String {
+        let request_body = OidcAuthorizeRequestBody {
+            data: OidcAuthorizeData {
+                device_name: Some("laptop".to_owned()),
+            },
+        };
+        let uri = std::format!("/api/user/oidc/{MOCK_PROVIDER}/authorize");
+        let response = post(router, &uri, &request_body).await;
+        assert_eq!(response.status(), 200);
+
+        deserialize_response_body::<OidcAuthorizeResponseBody>(response)
+            .await
+            .authorization_url
+    }
+
+    async fn callback(
+        router: &Router,
+        code: impl Into<String>,
+        state: impl Into<String>,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        let request_body = OidcCallbackRequestBody {
+            data: OidcCallbackData {
+                code: code.into(),
+                state: state.into(),
+            },
+        };
+        let uri = std::format!("/api/user/oidc/{MOCK_PROVIDER}/callback");
+
+        post(router, &uri, &request_body).await
+    }
+
+    async fn sign_in_with_provider(
+        router: &Router,
+        identity: MockIdentity,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        let authorization_url = authorize(router).await;
+        let (code, state) = MOCK_ISSUER.authorize(&authorization_url, identity);
+
+        callback(router, code, state).await
+    }
+
+    #[tokio::test]
+    async fn should_register_and_then_login_a_federated_user() {
+        let router = create_test_router().await;
+        let email = std::format!("{}@test.test", String::new_random(16));
+        let identity = create_identity(&email, true);
+
+        let response = sign_in_with_provider(&router, identity.clone()).await;
+        assert_eq!(response.status(), 201);
+
+        let access_token = get_auth_header_for_name(&response);
+        let access_token = AccessToken::from_token(access_token).expect("valid token");
+        assert!(access_token.get_user().email_verified);
+        assert_eq!(access_token.get_user().first_name.as_deref(), Some("Ada"));
+        assert!(TEST_MAILER.emails_to(&email).is_empty());
+
+        let response = sign_in_with_provider(&router, identity).await;
+        assert_eq!(response.status(), 202);
+
+        let second_access_token = get_auth_header_for_name(&response);
+        let second_access_token =
+            AccessToken::from_token(second_access_token).expect("valid token");
+        assert_eq!(
+            second_access_token.get_user().user_id,
+            access_token.get_user().user_id
+        );
+    }
+
+    #[tokio::test]
+    async fn should_keep_only_a_valid_locale() {
+        let router = create_test_router().await;
+
+        for (locale, language_code) in [("EN-gb", "en-GB"), ("klingon", "en"), ("xx-XX", "en")] {
+            let email = std::format!("{}@test.test", String::new_random(16));
+            let identity = MockIdentity {
+                locale: Some(locale.to_owned()),
+                ..create_identity(&email, true)
+            };
+            let response = sign_in_with_provider(&router, identity).await;
+            assert_eq!(response.status(), 201);
+
+            let access_token = get_auth_header_for_name(&response);
+            let response = get_with_auth_header(&router, "/api/user/me", Some(&access_token)).await;
+            assert_eq!(response.status(), 200);
+            let profile = deserialize_response_body::<serde_json::Value>(response).await;
+            assert_eq!(profile["language_code"], language_code);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_ask_to_verify_an_email_the_provider_has_not() {
+        let router = create_test_router().await;
+        let email = std::format!("{}@test.test", String::new_random(16));
+
+        let response = sign_in_with_provider(&router, create_identity(&email, false)).await;
+        assert_eq!(response.status(), 201);
+
+        let access_token = get_auth_header_for_name(&response);
+        let access_token = AccessToken::from_token(access_token).expect("valid token");
+        assert!(!access_token.get_user().email_verified);
+        assert_eq!(TEST_MAILER.emails_to(&email).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn should_link_a_verified_email_to_an_existing_user() {
+        let router = create_test_router().await;
+
+        let (request, access_token) = register_verified_user().await;
+        let access_token = AccessToken::from_token(access_token).expect("valid token");
+
+        let response = sign_in_with_provider(&router, create_identity(request.email(), true)).await;
+        assert_eq!(response.status(), 202);
+
+        let federated_access_token = get_auth_header_for_name(&response);
+        let federated_access_token =
+            AccessToken::from_token(federated_access_token).expect("valid token");
+        assert_eq!(
+            federated_access_token.get_user().user_id,
+            access_token.get_user().user_id
+        );
+    }
+
+    #[tokio::test]
+    async fn should_not_link_an_unverified_email() {
+        let router = create_test_router().await;
+
+        let (request, _) = register_new_user(None).await;
+
+        let response = sign_in_with_provider(&router, create_identity(request.email(), true)).await;
+        assert_eq!(response.status(), 208);
+
+        let (request, _) = register_verified_user().await;
+
+        let response =
+            sign_in_with_provider(&router, create_identity(request.email(), false)).await;
+        assert_eq!(response.status(), 208);
+    }
+
+    #[tokio::test]
+    async fn should_use_a_state_once() {
+        let router = create_test_router().await;
+        let identity = create_identity(std::format!("{}@test.test", String::new_random(16)), true);
+
+        let authorization_url = authorize(&router).await;
+        let (code, state) = MOCK_ISSUER.authorize(&authorization_url, identity.clone());
+
+        let response = callback(&router, code, &state).await;
+        assert_eq!(response.status(), 201);
+
+        let (code, _) = MOCK_ISSUER.authorize(&authorization_url, identity);
+        let response = callback(&router, code, &state).await;
+        assert_eq!(response.status(), 400);
+
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.error, INVALID_OIDC_STATE_ERROR_MSG);
+    }
+
+    #[tokio::test]
+    async fn should_reject_a_code_issued_for_another_login() {
+        let router = create_test_router().await;
+        let identity = create_identity(std::format!("{}@test.test", String::new_random(16)), true);
+
+        let first_authorization_url = authorize(&router).await;
+        let second_authorization_url = authorize(&router).await;
+        let (code, _) = MOCK_ISSUER.authorize(&first_authorization_url, identity.clone());
+        let (_, state) = MOCK_ISSUER.authorize(&second_authorization_url, identity);
+
+        // The PKCE verifier of the second login does not match the first challenge
+        let response = callback(&router, code, state).await;
+        assert_eq!(response.status(), 401);
+    }
+
+    #[tokio::test]
+    async fn should_not_find_an_unknown_provider() {
+        let router = create_test_router().await;
+
+        let request_body = OidcAuthorizeRequestBody {
+            data: OidcAuthorizeData::default(),
+        };
+        let response = post(&router, "/api/user/oidc/unknown/authorize", &request_body).await;
+        assert_eq!(response.status(), 404);
+    }
+}
@@ -0,0 +1,417 @@
+use crate::mailer::{Email, Mailer, MailerError};
+use crate::models::chat_members::ChatMemberDb;
+use crate::models::chat_message::ChatMessageDb;
+use crate::models::chats::ChatDb;
+use crate::models::company::CompanyDb;
+use crate::models::errors::DbError;
+use crate::models::project::ProjectDb;
+use crate::models::user::UserDb;
+use crate::utils::passwords::{hash_password, PasswordHashError};
+use crate::utils::tokens::{generate_opaque_token, hash_opaque_token};
+use crate::web::audit::record_audit_event;
+use crate::web::errors::{
+    create_bad_request_error, create_internal_server_error, INVALID_MAIL_MSG,
+    INVALID_PASSWORD_RESET_TOKEN_ERROR_MSG,
+};
+use crate::web::sessions::ClientInfo;
+use crate::web_service::{ErrorCode, ErrorResponseBody, WebService};
+use axum::extract::rejection::JsonRejection;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use database::audit_events::AuditEventType;
+use database::password_reset_tokens::PasswordResetTokenInput;
+use email_address::EmailAddress;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime, PrimitiveDateTime};
+use uuid::Uuid;
+
+lazy_static! {
+    static ref PASSWORD_RESET_TOKEN_DURATION: Duration = Duration::seconds(
+        std::env::var("PASSWORD_RESET_TOKEN_DURATION_IN_SECS")
+            .expect("PASSWORD_RESET_TOKEN_DURATION_IN_SECS must be in environment")
+            .parse::<i64>()
+            .expect("integer duration")
+    );
+    static ref PASSWORD_RESET_REQUESTS_PER_HOUR: i64 =
+        std::env::var("PASSWORD_RESET_REQUESTS_PER_HOUR")
+            .expect("PASSWORD_RESET_REQUESTS_PER_HOUR must be in environment")
+            .parse::<i64>()
+            .expect("integer limit");
+}
+
+fn to_primitive(date: OffsetDateTime) -> PrimitiveDateTime {
+    PrimitiveDateTime::new(date.date(), date.time())
+}
+
+#[derive(Debug)]
+pub enum SendPasswordResetEmailError {
+    DbError(DbError),
+    Mailer(MailerError),
+}
+
+/// Stores a hash of a fresh reset token and mails the token itself
+async fn send_password_reset_email(
+    user_db: &impl UserDb,
+    mailer: &dyn Mailer,
+    user_id: Uuid,
+    email: impl Into<String>,
+) -> Result<(), SendPasswordResetEmailError> {
+    let token = generate_opaque_token();
+    let expires_at = OffsetDateTime::now_utc()
+        .replace_nanosecond(0)
+        .expect("zero is a valid nanosecond")
+        + *PASSWORD_RESET_TOKEN_DURATION;
+
+    user_db
+        .insert_password_reset_token(&PasswordResetTokenInput {
+            user_id,
+            token_hash: hash_opaque_token(&token),
+            expires_at: to_primitive(expires_at),
+        })
+        .await
+        .map_err(SendPasswordResetEmailError::DbError)?;
+
+    let url =
+        std::env::var("PASSWORD_RESET_URL").expect("PASSWORD_RESET_URL must be in environment");
+
+    mailer
+        .send(Email {
+            to: email.into(),
+            subject: "Reset your password".to_owned(),
+            body: std::format!(
+                "Somebody asked to reset your password, ignore this email if it was not you. \
+                Otherwise follow the link: {url}{token}"
+            ),
+        })
+        .await
+        .map_err(SendPasswordResetEmailError::Mailer)
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ForgotPasswordData {
+    email: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ForgotPasswordRequestBody {
+    data: ForgotPasswordData,
+}
+
+#[derive(Debug)]
+pub enum ForgotPasswordErrorResponse {
+    DbError(DbError),
+    InvalidEmailFormat,
+    JsonRejection(JsonRejection),
+    SendPasswordResetEmail(SendPasswordResetEmailError),
+}
+
+impl IntoResponse for ForgotPasswordErrorResponse {
+    fn into_response(self) -> Response {
+        match self {
+            ForgotPasswordErrorResponse::DbError(db_error) => db_error.into_response(),
+            ForgotPasswordErrorResponse::InvalidEmailFormat => (
+                StatusCode::NOT_ACCEPTABLE,
+                Json(ErrorResponseBody {
+                    code: Some(ErrorCode::InvalidEmailFormat),
+                    error: INVALID_MAIL_MSG.into(),
+                }),
+            )
+                .into_response(),
+            ForgotPasswordErrorResponse::JsonRejection(error) => {
+                create_bad_request_error(error.to_string()).into_response()
+            }
+            ForgotPasswordErrorResponse::SendPasswordResetEmail(error) => {
+                create_internal_server_error(std::format!(
+                    "Can not send a password reset email: {:?}",
+                    error
+                ))
+                .into_response()
+            }
+        }
+    }
+}
+
+/// Mails a single-use password reset link
+///
+/// Answers the same way for unknown emails and for emails which have already got
+/// `PASSWORD_RESET_REQUESTS_PER_HOUR` links within the last hour, so neither leaks.
+#[tracing::instrument(skip(web_service))]
+pub async fn forgot<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    body_or_error: Result<Json<ForgotPasswordRequestBody>, JsonRejection>,
+) -> Result<StatusCode, ForgotPasswordErrorResponse> {
+    let Json(body) = body_or_error.map_err(ForgotPasswordErrorResponse::JsonRejection)?;
+
+    if !EmailAddress::is_valid(&body.data.email) {
+        return Err(ForgotPasswordErrorResponse::InvalidEmailFormat);
+    }
+
+    let user = match web_service
+        .user_db
+        .get_user_by_email(&body.data.email)
+        .await
+    {
+        Ok(user) => user,
+        Err(DbError::NotFoundError) => return Ok(StatusCode::ACCEPTED),
+        Err(db_error) => return Err(ForgotPasswordErrorResponse::DbError(db_error)),
+    };
+
+    let sent_within_hour = web_service
+        .user_db
+        .count_password_reset_tokens_since(
+            user.id,
+            to_primitive(OffsetDateTime::now_utc() - Duration::hours(1)),
+        )
+        .await
+        .map_err(ForgotPasswordErrorResponse::DbError)?;
+    if sent_within_hour >= *PASSWORD_RESET_REQUESTS_PER_HOUR {
+        tracing::info!("too many password reset requests for {}", user.id);
+        return Ok(StatusCode::ACCEPTED);
+    }
+
+    send_password_reset_email(
+        &web_service.user_db,
+        web_service.mailer.as_ref(),
+        user.id,
+        user.email,
+    )
+    .await
+    .map_err(ForgotPasswordErrorResponse::SendPasswordResetEmail)?;
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ResetPasswordData {
+    token: String,
+    password: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ResetPasswordRequestBody {
+    data: ResetPasswordData,
+}
+
+#[derive(Debug)]
+pub enum ResetPasswordErrorResponse {
+    DbError(DbError),
+    InvalidToken,
+    JsonRejection(JsonRejection),
+    PasswordHashError(PasswordHashError),
+}
+
+impl IntoResponse for ResetPasswordErrorResponse {
+    fn into_response(self) -> Response {
+        match self {
+            ResetPasswordErrorResponse::DbError(db_error) => db_error.into_response(),
+            ResetPasswordErrorResponse::InvalidToken => {
+                create_bad_request_error(INVALID_PASSWORD_RESET_TOKEN_ERROR_MSG.into())
+                    .into_response()
+            }
+            ResetPasswordErrorResponse::JsonRejection(error) => {
+                create_bad_request_error(error.to_string()).into_response()
+            }
+            ResetPasswordErrorResponse::PasswordHashError(error) => {
+                create_internal_server_error(std::format!("Can not hash a password: {:?}", error))
+                    .into_response()
+            }
+        }
+    }
+}
+
+/// Sets a new password with a mailed token
+///
+/// Every session of the user is revoked, so are refresh and access tokens issued for them
+/// and API keys, as a reset is how an account is taken back.
+#[tracing::instrument(skip(web_service, body_or_error))]
+pub async fn reset<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    client_info: ClientInfo,
+    body_or_error: Result<Json<ResetPasswordRequestBody>, JsonRejection>,
+) -> Result<StatusCode, ResetPasswordErrorResponse> {
+    let Json(body) = body_or_error.map_err(ResetPasswordErrorResponse::JsonRejection)?;
+
+    let token = match web_service
+        .user_db
+        .get_password_reset_token_by_hash(hash_opaque_token(&body.data.token))
+        .await
+    {
+        Ok(token) => token,
+        Err(DbError::NotFoundError) => return Err(ResetPasswordErrorResponse::InvalidToken),
+        Err(db_error) => return Err(ResetPasswordErrorResponse::DbError(db_error)),
+    };
+
+    let password_hash = hash_password(&body.data.password)
+        .map_err(ResetPasswordErrorResponse::PasswordHashError)?;
+
+    // Expired and already used tokens change nothing
+    let reset = web_service
+        .user_db
+        .reset_password(token.id, password_hash)
+        .await
+        .map_err(ResetPasswordErrorResponse::DbError)?;
+    if reset == 0 {
+        return Err(ResetPasswordErrorResponse::InvalidToken);
+    }
+
+    record_audit_event(
+        web_service.audit_log.as_ref(),
+        AuditEventType::PasswordReset,
+        token.user_id,
+        None,
+        &client_info,
+    )
+    .await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::api_keys::Scope;
+    use crate::web::api_keys::tests::create_api_key;
+    use crate::web::email_verification::tests::{get_mailed_token, register_verified_user};
+    use crate::web::errors::{INVALID_PASSWORD_RESET_TOKEN_ERROR_MSG, TOKEN_REVOKED_ERROR_MSG};
+    use crate::web::password_reset::{
+        ForgotPasswordData, ForgotPasswordRequestBody, ResetPasswordData, ResetPasswordRequestBody,
+        PASSWORD_RESET_REQUESTS_PER_HOUR,
+    };
+    use crate::web::tokens::tests::refresh_tokens;
+    use crate::web::users::tests::{
+        create_test_router, get_refresh_token_header, login_from_device,
+        login_with_email_and_password, register_new_user,
+    };
+    use crate::web_service::tests::{
+        deserialize_response_body, get_with_auth_header, post, TEST_MAILER,
+    };
+    use crate::web_service::ErrorResponseBody;
+    use axum::body::Bytes;
+    use axum::Router;
+    use database::utils::random_samples::RandomSample;
+    use http_body::combinators::UnsyncBoxBody;
+
+    async fn forgot_password(
+        router: &Router,
+        email: impl Into<String>,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        let request_body = ForgotPasswordRequestBody {
+            data: ForgotPasswordData {
+                email: email.into(),
+            },
+        };
+
+        post(router, "/api/user/password/forgot", &request_body).await
+    }
+
+    async fn reset_password(
+        router: &Router,
+        token: impl Into<String>,
+        password: impl Into<String>,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        let request_body = ResetPasswordRequestBody {
+            data: ResetPasswordData {
+                token: token.into(),
+                password: password.into(),
+            },
+        };
+
+        post(router, "/api/user/password/reset", &request_body).await
+    }
+
+    #[tokio::test]
+    async fn should_reset_a_password_once_and_revoke_every_token() {
+        let router = create_test_router().await;
+
+        let (request, access_token) = register_verified_user().await;
+        let response = login_from_device(&request, None).await;
+        let refresh_token = get_refresh_token_header(&response);
+        let api_key = create_api_key(&router, &access_token, vec![Scope::ProjectsRead]).await;
+
+        let response = forgot_password(&router, request.email()).await;
+        assert_eq!(response.status(), 202);
+        let token = get_mailed_token(request.email());
+
+        let password = String::new_random(32);
+        let response = reset_password(&router, &token, &password).await;
+        assert_eq!(response.status(), 204);
+
+        let response = reset_password(&router, &token, String::new_random(32)).await;
+        assert_eq!(response.status(), 400);
+
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.error, INVALID_PASSWORD_RESET_TOKEN_ERROR_MSG);
+
+        let response =
+            get_with_auth_header(&router, "/api/user/sessions", Some(&access_token)).await;
+        assert_eq!(response.status(), 401);
+
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.error, TOKEN_REVOKED_ERROR_MSG);
+
+        let response = refresh_tokens(&router, refresh_token).await;
+        assert_eq!(response.status(), 401);
+
+        let response = get_with_auth_header(&router, "/api/projects", Some(api_key.key())).await;
+        assert_eq!(response.status(), 401);
+
+        let response = login_from_device(&request, None).await;
+        assert_eq!(response.status(), 401);
+
+        let response = login_with_email_and_password(request.email().to_owned(), password).await;
+        assert_eq!(response.status(), 202);
+    }
+
+    #[tokio::test]
+    async fn should_not_reveal_unknown_emails() {
+        let router = create_test_router().await;
+
+        let email = std::format!("{}@test.test", String::new_random(16));
+        let response = forgot_password(&router, &email).await;
+        assert_eq!(response.status(), 202);
+        assert!(TEST_MAILER.emails_to(&email).is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_limit_password_reset_emails() {
+        let router = create_test_router().await;
+
+        let (request, _) = register_new_user(None).await;
+        // The first one is a verification email
+        let emails_before = TEST_MAILER.emails_to(request.email()).len();
+
+        for _ in 0..=*PASSWORD_RESET_REQUESTS_PER_HOUR {
+            let response = forgot_password(&router, request.email()).await;
+            assert_eq!(response.status(), 202);
+        }
+
+        assert_eq!(
+            TEST_MAILER.emails_to(request.email()).len() - emails_before,
+            *PASSWORD_RESET_REQUESTS_PER_HOUR as usize
+        );
+    }
+
+    #[tokio::test]
+    async fn should_reject_an_unknown_token() {
+        let router = create_test_router().await;
+
+        let response = reset_password(&router, String::new_random(43), "password").await;
+        assert_eq!(response.status(), 400);
+    }
+}
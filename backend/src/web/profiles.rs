@@ -0,0 +1,567 @@
+use crate::models::chat_members::ChatMemberDb;
+use crate::models::chat_message::ChatMessageDb;
+use crate::models::chats::ChatDb;
+use crate::models::company::CompanyDb;
+use crate::models::errors::DbError;
+use crate::models::project::ProjectDb;
+use crate::models::user::UserDb;
+use crate::utils::tokens::{AccessTokenResponse, UserInfo};
+use crate::web::authentication::{AuthHeaders, Authenticated, ScopeError};
+use crate::web::errors::create_invalid_response;
+use crate::web::formats::{deserialize_nullable, JsonDateTime};
+use crate::web::tokens::IssueTokensError;
+use crate::web_service::{ErrorCode, ErrorResponseBody, WebService};
+use axum::extract::rejection::{JsonRejection, PathRejection};
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use database::users::{User, UserProfileUpdate};
+use isocountry::CountryCode;
+use isolang::Language;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+const NAME_MAX_LENGTH: usize = 255;
+const PHONE_NUMBER_MAX_DIGITS: usize = 15;
+
+#[derive(Debug)]
+pub enum ProfileErrorResponse {
+    Forbidden(ScopeError),
+    DbError(DbError),
+    InvalidInputDataFormat(String),
+    /// A field, an alias or a phone number, is used by another user
+    AlreadyTaken(&'static str),
+    IssueTokens(IssueTokensError),
+}
+
+impl IntoResponse for ProfileErrorResponse {
+    fn into_response(self) -> Response {
+        match self {
+            ProfileErrorResponse::Forbidden(error) => error.into_response(),
+            ProfileErrorResponse::DbError(db_error) => db_error.into_response(),
+            ProfileErrorResponse::InvalidInputDataFormat(error) => {
+                create_invalid_response(error).into_response()
+            }
+            ProfileErrorResponse::AlreadyTaken(field) => (
+                StatusCode::CONFLICT,
+                Json(ErrorResponseBody {
+                    code: Some(ErrorCode::AlreadyTaken),
+                    error: std::format!("The {field} is already taken"),
+                }),
+            )
+                .into_response(),
+            ProfileErrorResponse::IssueTokens(error) => error.into_response(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ProfileResponseData {
+    user_id: Uuid,
+    alias: Option<String>,
+    first_name: Option<String>,
+    last_name: Option<String>,
+    email: String,
+    email_verified: bool,
+    phone_number: Option<String>,
+    language_code: String,
+    avatar: Option<String>,
+    country_code: Option<String>,
+    created_at: JsonDateTime,
+    updated_at: JsonDateTime,
+}
+
+impl From<User> for ProfileResponseData {
+    fn from(value: User) -> Self {
+        ProfileResponseData {
+            user_id: value.id,
+            alias: value.alias,
+            first_name: value.first_name,
+            last_name: value.last_name,
+            email: value.email,
+            email_verified: value.email_verified_at.is_some(),
+            phone_number: value.phone_number,
+            language_code: value.language_code,
+            avatar: value.avatar,
+            country_code: value.country_code,
+            created_at: value.created_at.into(),
+            updated_at: value.updated_at.into(),
+        }
+    }
+}
+
+/// What other users see, contacts are left out
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PublicProfileResponseData {
+    user_id: Uuid,
+    alias: Option<String>,
+    first_name: Option<String>,
+    last_name: Option<String>,
+    avatar: Option<String>,
+}
+
+impl From<User> for PublicProfileResponseData {
+    fn from(value: User) -> Self {
+        PublicProfileResponseData {
+            user_id: value.id,
+            alias: value.alias,
+            first_name: value.first_name,
+            last_name: value.last_name,
+            avatar: value.avatar,
+        }
+    }
+}
+
+/// Fields to change, a missing field is kept and a `null` one is cleared
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct UpdateProfileData {
+    #[serde(
+        default,
+        deserialize_with = "deserialize_nullable",
+        skip_serializing_if = "Option::is_none"
+    )]
+    alias: Option<Option<String>>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_nullable",
+        skip_serializing_if = "Option::is_none"
+    )]
+    first_name: Option<Option<String>>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_nullable",
+        skip_serializing_if = "Option::is_none"
+    )]
+    last_name: Option<Option<String>>,
+    /// E.164, e.g. +14155552671
+    #[serde(
+        default,
+        deserialize_with = "deserialize_nullable",
+        skip_serializing_if = "Option::is_none"
+    )]
+    phone_number: Option<Option<String>>,
+    /// ISO 639-1 with an optional ISO 3166-1 alpha-2 region, e.g. en or en-GB
+    #[serde(skip_serializing_if = "Option::is_none")]
+    language_code: Option<String>,
+    /// ISO 3166-1 alpha-2
+    #[serde(
+        default,
+        deserialize_with = "deserialize_nullable",
+        skip_serializing_if = "Option::is_none"
+    )]
+    country_code: Option<Option<String>>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UpdateProfileRequestBody {
+    data: UpdateProfileData,
+}
+
+fn validate_name(field: &str, value: String) -> Result<String, String> {
+    let value = value.trim().to_owned();
+    if value.is_empty() || value.chars().count() > NAME_MAX_LENGTH {
+        return Err(std::format!(
+            "{field} must be 1 to {NAME_MAX_LENGTH} characters, use null to clear it"
+        ));
+    }
+    Ok(value)
+}
+
+fn validate_phone_number(value: String) -> Result<String, String> {
+    let digits = value.strip_prefix('+').unwrap_or_default();
+    let valid = !digits.starts_with('0')
+        && (1..=PHONE_NUMBER_MAX_DIGITS).contains(&digits.len())
+        && digits.chars().all(|x| x.is_ascii_digit());
+    if !valid {
+        return Err("phone_number must be in E.164 format, e.g. +14155552671".to_owned());
+    }
+    Ok(value)
+}
+
+fn validate_country_code(value: String) -> Result<String, String> {
+    let value = value.to_ascii_uppercase();
+    CountryCode::for_alpha2(&value)
+        .map(|_| value)
+        .map_err(|_| "country_code must be an ISO 3166-1 alpha-2 code, e.g. GB".to_owned())
+}
+
+pub(crate) fn validate_language_code(value: String) -> Result<String, String> {
+    let error = || "language_code must be an ISO 639-1 code, e.g. en or en-GB".to_owned();
+
+    let (language, region) = match value.split_once('-') {
+        Some((language, region)) => (language, Some(region)),
+        None => (value.as_str(), None),
+    };
+    let language = language.to_ascii_lowercase();
+    Language::from_639_1(&language).ok_or_else(error)?;
+
+    match region {
+        Some(region) => {
+            let region = validate_country_code(region.to_owned()).map_err(|_| error())?;
+            Ok(std::format!("{language}-{region}"))
+        }
+        None => Ok(language),
+    }
+}
+
+fn merge_nullable(
+    value: Option<Option<String>>,
+    current: Option<String>,
+    validate: impl Fn(String) -> Result<String, String>,
+) -> Result<Option<String>, String> {
+    match value {
+        Some(value) => value.map(validate).transpose(),
+        None => Ok(current),
+    }
+}
+
+impl UpdateProfileData {
+    /// Applies the changes to the current profile, validating only the changed fields
+    fn merge(self, user: User) -> Result<UserProfileUpdate, String> {
+        Ok(UserProfileUpdate {
+            alias: merge_nullable(self.alias, user.alias, |x| validate_name("alias", x))?,
+            first_name: merge_nullable(self.first_name, user.first_name, |x| {
+                validate_name("first_name", x)
+            })?,
+            last_name: merge_nullable(self.last_name, user.last_name, |x| {
+                validate_name("last_name", x)
+            })?,
+            phone_number: merge_nullable(
+                self.phone_number,
+                user.phone_number,
+                validate_phone_number,
+            )?,
+            language_code: self
+                .language_code
+                .map(validate_language_code)
+                .transpose()?
+                .unwrap_or(user.language_code),
+            country_code: merge_nullable(
+                self.country_code,
+                user.country_code,
+                validate_country_code,
+            )?,
+        })
+    }
+}
+
+/// Returns the profile of the current user
+///
+#[tracing::instrument(skip(web_service))]
+pub async fn get_me<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authenticated: Authenticated,
+) -> Result<(StatusCode, Json<ProfileResponseData>), ProfileErrorResponse> {
+    let user = web_service
+        .user_db
+        .get_user(&authenticated.user().user_id)
+        .await
+        .map_err(ProfileErrorResponse::DbError)?;
+
+    Ok((StatusCode::OK, Json(user.into())))
+}
+
+/// Changes the profile of the current user
+///
+/// An access token embeds the names, so a fresh one of the same session is sent
+/// in the auth headers.
+#[tracing::instrument(skip(web_service))]
+pub async fn patch_me<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authenticated: Authenticated,
+    body_or_error: Result<Json<UpdateProfileRequestBody>, JsonRejection>,
+) -> Result<(StatusCode, HeaderMap, Json<ProfileResponseData>), ProfileErrorResponse> {
+    let user_info = authenticated
+        .require_user()
+        .map_err(ProfileErrorResponse::Forbidden)?;
+    let session_id = authenticated
+        .session_id()
+        .ok_or(ProfileErrorResponse::Forbidden(
+            ScopeError::ApiKeyNotAllowed,
+        ))?;
+
+    let Json(body) = body_or_error
+        .map_err(|x| x.to_string())
+        .map_err(ProfileErrorResponse::InvalidInputDataFormat)?;
+
+    let user = web_service
+        .user_db
+        .get_user(&user_info.user_id)
+        .await
+        .map_err(ProfileErrorResponse::DbError)?;
+    let update = body
+        .data
+        .merge(user)
+        .map_err(ProfileErrorResponse::InvalidInputDataFormat)?;
+
+    web_service
+        .user_db
+        .update_user_profile(&user_info.user_id, &update)
+        .await
+        .map_err(|error| match error {
+            DbError::UniqueViolation(constraint) if constraint == "users_alias_index" => {
+                ProfileErrorResponse::AlreadyTaken("alias")
+            }
+            DbError::UniqueViolation(constraint) if constraint == "users_phone_number_index" => {
+                ProfileErrorResponse::AlreadyTaken("phone number")
+            }
+            error => ProfileErrorResponse::DbError(error),
+        })?;
+
+    let user = web_service
+        .user_db
+        .get_user(&user_info.user_id)
+        .await
+        .map_err(ProfileErrorResponse::DbError)?;
+
+    let access_token = AccessTokenResponse::new(
+        UserInfo {
+            user_id: user.id,
+            first_name: user.first_name.clone(),
+            last_name: user.last_name.clone(),
+            email_verified: user.email_verified_at.is_some(),
+        },
+        session_id,
+    )
+    .map_err(IssueTokensError::CreateAccessToken)
+    .map_err(ProfileErrorResponse::IssueTokens)?;
+    let mut headers = HeaderMap::new();
+    headers
+        .add_auth_headers(access_token)
+        .map_err(IssueTokensError::AddHeader)
+        .map_err(ProfileErrorResponse::IssueTokens)?;
+
+    Ok((StatusCode::OK, headers, Json(user.into())))
+}
+
+/// Returns the public profile of any user who has not deleted their account
+///
+#[tracing::instrument(skip(web_service))]
+pub async fn get<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    _authenticated: Authenticated,
+    user_id_or_error: Result<Path<Uuid>, PathRejection>,
+) -> Result<(StatusCode, Json<PublicProfileResponseData>), ProfileErrorResponse> {
+    let Path(user_id) = user_id_or_error
+        .map_err(|x| x.to_string())
+        .map_err(ProfileErrorResponse::InvalidInputDataFormat)?;
+
+    let user = web_service
+        .user_db
+        .get_user(&user_id)
+        .await
+        .map_err(ProfileErrorResponse::DbError)?;
+    if user.deleted_at.is_some() {
+        return Err(ProfileErrorResponse::DbError(DbError::NotFoundError));
+    }
+
+    Ok((StatusCode::OK, Json(user.into())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::tokens::AccessToken;
+    use crate::web::email_verification::tests::register_verified_user;
+    use crate::web::users::tests::{create_test_router, get_auth_header_for_name, user_id};
+    use crate::web_service::tests::{
+        deserialize_response_body, get_with_auth_header, patch_with_auth_header,
+    };
+    use axum::Router;
+    use database::utils::random_samples::RandomSample;
+
+    async fn patch_me(
+        router: &Router,
+        token: &str,
+        data: UpdateProfileData,
+    ) -> hyper::Response<http_body::combinators::UnsyncBoxBody<axum::body::Bytes, axum::Error>>
+    {
+        patch_with_auth_header(
+            router,
+            "/api/user/me",
+            &UpdateProfileRequestBody { data },
+            Some(token),
+        )
+        .await
+    }
+
+    #[test]
+    fn test_validate_phone_number() {
+        for valid in ["+14155552671", "+1", "+442071838750"] {
+            assert!(validate_phone_number(valid.to_owned()).is_ok(), "{valid}");
+        }
+        for invalid in ["14155552671", "+", "+0123", "+1415555267123456", "+1 415"] {
+            assert!(
+                validate_phone_number(invalid.to_owned()).is_err(),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_validate_codes() {
+        assert_eq!(validate_country_code("gb".to_owned()), Ok("GB".to_owned()));
+        assert!(validate_country_code("XX".to_owned()).is_err());
+        assert_eq!(validate_language_code("en".to_owned()), Ok("en".to_owned()));
+        assert_eq!(
+            validate_language_code("ru-ru".to_owned()),
+            Ok("ru-RU".to_owned())
+        );
+        assert!(validate_language_code("xx".to_owned()).is_err());
+        assert!(validate_language_code("en-XX".to_owned()).is_err());
+        assert!(validate_language_code("english".to_owned()).is_err());
+    }
+
+    #[tokio::test]
+    async fn should_update_profile_and_reissue_token() {
+        let router = create_test_router().await;
+        let (request, token) = register_verified_user().await;
+
+        let response = get_with_auth_header(&router, "/api/user/me", Some(&token)).await;
+        assert_eq!(response.status(), 200);
+        let profile = deserialize_response_body::<ProfileResponseData>(response).await;
+        assert_eq!(profile.email, request.email());
+        assert!(profile.email_verified);
+
+        let alias = std::format!("alias:{}", String::new_random(22));
+        let response = patch_me(
+            &router,
+            &token,
+            UpdateProfileData {
+                alias: Some(Some(alias.clone())),
+                first_name: Some(Some("Volodymyr".to_owned())),
+                last_name: Some(None),
+                country_code: Some(Some("ua".to_owned())),
+                language_code: Some("uk".to_owned()),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+
+        let new_token = get_auth_header_for_name(&response);
+        let access_token = AccessToken::from_token(&new_token).expect("valid token");
+        assert_eq!(
+            access_token.get_user().first_name.as_deref(),
+            Some("Volodymyr")
+        );
+        assert_eq!(access_token.get_user().last_name, None);
+        assert_eq!(
+            access_token.get_session_id(),
+            AccessToken::from_token(&token)
+                .expect("valid token")
+                .get_session_id()
+        );
+
+        let profile = deserialize_response_body::<ProfileResponseData>(response).await;
+        assert_eq!(profile.alias, Some(alias.clone()));
+        assert_eq!(profile.last_name, None);
+        assert_eq!(profile.phone_number, None);
+        assert_eq!(profile.country_code.as_deref(), Some("UA"));
+        assert_eq!(profile.language_code, "uk");
+
+        let response = get_with_auth_header(
+            &router,
+            std::format!("/api/user/{}", user_id(&token)),
+            Some(&new_token),
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        let public_profile = deserialize_response_body::<PublicProfileResponseData>(response).await;
+        assert_eq!(public_profile.alias, Some(alias));
+    }
+
+    #[tokio::test]
+    async fn should_reject_taken_alias_and_invalid_fields() {
+        let router = create_test_router().await;
+        let (_, token) = register_verified_user().await;
+        let (_, other_token) = register_verified_user().await;
+
+        let alias = std::format!("alias:{}", String::new_random(22));
+        let response = patch_me(
+            &router,
+            &token,
+            UpdateProfileData {
+                alias: Some(Some(alias.clone())),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+
+        let response = patch_me(
+            &router,
+            &other_token,
+            UpdateProfileData {
+                alias: Some(Some(alias)),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(response.status(), 409);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code, Some(ErrorCode::AlreadyTaken));
+
+        let invalid_updates = [
+            UpdateProfileData {
+                phone_number: Some(Some("12345".to_owned())),
+                ..Default::default()
+            },
+            UpdateProfileData {
+                country_code: Some(Some("XX".to_owned())),
+                ..Default::default()
+            },
+            UpdateProfileData {
+                language_code: Some("english".to_owned()),
+                ..Default::default()
+            },
+            UpdateProfileData {
+                alias: Some(Some(" ".to_owned())),
+                ..Default::default()
+            },
+        ];
+        for data in invalid_updates {
+            let response = patch_me(&router, &other_token, data.clone()).await;
+            assert_eq!(response.status(), 400, "{data:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn should_return_public_profile_of_existing_users_only() {
+        let router = create_test_router().await;
+        let (_, token) = register_verified_user().await;
+
+        let response = get_with_auth_header(
+            &router,
+            std::format!("/api/user/{}", Uuid::new_v4()),
+            Some(&token),
+        )
+        .await;
+        assert_eq!(response.status(), 404);
+
+        let response = get_with_auth_header(&router, "/api/user/not-a-uuid", Some(&token)).await;
+        assert_eq!(response.status(), 400);
+    }
+}
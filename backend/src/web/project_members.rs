@@ -0,0 +1,872 @@
+use crate::mailer::{Email, MailerError};
+use crate::models::chat_members::ChatMemberDb;
+use crate::models::chat_message::ChatMessageDb;
+use crate::models::chats::ChatDb;
+use crate::models::company::CompanyDb;
+use crate::models::errors::DbError;
+use crate::models::project::{OwnedProjectInvitationInput, ProjectDb};
+use crate::models::user::UserDb;
+use crate::policy::{is_allowed, Action, Resource};
+use crate::utils::tokens::{generate_opaque_token, hash_opaque_token};
+use crate::web::authentication::{Authenticated, ScopeError};
+use crate::web::authorization::{
+    create_forbidden_response, Authorized, ManageProjectMembers, ReadProject,
+};
+use crate::web::errors::{
+    create_bad_request_error, create_internal_server_error, ALREADY_PROJECT_MEMBER_ERROR_MSG,
+    FORBIDDEN_ERROR_MSG, INVALID_MAIL_MSG, INVALID_PROJECT_INVITATION_ERROR_MSG,
+    PROJECT_OWNER_ERROR_MSG,
+};
+use crate::web::formats::JsonDateTime;
+use crate::web_service::{ErrorCode, ErrorResponseBody, WebService};
+use axum::extract::rejection::JsonRejection;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use database::project_invitations::ProjectInvitation;
+use database::projects::{ProjectMemberProfile, ProjectMemberRole};
+use email_address::EmailAddress;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime, PrimitiveDateTime};
+use uuid::Uuid;
+
+lazy_static! {
+    static ref PROJECT_INVITATION_TOKEN_DURATION: Duration = Duration::seconds(
+        std::env::var("PROJECT_INVITATION_TOKEN_DURATION_IN_SECS")
+            .expect("PROJECT_INVITATION_TOKEN_DURATION_IN_SECS must be in environment")
+            .parse::<i64>()
+            .expect("integer duration")
+    );
+    static ref PROJECT_INVITATION_URL: String = std::env::var("PROJECT_INVITATION_URL")
+        .expect("PROJECT_INVITATION_URL must be in environment");
+}
+
+#[derive(Debug)]
+pub enum ProjectMemberErrorResponse {
+    Forbidden(ScopeError),
+    NotAllowed,
+    DbError(DbError),
+    InvalidEmailFormat,
+    InvalidToken,
+    AlreadyMember,
+    ProjectOwner,
+    JsonRejection(JsonRejection),
+    Mailer(MailerError),
+}
+
+impl IntoResponse for ProjectMemberErrorResponse {
+    fn into_response(self) -> Response {
+        match self {
+            ProjectMemberErrorResponse::Forbidden(error) => error.into_response(),
+            ProjectMemberErrorResponse::NotAllowed => {
+                create_forbidden_response(FORBIDDEN_ERROR_MSG)
+            }
+            ProjectMemberErrorResponse::DbError(db_error) => db_error.into_response(),
+            ProjectMemberErrorResponse::InvalidEmailFormat => (
+                StatusCode::NOT_ACCEPTABLE,
+                Json(ErrorResponseBody {
+                    code: Some(ErrorCode::InvalidEmailFormat),
+                    error: INVALID_MAIL_MSG.into(),
+                }),
+            )
+                .into_response(),
+            ProjectMemberErrorResponse::InvalidToken => {
+                create_bad_request_error(INVALID_PROJECT_INVITATION_ERROR_MSG.into())
+                    .into_response()
+            }
+            ProjectMemberErrorResponse::AlreadyMember => (
+                StatusCode::CONFLICT,
+                Json(ErrorResponseBody {
+                    code: Some(ErrorCode::AlreadyTaken),
+                    error: ALREADY_PROJECT_MEMBER_ERROR_MSG.into(),
+                }),
+            )
+                .into_response(),
+            ProjectMemberErrorResponse::ProjectOwner => {
+                create_forbidden_response(PROJECT_OWNER_ERROR_MSG)
+            }
+            ProjectMemberErrorResponse::JsonRejection(error) => {
+                create_bad_request_error(error.to_string()).into_response()
+            }
+            ProjectMemberErrorResponse::Mailer(error) => {
+                create_internal_server_error(std::format!("Can not send an email: {:?}", error))
+                    .into_response()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProjectMemberRoleParameter {
+    Owner,
+    Editor,
+    Viewer,
+}
+
+impl From<ProjectMemberRoleParameter> for ProjectMemberRole {
+    fn from(value: ProjectMemberRoleParameter) -> Self {
+        match value {
+            ProjectMemberRoleParameter::Owner => ProjectMemberRole::Owner,
+            ProjectMemberRoleParameter::Editor => ProjectMemberRole::Editor,
+            ProjectMemberRoleParameter::Viewer => ProjectMemberRole::Viewer,
+        }
+    }
+}
+
+impl From<ProjectMemberRole> for ProjectMemberRoleParameter {
+    fn from(value: ProjectMemberRole) -> Self {
+        match value {
+            ProjectMemberRole::Owner => ProjectMemberRoleParameter::Owner,
+            ProjectMemberRole::Editor => ProjectMemberRoleParameter::Editor,
+            ProjectMemberRole::Viewer => ProjectMemberRoleParameter::Viewer,
+        }
+    }
+}
+
+pub fn project_member_role_name(role: ProjectMemberRole) -> &'static str {
+    match role {
+        ProjectMemberRole::Owner => "owner",
+        ProjectMemberRole::Editor => "editor",
+        ProjectMemberRole::Viewer => "viewer",
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ProjectMemberResponseData {
+    user_id: Uuid,
+    email: String,
+    alias: Option<String>,
+    first_name: Option<String>,
+    last_name: Option<String>,
+    role: ProjectMemberRoleParameter,
+    created_at: JsonDateTime,
+}
+
+impl From<ProjectMemberProfile> for ProjectMemberResponseData {
+    fn from(value: ProjectMemberProfile) -> Self {
+        ProjectMemberResponseData {
+            user_id: value.user_id,
+            email: value.email,
+            alias: value.alias,
+            first_name: value.first_name,
+            last_name: value.last_name,
+            role: value.role.into(),
+            created_at: value.created_at.into(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ProjectMembersResponseBody {
+    members: Vec<ProjectMemberResponseData>,
+}
+
+/// Lists members of a project, its owner first
+///
+#[tracing::instrument(skip(web_service))]
+pub async fn get_all<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authorized: Authorized<ReadProject>,
+) -> Result<Json<ProjectMembersResponseBody>, DbError> {
+    let members = web_service
+        .project_db
+        .get_project_members(authorized.resource_id())
+        .await?;
+
+    Ok(Json(ProjectMembersResponseBody {
+        members: members.into_iter().map(Into::into).collect(),
+    }))
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct InviteProjectMemberData {
+    email: String,
+    role: ProjectMemberRoleParameter,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct InviteProjectMemberRequestBody {
+    data: InviteProjectMemberData,
+}
+
+/// Either a user who has become a member or an invitation mailed to somebody without an account
+#[derive(Debug, Deserialize, Serialize)]
+pub struct InviteProjectMemberResponseBody {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    invitation_id: Option<Uuid>,
+}
+
+/// Adds a user with a given email to a project
+///
+/// Registered users become members at once and are told by email (201).
+/// Anybody else is mailed a single-use invitation link, see `accept_invitation` (202).
+#[tracing::instrument(skip(web_service, body_or_error))]
+pub async fn post<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authorized: Authorized<ManageProjectMembers>,
+    body_or_error: Result<Json<InviteProjectMemberRequestBody>, JsonRejection>,
+) -> Result<(StatusCode, Json<InviteProjectMemberResponseBody>), ProjectMemberErrorResponse> {
+    let Json(body) = body_or_error.map_err(ProjectMemberErrorResponse::JsonRejection)?;
+
+    if !EmailAddress::is_valid(&body.data.email) {
+        return Err(ProjectMemberErrorResponse::InvalidEmailFormat);
+    }
+
+    let project = web_service
+        .project_db
+        .get_project_by_id(&authorized.resource_id())
+        .await
+        .map_err(ProjectMemberErrorResponse::DbError)?;
+    let role = body.data.role.into();
+
+    let user = match web_service
+        .user_db
+        .get_user_by_email(&body.data.email)
+        .await
+    {
+        Ok(user) => Some(user),
+        Err(DbError::NotFoundError) => None,
+        Err(db_error) => return Err(ProjectMemberErrorResponse::DbError(db_error)),
+    };
+
+    if let Some(user) = user {
+        if user.id == project.user_id {
+            return Err(ProjectMemberErrorResponse::AlreadyMember);
+        }
+        web_service
+            .project_db
+            .insert_project_member(project.id, user.id, role)
+            .await
+            .map_err(|error| match error {
+                DbError::UniqueViolation(_) => ProjectMemberErrorResponse::AlreadyMember,
+                error => ProjectMemberErrorResponse::DbError(error),
+            })?;
+
+        // The membership is made already, so a failed notice does not fail the request
+        let notice = web_service
+            .mailer
+            .send(Email {
+                to: user.email,
+                subject: std::format!("You have been added to {}", project.name),
+                body: std::format!(
+                    "You are now a member of the project {} as {}.",
+                    project.name,
+                    project_member_role_name(role)
+                ),
+            })
+            .await;
+        if let Err(error) = notice {
+            tracing::warn!("can not notify {} of a membership: {:?}", user.id, error);
+        }
+
+        return Ok((
+            StatusCode::CREATED,
+            Json(InviteProjectMemberResponseBody {
+                user_id: Some(user.id),
+                invitation_id: None,
+            }),
+        ));
+    }
+
+    let token = generate_opaque_token();
+    let expires_at = OffsetDateTime::now_utc()
+        .replace_nanosecond(0)
+        .expect("zero is a valid nanosecond")
+        + *PROJECT_INVITATION_TOKEN_DURATION;
+    let invitation_id = web_service
+        .project_db
+        .insert_project_invitation(&OwnedProjectInvitationInput {
+            project_id: project.id,
+            email: body.data.email.clone(),
+            role,
+            invited_by: authorized.user().user_id,
+            token_hash: hash_opaque_token(&token),
+            expires_at: PrimitiveDateTime::new(expires_at.date(), expires_at.time()),
+        })
+        .await
+        .map_err(ProjectMemberErrorResponse::DbError)?;
+
+    web_service
+        .mailer
+        .send(Email {
+            to: body.data.email,
+            subject: std::format!("You are invited to {}", project.name),
+            body: std::format!(
+                "You are invited to the project {}, please sign up and accept the invitation by following the link: {}{token}",
+                project.name,
+                *PROJECT_INVITATION_URL
+            ),
+        })
+        .await
+        .map_err(ProjectMemberErrorResponse::Mailer)?;
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(InviteProjectMemberResponseBody {
+            user_id: None,
+            invitation_id: Some(invitation_id),
+        }),
+    ))
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UpdateProjectMemberData {
+    role: ProjectMemberRoleParameter,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UpdateProjectMemberRequestBody {
+    data: UpdateProjectMemberData,
+}
+
+/// Changes a role of a member, the owner of the project keeps theirs
+///
+#[tracing::instrument(skip(web_service, body_or_error))]
+pub async fn patch<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authorized: Authorized<ManageProjectMembers>,
+    Path((_, user_id)): Path<(Uuid, Uuid)>,
+    body_or_error: Result<Json<UpdateProjectMemberRequestBody>, JsonRejection>,
+) -> Result<StatusCode, ProjectMemberErrorResponse> {
+    let Json(body) = body_or_error.map_err(ProjectMemberErrorResponse::JsonRejection)?;
+
+    let project = web_service
+        .project_db
+        .get_project_by_id(&authorized.resource_id())
+        .await
+        .map_err(ProjectMemberErrorResponse::DbError)?;
+    if project.user_id == user_id {
+        return Err(ProjectMemberErrorResponse::ProjectOwner);
+    }
+
+    let updated = web_service
+        .project_db
+        .update_project_member_role(authorized.resource_id(), user_id, body.data.role.into())
+        .await
+        .map_err(ProjectMemberErrorResponse::DbError)?;
+    if updated == 0 {
+        return Err(ProjectMemberErrorResponse::DbError(DbError::NotFoundError));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Removes a member from a project
+///
+/// Members leave a project on their own, removing others needs the right to manage members.
+#[tracing::instrument(skip(web_service))]
+pub async fn delete<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authorized: Authorized<ReadProject>,
+    Path((_, user_id)): Path<(Uuid, Uuid)>,
+) -> Result<StatusCode, ProjectMemberErrorResponse> {
+    let caller_id = authorized.user().user_id;
+    if caller_id != user_id {
+        let allowed = is_allowed(
+            &web_service.user_db,
+            caller_id,
+            Action::ManageMembers,
+            Resource::Project(authorized.resource_id()),
+        )
+        .await
+        .map_err(ProjectMemberErrorResponse::DbError)?;
+        if !allowed {
+            return Err(ProjectMemberErrorResponse::NotAllowed);
+        }
+    }
+
+    let project = web_service
+        .project_db
+        .get_project_by_id(&authorized.resource_id())
+        .await
+        .map_err(ProjectMemberErrorResponse::DbError)?;
+    if project.user_id == user_id {
+        return Err(ProjectMemberErrorResponse::ProjectOwner);
+    }
+
+    let deleted = web_service
+        .project_db
+        .delete_project_member(authorized.resource_id(), user_id)
+        .await
+        .map_err(ProjectMemberErrorResponse::DbError)?;
+    if deleted == 0 {
+        return Err(ProjectMemberErrorResponse::DbError(DbError::NotFoundError));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ProjectInvitationResponseData {
+    id: Uuid,
+    email: String,
+    role: ProjectMemberRoleParameter,
+    invited_by: Uuid,
+    created_at: JsonDateTime,
+    expires_at: JsonDateTime,
+}
+
+impl From<ProjectInvitation> for ProjectInvitationResponseData {
+    fn from(value: ProjectInvitation) -> Self {
+        ProjectInvitationResponseData {
+            id: value.id,
+            email: value.email,
+            role: value.role.into(),
+            invited_by: value.invited_by,
+            created_at: value.created_at.into(),
+            expires_at: value.expires_at.into(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ProjectInvitationsResponseBody {
+    invitations: Vec<ProjectInvitationResponseData>,
+}
+
+/// Lists invitations of a project which are neither accepted nor expired
+///
+#[tracing::instrument(skip(web_service))]
+pub async fn get_invitations<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authorized: Authorized<ManageProjectMembers>,
+) -> Result<Json<ProjectInvitationsResponseBody>, DbError> {
+    let invitations = web_service
+        .project_db
+        .get_pending_project_invitations(authorized.resource_id())
+        .await?;
+
+    Ok(Json(ProjectInvitationsResponseBody {
+        invitations: invitations.into_iter().map(Into::into).collect(),
+    }))
+}
+
+/// Withdraws an invitation, its link stops working
+///
+#[tracing::instrument(skip(web_service))]
+pub async fn delete_invitation<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authorized: Authorized<ManageProjectMembers>,
+    Path((_, invitation_id)): Path<(Uuid, Uuid)>,
+) -> Result<StatusCode, DbError> {
+    let deleted = web_service
+        .project_db
+        .delete_project_invitation(authorized.resource_id(), invitation_id)
+        .await?;
+    if deleted == 0 {
+        return Err(DbError::NotFoundError);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AcceptProjectInvitationData {
+    token: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AcceptProjectInvitationRequestBody {
+    data: AcceptProjectInvitationData,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AcceptProjectInvitationResponseBody {
+    project_id: Uuid,
+}
+
+/// Makes the current user a member of a project with a mailed invitation token
+///
+/// The token is all it takes, people may sign up with another email than the invited one.
+#[tracing::instrument(skip(web_service, body_or_error))]
+pub async fn accept_invitation<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authenticated: Authenticated,
+    body_or_error: Result<Json<AcceptProjectInvitationRequestBody>, JsonRejection>,
+) -> Result<Json<AcceptProjectInvitationResponseBody>, ProjectMemberErrorResponse> {
+    let user_info = authenticated
+        .require_user()
+        .map_err(ProjectMemberErrorResponse::Forbidden)?;
+    let Json(body) = body_or_error.map_err(ProjectMemberErrorResponse::JsonRejection)?;
+
+    let invitation = match web_service
+        .project_db
+        .get_project_invitation_by_hash(hash_opaque_token(&body.data.token))
+        .await
+    {
+        Ok(invitation) => invitation,
+        Err(DbError::NotFoundError) => return Err(ProjectMemberErrorResponse::InvalidToken),
+        Err(db_error) => return Err(ProjectMemberErrorResponse::DbError(db_error)),
+    };
+
+    let project_id = web_service
+        .project_db
+        .accept_project_invitation(invitation.id, user_info.user_id)
+        .await
+        .map_err(ProjectMemberErrorResponse::DbError)?
+        .ok_or(ProjectMemberErrorResponse::InvalidToken)?;
+
+    Ok(Json(AcceptProjectInvitationResponseBody { project_id }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::web::email_verification::tests::{get_mailed_token, register_verified_user};
+    use crate::web::projects::tests::create_project_with_token;
+    use crate::web::projects::ProjectsResponseBody;
+    use crate::web::users::tests::{create_test_router, user_id};
+    use crate::web_service::tests::{
+        delete_with_auth_header, deserialize_response_body, get_with_auth_header,
+        patch_with_auth_header, post_with_auth_header, TEST_MAILER,
+    };
+    use axum::body::Bytes;
+    use axum::Router;
+    use database::utils::random_samples::RandomSample;
+    use http_body::combinators::UnsyncBoxBody;
+
+    async fn invite(
+        router: &Router,
+        project_id: Uuid,
+        email: &str,
+        role: ProjectMemberRoleParameter,
+        token: &str,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        let request_body = InviteProjectMemberRequestBody {
+            data: InviteProjectMemberData {
+                email: email.to_owned(),
+                role,
+            },
+        };
+
+        post_with_auth_header(
+            router,
+            std::format!("/api/project/{project_id}/members"),
+            &request_body,
+            Some(token),
+        )
+        .await
+    }
+
+    async fn get_members(
+        router: &Router,
+        project_id: Uuid,
+        token: &str,
+    ) -> Vec<(Uuid, ProjectMemberRoleParameter)> {
+        let response = get_with_auth_header(
+            router,
+            std::format!("/api/project/{project_id}/members"),
+            Some(token),
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+
+        deserialize_response_body::<ProjectMembersResponseBody>(response)
+            .await
+            .members
+            .into_iter()
+            .map(|x| (x.user_id, x.role))
+            .collect()
+    }
+
+    async fn update_role(
+        router: &Router,
+        project_id: Uuid,
+        user_id: Uuid,
+        role: ProjectMemberRoleParameter,
+        token: &str,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        let request_body = UpdateProjectMemberRequestBody {
+            data: UpdateProjectMemberData { role },
+        };
+
+        patch_with_auth_header(
+            router,
+            std::format!("/api/project/{project_id}/members/{user_id}"),
+            &request_body,
+            Some(token),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn should_manage_members_of_a_project() {
+        let router = create_test_router().await;
+        let (_, owner_token) = register_verified_user().await;
+        let (editor, editor_token) = register_verified_user().await;
+        let (viewer, viewer_token) = register_verified_user().await;
+        let (_, project) = create_project_with_token(&router, &owner_token).await;
+        let project_id = project.project_id();
+
+        let response = invite(
+            &router,
+            project_id,
+            editor.email(),
+            ProjectMemberRoleParameter::Editor,
+            &owner_token,
+        )
+        .await;
+        assert_eq!(response.status(), 201);
+        assert!(TEST_MAILER
+            .emails_to(editor.email())
+            .pop()
+            .is_some_and(|x| x.body.contains("as editor")));
+        let response = invite(
+            &router,
+            project_id,
+            viewer.email(),
+            ProjectMemberRoleParameter::Viewer,
+            &owner_token,
+        )
+        .await;
+        assert_eq!(response.status(), 201);
+        let response = invite(
+            &router,
+            project_id,
+            viewer.email(),
+            ProjectMemberRoleParameter::Viewer,
+            &owner_token,
+        )
+        .await;
+        assert_eq!(response.status(), 409);
+
+        assert_eq!(
+            get_members(&router, project_id, &viewer_token).await,
+            vec![
+                (user_id(&owner_token), ProjectMemberRoleParameter::Owner),
+                (user_id(&editor_token), ProjectMemberRoleParameter::Editor),
+                (user_id(&viewer_token), ProjectMemberRoleParameter::Viewer),
+            ]
+        );
+
+        let response = get_with_auth_header(&router, "/api/projects", Some(&editor_token)).await;
+        assert_eq!(response.status(), 200);
+        let projects =
+            serde_json::to_value(deserialize_response_body::<ProjectsResponseBody>(response).await)
+                .expect("projects");
+        assert_eq!(projects["projects"][0]["id"], project_id.to_string());
+
+        // Editors work on a project but do not manage its members
+        let response = update_role(
+            &router,
+            project_id,
+            user_id(&viewer_token),
+            ProjectMemberRoleParameter::Editor,
+            &editor_token,
+        )
+        .await;
+        assert_eq!(response.status(), 403);
+
+        let response = update_role(
+            &router,
+            project_id,
+            user_id(&editor_token),
+            ProjectMemberRoleParameter::Owner,
+            &owner_token,
+        )
+        .await;
+        assert_eq!(response.status(), 204);
+        let response = update_role(
+            &router,
+            project_id,
+            user_id(&owner_token),
+            ProjectMemberRoleParameter::Viewer,
+            &editor_token,
+        )
+        .await;
+        assert_eq!(response.status(), 403);
+
+        let response = delete_with_auth_header(
+            &router,
+            std::format!(
+                "/api/project/{project_id}/members/{}",
+                user_id(&editor_token)
+            ),
+            Some(&viewer_token),
+        )
+        .await;
+        assert_eq!(response.status(), 403);
+        let response = delete_with_auth_header(
+            &router,
+            std::format!(
+                "/api/project/{project_id}/members/{}",
+                user_id(&viewer_token)
+            ),
+            Some(&viewer_token),
+        )
+        .await;
+        assert_eq!(response.status(), 204);
+
+        let response = get_with_auth_header(
+            &router,
+            std::format!("/api/project/{project_id}/members"),
+            Some(&viewer_token),
+        )
+        .await;
+        assert_eq!(response.status(), 403);
+        assert_eq!(
+            get_members(&router, project_id, &editor_token).await,
+            vec![
+                (user_id(&owner_token), ProjectMemberRoleParameter::Owner),
+                (user_id(&editor_token), ProjectMemberRoleParameter::Owner),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_invite_somebody_without_an_account() {
+        let router = create_test_router().await;
+        let (_, owner_token) = register_verified_user().await;
+        let (_, invited_token) = register_verified_user().await;
+        let (_, project) = create_project_with_token(&router, &owner_token).await;
+        let project_id = project.project_id();
+        let email = std::format!("{}@test.test", String::new_random(16));
+
+        let response = invite(
+            &router,
+            project_id,
+            "not an email",
+            ProjectMemberRoleParameter::Viewer,
+            &owner_token,
+        )
+        .await;
+        assert_eq!(response.status(), 406);
+
+        let response = invite(
+            &router,
+            project_id,
+            &email,
+            ProjectMemberRoleParameter::Editor,
+            &owner_token,
+        )
+        .await;
+        assert_eq!(response.status(), 202);
+        let invitation = deserialize_response_body::<InviteProjectMemberResponseBody>(response)
+            .await
+            .invitation_id
+            .expect("an invitation");
+
+        let response = get_with_auth_header(
+            &router,
+            std::format!("/api/project/{project_id}/invitations"),
+            Some(&owner_token),
+        )
+        .await;
+        assert_eq!(response.status(), 200);
+        let invitations =
+            deserialize_response_body::<ProjectInvitationsResponseBody>(response).await;
+        assert_eq!(
+            invitations
+                .invitations
+                .iter()
+                .map(|x| x.id)
+                .collect::<Vec<_>>(),
+            vec![invitation]
+        );
+
+        let accept = |token: String| {
+            let router = router.clone();
+            let invited_token = invited_token.clone();
+            async move {
+                post_with_auth_header(
+                    &router,
+                    "/api/user/project-invitations/accept",
+                    &AcceptProjectInvitationRequestBody {
+                        data: AcceptProjectInvitationData { token },
+                    },
+                    Some(&invited_token),
+                )
+                .await
+            }
+        };
+
+        let token = get_mailed_token(&email);
+        let response = accept(token.clone()).await;
+        assert_eq!(response.status(), 200);
+        let response_body =
+            deserialize_response_body::<AcceptProjectInvitationResponseBody>(response).await;
+        assert_eq!(response_body.project_id, project_id);
+        let response = accept(token).await;
+        assert_eq!(response.status(), 400);
+
+        assert_eq!(
+            get_members(&router, project_id, &invited_token).await,
+            vec![
+                (user_id(&owner_token), ProjectMemberRoleParameter::Owner),
+                (user_id(&invited_token), ProjectMemberRoleParameter::Editor),
+            ]
+        );
+
+        let response = invite(
+            &router,
+            project_id,
+            &std::format!("{}@test.test", String::new_random(16)),
+            ProjectMemberRoleParameter::Viewer,
+            &owner_token,
+        )
+        .await;
+        let revoked = deserialize_response_body::<InviteProjectMemberResponseBody>(response)
+            .await
+            .invitation_id
+            .expect("an invitation");
+        let uri = std::format!("/api/project/{project_id}/invitations/{revoked}");
+        let response = delete_with_auth_header(&router, &uri, Some(&owner_token)).await;
+        assert_eq!(response.status(), 204);
+        let response = delete_with_auth_header(&router, &uri, Some(&owner_token)).await;
+        assert_eq!(response.status(), 404);
+    }
+}
@@ -1,20 +1,43 @@
+use crate::models::chat_members::ChatMemberDb;
+use crate::models::chat_message::ChatMessageDb;
+use crate::models::chats::ChatDb;
+use crate::models::company::CompanyDb;
 use crate::models::errors::DbError;
 use crate::models::project::ProjectDb;
 use crate::models::user::UserDb;
-use crate::utils::tokens::AccessToken;
-use crate::web::errors::{create_invalid_response, UNAUTHORIZED_ERROR_RESPONSE};
+use crate::utils::api_keys::Scope;
+use crate::web::audit::record_audit_event;
+use crate::web::authentication::{Authenticated, ScopeError};
+use crate::web::authorization::{Authorized, DeleteProject, ReadProject, UpdateProject};
+use crate::web::errors::{
+    create_invalid_response, PRECONDITION_FAILED_ERROR_MSG, PRECONDITION_REQUIRED_ERROR_MSG,
+};
 use crate::web::formats::JsonDateTime;
-use crate::web_service::WebService;
-use axum::extract::rejection::{JsonRejection, PathRejection};
-use axum::extract::{Path, State};
-use axum::http::StatusCode;
+use crate::web::sessions::ClientInfo;
+use crate::web_service::{ErrorCode, ErrorResponseBody, WebService};
+use axum::extract::rejection::{JsonRejection, QueryRejection};
+use axum::extract::{Query, State};
+use axum::http::header::{ETAG, IF_MATCH};
+use axum::http::{HeaderMap, StatusCode};
 use axum::response::{IntoResponse, Response};
 use axum::Json;
-use axum_auth::AuthBearer;
-use database::projects::{Project, ProjectInput};
+use base64::engine::{general_purpose, GeneralPurpose};
+use base64::Engine;
+use database::audit_events::AuditEventType;
+use database::projects::{
+    Project, ProjectCursor, ProjectFilter, ProjectInput, ProjectSort, ProjectUpdate, SortOrder,
+};
 use serde::{Deserialize, Serialize};
+use sqlx::types::time::PrimitiveDateTime;
+use time::OffsetDateTime;
 use uuid::Uuid;
 
+const BASE_64_URL: GeneralPurpose = general_purpose::URL_SAFE_NO_PAD;
+
+const NAME_MAX_LENGTH: usize = 255;
+const DEFAULT_PROJECTS_LIMIT: i64 = 20;
+const MAX_PROJECTS_LIMIT: i64 = 100;
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct CreateProject {
     name: String,
@@ -28,7 +51,7 @@ pub struct CreateProjectResponseBody {
 
 #[derive(Debug)]
 pub enum CreateProjectErrorResponse {
-    UnAuthorized,
+    Forbidden(ScopeError),
     DbError(DbError),
     InvalidInputDataFormat(String),
 }
@@ -36,9 +59,7 @@ pub enum CreateProjectErrorResponse {
 impl IntoResponse for CreateProjectErrorResponse {
     fn into_response(self) -> Response {
         match self {
-            CreateProjectErrorResponse::UnAuthorized => {
-                UNAUTHORIZED_ERROR_RESPONSE.clone().into_response()
-            }
+            CreateProjectErrorResponse::Forbidden(error) => error.into_response(),
             CreateProjectErrorResponse::DbError(db_error) => db_error.into_response(),
             CreateProjectErrorResponse::InvalidInputDataFormat(error) => {
                 create_invalid_response(error).into_response()
@@ -50,14 +71,22 @@ impl IntoResponse for CreateProjectErrorResponse {
 /// Creates a new project
 ///
 #[tracing::instrument(skip(web_service))]
-pub async fn post<UDB: UserDb, PDB: ProjectDb>(
-    State(web_service): State<WebService<UDB, PDB>>,
-    AuthBearer(token): AuthBearer,
+pub async fn post<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authenticated: Authenticated,
+    client_info: ClientInfo,
     body_or_error: Result<Json<CreateProject>, JsonRejection>,
 ) -> Result<(StatusCode, Json<CreateProjectResponseBody>), CreateProjectErrorResponse> {
-    let access_token =
-        AccessToken::from_token(token).map_err(|_| CreateProjectErrorResponse::UnAuthorized)?;
-    let user_info = access_token.get_user();
+    let user_info = authenticated
+        .require_scope(Scope::ProjectsWrite)
+        .map_err(CreateProjectErrorResponse::Forbidden)?;
 
     let request = body_or_error
         .map_err(|x| x.to_string())
@@ -75,6 +104,15 @@ pub async fn post<UDB: UserDb, PDB: ProjectDb>(
         .await
         .map_err(CreateProjectErrorResponse::DbError)?;
 
+    record_audit_event(
+        web_service.audit_log.as_ref(),
+        AuditEventType::ProjectCreated,
+        user_info.user_id,
+        Some(project_id),
+        &client_info,
+    )
+    .await;
+
     Ok((
         StatusCode::CREATED,
         Json(CreateProjectResponseBody { project_id }),
@@ -83,6 +121,7 @@ pub async fn post<UDB: UserDb, PDB: ProjectDb>(
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ProjectResponseData {
+    id: Uuid,
     name: String,
     description: String,
     user_id: Uuid,
@@ -93,6 +132,7 @@ pub struct ProjectResponseData {
 impl From<Project> for ProjectResponseData {
     fn from(value: Project) -> Self {
         ProjectResponseData {
+            id: value.id,
             name: value.name,
             description: value.description,
             user_id: value.user_id,
@@ -105,78 +145,453 @@ impl From<Project> for ProjectResponseData {
 #[derive(Debug)]
 pub enum GetProjectErrorResponse {
     DbError(DbError),
-    InvalidInputDataFormat(String),
 }
 
 impl IntoResponse for GetProjectErrorResponse {
     fn into_response(self) -> Response {
         match self {
             GetProjectErrorResponse::DbError(db_error) => db_error.into_response(),
-            GetProjectErrorResponse::InvalidInputDataFormat(error) => {
+        }
+    }
+}
+
+fn micros_to_date_time(micros: i128) -> Option<PrimitiveDateTime> {
+    OffsetDateTime::from_unix_timestamp_nanos(micros.checked_mul(1000)?)
+        .ok()
+        .map(|date| PrimitiveDateTime::new(date.date(), date.time()))
+}
+
+/// `updated_at` in microseconds, a project gets a new one with every update
+fn project_etag(project: &Project) -> String {
+    let micros = project.updated_at.assume_utc().unix_timestamp_nanos() / 1000;
+    std::format!("\"{micros}\"")
+}
+
+/// Reads the `updated_at` back from an `If-Match` ETag, `None` when it is no ETag of ours
+fn parse_project_etag(etag: &str) -> Option<PrimitiveDateTime> {
+    etag.trim()
+        .strip_prefix('"')
+        .and_then(|etag| etag.strip_suffix('"'))
+        .and_then(|micros| micros.parse::<i128>().ok())
+        .and_then(micros_to_date_time)
+}
+
+fn project_response(status: StatusCode, project: Project) -> Response {
+    let etag = project_etag(&project);
+    let response_data: ProjectResponseData = project.into();
+
+    (status, [(ETAG, etag)], Json(response_data)).into_response()
+}
+
+/// Returns a project to its owner, members and members of its companies
+///
+/// The `ETag` header goes back as `If-Match` of an update.
+#[tracing::instrument(skip(web_service))]
+pub async fn get<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authorized: Authorized<ReadProject>,
+    client_info: ClientInfo,
+) -> Result<Response, GetProjectErrorResponse> {
+    let project = web_service
+        .project_db
+        .get_project_by_id(&authorized.resource_id())
+        .await
+        .map_err(GetProjectErrorResponse::DbError)?;
+
+    record_audit_event(
+        web_service.audit_log.as_ref(),
+        AuditEventType::ProjectRead,
+        authorized.user().user_id,
+        Some(project.id),
+        &client_info,
+    )
+    .await;
+
+    Ok(project_response(StatusCode::OK, project))
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProjectSortParameter {
+    CreatedAt,
+    UpdatedAt,
+}
+
+impl From<ProjectSortParameter> for ProjectSort {
+    fn from(value: ProjectSortParameter) -> Self {
+        match value {
+            ProjectSortParameter::CreatedAt => ProjectSort::CreatedAt,
+            ProjectSortParameter::UpdatedAt => ProjectSort::UpdatedAt,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrderParameter {
+    Asc,
+    Desc,
+}
+
+impl From<SortOrderParameter> for SortOrder {
+    fn from(value: SortOrderParameter) -> Self {
+        match value {
+            SortOrderParameter::Asc => SortOrder::Asc,
+            SortOrderParameter::Desc => SortOrder::Desc,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ProjectsQuery {
+    owner_id: Option<Uuid>,
+    pub company_id: Option<Uuid>,
+    member_id: Option<Uuid>,
+    /// `created_at` by default
+    sort: Option<ProjectSortParameter>,
+    /// `desc` by default
+    order: Option<SortOrderParameter>,
+    /// `next_cursor` of the previous page, sent with the same sort and order
+    cursor: Option<String>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ProjectsResponseBody {
+    projects: Vec<ProjectResponseData>,
+    /// `None` on the last page
+    next_cursor: Option<String>,
+}
+
+/// `<sorted at in microseconds>.<id>`, opaque to clients
+fn encode_cursor(cursor: ProjectCursor) -> String {
+    let micros = cursor.sorted_at.assume_utc().unix_timestamp_nanos() / 1000;
+    BASE_64_URL.encode(std::format!("{micros}.{}", cursor.id))
+}
+
+fn decode_cursor(cursor: &str) -> Option<ProjectCursor> {
+    let decoded = String::from_utf8(BASE_64_URL.decode(cursor).ok()?).ok()?;
+    let (micros, id) = decoded.split_once('.')?;
+
+    Some(ProjectCursor {
+        sorted_at: micros_to_date_time(micros.parse().ok()?)?,
+        id: Uuid::parse_str(id).ok()?,
+    })
+}
+
+#[derive(Debug)]
+pub enum ListProjectsErrorResponse {
+    Forbidden(ScopeError),
+    DbError(DbError),
+    InvalidInputDataFormat(String),
+}
+
+impl IntoResponse for ListProjectsErrorResponse {
+    fn into_response(self) -> Response {
+        match self {
+            ListProjectsErrorResponse::Forbidden(error) => error.into_response(),
+            ListProjectsErrorResponse::DbError(db_error) => db_error.into_response(),
+            ListProjectsErrorResponse::InvalidInputDataFormat(error) => {
                 create_invalid_response(error).into_response()
             }
         }
     }
 }
 
-/// Creates a new doc
+/// Lists projects the user owns, is a member of or reads through a company, a page at a time
 ///
 #[tracing::instrument(skip(web_service))]
-pub async fn get<UDB: UserDb, PDB: ProjectDb>(
-    State(web_service): State<WebService<UDB, PDB>>,
-    project_id_or_error: Result<Path<Uuid>, PathRejection>,
-) -> Result<(StatusCode, Json<ProjectResponseData>), GetProjectErrorResponse> {
-    let project_id = project_id_or_error
+pub async fn get_all<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authenticated: Authenticated,
+    query_or_error: Result<Query<ProjectsQuery>, QueryRejection>,
+) -> Result<(StatusCode, Json<ProjectsResponseBody>), ListProjectsErrorResponse> {
+    let user_info = authenticated
+        .require_scope(Scope::ProjectsRead)
+        .map_err(ListProjectsErrorResponse::Forbidden)?;
+
+    let Query(query) = query_or_error
+        .map_err(|x| x.to_string())
+        .map_err(ListProjectsErrorResponse::InvalidInputDataFormat)?;
+
+    list_projects(&web_service.project_db, user_info.user_id, query)
+        .await
+        .map(|body| (StatusCode::OK, Json(body)))
+}
+
+/// A page of projects visible to a user, shared by the project and the company listings
+pub async fn list_projects(
+    project_db: &impl ProjectDb,
+    user_id: Uuid,
+    query: ProjectsQuery,
+) -> Result<ProjectsResponseBody, ListProjectsErrorResponse> {
+    let limit = query.limit.unwrap_or(DEFAULT_PROJECTS_LIMIT);
+    if !(1..=MAX_PROJECTS_LIMIT).contains(&limit) {
+        return Err(ListProjectsErrorResponse::InvalidInputDataFormat(
+            std::format!("limit must be 1 to {MAX_PROJECTS_LIMIT}"),
+        ));
+    }
+    let after = query
+        .cursor
+        .as_deref()
+        .map(|cursor| {
+            decode_cursor(cursor).ok_or_else(|| {
+                ListProjectsErrorResponse::InvalidInputDataFormat("Invalid cursor".to_owned())
+            })
+        })
+        .transpose()?;
+    let sort = query.sort.map(Into::into).unwrap_or_default();
+    let order = query.order.map(Into::into).unwrap_or_default();
+    let filter = ProjectFilter {
+        owner_id: query.owner_id,
+        company_id: query.company_id,
+        member_id: query.member_id,
+    };
+
+    // One more than asked for tells whether there is a next page
+    let mut projects = project_db
+        .get_projects(user_id, &filter, sort, order, after, limit + 1)
+        .await
+        .map_err(ListProjectsErrorResponse::DbError)?;
+    let next_cursor = if projects.len() as i64 > limit {
+        projects.truncate(limit as usize);
+        projects
+            .last()
+            .map(|project| encode_cursor(ProjectCursor::new(project, sort)))
+    } else {
+        None
+    };
+
+    Ok(ProjectsResponseBody {
+        projects: projects.into_iter().map(Into::into).collect(),
+        next_cursor,
+    })
+}
+
+/// Fields left out keep their values
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct UpdateProjectRequestBody {
+    name: Option<String>,
+    description: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum UpdateProjectErrorResponse {
+    DbError(DbError),
+    InvalidInputDataFormat(String),
+    PreconditionRequired,
+    PreconditionFailed,
+}
+
+impl IntoResponse for UpdateProjectErrorResponse {
+    fn into_response(self) -> Response {
+        match self {
+            UpdateProjectErrorResponse::DbError(db_error) => db_error.into_response(),
+            UpdateProjectErrorResponse::InvalidInputDataFormat(error) => {
+                create_invalid_response(error).into_response()
+            }
+            UpdateProjectErrorResponse::PreconditionRequired => (
+                StatusCode::PRECONDITION_REQUIRED,
+                Json(ErrorResponseBody {
+                    code: Some(ErrorCode::PreconditionRequired),
+                    error: PRECONDITION_REQUIRED_ERROR_MSG.to_owned(),
+                }),
+            )
+                .into_response(),
+            UpdateProjectErrorResponse::PreconditionFailed => (
+                StatusCode::PRECONDITION_FAILED,
+                Json(ErrorResponseBody {
+                    code: Some(ErrorCode::PreconditionFailed),
+                    error: PRECONDITION_FAILED_ERROR_MSG.to_owned(),
+                }),
+            )
+                .into_response(),
+        }
+    }
+}
+
+fn validate_project_name(name: &str) -> Result<(), UpdateProjectErrorResponse> {
+    if name.trim().is_empty() || name.chars().count() > NAME_MAX_LENGTH {
+        return Err(UpdateProjectErrorResponse::InvalidInputDataFormat(
+            std::format!("name must be 1 to {NAME_MAX_LENGTH} characters"),
+        ));
+    }
+    Ok(())
+}
+
+/// Updates a project, `If-Match` carries the ETag the client has seen
+///
+/// Fails with 412 when someone else has updated the project since.
+#[tracing::instrument(skip(web_service, body_or_error))]
+pub async fn patch<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authorized: Authorized<UpdateProject>,
+    client_info: ClientInfo,
+    headers: HeaderMap,
+    body_or_error: Result<Json<UpdateProjectRequestBody>, JsonRejection>,
+) -> Result<Response, UpdateProjectErrorResponse> {
+    let if_match = headers
+        .get(IF_MATCH)
+        .ok_or(UpdateProjectErrorResponse::PreconditionRequired)?;
+    let updated_at = if_match
+        .to_str()
+        .ok()
+        .and_then(parse_project_etag)
+        .ok_or(UpdateProjectErrorResponse::PreconditionFailed)?;
+
+    let Json(request) = body_or_error
         .map_err(|x| x.to_string())
-        .map_err(GetProjectErrorResponse::InvalidInputDataFormat)?;
+        .map_err(UpdateProjectErrorResponse::InvalidInputDataFormat)?;
+    if let Some(name) = &request.name {
+        validate_project_name(name)?;
+    }
 
     let project = web_service
         .project_db
-        .get_project_by_id(&project_id)
+        .get_project_by_id(&authorized.resource_id())
         .await
-        .map_err(GetProjectErrorResponse::DbError)?;
+        .map_err(UpdateProjectErrorResponse::DbError)?;
+    let update = ProjectUpdate {
+        name: request.name.unwrap_or(project.name),
+        description: request.description.unwrap_or(project.description),
+    };
 
-    Ok((StatusCode::CREATED, Json(project.into())))
+    let project = web_service
+        .project_db
+        .update_project(&project.id, &update, updated_at, authorized.user().user_id)
+        .await
+        .map_err(UpdateProjectErrorResponse::DbError)?
+        .ok_or(UpdateProjectErrorResponse::PreconditionFailed)?;
+
+    record_audit_event(
+        web_service.audit_log.as_ref(),
+        AuditEventType::ProjectUpdated,
+        authorized.user().user_id,
+        Some(project.id),
+        &client_info,
+    )
+    .await;
+
+    Ok(project_response(StatusCode::OK, project))
+}
+
+/// Soft deletes a project, only its owner may
+///
+#[tracing::instrument(skip(web_service))]
+pub async fn delete<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authorized: Authorized<DeleteProject>,
+    client_info: ClientInfo,
+) -> Result<StatusCode, DbError> {
+    let deleted = web_service
+        .project_db
+        .delete_project(&authorized.resource_id(), authorized.user().user_id)
+        .await?;
+    if deleted == 0 {
+        return Err(DbError::NotFoundError);
+    }
+
+    record_audit_event(
+        web_service.audit_log.as_ref(),
+        AuditEventType::ProjectDeleted,
+        authorized.user().user_id,
+        Some(authorized.resource_id()),
+        &client_info,
+    )
+    .await;
+
+    Ok(StatusCode::NO_CONTENT)
 }
 
 #[cfg(test)]
-mod tests {
-    use crate::web::projects::{CreateProject, CreateProjectResponseBody, ProjectResponseData};
-    use crate::web::users::tests::{
-        create_test_router, get_auth_header_for_name, register_new_user,
+pub mod tests {
+    use crate::web::email_verification::tests::register_verified_user;
+    use crate::web::errors::FORBIDDEN_ERROR_MSG;
+    use crate::web::projects::{
+        CreateProject, CreateProjectResponseBody, ProjectResponseData, ProjectsResponseBody,
+        UpdateProjectRequestBody,
     };
+    use crate::web::users::tests::{create_test_router, user_id};
     use crate::web_service::tests::{
-        deserialize_response_body, get, get_with_auth_header, post_with_auth_header,
+        delete_with_auth_header, deserialize_response_body, get, get_with_auth_header,
+        post_with_auth_header, send_request,
     };
+    use crate::web_service::{ErrorCode, ErrorResponseBody};
+    use axum::body::Bytes;
+    use axum::http::header::{AUTHORIZATION, CONTENT_TYPE, ETAG, IF_MATCH};
+    use axum::http::{Method, Request};
+    use axum::Router;
     use database::utils::random_samples::RandomSample;
+    use http_body::combinators::UnsyncBoxBody;
     use uuid::Uuid;
 
-    async fn create_project() -> (CreateProject, CreateProjectResponseBody, String) {
-        let (_, response) = register_new_user(None).await;
-
-        let auth_token = get_auth_header_for_name(&response);
-
-        let router = create_test_router().await;
+    pub fn create_project_request() -> CreateProject {
+        CreateProject {
+            name: String::new_random(100),
+            description: String::new_random(100),
+        }
+    }
 
-        let name = String::new_random(100);
-        let description = String::new_random(100);
-
-        let request_body = CreateProject {
-            name: name.clone(),
-            description: description.clone(),
-        };
+    /// Creates a project with a random name on behalf of an access token or an API key
+    pub async fn create_project_with_token(
+        router: &Router,
+        token: &str,
+    ) -> (CreateProject, CreateProjectResponseBody) {
+        let request_body = create_project_request();
 
         let uri = "/api/project/new";
 
-        let response = post_with_auth_header(&router, uri, &request_body, Some(&auth_token)).await;
+        let response = post_with_auth_header(router, uri, &request_body, Some(token)).await;
         assert_eq!(response.status(), 201);
 
-        let auth_tokens = get_auth_header_for_name(&response);
-        assert_eq!(auth_tokens, auth_token);
-
         let create_project_response =
             deserialize_response_body::<CreateProjectResponseBody>(response).await;
 
+        (request_body, create_project_response)
+    }
+
+    impl CreateProjectResponseBody {
+        pub fn project_id(&self) -> Uuid {
+            self.project_id
+        }
+    }
+
+    async fn create_project() -> (CreateProject, CreateProjectResponseBody, String) {
+        let (_, auth_token) = register_verified_user().await;
+
+        let router = create_test_router().await;
+
+        let (request_body, create_project_response) =
+            create_project_with_token(&router, &auth_token).await;
+
         (request_body, create_project_response, auth_token)
     }
 
@@ -188,10 +603,8 @@ mod tests {
 
         let uri = std::format!("/api/project/{}", create_project_response.project_id);
         let response = get_with_auth_header(&router, uri, Some(&token)).await;
-        assert_eq!(response.status(), 201);
-
-        let auth_tokens = get_auth_header_for_name(&response);
-        assert_eq!(auth_tokens, token);
+        assert_eq!(response.status(), 200);
+        assert!(response.headers().contains_key(ETAG));
 
         let project_response = deserialize_response_body::<ProjectResponseData>(response).await;
         assert_eq!(project_response.name, create_project_request.name);
@@ -209,4 +622,194 @@ mod tests {
         let response = get(&router, uri).await;
         assert_eq!(response.status(), 401);
     }
+
+    #[tokio::test]
+    async fn should_not_return_a_project_to_another_user() {
+        let router = create_test_router().await;
+
+        let (_, create_project_response, _) = create_project().await;
+        let (_, other_token) = register_verified_user().await;
+
+        let uri = std::format!("/api/project/{}", create_project_response.project_id);
+        let response = get_with_auth_header(&router, uri, Some(&other_token)).await;
+        assert_eq!(response.status(), 403);
+
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code, Some(ErrorCode::Forbidden));
+        assert_eq!(response_body.error, FORBIDDEN_ERROR_MSG);
+    }
+
+    #[tokio::test]
+    async fn should_not_find_an_unknown_project() {
+        let router = create_test_router().await;
+        let (_, token) = register_verified_user().await;
+
+        let uri = std::format!("/api/project/{}", Uuid::new_v4());
+        let response = get_with_auth_header(&router, uri, Some(&token)).await;
+        assert_eq!(response.status(), 404);
+    }
+
+    async fn patch_project(
+        router: &Router,
+        project_id: Uuid,
+        body: &UpdateProjectRequestBody,
+        if_match: Option<&str>,
+        token: &str,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        let mut request = Request::builder()
+            .method(Method::PATCH)
+            .uri(std::format!("/api/project/{project_id}"))
+            .header(CONTENT_TYPE, "application/json")
+            .header(AUTHORIZATION, std::format!("Bearer {token}"));
+        if let Some(if_match) = if_match {
+            request = request.header(IF_MATCH, if_match);
+        }
+        let request = request
+            .body(serde_json::to_vec(body).expect("body is serialized").into())
+            .expect("failed to build PATCH request");
+
+        send_request(router, request).await
+    }
+
+    async fn list_projects(router: &Router, query: &str, token: &str) -> ProjectsResponseBody {
+        let response =
+            get_with_auth_header(router, std::format!("/api/projects?{query}"), Some(token)).await;
+        assert_eq!(response.status(), 200);
+        deserialize_response_body::<ProjectsResponseBody>(response).await
+    }
+
+    fn project_ids(response: &ProjectsResponseBody) -> Vec<Uuid> {
+        response.projects.iter().map(|x| x.id).collect()
+    }
+
+    #[tokio::test]
+    async fn should_list_projects_a_page_at_a_time() {
+        let router = create_test_router().await;
+        let (_, token) = register_verified_user().await;
+        let mut created = Vec::new();
+        for _ in 0..3 {
+            created.push(
+                create_project_with_token(&router, &token)
+                    .await
+                    .1
+                    .project_id,
+            );
+        }
+
+        let first_page = list_projects(&router, "limit=2", &token).await;
+        assert_eq!(project_ids(&first_page), vec![created[2], created[1]]);
+        let cursor = first_page.next_cursor.expect("there is a next page");
+
+        let second_page =
+            list_projects(&router, &std::format!("limit=2&cursor={cursor}"), &token).await;
+        assert_eq!(project_ids(&second_page), vec![created[0]]);
+        assert_eq!(second_page.next_cursor, None);
+
+        let ascending = list_projects(&router, "sort=updated_at&order=asc", &token).await;
+        assert_eq!(project_ids(&ascending), created);
+
+        let user_id = user_id(&token);
+        let owned = list_projects(&router, &std::format!("owner_id={user_id}"), &token).await;
+        assert_eq!(owned.projects.len(), 3);
+        let others = list_projects(
+            &router,
+            &std::format!("owner_id={}", Uuid::new_v4()),
+            &token,
+        )
+        .await;
+        assert!(others.projects.is_empty());
+
+        let (_, other_token) = register_verified_user().await;
+        let other = list_projects(&router, "", &other_token).await;
+        assert!(other.projects.is_empty());
+
+        let response =
+            get_with_auth_header(&router, "/api/projects?cursor=invalid", Some(&token)).await;
+        assert_eq!(response.status(), 400);
+        let response = get_with_auth_header(&router, "/api/projects?sort=name", Some(&token)).await;
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn should_update_a_project_unless_it_changed() {
+        let router = create_test_router().await;
+        let (request, project, token) = create_project().await;
+        let uri = std::format!("/api/project/{}", project.project_id);
+
+        let response = get_with_auth_header(&router, &uri, Some(&token)).await;
+        let etag = response.headers()[ETAG]
+            .to_str()
+            .expect("ASCII ETag")
+            .to_owned();
+        let update = UpdateProjectRequestBody {
+            name: Some(String::new_random(50)),
+            description: None,
+        };
+
+        let response = patch_project(&router, project.project_id, &update, None, &token).await;
+        assert_eq!(response.status(), 428);
+
+        let response =
+            patch_project(&router, project.project_id, &update, Some(&etag), &token).await;
+        assert_eq!(response.status(), 200);
+        let new_etag = response.headers()[ETAG]
+            .to_str()
+            .expect("ASCII ETag")
+            .to_owned();
+        assert_ne!(new_etag, etag);
+        let response_body = deserialize_response_body::<ProjectResponseData>(response).await;
+        assert_eq!(Some(response_body.name), update.name);
+        assert_eq!(response_body.description, request.description);
+
+        let response =
+            patch_project(&router, project.project_id, &update, Some(&etag), &token).await;
+        assert_eq!(response.status(), 412);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code, Some(ErrorCode::PreconditionFailed));
+
+        let invalid = UpdateProjectRequestBody {
+            name: Some(" ".to_owned()),
+            description: None,
+        };
+        let response = patch_project(
+            &router,
+            project.project_id,
+            &invalid,
+            Some(&new_etag),
+            &token,
+        )
+        .await;
+        assert_eq!(response.status(), 400);
+
+        let (_, other_token) = register_verified_user().await;
+        let response = patch_project(
+            &router,
+            project.project_id,
+            &update,
+            Some(&new_etag),
+            &other_token,
+        )
+        .await;
+        assert_eq!(response.status(), 403);
+    }
+
+    #[tokio::test]
+    async fn should_soft_delete_a_project() {
+        let router = create_test_router().await;
+        let (_, project, token) = create_project().await;
+        let uri = std::format!("/api/project/{}", project.project_id);
+
+        let (_, other_token) = register_verified_user().await;
+        let response = delete_with_auth_header(&router, &uri, Some(&other_token)).await;
+        assert_eq!(response.status(), 403);
+
+        let response = delete_with_auth_header(&router, &uri, Some(&token)).await;
+        assert_eq!(response.status(), 204);
+
+        let response = get_with_auth_header(&router, &uri, Some(&token)).await;
+        assert_eq!(response.status(), 404);
+        let response = delete_with_auth_header(&router, &uri, Some(&token)).await;
+        assert_eq!(response.status(), 404);
+        assert!(list_projects(&router, "", &token).await.projects.is_empty());
+    }
 }
@@ -0,0 +1,329 @@
+use crate::models::chat_members::ChatMemberDb;
+use crate::models::chat_message::ChatMessageDb;
+use crate::models::chats::ChatDb;
+use crate::models::company::CompanyDb;
+use crate::models::errors::DbError;
+use crate::models::project::ProjectDb;
+use crate::models::user::UserDb;
+use crate::rate_limit::{BucketLimit, RateLimiter};
+use crate::utils::tokens::MfaPendingToken;
+use crate::web::authentication::Authenticated;
+use crate::web::errors::{
+    create_bad_request_error, ACCOUNT_LOCKED_ERROR_MSG, BODY_TOO_LARGE_ERROR_MSG,
+    TOO_MANY_REQUESTS_ERROR_MSG,
+};
+use crate::web_service::{ErrorCode, ErrorResponseBody, WebService};
+use axum::body::Body;
+use axum::extract::{ConnectInfo, State};
+use axum::http::header::RETRY_AFTER;
+use axum::http::{Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use http_body::{LengthLimitError, Limited};
+use serde::Deserialize;
+use std::net::SocketAddr;
+use time::Duration;
+
+/// Login bodies are small, bigger ones are not buffered to look for an account
+const LOGIN_BODY_LIMIT: usize = 16 * 1024;
+
+/// Marks a response to a wrong password, counted towards locking the account
+#[derive(Debug, Clone, Copy)]
+pub struct FailedLogin;
+
+#[derive(Deserialize)]
+struct AccountData {
+    email: String,
+}
+
+#[derive(Deserialize)]
+struct AccountBody {
+    data: AccountData,
+}
+
+#[derive(Deserialize)]
+struct MfaLoginData {
+    mfa_token: String,
+}
+
+#[derive(Deserialize)]
+struct MfaLoginBody {
+    data: MfaLoginData,
+}
+
+/// Email of a request body like `{"data": {"email": ...}}`, case does not make another account
+///
+/// A second factor login has no email, the account is the user of its MFA token. Requests of
+/// a signed in user re-entering their password are counted towards the user's own account.
+async fn account_of(
+    user_db: &impl UserDb,
+    authenticated: Option<&Authenticated>,
+    body: &[u8],
+) -> Option<String> {
+    if let Some(authenticated) = authenticated {
+        let user = user_db.get_user(&authenticated.user().user_id).await.ok()?;
+        return Some(user.email.trim().to_lowercase());
+    }
+    if let Ok(body) = serde_json::from_slice::<AccountBody>(body) {
+        return Some(body.data.email.trim().to_lowercase());
+    }
+
+    let body = serde_json::from_slice::<MfaLoginBody>(body).ok()?;
+    let mfa_token = MfaPendingToken::from_token(body.data.mfa_token).ok()?;
+    let user = user_db.get_user(&mfa_token.user_id).await.ok()?;
+
+    Some(user.email.trim().to_lowercase())
+}
+
+fn create_too_many_requests_response(retry_after: Duration, error: &str) -> Response {
+    let seconds = retry_after.as_seconds_f64().ceil().max(1.0) as u64;
+
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(RETRY_AFTER, seconds.to_string())],
+        Json(ErrorResponseBody {
+            code: Some(ErrorCode::TooManyRequests),
+            error: error.to_owned(),
+        }),
+    )
+        .into_response()
+}
+
+fn create_body_too_large_response() -> Response {
+    (
+        StatusCode::PAYLOAD_TOO_LARGE,
+        Json(ErrorResponseBody {
+            code: None,
+            error: BODY_TOO_LARGE_ERROR_MSG.to_owned(),
+        }),
+    )
+        .into_response()
+}
+
+async fn take_token(
+    rate_limiter: &RateLimiter,
+    key: String,
+    limit: BucketLimit,
+) -> Result<(), Response> {
+    match rate_limiter.store.take_token(&key, limit).await {
+        Ok(None) => Ok(()),
+        Ok(Some(retry_after)) => Err(create_too_many_requests_response(
+            retry_after,
+            TOO_MANY_REQUESTS_ERROR_MSG,
+        )),
+        Err(db_error) => Err(db_error.into_response()),
+    }
+}
+
+/// Token buckets per IP and per account in front of routes taking passwords or emails
+///
+/// Accounts get locked after failed logins, marked by handlers with the `FailedLogin`
+/// response extension, and unlocked by a login which has issued tokens.
+pub async fn limit_login_attempts<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Result<Response, Response> {
+    let rate_limiter = web_service.rate_limiter.as_ref();
+    let (parts, body) = req.into_parts();
+
+    if let Some(ConnectInfo(addr)) = parts.extensions.get::<ConnectInfo<SocketAddr>>() {
+        take_token(
+            rate_limiter,
+            std::format!("ip:{}", addr.ip()),
+            rate_limiter.limits.per_ip,
+        )
+        .await?;
+    }
+
+    let body = hyper::body::to_bytes(Limited::new(body, LOGIN_BODY_LIMIT))
+        .await
+        .map_err(|error| {
+            if error.is::<LengthLimitError>() {
+                create_body_too_large_response()
+            } else {
+                create_bad_request_error(error.to_string()).into_response()
+            }
+        })?;
+    let account = account_of(
+        &web_service.user_db,
+        parts.extensions.get::<Authenticated>(),
+        &body,
+    )
+    .await;
+
+    if let Some(account) = &account {
+        match rate_limiter.store.get_lockout(account).await {
+            Ok(None) => {}
+            Ok(Some(lockout)) => {
+                return Err(create_too_many_requests_response(
+                    lockout,
+                    ACCOUNT_LOCKED_ERROR_MSG,
+                ))
+            }
+            Err(db_error) => return Err(db_error.into_response()),
+        }
+        take_token(
+            rate_limiter,
+            std::format!("account:{account}"),
+            rate_limiter.limits.per_account,
+        )
+        .await?;
+    }
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+
+    if let Some(account) = account {
+        let result: Result<(), DbError> = if response.extensions().get::<FailedLogin>().is_some() {
+            rate_limiter
+                .store
+                .record_failed_login(&account, rate_limiter.limits.lockout)
+                .await
+                .map(|_| ())
+        } else if response.headers().contains_key("x-auth-token") {
+            rate_limiter.store.reset_failed_logins(&account).await
+        } else {
+            Ok(())
+        };
+
+        if let Err(error) = result {
+            tracing::error!("Can not update failed logins of {account}: {:?}", error);
+        }
+    }
+
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rate_limit::RateLimits;
+    use crate::web::users::tests::{create_test_router, register_new_user};
+    use crate::web::users::{LoginUserData, LoginUserDataBody};
+    use crate::web_service::tests::{deserialize_response_body, post, send_request};
+    use axum::http::header::CONTENT_TYPE;
+    use axum::http::Method;
+    use axum::Router;
+
+    fn login_request(email: &str, password: &str) -> LoginUserDataBody {
+        LoginUserDataBody::new(LoginUserData::new(email, password))
+    }
+
+    async fn login(router: &Router, email: &str, password: &str) -> StatusCode {
+        post(router, "/api/user/login", &login_request(email, password))
+            .await
+            .status()
+    }
+
+    #[tokio::test]
+    async fn should_lock_an_account_after_failed_logins() {
+        let (user, response) = register_new_user(None).await;
+        assert_eq!(response.status(), 201);
+        let router = create_test_router().await;
+        let threshold = RateLimits::from_env().lockout.threshold;
+
+        for _ in 0..threshold {
+            assert_eq!(
+                login(&router, user.email(), "wrong password").await,
+                StatusCode::UNAUTHORIZED
+            );
+        }
+
+        let response = post(
+            &router,
+            "/api/user/login",
+            &login_request(&user.email().to_uppercase(), user.password()),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok())
+            .expect("Retry-After in seconds");
+        assert!(retry_after > 0);
+
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.code, Some(ErrorCode::TooManyRequests));
+        assert_eq!(response_body.error, ACCOUNT_LOCKED_ERROR_MSG);
+    }
+
+    #[tokio::test]
+    async fn should_forget_failed_logins_after_a_successful_one() {
+        let (user, response) = register_new_user(None).await;
+        assert_eq!(response.status(), 201);
+        let router = create_test_router().await;
+        let threshold = RateLimits::from_env().lockout.threshold;
+
+        for _ in 1..threshold {
+            login(&router, user.email(), "wrong password").await;
+        }
+        assert_eq!(
+            login(&router, user.email(), user.password()).await,
+            StatusCode::ACCEPTED
+        );
+        for _ in 1..threshold {
+            assert_eq!(
+                login(&router, user.email(), "wrong password").await,
+                StatusCode::UNAUTHORIZED
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn should_reject_a_too_large_body() {
+        let router = create_test_router().await;
+        let password = "p".repeat(LOGIN_BODY_LIMIT);
+
+        let response = post(
+            &router,
+            "/api/user/login",
+            &login_request("large@test.test", &password),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.error, BODY_TOO_LARGE_ERROR_MSG);
+    }
+
+    #[tokio::test]
+    async fn should_limit_login_attempts_per_ip() {
+        let router = create_test_router().await;
+        let capacity = RateLimits::from_env().per_ip.capacity;
+        let addr = SocketAddr::from(([10, 0, 0, 1], 4000));
+
+        let send = |email: String| {
+            let request = Request::builder()
+                .method(Method::POST)
+                .uri("/api/user/login")
+                .header(CONTENT_TYPE, "application/json")
+                .extension(ConnectInfo(addr))
+                .body(
+                    serde_json::to_vec(&login_request(&email, "password"))
+                        .expect("failed to serialize POST body")
+                        .into(),
+                )
+                .expect("failed to build POST request");
+            send_request(&router, request)
+        };
+
+        for attempt in 0..capacity {
+            let response = send(std::format!("unknown-{attempt}@test.test")).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        }
+
+        let response = send("unknown@test.test".to_owned()).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key(RETRY_AFTER));
+    }
+}
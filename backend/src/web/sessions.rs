@@ -0,0 +1,509 @@
+use crate::models::chat_members::ChatMemberDb;
+use crate::models::chat_message::ChatMessageDb;
+use crate::models::chats::ChatDb;
+use crate::models::company::CompanyDb;
+use crate::models::errors::DbError;
+use crate::models::project::ProjectDb;
+use crate::models::user::{OwnedSessionInput, UserDb};
+use crate::web::authentication::{Authenticated, Credential, ScopeError};
+use crate::web::errors::create_invalid_response;
+use crate::web::formats::JsonDateTime;
+use crate::web_service::WebService;
+use axum::extract::rejection::PathRejection;
+use axum::extract::{ConnectInfo, FromRequestParts, Path, State};
+use axum::http::header::USER_AGENT;
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use database::revoked_tokens::RevokedTokenInput;
+use database::sessions::Session;
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use time::PrimitiveDateTime;
+use uuid::Uuid;
+
+/// Where a login comes from, stored with a new session
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    user_agent: Option<String>,
+    ip_address: Option<String>,
+}
+
+impl ClientInfo {
+    pub fn user_agent(&self) -> Option<&str> {
+        self.user_agent.as_deref()
+    }
+
+    pub fn ip_address(&self) -> Option<&str> {
+        self.ip_address.as_deref()
+    }
+}
+
+#[async_trait::async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientInfo {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let user_agent = parts
+            .headers
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
+        // Only present when the server is started with `into_make_service_with_connect_info`
+        let ip_address = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string());
+
+        Ok(ClientInfo {
+            user_agent,
+            ip_address,
+        })
+    }
+}
+
+/// Starts a session for a newly signed in device, its id becomes the refresh token family
+pub async fn start_session(
+    user_db: &impl UserDb,
+    user_id: Uuid,
+    device_name: Option<String>,
+    client_info: ClientInfo,
+) -> Result<Uuid, DbError> {
+    user_db
+        .insert_session(&OwnedSessionInput {
+            user_id,
+            device_name,
+            user_agent: client_info.user_agent,
+            ip_address: client_info.ip_address,
+        })
+        .await
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SessionResponseData {
+    id: Uuid,
+    device_name: Option<String>,
+    user_agent: Option<String>,
+    ip_address: Option<String>,
+    created_at: JsonDateTime,
+    last_seen_at: JsonDateTime,
+    current: bool,
+}
+
+impl SessionResponseData {
+    fn new(session: Session, current_session_id: Uuid) -> Self {
+        SessionResponseData {
+            id: session.id,
+            device_name: session.device_name,
+            user_agent: session.user_agent,
+            ip_address: session.ip_address,
+            created_at: session.created_at.into(),
+            last_seen_at: session.last_seen_at.into(),
+            current: session.id == current_session_id,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SessionsResponseBody {
+    sessions: Vec<SessionResponseData>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RevokedSessionsResponseBody {
+    revoked: u64,
+}
+
+#[derive(Debug)]
+pub enum SessionErrorResponse {
+    Forbidden(ScopeError),
+    DbError(DbError),
+    InvalidInputDataFormat(String),
+}
+
+impl IntoResponse for SessionErrorResponse {
+    fn into_response(self) -> Response {
+        match self {
+            SessionErrorResponse::Forbidden(error) => error.into_response(),
+            SessionErrorResponse::DbError(db_error) => db_error.into_response(),
+            SessionErrorResponse::InvalidInputDataFormat(error) => {
+                create_invalid_response(error).into_response()
+            }
+        }
+    }
+}
+
+/// Lists signed in devices of the current user, the most recently seen first
+///
+#[tracing::instrument(skip(web_service))]
+pub async fn get_all<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authenticated: Authenticated,
+) -> Result<(StatusCode, Json<SessionsResponseBody>), SessionErrorResponse> {
+    let user_info = authenticated
+        .require_user()
+        .map_err(SessionErrorResponse::Forbidden)?;
+    let current_session_id = authenticated
+        .session_id()
+        .ok_or(SessionErrorResponse::Forbidden(
+            ScopeError::ApiKeyNotAllowed,
+        ))?;
+
+    let sessions = web_service
+        .user_db
+        .get_active_sessions(user_info.user_id)
+        .await
+        .map_err(SessionErrorResponse::DbError)?;
+
+    Ok((
+        StatusCode::OK,
+        Json(SessionsResponseBody {
+            sessions: sessions
+                .into_iter()
+                .map(|session| SessionResponseData::new(session, current_session_id))
+                .collect(),
+        }),
+    ))
+}
+
+/// Logs out a single device of the current user
+///
+#[tracing::instrument(skip(web_service))]
+pub async fn delete<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authenticated: Authenticated,
+    session_id_or_error: Result<Path<Uuid>, PathRejection>,
+) -> Result<StatusCode, SessionErrorResponse> {
+    let user_info = authenticated
+        .require_user()
+        .map_err(SessionErrorResponse::Forbidden)?;
+
+    let Path(session_id) = session_id_or_error
+        .map_err(|x| x.to_string())
+        .map_err(SessionErrorResponse::InvalidInputDataFormat)?;
+
+    let revoked = web_service
+        .user_db
+        .revoke_session(session_id, user_info.user_id)
+        .await
+        .map_err(SessionErrorResponse::DbError)?;
+
+    if revoked == 0 {
+        return Err(SessionErrorResponse::DbError(DbError::NotFoundError));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Logs out every device of the current user, the current one included
+///
+#[tracing::instrument(skip(web_service))]
+pub async fn delete_all<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authenticated: Authenticated,
+) -> Result<(StatusCode, Json<RevokedSessionsResponseBody>), SessionErrorResponse> {
+    let user_info = authenticated
+        .require_user()
+        .map_err(SessionErrorResponse::Forbidden)?;
+
+    let revoked = web_service
+        .user_db
+        .revoke_user_sessions(user_info.user_id)
+        .await
+        .map_err(SessionErrorResponse::DbError)?;
+
+    Ok((
+        StatusCode::OK,
+        Json(RevokedSessionsResponseBody { revoked }),
+    ))
+}
+
+/// Logs out the current device
+///
+/// The access token is put on the denylist until it expires, so it stops working at once.
+#[tracing::instrument(skip(web_service))]
+pub async fn logout<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    authenticated: Authenticated,
+) -> Result<StatusCode, SessionErrorResponse> {
+    let user_id = authenticated
+        .require_user()
+        .map_err(SessionErrorResponse::Forbidden)?
+        .user_id;
+    let Credential::AccessToken {
+        token_id,
+        session_id,
+        expires_at,
+    } = *authenticated.credential()
+    else {
+        return Err(SessionErrorResponse::Forbidden(
+            ScopeError::ApiKeyNotAllowed,
+        ));
+    };
+
+    web_service
+        .user_db
+        .revoke_access_token(&RevokedTokenInput {
+            id: token_id,
+            user_id,
+            expires_at: PrimitiveDateTime::new(expires_at.date(), expires_at.time()),
+        })
+        .await
+        .map_err(SessionErrorResponse::DbError)?;
+
+    web_service
+        .user_db
+        .revoke_session(session_id, user_id)
+        .await
+        .map_err(SessionErrorResponse::DbError)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::api_keys::Scope;
+    use crate::web::api_keys::tests::create_api_key;
+    use crate::web::email_verification::tests::register_verified_user;
+    use crate::web::errors::{API_KEY_NOT_ALLOWED_ERROR_MSG, TOKEN_REVOKED_ERROR_MSG};
+    use crate::web::sessions::{RevokedSessionsResponseBody, SessionsResponseBody};
+    use crate::web::tokens::tests::refresh_tokens;
+    use crate::web::users::tests::{
+        create_test_router, get_auth_header_for_name, get_refresh_token_header, login_from_device,
+        register_new_user,
+    };
+    use crate::web_service::tests::{
+        delete_with_auth_header, deserialize_response_body, get_with_auth_header,
+        post_with_auth_header,
+    };
+    use crate::web_service::ErrorResponseBody;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn should_list_sessions_of_every_device() {
+        let router = create_test_router().await;
+
+        let (request, response) = register_new_user(None).await;
+        let first_access_token = get_auth_header_for_name(&response);
+
+        let response = login_from_device(&request, Some("laptop".to_owned())).await;
+        assert_eq!(response.status(), 202);
+        let second_access_token = get_auth_header_for_name(&response);
+
+        let response =
+            get_with_auth_header(&router, "/api/user/sessions", Some(&second_access_token)).await;
+        assert_eq!(response.status(), 200);
+
+        let response_body = deserialize_response_body::<SessionsResponseBody>(response).await;
+        assert_eq!(response_body.sessions.len(), 2);
+
+        let (current, other): (Vec<_>, Vec<_>) = response_body
+            .sessions
+            .iter()
+            .partition(|session| session.current);
+        assert_eq!(current.len(), 1);
+        assert_eq!(current[0].device_name.as_deref(), Some("laptop"));
+        assert_eq!(other[0].device_name, None);
+
+        let response =
+            get_with_auth_header(&router, "/api/user/sessions", Some(&first_access_token)).await;
+        let response_body = deserialize_response_body::<SessionsResponseBody>(response).await;
+        assert_eq!(
+            response_body
+                .sessions
+                .iter()
+                .filter(|session| session.current)
+                .count(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn should_log_out_a_single_device() {
+        let router = create_test_router().await;
+
+        let (request, response) = register_new_user(None).await;
+        let first_refresh_token = get_refresh_token_header(&response);
+
+        let response = login_from_device(&request, None).await;
+        let access_token = get_auth_header_for_name(&response);
+        let second_refresh_token = get_refresh_token_header(&response);
+
+        let response =
+            get_with_auth_header(&router, "/api/user/sessions", Some(&access_token)).await;
+        let response_body = deserialize_response_body::<SessionsResponseBody>(response).await;
+        let first_session = response_body
+            .sessions
+            .iter()
+            .find(|session| !session.current)
+            .expect("first device session");
+
+        let uri = std::format!("/api/user/sessions/{}", first_session.id);
+        let response = delete_with_auth_header(&router, &uri, Some(&access_token)).await;
+        assert_eq!(response.status(), 204);
+
+        let response = delete_with_auth_header(&router, &uri, Some(&access_token)).await;
+        assert_eq!(response.status(), 404);
+
+        let response = refresh_tokens(&router, &first_refresh_token).await;
+        assert_eq!(response.status(), 401);
+
+        let response = refresh_tokens(&router, &second_refresh_token).await;
+        assert_eq!(response.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn should_not_log_out_a_device_of_another_user() {
+        let router = create_test_router().await;
+
+        let (_, response) = register_new_user(None).await;
+        let victim_access_token = get_auth_header_for_name(&response);
+        let victim_refresh_token = get_refresh_token_header(&response);
+
+        let (_, response) = register_new_user(None).await;
+        let access_token = get_auth_header_for_name(&response);
+
+        let response =
+            get_with_auth_header(&router, "/api/user/sessions", Some(&victim_access_token)).await;
+        let response_body = deserialize_response_body::<SessionsResponseBody>(response).await;
+
+        let uri = std::format!("/api/user/sessions/{}", response_body.sessions[0].id);
+        let response = delete_with_auth_header(&router, &uri, Some(&access_token)).await;
+        assert_eq!(response.status(), 404);
+
+        let uri = std::format!("/api/user/sessions/{}", Uuid::new_v4());
+        let response = delete_with_auth_header(&router, &uri, Some(&access_token)).await;
+        assert_eq!(response.status(), 404);
+
+        let response = refresh_tokens(&router, &victim_refresh_token).await;
+        assert_eq!(response.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn should_log_out_everywhere() {
+        let router = create_test_router().await;
+
+        let (request, response) = register_new_user(None).await;
+        let first_refresh_token = get_refresh_token_header(&response);
+
+        let response = login_from_device(&request, None).await;
+        let access_token = get_auth_header_for_name(&response);
+        let second_refresh_token = get_refresh_token_header(&response);
+
+        let response =
+            delete_with_auth_header(&router, "/api/user/sessions", Some(&access_token)).await;
+        assert_eq!(response.status(), 200);
+
+        let response_body =
+            deserialize_response_body::<RevokedSessionsResponseBody>(response).await;
+        assert_eq!(response_body.revoked, 2);
+
+        for refresh_token in [first_refresh_token, second_refresh_token] {
+            let response = refresh_tokens(&router, refresh_token).await;
+            assert_eq!(response.status(), 401);
+        }
+
+        // Access tokens of revoked sessions are dead too
+        let response =
+            get_with_auth_header(&router, "/api/user/sessions", Some(&access_token)).await;
+        assert_eq!(response.status(), 401);
+
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.error, TOKEN_REVOKED_ERROR_MSG);
+    }
+
+    #[tokio::test]
+    async fn should_log_out_the_current_device() {
+        let router = create_test_router().await;
+
+        let (request, response) = register_new_user(None).await;
+        let access_token = get_auth_header_for_name(&response);
+        let refresh_token = get_refresh_token_header(&response);
+
+        let response = login_from_device(&request, None).await;
+        let other_access_token = get_auth_header_for_name(&response);
+
+        let response =
+            post_with_auth_header(&router, "/api/user/logout", &(), Some(&access_token)).await;
+        assert_eq!(response.status(), 204);
+
+        // The access token is dead before it expires
+        let response =
+            get_with_auth_header(&router, "/api/user/sessions", Some(&access_token)).await;
+        assert_eq!(response.status(), 401);
+
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.error, TOKEN_REVOKED_ERROR_MSG);
+
+        let response = refresh_tokens(&router, &refresh_token).await;
+        assert_eq!(response.status(), 401);
+
+        let response =
+            get_with_auth_header(&router, "/api/user/sessions", Some(&other_access_token)).await;
+        assert_eq!(response.status(), 200);
+
+        let response_body = deserialize_response_body::<SessionsResponseBody>(response).await;
+        assert_eq!(response_body.sessions.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn should_not_manage_sessions_with_an_api_key() {
+        let router = create_test_router().await;
+        let (_, access_token) = register_verified_user().await;
+        let api_key = create_api_key(
+            &router,
+            &access_token,
+            vec![Scope::ProjectsRead, Scope::ChatsRead],
+        )
+        .await;
+
+        let response =
+            get_with_auth_header(&router, "/api/user/sessions", Some(api_key.key())).await;
+        assert_eq!(response.status(), 403);
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.error, API_KEY_NOT_ALLOWED_ERROR_MSG);
+
+        for uri in [
+            "/api/user/logout",
+            "/api/user/mfa/totp",
+            "/api/user/verify-email/resend",
+        ] {
+            let response = post_with_auth_header(&router, uri, &(), Some(api_key.key())).await;
+            assert_eq!(response.status(), 403);
+        }
+
+        let response =
+            get_with_auth_header(&router, "/api/user/sessions", Some(&access_token)).await;
+        assert_eq!(response.status(), 200);
+    }
+}
@@ -0,0 +1,322 @@
+use crate::models::chat_members::ChatMemberDb;
+use crate::models::chat_message::ChatMessageDb;
+use crate::models::chats::ChatDb;
+use crate::models::company::CompanyDb;
+use crate::models::errors::DbError;
+use crate::models::project::ProjectDb;
+use crate::models::user::UserDb;
+use crate::utils::tokens::{
+    hash_opaque_token, AccessTokenResponse, CreateAccessTokenError, RefreshTokenResponse, UserInfo,
+};
+use crate::web::audit::record_audit_event;
+use crate::web::authentication::{AddHeaderError, AuthHeaders};
+use crate::web::errors::{
+    create_bad_request_error, create_internal_server_error, INVALID_REFRESH_TOKEN_ERROR_MSG,
+};
+use crate::web::sessions::ClientInfo;
+use crate::web_service::{ErrorResponseBody, WebService};
+use axum::extract::rejection::JsonRejection;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use database::audit_events::AuditEventType;
+use database::refresh_tokens::RefreshTokenInput;
+use serde::{Deserialize, Serialize};
+use time::{OffsetDateTime, PrimitiveDateTime};
+use uuid::Uuid;
+
+#[derive(Debug)]
+pub enum IssueTokensError {
+    CreateAccessToken(CreateAccessTokenError),
+    DbError(DbError),
+    AddHeader(AddHeaderError),
+}
+
+impl IntoResponse for IssueTokensError {
+    fn into_response(self) -> Response {
+        match self {
+            IssueTokensError::DbError(db_error) => db_error.into_response(),
+            IssueTokensError::CreateAccessToken(error) => create_internal_server_error(
+                std::format!("Can not create an access token: {:?}", error),
+            )
+            .into_response(),
+            IssueTokensError::AddHeader(error) => {
+                create_internal_server_error(std::format!("Add header error: {:?}", error))
+                    .into_response()
+            }
+        }
+    }
+}
+
+/// Mints an access token and a refresh token of a given session as auth headers
+///
+/// Every login starts a new session, so each device rotates its own chain of refresh tokens.
+pub async fn create_auth_headers(
+    user_db: &impl UserDb,
+    user: UserInfo,
+    session_id: Uuid,
+) -> Result<HeaderMap, IssueTokensError> {
+    let user_id = user.user_id;
+
+    let access_token =
+        AccessTokenResponse::new(user, session_id).map_err(IssueTokensError::CreateAccessToken)?;
+    let refresh_token = RefreshTokenResponse::generate();
+
+    let expires_at = refresh_token.expires_at;
+    user_db
+        .insert_refresh_token(&RefreshTokenInput {
+            family_id: session_id,
+            user_id,
+            token_hash: refresh_token.hash(),
+            expires_at: PrimitiveDateTime::new(expires_at.date(), expires_at.time()),
+        })
+        .await
+        .map_err(IssueTokensError::DbError)?;
+
+    let mut headers = HeaderMap::new();
+    headers
+        .add_auth_headers(access_token)
+        .map_err(IssueTokensError::AddHeader)?;
+    headers
+        .add_refresh_token_headers(refresh_token)
+        .map_err(IssueTokensError::AddHeader)?;
+
+    Ok(headers)
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RefreshTokenData {
+    refresh_token: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RefreshTokenRequestBody {
+    data: RefreshTokenData,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RefreshTokenResponseBody {
+    user_id: Uuid,
+}
+
+#[derive(Debug)]
+pub enum RefreshTokenErrorResponse {
+    DbError(DbError),
+    InvalidRefreshToken,
+    IssueTokensError(IssueTokensError),
+    JsonRejection(JsonRejection),
+}
+
+impl IntoResponse for RefreshTokenErrorResponse {
+    fn into_response(self) -> Response {
+        match self {
+            RefreshTokenErrorResponse::DbError(db_error) => db_error.into_response(),
+            RefreshTokenErrorResponse::InvalidRefreshToken => (
+                StatusCode::UNAUTHORIZED,
+                Json(ErrorResponseBody {
+                    code: None,
+                    error: INVALID_REFRESH_TOKEN_ERROR_MSG.into(),
+                }),
+            )
+                .into_response(),
+            RefreshTokenErrorResponse::IssueTokensError(error) => error.into_response(),
+            RefreshTokenErrorResponse::JsonRejection(error) => {
+                create_bad_request_error(error.to_string()).into_response()
+            }
+        }
+    }
+}
+
+/// Exchanges a refresh token for a new pair of tokens
+///
+/// A refresh token can be used once. Presenting an already rotated token means that it
+/// has leaked, so the whole session gets revoked and the device has to login again.
+#[tracing::instrument(skip(web_service, body_or_error))]
+pub async fn refresh<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    client_info: ClientInfo,
+    body_or_error: Result<Json<RefreshTokenRequestBody>, JsonRejection>,
+) -> Result<(StatusCode, HeaderMap, Json<RefreshTokenResponseBody>), RefreshTokenErrorResponse> {
+    let Json(body) = body_or_error.map_err(RefreshTokenErrorResponse::JsonRejection)?;
+
+    let user_db = &web_service.user_db;
+
+    let refresh_token = match user_db
+        .get_refresh_token_by_hash(hash_opaque_token(&body.data.refresh_token))
+        .await
+    {
+        Ok(refresh_token) => refresh_token,
+        Err(DbError::NotFoundError) => return Err(RefreshTokenErrorResponse::InvalidRefreshToken),
+        Err(db_error) => return Err(RefreshTokenErrorResponse::DbError(db_error)),
+    };
+
+    if refresh_token.revoked_at.is_some()
+        || refresh_token.expires_at.assume_utc() <= OffsetDateTime::now_utc()
+    {
+        return Err(RefreshTokenErrorResponse::InvalidRefreshToken);
+    }
+
+    let used = user_db
+        .use_refresh_token(refresh_token.id)
+        .await
+        .map_err(RefreshTokenErrorResponse::DbError)?;
+    if used == 0 {
+        tracing::warn!(
+            "refresh token reuse detected, revoking session: {}",
+            refresh_token.family_id
+        );
+        user_db
+            .revoke_session(refresh_token.family_id, refresh_token.user_id)
+            .await
+            .map_err(RefreshTokenErrorResponse::DbError)?;
+        return Err(RefreshTokenErrorResponse::InvalidRefreshToken);
+    }
+
+    user_db
+        .touch_session(refresh_token.family_id)
+        .await
+        .map_err(RefreshTokenErrorResponse::DbError)?;
+
+    let user = user_db
+        .get_user(&refresh_token.user_id)
+        .await
+        .map_err(RefreshTokenErrorResponse::DbError)?;
+
+    let headers = create_auth_headers(
+        user_db,
+        UserInfo {
+            first_name: user.first_name,
+            last_name: user.last_name,
+            user_id: user.id,
+            email_verified: user.email_verified_at.is_some(),
+        },
+        refresh_token.family_id,
+    )
+    .await
+    .map_err(RefreshTokenErrorResponse::IssueTokensError)?;
+
+    record_audit_event(
+        web_service.audit_log.as_ref(),
+        AuditEventType::TokenRefreshed,
+        user.id,
+        None,
+        &client_info,
+    )
+    .await;
+
+    Ok((
+        StatusCode::OK,
+        headers,
+        Json(RefreshTokenResponseBody { user_id: user.id }),
+    ))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::utils::tokens::AccessToken;
+    use crate::web::errors::INVALID_REFRESH_TOKEN_ERROR_MSG;
+    use crate::web::tokens::{RefreshTokenData, RefreshTokenRequestBody, RefreshTokenResponseBody};
+    use crate::web::users::tests::{
+        create_test_router, get_auth_header_for_name, get_refresh_token_header, register_new_user,
+    };
+    use crate::web_service::tests::{deserialize_response_body, post};
+    use crate::web_service::ErrorResponseBody;
+    use axum::body::Bytes;
+    use axum::Router;
+    use database::utils::random_samples::RandomSample;
+    use http_body::combinators::UnsyncBoxBody;
+
+    pub async fn refresh_tokens(
+        router: &Router,
+        refresh_token: impl Into<String>,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        let request_body = RefreshTokenRequestBody {
+            data: RefreshTokenData {
+                refresh_token: refresh_token.into(),
+            },
+        };
+
+        post(router, "/api/user/token/refresh", &request_body).await
+    }
+
+    #[tokio::test]
+    async fn should_rotate_a_refresh_token() {
+        let router = create_test_router().await;
+
+        let (_, response) = register_new_user(None).await;
+        let access_token = get_auth_header_for_name(&response);
+        let refresh_token = get_refresh_token_header(&response);
+
+        let response = refresh_tokens(&router, &refresh_token).await;
+        assert_eq!(response.status(), 200);
+
+        let new_access_token = get_auth_header_for_name(&response);
+        let new_refresh_token = get_refresh_token_header(&response);
+        assert_ne!(new_refresh_token, refresh_token);
+
+        let response_body = deserialize_response_body::<RefreshTokenResponseBody>(response).await;
+
+        let old_user = AccessToken::from_token(access_token).expect("valid token");
+        let new_user = AccessToken::from_token(new_access_token).expect("valid token");
+        assert_eq!(old_user.get_user(), new_user.get_user());
+        assert_eq!(new_user.get_user().user_id, response_body.user_id);
+    }
+
+    #[tokio::test]
+    async fn should_revoke_a_family_when_a_rotated_token_is_reused() {
+        let router = create_test_router().await;
+
+        let (_, response) = register_new_user(None).await;
+        let stolen_refresh_token = get_refresh_token_header(&response);
+
+        let response = refresh_tokens(&router, &stolen_refresh_token).await;
+        assert_eq!(response.status(), 200);
+        let rotated_refresh_token = get_refresh_token_header(&response);
+
+        let response = refresh_tokens(&router, &stolen_refresh_token).await;
+        assert_eq!(response.status(), 401);
+
+        let response_body = deserialize_response_body::<ErrorResponseBody>(response).await;
+        assert_eq!(response_body.error, INVALID_REFRESH_TOKEN_ERROR_MSG);
+
+        // The legitimate device is logged out as well
+        let response = refresh_tokens(&router, &rotated_refresh_token).await;
+        assert_eq!(response.status(), 401);
+    }
+
+    #[tokio::test]
+    async fn should_keep_other_devices_when_a_token_is_reused() {
+        let router = create_test_router().await;
+
+        let (request, response) = register_new_user(None).await;
+        let first_device_token = get_refresh_token_header(&response);
+
+        let (_, response) = register_new_user(Some(request)).await;
+        assert_eq!(response.status(), 202);
+        let second_device_token = get_refresh_token_header(&response);
+
+        let response = refresh_tokens(&router, &first_device_token).await;
+        assert_eq!(response.status(), 200);
+        let response = refresh_tokens(&router, &first_device_token).await;
+        assert_eq!(response.status(), 401);
+
+        let response = refresh_tokens(&router, &second_device_token).await;
+        assert_eq!(response.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn should_reject_an_unknown_refresh_token() {
+        let router = create_test_router().await;
+
+        let response = refresh_tokens(&router, String::new_random(43)).await;
+        assert_eq!(response.status(), 401);
+    }
+}
@@ -1,24 +1,38 @@
+use crate::mailer::Mailer;
+use crate::models::audit::AuditLog;
+use crate::models::chat_members::ChatMemberDb;
+use crate::models::chat_message::ChatMessageDb;
+use crate::models::chats::ChatDb;
+use crate::models::company::CompanyDb;
 use crate::models::errors::DbError;
 use crate::models::project::ProjectDb;
-use crate::models::user::{OwnedUser, UserDb};
-use crate::utils::salted_hashes::{
-    generate_b64_hash_for_text_and_salt, generate_hash_and_salt_for_text,
+use crate::models::user::{OwnedUserIdentityInput, UserDb};
+use crate::utils::passwords::{
+    hash_password, verify_password, PasswordHashError, PasswordVerification, NO_PASSWORD_HASH,
 };
-use crate::utils::tokens::{AccessToken, AccessTokenResponse, CreateAccessTokenError, UserInfo};
-use crate::web::authentication::{AddHeaderError, AuthHeaders};
+use crate::utils::tokens::{CreateAccessTokenError, MfaPendingToken, UserInfo};
+use crate::web::audit::record_audit_event;
+use crate::web::email_verification::send_verification_email;
 use crate::web::errors;
 use crate::web::errors::{create_bad_request_error, create_internal_server_error};
+use crate::web::rate_limiting::FailedLogin;
+use crate::web::sessions::{start_session, ClientInfo};
+use crate::web::tokens::{create_auth_headers, IssueTokensError};
 use crate::web_service::{ErrorCode, ErrorResponseBody, WebService};
 use axum::extract::rejection::JsonRejection;
 use axum::http::HeaderMap;
 use axum::response::{IntoResponse, Response};
-use axum::{extract::State, http::StatusCode, Json};
-use database::users::User;
+use axum::{extract::State, http::StatusCode, Extension, Json};
+use database::audit_events::AuditEventType;
+use database::users::{User, UserInput};
 use email_address::EmailAddress;
 use errors::INVALID_MAIL_MSG;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+/// For users whose identity provider does not share a usable locale
+const DEFAULT_LANGUAGE_CODE: &str = "en";
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct RegisterUserData {
     email: String,
@@ -26,6 +40,8 @@ pub struct RegisterUserData {
     first_name: Option<String>,
     last_name: Option<String>,
     language_code: String,
+    #[serde(default)]
+    device_name: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -42,10 +58,10 @@ pub struct RegisterUserResponseBody {
 pub enum RegisterUserErrorResponse {
     DbError(DbError),
     AlreadyRegistered,
-    AddHeaderError(AddHeaderError),
     InvalidEmailFormat,
     JsonRejection(JsonRejection),
-    CreateAccessTokenError(CreateAccessTokenError),
+    IssueTokensError(IssueTokensError),
+    PasswordHashError(PasswordHashError),
 }
 
 impl IntoResponse for RegisterUserErrorResponse {
@@ -54,6 +70,7 @@ impl IntoResponse for RegisterUserErrorResponse {
             RegisterUserErrorResponse::DbError(db_error) => db_error.into_response(),
             RegisterUserErrorResponse::AlreadyRegistered => (
                 StatusCode::ALREADY_REPORTED,
+                Extension(FailedLogin),
                 Json(ErrorResponseBody {
                     code: Some(ErrorCode::AlreadyRegistered),
                     error: "user for given email already exists".to_owned(),
@@ -68,19 +85,13 @@ impl IntoResponse for RegisterUserErrorResponse {
                 }),
             )
                 .into_response(),
-            RegisterUserErrorResponse::AddHeaderError(error) => {
-                create_internal_server_error(std::format!("Add header error: {:?}", error))
-                    .into_response()
-            }
             RegisterUserErrorResponse::JsonRejection(error) => {
                 create_bad_request_error(error.to_string()).into_response()
             }
-            RegisterUserErrorResponse::CreateAccessTokenError(error) => {
-                create_internal_server_error(std::format!(
-                    "Can not create an access token: {:?}",
-                    error
-                ))
-                .into_response()
+            RegisterUserErrorResponse::IssueTokensError(error) => error.into_response(),
+            RegisterUserErrorResponse::PasswordHashError(error) => {
+                create_internal_server_error(std::format!("Can not hash a password: {:?}", error))
+                    .into_response()
             }
         }
     }
@@ -88,62 +99,132 @@ impl IntoResponse for RegisterUserErrorResponse {
 
 enum LoginError {
     WrongPassword,
-    DbError(DbError),
-    CreateAccessTokenError(CreateAccessTokenError),
-    InvalidTokenFormatInDb,
-    AddHeaderError(AddHeaderError),
+    InvalidPasswordHashInDb(PasswordHashError),
+    StartLogin(StartLoginError),
 }
 
 async fn login_user(
     password: impl AsRef<str>,
     user_db: &impl UserDb,
+    audit_log: &dyn AuditLog,
     user: &User,
+    device_name: Option<String>,
+    client_info: ClientInfo,
 ) -> Result<(StatusCode, HeaderMap, Json<LoginUserResponseBody>), LoginError> {
-    let input_hash = generate_b64_hash_for_text_and_salt(password, &user.password_salt)
-        .map_err(|x| LoginError::CreateAccessTokenError(CreateAccessTokenError::DecodeError(x)))?;
-    let existing_hash = &user.password_sha512;
-    if existing_hash != &input_hash {
-        return Err(LoginError::WrongPassword);
+    let verification = verify_password(&password, &user.password_hash)
+        .map_err(LoginError::InvalidPasswordHashInDb)?;
+    match verification {
+        PasswordVerification::Invalid => {
+            record_audit_event(
+                audit_log,
+                AuditEventType::LoginFailed,
+                user.id,
+                None,
+                &client_info,
+            )
+            .await;
+            return Err(LoginError::WrongPassword);
+        }
+        PasswordVerification::Valid => {}
+        // A failed rehash is retried on the next login, so it does not fail this one
+        PasswordVerification::ValidNeedsRehash => match hash_password(&password) {
+            Ok(password_hash) => {
+                if let Err(error) = user_db.update_password_hash(&user.id, password_hash).await {
+                    tracing::warn!("can not rehash a password of {}: {:?}", user.id, error);
+                }
+            }
+            Err(error) => tracing::warn!("can not rehash a password of {}: {:?}", user.id, error),
+        },
     }
 
-    let token_response = AccessTokenResponse::new(UserInfo {
-        user_id: user.id,
-        first_name: user.first_name.clone(),
-        last_name: user.last_name.clone(),
-    })
-    .map_err(LoginError::CreateAccessTokenError)?;
-
-    let tokens_updated = user_db
-        .update_user_token(&user.id, &token_response.token, &user.access_token)
+    start_login(user_db, audit_log, user, device_name, client_info)
         .await
-        .map_err(LoginError::DbError)?;
+        .map_err(LoginError::StartLogin)
+}
 
-    let token_response = if tokens_updated == 0 {
-        // In case of token refresh race condition, return token from a database
-        let token = user_db
-            .get_access_token(&user.id)
-            .await
-            .map_err(LoginError::DbError)?;
-        let access_token = AccessToken::from_token(token.clone())
-            .map_err(|_| LoginError::InvalidTokenFormatInDb)?;
-        AccessTokenResponse {
-            token,
-            expires_at: *access_token.get_expires_at(),
-            refresh_at: *access_token.get_refresh_at(),
+#[derive(Debug)]
+pub enum StartLoginError {
+    CreateMfaToken(CreateAccessTokenError),
+    IssueTokensError(IssueTokensError),
+}
+
+impl IntoResponse for StartLoginError {
+    fn into_response(self) -> Response {
+        match self {
+            StartLoginError::CreateMfaToken(error) => create_internal_server_error(std::format!(
+                "Can not create an MFA token: {:?}",
+                error
+            ))
+            .into_response(),
+            StartLoginError::IssueTokensError(error) => error.into_response(),
         }
-    } else {
-        token_response
-    };
+    }
+}
 
-    let mut headers = HeaderMap::new();
-    headers
-        .add_auth_headers(token_response)
-        .map_err(LoginError::AddHeaderError)?;
+/// Signs in a user who has passed the first factor, or asks for the second one
+async fn start_login(
+    user_db: &impl UserDb,
+    audit_log: &dyn AuditLog,
+    user: &User,
+    device_name: Option<String>,
+    client_info: ClientInfo,
+) -> Result<(StatusCode, HeaderMap, Json<LoginUserResponseBody>), StartLoginError> {
+    // No tokens until the second factor, see `mfa::login`
+    if user.totp_enabled_at.is_some() {
+        let mfa_token = MfaPendingToken::new(user.id, device_name)
+            .encode()
+            .map_err(StartLoginError::CreateMfaToken)?;
+
+        return Ok((
+            StatusCode::ACCEPTED,
+            HeaderMap::new(),
+            Json(LoginUserResponseBody {
+                user_id: user.id,
+                mfa_token: Some(mfa_token),
+            }),
+        ));
+    }
+
+    sign_in(user_db, audit_log, user, device_name, client_info)
+        .await
+        .map_err(StartLoginError::IssueTokensError)
+}
+
+/// Starts a session for a user whose credentials are checked and sets auth headers
+pub async fn sign_in(
+    user_db: &impl UserDb,
+    audit_log: &dyn AuditLog,
+    user: &User,
+    device_name: Option<String>,
+    client_info: ClientInfo,
+) -> Result<(StatusCode, HeaderMap, Json<LoginUserResponseBody>), IssueTokensError> {
+    let user_info = UserInfo {
+        user_id: user.id,
+        first_name: user.first_name.clone(),
+        last_name: user.last_name.clone(),
+        email_verified: user.email_verified_at.is_some(),
+    };
+    let session_id = start_session(user_db, user.id, device_name, client_info.clone())
+        .await
+        .map_err(IssueTokensError::DbError)?;
+    let headers = create_auth_headers(user_db, user_info, session_id).await?;
+
+    record_audit_event(
+        audit_log,
+        AuditEventType::LoginSucceeded,
+        user.id,
+        None,
+        &client_info,
+    )
+    .await;
 
     Ok((
         StatusCode::ACCEPTED,
         headers,
-        Json(LoginUserResponseBody { user_id: user.id }),
+        Json(LoginUserResponseBody {
+            user_id: user.id,
+            mfa_token: None,
+        }),
     ))
 }
 
@@ -151,8 +232,16 @@ async fn login_user(
 ///
 // TODO: Validate input, each field, format and length
 #[tracing::instrument(skip(web_service))]
-pub async fn post<UDB: UserDb, PDB: ProjectDb>(
-    State(web_service): State<WebService<UDB, PDB>>,
+pub async fn post<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    client_info: ClientInfo,
     body_or_error: Result<Json<RegisterUserRequestBody>, JsonRejection>,
 ) -> Result<(StatusCode, HeaderMap, Json<LoginUserResponseBody>), RegisterUserErrorResponse> {
     let Json(body) = body_or_error.map_err(RegisterUserErrorResponse::JsonRejection)?;
@@ -166,32 +255,30 @@ pub async fn post<UDB: UserDb, PDB: ProjectDb>(
         .get_user_by_email(&body.data.email)
         .await;
     match user_or_error {
-        Ok(user) => login_user(&body.data.password, &web_service.user_db, &user)
-            .await
-            // TODO return more accurate error
-            .map_err(|_| RegisterUserErrorResponse::AlreadyRegistered),
+        Ok(user) => login_user(
+            &body.data.password,
+            &web_service.user_db,
+            web_service.audit_log.as_ref(),
+            &user,
+            body.data.device_name,
+            client_info,
+        )
+        .await
+        // TODO return more accurate error
+        .map_err(|_| RegisterUserErrorResponse::AlreadyRegistered),
         Err(DbError::NotFoundError) => {
-            let (password_sha512, password_salt) =
-                generate_hash_and_salt_for_text(&body.data.password);
+            let password_hash = hash_password(&body.data.password)
+                .map_err(RegisterUserErrorResponse::PasswordHashError)?;
 
             let user_id = Uuid::new_v4();
 
-            let token_response = AccessTokenResponse::new(UserInfo {
-                first_name: body.data.first_name.clone(),
-                last_name: body.data.last_name.clone(),
-                user_id,
-            })
-            .map_err(RegisterUserErrorResponse::CreateAccessTokenError)?;
-
-            let user = OwnedUser {
+            let user = UserInput {
                 user_id,
                 alias: None,
                 first_name: body.data.first_name.clone(),
                 last_name: body.data.last_name.clone(),
                 email: body.data.email.clone(),
-                password_salt,
-                password_sha512,
-                access_token: token_response.token.clone(),
+                password_hash,
                 phone_number: None,
                 language_code: body.data.language_code.clone(),
                 avatar: None,
@@ -204,15 +291,52 @@ pub async fn post<UDB: UserDb, PDB: ProjectDb>(
                 .await
                 .map_err(RegisterUserErrorResponse::DbError)?;
 
-            let mut headers = HeaderMap::new();
-            headers
-                .add_auth_headers(token_response)
-                .map_err(RegisterUserErrorResponse::AddHeaderError)?;
+            record_audit_event(
+                web_service.audit_log.as_ref(),
+                AuditEventType::Registered,
+                user_id,
+                None,
+                &client_info,
+            )
+            .await;
+
+            if let Err(error) =
+                send_verification_email(web_service.mailer.as_ref(), user_id, &body.data.email)
+                    .await
+            {
+                // The user can ask for another mail
+                tracing::warn!(
+                    "can not send a verification email to {}: {:?}",
+                    user_id,
+                    error
+                );
+            }
+
+            let user_info = UserInfo {
+                first_name: body.data.first_name.clone(),
+                last_name: body.data.last_name.clone(),
+                user_id,
+                email_verified: false,
+            };
+            let session_id = start_session(
+                &web_service.user_db,
+                user_id,
+                body.data.device_name,
+                client_info,
+            )
+            .await
+            .map_err(RegisterUserErrorResponse::DbError)?;
+            let headers = create_auth_headers(&web_service.user_db, user_info, session_id)
+                .await
+                .map_err(RegisterUserErrorResponse::IssueTokensError)?;
 
             Ok((
                 StatusCode::CREATED,
                 headers,
-                Json(LoginUserResponseBody { user_id }),
+                Json(LoginUserResponseBody {
+                    user_id,
+                    mfa_token: None,
+                }),
             ))
         }
         Err(db_error) => Err(RegisterUserErrorResponse::DbError(db_error)),
@@ -223,6 +347,8 @@ pub async fn post<UDB: UserDb, PDB: ProjectDb>(
 pub struct LoginUserData {
     email: String,
     password: String,
+    #[serde(default)]
+    device_name: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -233,6 +359,9 @@ pub struct LoginUserDataBody {
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct LoginUserResponseBody {
     user_id: Uuid,
+    /// Set instead of auth headers when a second factor is required
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mfa_token: Option<String>,
 }
 
 #[derive(Debug)]
@@ -266,6 +395,7 @@ impl IntoResponse for LoginUserErrorResponse {
                 .into_response(),
             LoginUserErrorResponse::InvalidPassword => (
                 StatusCode::UNAUTHORIZED,
+                Extension(FailedLogin),
                 Json(ErrorResponseBody {
                     code: None,
                     error: "Entered password is wrong, please try again".to_owned(),
@@ -282,8 +412,16 @@ impl IntoResponse for LoginUserErrorResponse {
 /// Login existing user
 ///
 #[tracing::instrument(skip(web_service))]
-pub async fn login<UDB: UserDb, PDB: ProjectDb>(
-    State(web_service): State<WebService<UDB, PDB>>,
+pub async fn login<
+    UDB: UserDb,
+    PDB: ProjectDb,
+    CDB: CompanyDb,
+    CHDB: ChatDb,
+    CMDB: ChatMemberDb,
+    MDB: ChatMessageDb,
+>(
+    State(web_service): State<WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>>,
+    client_info: ClientInfo,
     body_or_error: Result<Json<LoginUserDataBody>, JsonRejection>,
 ) -> Result<(StatusCode, HeaderMap, Json<LoginUserResponseBody>), LoginUserErrorResponse> {
     let Json(body) = body_or_error.map_err(LoginUserErrorResponse::JsonRejection)?;
@@ -297,22 +435,184 @@ pub async fn login<UDB: UserDb, PDB: ProjectDb>(
         .get_user_by_email(&body.data.email)
         .await;
     match user_or_error {
-        Ok(user) => login_user(&body.data.password, &web_service.user_db, &user)
-            .await
-            // TODO return more accurate error
-            .map_err(|_| LoginUserErrorResponse::InvalidPassword),
+        Ok(user) => login_user(
+            &body.data.password,
+            &web_service.user_db,
+            web_service.audit_log.as_ref(),
+            &user,
+            body.data.device_name,
+            client_info,
+        )
+        .await
+        // TODO return more accurate error
+        .map_err(|_| LoginUserErrorResponse::InvalidPassword),
         Err(DbError::NotFoundError) => Err(LoginUserErrorResponse::NotFound),
         Err(db_error) => Err(LoginUserErrorResponse::DbError(db_error)),
     }
 }
 
+/// An account at an identity provider, taken from a validated ID token
+#[derive(Debug, Clone)]
+pub struct FederatedIdentity {
+    pub issuer: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub language_code: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum FederatedLoginError {
+    DbError(DbError),
+    InvalidEmail,
+    AlreadyRegistered,
+    StartLogin(StartLoginError),
+}
+
+impl IntoResponse for FederatedLoginError {
+    fn into_response(self) -> Response {
+        match self {
+            FederatedLoginError::DbError(db_error) => db_error.into_response(),
+            FederatedLoginError::InvalidEmail => (
+                StatusCode::NOT_ACCEPTABLE,
+                Json(ErrorResponseBody {
+                    code: Some(ErrorCode::InvalidEmailFormat),
+                    error: "identity provider has not shared a valid email".to_owned(),
+                }),
+            )
+                .into_response(),
+            FederatedLoginError::AlreadyRegistered => (
+                StatusCode::ALREADY_REPORTED,
+                Json(ErrorResponseBody {
+                    code: Some(ErrorCode::AlreadyRegistered),
+                    error: "user for given email already exists".to_owned(),
+                }),
+            )
+                .into_response(),
+            FederatedLoginError::StartLogin(error) => error.into_response(),
+        }
+    }
+}
+
+/// Logs in or registers a user through an identity provider
+///
+/// A known identity logs its user in. An unknown one is linked to an existing user only
+/// when both the provider and we have verified the email, otherwise a new user without
+/// a password is registered.
+pub async fn login_federated(
+    user_db: &impl UserDb,
+    mailer: &dyn Mailer,
+    audit_log: &dyn AuditLog,
+    identity: FederatedIdentity,
+    device_name: Option<String>,
+    client_info: ClientInfo,
+) -> Result<(StatusCode, HeaderMap, Json<LoginUserResponseBody>), FederatedLoginError> {
+    let user_or_error = user_db
+        .get_user_by_identity(identity.issuer.clone(), identity.subject.clone())
+        .await;
+    match user_or_error {
+        Ok(user) => {
+            return start_login(user_db, audit_log, &user, device_name, client_info)
+                .await
+                .map_err(FederatedLoginError::StartLogin)
+        }
+        Err(DbError::NotFoundError) => {}
+        Err(db_error) => return Err(FederatedLoginError::DbError(db_error)),
+    }
+
+    let email = identity
+        .email
+        .filter(|email| EmailAddress::is_valid(email))
+        .ok_or(FederatedLoginError::InvalidEmail)?;
+
+    let user_or_error = user_db.get_user_by_email(&email).await;
+    match user_or_error {
+        Ok(user) if identity.email_verified && user.email_verified_at.is_some() => {
+            user_db
+                .insert_user_identity(&OwnedUserIdentityInput {
+                    user_id: user.id,
+                    issuer: identity.issuer,
+                    subject: identity.subject,
+                    email: Some(email),
+                })
+                .await
+                .map_err(FederatedLoginError::DbError)?;
+
+            start_login(user_db, audit_log, &user, device_name, client_info)
+                .await
+                .map_err(FederatedLoginError::StartLogin)
+        }
+        Ok(_) => Err(FederatedLoginError::AlreadyRegistered),
+        Err(DbError::NotFoundError) => {
+            let user_id = Uuid::new_v4();
+
+            let user = UserInput {
+                user_id,
+                alias: None,
+                first_name: identity.first_name,
+                last_name: identity.last_name,
+                email: email.clone(),
+                password_hash: NO_PASSWORD_HASH.to_owned(),
+                phone_number: None,
+                language_code: identity
+                    .language_code
+                    .unwrap_or_else(|| DEFAULT_LANGUAGE_CODE.to_owned()),
+                avatar: None,
+                country_code: None,
+            };
+
+            user_db
+                .insert_user_with_identity(
+                    &user,
+                    identity.issuer,
+                    identity.subject,
+                    identity.email_verified,
+                )
+                .await
+                .map_err(FederatedLoginError::DbError)?;
+
+            record_audit_event(
+                audit_log,
+                AuditEventType::Registered,
+                user_id,
+                None,
+                &client_info,
+            )
+            .await;
+
+            if !identity.email_verified {
+                if let Err(error) = send_verification_email(mailer, user_id, &email).await {
+                    // The user can ask for another mail
+                    tracing::warn!(
+                        "can not send a verification email to {}: {:?}",
+                        user_id,
+                        error
+                    );
+                }
+            }
+
+            let user = user_db
+                .get_user(&user_id)
+                .await
+                .map_err(FederatedLoginError::DbError)?;
+            sign_in(user_db, audit_log, &user, device_name, client_info)
+                .await
+                .map(|(_, headers, body)| (StatusCode::CREATED, headers, body))
+                .map_err(|error| {
+                    FederatedLoginError::StartLogin(StartLoginError::IssueTokensError(error))
+                })
+        }
+        Err(db_error) => Err(FederatedLoginError::DbError(db_error)),
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
     use crate::models::user::{PgUserDb, UserDb};
-    use crate::utils::salted_hashes::{
-        generate_b64_hash_for_text_and_salt, generate_hash_and_salt_for_text,
-    };
-    use crate::utils::tokens::AccessToken;
+    use crate::utils::passwords::tests::create_legacy_password_hash;
+    use crate::utils::tokens::{hash_opaque_token, AccessToken};
     use crate::web::users::{
         LoginUserData, LoginUserDataBody, LoginUserResponseBody, RegisterUserData,
         RegisterUserRequestBody, RegisterUserResponseBody,
@@ -321,22 +621,45 @@ pub mod tests {
     use crate::web_service::{ErrorCode, ErrorResponseBody, WebService};
     use axum::body::Bytes;
     use axum::Router;
+    use database::users::UserInput;
     use database::utils::random_samples::RandomSample;
     use http_body::combinators::UnsyncBoxBody;
+    use uuid::Uuid;
 
     pub async fn create_test_router() -> Router {
         WebService::new_test().await.into_router()
     }
 
-    #[test]
-    fn test_passwords() {
-        let password = String::new_random(1024);
+    impl RegisterUserData {
+        pub fn email(&self) -> &str {
+            &self.email
+        }
 
-        let (hash, salt) = generate_hash_and_salt_for_text(&password);
+        pub fn password(&self) -> &str {
+            &self.password
+        }
+    }
 
-        let hash2 = generate_b64_hash_for_text_and_salt(password, salt).expect("valid salt");
+    impl LoginUserData {
+        pub fn new(email: impl Into<String>, password: impl Into<String>) -> Self {
+            Self {
+                email: email.into(),
+                password: password.into(),
+                device_name: None,
+            }
+        }
+    }
 
-        assert_eq!(hash, hash2)
+    impl LoginUserDataBody {
+        pub fn new(data: LoginUserData) -> Self {
+            Self { data }
+        }
+    }
+
+    impl LoginUserResponseBody {
+        pub fn mfa_token(&self) -> Option<&str> {
+            self.mfa_token.as_deref()
+        }
     }
 
     pub async fn register_new_user(
@@ -361,6 +684,7 @@ pub mod tests {
             first_name: Some(first_name.clone()),
             last_name: Some(last_name.clone()),
             language_code,
+            device_name: None,
         });
 
         let request_body = RegisterUserRequestBody {
@@ -373,18 +697,39 @@ pub mod tests {
         (result, response)
     }
 
-    pub fn get_auth_header_for_name(
+    fn get_header(
         response: &hyper::Response<UnsyncBoxBody<Bytes, axum::Error>>,
+        name: &str,
     ) -> String {
         response
             .headers()
             .iter()
-            .filter(|(x, _)| x.as_str() == "x-auth-token")
+            .filter(|(x, _)| x.as_str() == name)
             .flat_map(|(_, x)| x.to_str().map(String::from))
             .next()
             .expect("existing header")
     }
 
+    pub fn get_auth_header_for_name(
+        response: &hyper::Response<UnsyncBoxBody<Bytes, axum::Error>>,
+    ) -> String {
+        get_header(response, "x-auth-token")
+    }
+
+    pub fn get_refresh_token_header(
+        response: &hyper::Response<UnsyncBoxBody<Bytes, axum::Error>>,
+    ) -> String {
+        get_header(response, "x-refresh-token")
+    }
+
+    /// Id of the user an access token is issued to
+    pub fn user_id(access_token: &str) -> Uuid {
+        AccessToken::from_token(access_token)
+            .expect("valid token")
+            .get_user()
+            .user_id
+    }
+
     #[tokio::test]
     async fn should_register_user_with_valid_parameters() {
         let (request, response) = register_new_user(None).await;
@@ -503,7 +848,7 @@ pub mod tests {
         assert_eq!(user.last_name, request.last_name);
     }
 
-    async fn login_with_email_and_password(
+    pub async fn login_with_email_and_password(
         email: String,
         password: String,
     ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
@@ -511,7 +856,30 @@ pub mod tests {
 
         let uri = "/api/user/login";
 
-        let login_data = LoginUserData { email, password };
+        let login_data = LoginUserData {
+            email,
+            password,
+            device_name: None,
+        };
+
+        let request_body = LoginUserDataBody { data: login_data };
+
+        post(&router, uri, &request_body).await
+    }
+
+    pub async fn login_from_device(
+        user: &RegisterUserData,
+        device_name: Option<String>,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        let router = create_test_router().await;
+
+        let uri = "/api/user/login";
+
+        let login_data = LoginUserData {
+            email: user.email.clone(),
+            password: user.password.clone(),
+            device_name,
+        };
 
         let request_body = LoginUserDataBody { data: login_data };
 
@@ -588,10 +956,11 @@ pub mod tests {
         assert_eq!(response.status(), 202);
 
         let access_token = get_auth_header_for_name(&response);
+        let refresh_token = get_refresh_token_header(&response);
 
         let _ = deserialize_response_body::<LoginUserResponseBody>(response).await;
 
-        // Test token
+        // Test tokens
         let pool = crate::pg_pool()
             .await
             .expect("failed to create postgres pool");
@@ -602,6 +971,53 @@ pub mod tests {
             .await
             .expect("user exists");
 
-        assert_eq!(access_token, user.access_token)
+        let parsed_access_token = AccessToken::from_token(access_token).expect("valid token");
+        assert_eq!(parsed_access_token.get_user().user_id, user.id);
+
+        let refresh_token = user_db
+            .get_refresh_token_by_hash(hash_opaque_token(refresh_token))
+            .await
+            .expect("refresh token is stored");
+        assert_eq!(refresh_token.user_id, user.id);
+    }
+
+    #[tokio::test]
+    async fn should_rehash_a_legacy_password_on_login() {
+        let pool = crate::pg_pool()
+            .await
+            .expect("failed to create postgres pool");
+        let user_db = PgUserDb::new(pool);
+
+        let email = std::format!("{:?}@test.test", String::new_random(32));
+        let password = std::format!("password:{:?}", String::new_random(124));
+
+        let user_id = user_db
+            .insert_user(&UserInput {
+                user_id: Uuid::new_v4(),
+                alias: None,
+                first_name: None,
+                last_name: None,
+                email: email.clone(),
+                password_hash: create_legacy_password_hash(&password),
+                phone_number: None,
+                language_code: "ru-ru".to_owned(),
+                avatar: None,
+                country_code: None,
+            })
+            .await
+            .expect("user is created");
+
+        let response = login_with_email_and_password(email.clone(), password.clone()).await;
+        assert_eq!(response.status(), 202);
+
+        let user = user_db.get_user(&user_id).await.expect("user exists");
+        assert!(user.password_hash.starts_with("$argon2id$"));
+
+        // The new hash keeps working
+        let response = login_with_email_and_password(email.clone(), password).await;
+        assert_eq!(response.status(), 202);
+
+        let response = login_with_email_and_password(email, String::new_random(124)).await;
+        assert_eq!(response.status(), 401);
     }
 }
@@ -1,20 +1,42 @@
+use crate::blob_store::BlobStore;
+use crate::event_hub::EventHub;
+use crate::mailer::Mailer;
+use crate::models::audit::AuditLog;
+use crate::models::chat_members::ChatMemberDb;
+use crate::models::chat_message::ChatMessageDb;
+use crate::models::chats::ChatDb;
+use crate::models::company::CompanyDb;
 use crate::models::project::ProjectDb;
 use crate::models::user::UserDb;
-use crate::web::authentication::check_and_refresh_auth_token;
-use crate::web::{projects, users};
+use crate::oidc::OidcProviders;
+use crate::rate_limit::RateLimiter;
+use crate::web::authentication::check_auth_token;
+use crate::web::rate_limiting::limit_login_attempts;
+use crate::web::{
+    accounts, api_keys, audit, chat_members, chat_messages, chats, companies, company_members,
+    credentials, email_verification, events, jwks, media, mfa, oidc, password_reset, profiles,
+    project_members, projects, sessions, tokens, users,
+};
 use axum::http::Request;
 use axum::middleware::Next;
 use axum::response::Response;
-use axum::routing::get;
+use axum::routing::{delete, get, patch, put};
 use axum::{middleware, routing::post, Router};
 use axum_tracing_opentelemetry::{find_current_trace_id, opentelemetry_tracing_layer};
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
 #[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
 pub enum ErrorCode {
     InvalidEmailFormat,
     AlreadyRegistered,
     InvalidInput,
+    EmailNotVerified,
+    Forbidden,
+    TooManyRequests,
+    AlreadyTaken,
+    PreconditionRequired,
+    PreconditionFailed,
 }
 
 #[derive(Clone, Deserialize, Serialize)]
@@ -24,29 +46,225 @@ pub struct ErrorResponseBody {
 }
 
 #[derive(Clone)]
-pub struct WebService<UDB, PDB> {
+pub struct WebService<UDB, PDB, CDB, CHDB, CMDB, MDB> {
     pub user_db: UDB,
     pub project_db: PDB,
+    pub company_db: CDB,
+    pub chat_db: CHDB,
+    pub chat_member_db: CMDB,
+    pub chat_message_db: MDB,
+    pub mailer: Arc<dyn Mailer>,
+    pub audit_log: Arc<dyn AuditLog>,
+    pub oidc_providers: Arc<OidcProviders>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub blob_store: Arc<dyn BlobStore>,
+    pub event_hub: Arc<EventHub>,
 }
 
-impl<UDB: UserDb, PDB: ProjectDb> WebService<UDB, PDB> {
-    pub fn new(user_db: UDB, project_db: PDB) -> Self {
+impl<
+        UDB: UserDb,
+        PDB: ProjectDb,
+        CDB: CompanyDb,
+        CHDB: ChatDb,
+        CMDB: ChatMemberDb,
+        MDB: ChatMessageDb,
+    > WebService<UDB, PDB, CDB, CHDB, CMDB, MDB>
+{
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        user_db: UDB,
+        project_db: PDB,
+        company_db: CDB,
+        chat_db: CHDB,
+        chat_member_db: CMDB,
+        chat_message_db: MDB,
+        mailer: Arc<dyn Mailer>,
+        audit_log: Arc<dyn AuditLog>,
+        oidc_providers: Arc<OidcProviders>,
+        rate_limiter: Arc<RateLimiter>,
+        blob_store: Arc<dyn BlobStore>,
+        event_hub: Arc<EventHub>,
+    ) -> Self {
         Self {
             user_db,
             project_db,
+            company_db,
+            chat_db,
+            chat_member_db,
+            chat_message_db,
+            mailer,
+            audit_log,
+            oidc_providers,
+            rate_limiter,
+            blob_store,
+            event_hub,
         }
     }
 
     pub fn into_router(self) -> Router {
         Router::new()
             .route("/api/project/new", post(projects::post))
-            .route("/api/project/:payment_id", get(projects::get))
+            .route(
+                "/api/project/:project_id",
+                get(projects::get)
+                    .patch(projects::patch)
+                    .delete(projects::delete),
+            )
+            .route(
+                "/api/project/:project_id/members",
+                get(project_members::get_all).post(project_members::post),
+            )
+            .route(
+                "/api/project/:project_id/members/:user_id",
+                patch(project_members::patch).delete(project_members::delete),
+            )
+            .route(
+                "/api/project/:project_id/invitations",
+                get(project_members::get_invitations),
+            )
+            .route(
+                "/api/project/:project_id/invitations/:invitation_id",
+                delete(project_members::delete_invitation),
+            )
+            .route("/api/projects", get(projects::get_all))
+            .route("/api/company", post(companies::post))
+            .route(
+                "/api/company/:company_id",
+                get(companies::get).patch(companies::patch),
+            )
+            .route(
+                "/api/company/:company_id/projects",
+                get(companies::get_projects).post(companies::post_project),
+            )
+            .route(
+                "/api/company/:company_id/projects/:project_id",
+                delete(companies::delete_project),
+            )
+            .route(
+                "/api/company/:company_id/members",
+                get(company_members::get_all).post(company_members::post),
+            )
+            .route(
+                "/api/company/:company_id/members/:user_id",
+                patch(company_members::patch).delete(company_members::delete),
+            )
+            .route(
+                "/api/company/:company_id/invitations",
+                get(company_members::get_invitations),
+            )
+            .route(
+                "/api/company/:company_id/invitations/:invitation_id",
+                delete(company_members::delete_invitation),
+            )
+            .route("/api/companies", get(companies::get_all))
+            .route("/api/chat", post(chats::post))
+            .route("/api/chat/:chat_id", get(chats::get))
+            .route(
+                "/api/chat/:chat_id/members",
+                get(chat_members::get_all).post(chat_members::post),
+            )
+            .route(
+                "/api/chat/:chat_id/members/:user_id",
+                patch(chat_members::patch).delete(chat_members::delete),
+            )
+            .route(
+                "/api/chat/:chat_id/messages",
+                get(chat_messages::get_all).post(chat_messages::post),
+            )
+            .route(
+                "/api/chat/:chat_id/messages/:message_id",
+                patch(chat_messages::patch).delete(chat_messages::delete),
+            )
+            .route(
+                "/api/chat/:chat_id/messages/:message_id/context",
+                get(chat_messages::get_context),
+            )
+            .route(
+                "/api/chat/:chat_id/messages/:message_id/revisions",
+                get(chat_messages::get_revisions),
+            )
+            .route(
+                "/api/chat/:chat_id/messages/:message_id/thread",
+                get(chat_messages::get_thread),
+            )
+            .route("/api/chats", get(chats::get_all))
+            .route("/api/events", get(events::get))
+            .route("/api/ws", get(events::get_socket))
+            .route(
+                "/api/user/sessions",
+                get(sessions::get_all).delete(sessions::delete_all),
+            )
+            .route("/api/user/sessions/:session_id", delete(sessions::delete))
+            .route("/api/user/logout", post(sessions::logout))
+            .route(
+                "/api/user/verify-email/resend",
+                post(email_verification::resend),
+            )
+            .route("/api/user/mfa/totp", post(mfa::enrol_totp))
+            .route("/api/user/mfa/totp/confirm", post(mfa::confirm_totp))
+            .route(
+                "/api/user/api-keys",
+                get(api_keys::get_all).post(api_keys::post),
+            )
+            .route("/api/user/api-keys/:api_key_id", delete(api_keys::delete))
+            .route(
+                "/api/user/me",
+                get(profiles::get_me)
+                    .patch(profiles::patch_me)
+                    .delete(accounts::delete),
+            )
+            .route("/api/user/me/export", get(accounts::export))
+            .route(
+                "/api/user/me/avatar",
+                put(media::put_user_avatar)
+                    .delete(media::delete_user_avatar)
+                    .layer(media::avatar_body_limit()),
+            )
+            .route("/api/user/:user_id", get(profiles::get))
+            .route(
+                "/api/user/project-invitations/accept",
+                post(project_members::accept_invitation),
+            )
+            .route(
+                "/api/user/company-invitations/accept",
+                post(company_members::accept_invitation),
+            )
+            .route("/api/admin/audit", get(audit::get))
+            .merge(
+                Router::new()
+                    .route("/api/user/password", post(credentials::change_password))
+                    .route("/api/user/email", post(credentials::change_email))
+                    .route_layer(middleware::from_fn_with_state(
+                        self.clone(),
+                        limit_login_attempts::<UDB, PDB, CDB, CHDB, CMDB, MDB>,
+                    )),
+            )
             .layer(middleware::from_fn_with_state(
                 self.clone(),
-                check_and_refresh_auth_token,
+                check_auth_token::<UDB, PDB, CDB, CHDB, CMDB, MDB, _>,
             ))
-            .route("/api/user", post(users::post))
-            .route("/api/user/login", post(users::login))
+            .merge(
+                Router::new()
+                    .route("/api/user", post(users::post))
+                    .route("/api/user/login", post(users::login))
+                    .route("/api/user/login/mfa", post(mfa::login))
+                    .route("/api/user/password/forgot", post(password_reset::forgot))
+                    .route("/api/user/password/reset", post(password_reset::reset))
+                    .route_layer(middleware::from_fn_with_state(
+                        self.clone(),
+                        limit_login_attempts::<UDB, PDB, CDB, CHDB, CMDB, MDB>,
+                    )),
+            )
+            .route("/api/user/token/refresh", post(tokens::refresh))
+            .route("/api/user/verify-email", post(email_verification::verify))
+            .route(
+                "/api/user/email/confirm",
+                post(credentials::confirm_email_change),
+            )
+            .route("/api/user/oidc/:provider/authorize", post(oidc::authorize))
+            .route("/api/user/oidc/:provider/callback", post(oidc::callback))
+            .route("/.well-known/jwks.json", get(jwks::get))
+            .route("/api/media/*key", get(media::get))
             .layer(middleware::from_fn(propagate_b3_headers))
             .layer(opentelemetry_tracing_layer())
             .with_state(self)
@@ -73,8 +291,17 @@ async fn propagate_b3_headers<B>(req: Request<B>, next: Next<B>) -> Result<Respo
 
 #[cfg(test)]
 pub mod tests {
+    use crate::blob_store::InMemoryBlobStore;
+    use crate::mailer::InMemoryMailer;
+    use crate::models::audit::PgAuditLog;
+    use crate::models::chat_members::PgChatMemberDb;
+    use crate::models::chat_message::PgChatMessageDb;
+    use crate::models::chats::PgChatDb;
+    use crate::models::company::PgCompanyDb;
     use crate::models::project::PgProjectDb;
     use crate::models::user::PgUserDb;
+    use crate::oidc::tests::MOCK_ISSUER;
+    use crate::rate_limit::{InMemoryRateLimitStore, RateLimits};
     use crate::utils::modify_builder::ModifyBuilder;
     use axum::http::request::Builder;
     use axum::{
@@ -87,15 +314,38 @@ pub mod tests {
     use tower::ServiceExt;
 
     use super::*;
+    use lazy_static::lazy_static;
+
+    lazy_static! {
+        /// Shared by every test router, look mails up by a recipient
+        pub static ref TEST_MAILER: InMemoryMailer = InMemoryMailer::default();
+    }
 
-    impl WebService<PgUserDb, PgProjectDb> {
+    impl WebService<PgUserDb, PgProjectDb, PgCompanyDb, PgChatDb, PgChatMemberDb, PgChatMessageDb> {
         pub async fn new_test() -> Self {
             let pool = crate::pg_pool()
                 .await
                 .expect("failed to create postgres pool");
             Self {
                 user_db: PgUserDb::new(pool.clone()),
-                project_db: PgProjectDb::new(pool),
+                project_db: PgProjectDb::new(pool.clone()),
+                company_db: PgCompanyDb::new(pool.clone()),
+                chat_db: PgChatDb::new(pool.clone()),
+                chat_member_db: PgChatMemberDb::new(pool.clone()),
+                chat_message_db: PgChatMessageDb::new(pool.clone()),
+                mailer: Arc::new(TEST_MAILER.clone()),
+                audit_log: Arc::new(PgAuditLog::new(pool.clone())),
+                oidc_providers: Arc::new(OidcProviders::new(
+                    vec![MOCK_ISSUER.provider_config()],
+                    std::env::var("OIDC_REDIRECT_URL")
+                        .expect("OIDC_REDIRECT_URL must be in environment"),
+                )),
+                rate_limiter: Arc::new(RateLimiter::new(
+                    Arc::new(InMemoryRateLimitStore::default()),
+                    RateLimits::from_env(),
+                )),
+                blob_store: Arc::new(InMemoryBlobStore::default()),
+                event_hub: EventHub::start(pool),
             }
         }
     }
@@ -166,6 +416,97 @@ pub mod tests {
         post_with_auth_header(router, uri, body, Option::<String>::None).await
     }
 
+    pub async fn patch_with_auth_header(
+        router: &Router,
+        uri: impl AsRef<str>,
+        body: &impl Serialize,
+        token: Option<impl AsRef<str> + Display>,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        let request = Request::builder()
+            .method(Method::PATCH)
+            .uri(uri.as_ref())
+            .header(CONTENT_TYPE, "application/json")
+            .modify(token.as_ref(), |this, token| {
+                this.header("Authorization", std::format!("Bearer {token}"))
+            })
+            .body(
+                serde_json::to_vec(body)
+                    .expect("failed to serialize PATCH body")
+                    .into(),
+            )
+            .expect("failed to build PATCH request");
+        send_request(router, request).await
+    }
+
+    pub async fn delete_with_auth_header(
+        router: &Router,
+        uri: impl AsRef<str>,
+        token: Option<impl AsRef<str> + Display>,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        let request = Request::builder()
+            .method(Method::DELETE)
+            .uri(uri.as_ref())
+            .modify(token.as_ref(), |this, token| {
+                this.header("Authorization", std::format!("Bearer {token}"))
+            })
+            .body(hyper::Body::empty())
+            .expect("failed to build DELETE request");
+        send_request(router, request).await
+    }
+
+    pub async fn delete_json_with_auth_header(
+        router: &Router,
+        uri: impl AsRef<str>,
+        body: &impl Serialize,
+        token: Option<impl AsRef<str> + Display>,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        let request = Request::builder()
+            .method(Method::DELETE)
+            .uri(uri.as_ref())
+            .header(CONTENT_TYPE, "application/json")
+            .modify(token.as_ref(), |this, token| {
+                this.header("Authorization", std::format!("Bearer {token}"))
+            })
+            .body(
+                serde_json::to_vec(body)
+                    .expect("failed to serialize DELETE body")
+                    .into(),
+            )
+            .expect("failed to build DELETE request");
+        send_request(router, request).await
+    }
+
+    /// Sends bytes as the `file` field of a multipart form
+    pub async fn put_file_with_auth_header(
+        router: &Router,
+        uri: impl AsRef<str>,
+        content_type: &str,
+        bytes: &[u8],
+        token: Option<impl AsRef<str> + Display>,
+    ) -> hyper::Response<UnsyncBoxBody<Bytes, axum::Error>> {
+        let boundary = "exchange-test-boundary";
+        let mut body = std::format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"upload\"\r\nContent-Type: {content_type}\r\n\r\n"
+        )
+        .into_bytes();
+        body.extend_from_slice(bytes);
+        body.extend_from_slice(std::format!("\r\n--{boundary}--\r\n").as_bytes());
+
+        let request = Request::builder()
+            .method(Method::PUT)
+            .uri(uri.as_ref())
+            .header(
+                CONTENT_TYPE,
+                std::format!("multipart/form-data; boundary={boundary}"),
+            )
+            .modify(token.as_ref(), |this, token| {
+                this.header("Authorization", std::format!("Bearer {token}"))
+            })
+            .body(body.into())
+            .expect("failed to build PUT request");
+        send_request(router, request).await
+    }
+
     pub async fn deserialize_response_body<T>(
         response: hyper::Response<UnsyncBoxBody<Bytes, axum::Error>>,
     ) -> T
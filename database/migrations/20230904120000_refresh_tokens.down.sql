@@ -0,0 +1,16 @@
+-- Users
+
+ALTER TABLE users
+ADD COLUMN access_token text NOT NULL DEFAULT '',
+ADD COLUMN previous_access_token text;
+
+ALTER TABLE users
+ALTER COLUMN access_token DROP DEFAULT;
+
+-- Refresh Tokens
+
+DROP INDEX refresh_tokens_user_id_index;
+DROP INDEX refresh_tokens_family_id_index;
+DROP INDEX refresh_tokens_token_hash_index;
+DROP INDEX refresh_tokens_id_index;
+DROP TABLE refresh_tokens;
@@ -0,0 +1,23 @@
+-- Refresh Tokens
+
+CREATE TABLE refresh_tokens
+(
+    id         uuid PRIMARY KEY,
+    family_id  uuid NOT NULL, -- All tokens rotated from a single login, one family per device
+    user_id    uuid REFERENCES users(id) NOT NULL,
+    token_hash character varying(88) NOT NULL, -- Base64 SHA-512 of an opaque token
+    created_at timestamp(0) without time zone NOT NULL,
+    expires_at timestamp(0) without time zone NOT NULL,
+    used_at    timestamp(0) without time zone, -- Set once the token is rotated
+    revoked_at timestamp(0) without time zone
+);
+CREATE UNIQUE INDEX refresh_tokens_id_index ON refresh_tokens (id uuid_ops);
+CREATE UNIQUE INDEX refresh_tokens_token_hash_index ON refresh_tokens (token_hash);
+CREATE INDEX refresh_tokens_family_id_index ON refresh_tokens (family_id);
+CREATE INDEX refresh_tokens_user_id_index ON refresh_tokens (user_id);
+
+-- Users
+
+ALTER TABLE users
+DROP COLUMN access_token,
+DROP COLUMN previous_access_token;
@@ -0,0 +1,8 @@
+ALTER TABLE refresh_tokens
+DROP CONSTRAINT refresh_tokens_family_id_fkey;
+
+-- Sessions
+
+DROP INDEX sessions_user_id_index;
+DROP INDEX sessions_id_index;
+DROP TABLE sessions;
@@ -0,0 +1,25 @@
+-- Sessions
+
+CREATE TABLE sessions
+(
+    id           uuid PRIMARY KEY,
+    user_id      uuid REFERENCES users(id) NOT NULL,
+    device_name  character varying(255),
+    user_agent   text,
+    ip_address   character varying(45), -- Fits IPv6 textual form
+    created_at   timestamp(0) without time zone NOT NULL,
+    last_seen_at timestamp(0) without time zone NOT NULL,
+    revoked_at   timestamp(0) without time zone
+);
+CREATE UNIQUE INDEX sessions_id_index ON sessions (id uuid_ops);
+CREATE INDEX sessions_user_id_index ON sessions (user_id);
+
+-- Every refresh token family becomes a session
+
+INSERT INTO sessions ( id, user_id, created_at, last_seen_at, revoked_at )
+SELECT family_id, min(user_id::text)::uuid, min(created_at), max(created_at), max(revoked_at)
+FROM refresh_tokens
+GROUP BY family_id;
+
+ALTER TABLE refresh_tokens
+ADD CONSTRAINT refresh_tokens_family_id_fkey FOREIGN KEY (family_id) REFERENCES sessions(id);
@@ -0,0 +1,5 @@
+-- Revoked Tokens
+
+DROP INDEX revoked_tokens_expires_at_index;
+DROP INDEX revoked_tokens_id_index;
+DROP TABLE revoked_tokens;
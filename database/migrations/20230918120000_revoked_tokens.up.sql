@@ -0,0 +1,11 @@
+-- Revoked Tokens
+
+CREATE TABLE revoked_tokens
+(
+    id         uuid PRIMARY KEY, -- jti of a revoked access token
+    user_id    uuid REFERENCES users(id) NOT NULL,
+    revoked_at timestamp(0) without time zone NOT NULL,
+    expires_at timestamp(0) without time zone NOT NULL -- exp of the token, the entry is useless after it
+);
+CREATE UNIQUE INDEX revoked_tokens_id_index ON revoked_tokens (id uuid_ops);
+CREATE INDEX revoked_tokens_expires_at_index ON revoked_tokens (expires_at);
@@ -0,0 +1,15 @@
+-- Users
+
+-- Argon2id hashes can not be converted back, such users have to reset their passwords
+ALTER TABLE users
+ADD COLUMN password_salt   character varying(88),
+ADD COLUMN password_sha512 character varying(88);
+
+UPDATE users
+SET password_salt   = CASE WHEN password_hash LIKE '$hmac-sha512$%' THEN split_part(password_hash, '$', 3) ELSE '' END,
+    password_sha512 = CASE WHEN password_hash LIKE '$hmac-sha512$%' THEN split_part(password_hash, '$', 4) ELSE '' END;
+
+ALTER TABLE users
+ALTER COLUMN password_salt SET NOT NULL,
+ALTER COLUMN password_sha512 SET NOT NULL,
+DROP COLUMN password_hash;
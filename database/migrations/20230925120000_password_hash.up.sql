@@ -0,0 +1,14 @@
+-- Users
+
+-- PHC strings, legacy HMAC-SHA512 hashes are kept as `$hmac-sha512$<salt>$<hash>`
+-- until their owners login again and get re-hashed with Argon2id
+ALTER TABLE users
+ADD COLUMN password_hash character varying(255);
+
+UPDATE users
+SET password_hash = '$hmac-sha512$' || password_salt || '$' || password_sha512;
+
+ALTER TABLE users
+ALTER COLUMN password_hash SET NOT NULL,
+DROP COLUMN password_salt,
+DROP COLUMN password_sha512;
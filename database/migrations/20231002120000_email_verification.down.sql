@@ -0,0 +1,4 @@
+-- Users
+
+ALTER TABLE users
+DROP COLUMN email_verified_at;
@@ -0,0 +1,8 @@
+-- Users
+
+ALTER TABLE users
+ADD COLUMN email_verified_at timestamp(0) without time zone;
+
+-- Accounts registered before the verification flow are trusted
+UPDATE users
+SET email_verified_at = created_at;
@@ -0,0 +1,6 @@
+-- Password Reset Tokens
+
+DROP INDEX password_reset_tokens_user_id_index;
+DROP INDEX password_reset_tokens_token_hash_index;
+DROP INDEX password_reset_tokens_id_index;
+DROP TABLE password_reset_tokens;
@@ -0,0 +1,14 @@
+-- Password Reset Tokens
+
+CREATE TABLE password_reset_tokens
+(
+    id         uuid PRIMARY KEY,
+    user_id    uuid REFERENCES users(id) NOT NULL,
+    token_hash character varying(88) NOT NULL, -- Base64 SHA-512 of a mailed opaque token
+    created_at timestamp(0) without time zone NOT NULL,
+    expires_at timestamp(0) without time zone NOT NULL,
+    used_at    timestamp(0) without time zone
+);
+CREATE UNIQUE INDEX password_reset_tokens_id_index ON password_reset_tokens (id uuid_ops);
+CREATE UNIQUE INDEX password_reset_tokens_token_hash_index ON password_reset_tokens (token_hash);
+CREATE INDEX password_reset_tokens_user_id_index ON password_reset_tokens (user_id);
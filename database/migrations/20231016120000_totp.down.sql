@@ -0,0 +1,12 @@
+-- MFA Recovery Codes
+
+DROP INDEX mfa_recovery_codes_user_id_index;
+DROP INDEX mfa_recovery_codes_id_index;
+DROP TABLE mfa_recovery_codes;
+
+-- Users
+
+ALTER TABLE users
+DROP COLUMN totp_last_step,
+DROP COLUMN totp_enabled_at,
+DROP COLUMN totp_secret;
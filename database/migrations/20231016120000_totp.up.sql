@@ -0,0 +1,19 @@
+-- Users
+
+ALTER TABLE users
+ADD COLUMN totp_secret character varying(32), -- Base32, set on enrolment and kept once confirmed
+ADD COLUMN totp_enabled_at timestamp(0) without time zone,
+ADD COLUMN totp_last_step bigint; -- The last accepted time step, a code works once
+
+-- MFA Recovery Codes
+
+CREATE TABLE mfa_recovery_codes
+(
+    id         uuid PRIMARY KEY,
+    user_id    uuid REFERENCES users(id) NOT NULL,
+    code_hash  character varying(88) NOT NULL, -- Base64 SHA-512 of a code
+    created_at timestamp(0) without time zone NOT NULL,
+    used_at    timestamp(0) without time zone
+);
+CREATE UNIQUE INDEX mfa_recovery_codes_id_index ON mfa_recovery_codes (id uuid_ops);
+CREATE INDEX mfa_recovery_codes_user_id_index ON mfa_recovery_codes (user_id);
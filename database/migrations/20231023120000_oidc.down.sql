@@ -0,0 +1,13 @@
+-- OIDC Login States
+
+DROP INDEX oidc_login_states_expires_at_index;
+DROP INDEX oidc_login_states_state_hash_index;
+DROP INDEX oidc_login_states_id_index;
+DROP TABLE oidc_login_states;
+
+-- User Identities
+
+DROP INDEX user_identities_user_id_index;
+DROP INDEX user_identities_issuer_subject_index;
+DROP INDEX user_identities_id_index;
+DROP TABLE user_identities;
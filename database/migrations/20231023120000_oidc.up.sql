@@ -0,0 +1,31 @@
+-- User Identities
+
+CREATE TABLE user_identities
+(
+    id         uuid PRIMARY KEY,
+    user_id    uuid REFERENCES users(id) NOT NULL,
+    issuer     character varying(255) NOT NULL, -- `iss` of an OpenID Connect provider
+    subject    character varying(255) NOT NULL, -- `sub`, stable within the issuer
+    email      character varying(320),
+    created_at timestamp(0) without time zone NOT NULL
+);
+CREATE UNIQUE INDEX user_identities_id_index ON user_identities (id uuid_ops);
+CREATE UNIQUE INDEX user_identities_issuer_subject_index ON user_identities (issuer, subject);
+CREATE INDEX user_identities_user_id_index ON user_identities (user_id);
+
+-- OIDC Login States
+
+CREATE TABLE oidc_login_states
+(
+    id            uuid PRIMARY KEY,
+    state_hash    character varying(88) NOT NULL, -- Base64 SHA-512 of the `state` parameter
+    provider      character varying(64) NOT NULL,
+    nonce         character varying(64) NOT NULL,
+    code_verifier character varying(128) NOT NULL, -- PKCE, never leaves the backend
+    device_name   character varying(255),
+    created_at    timestamp(0) without time zone NOT NULL,
+    expires_at    timestamp(0) without time zone NOT NULL
+);
+CREATE UNIQUE INDEX oidc_login_states_id_index ON oidc_login_states (id uuid_ops);
+CREATE UNIQUE INDEX oidc_login_states_state_hash_index ON oidc_login_states (state_hash);
+CREATE INDEX oidc_login_states_expires_at_index ON oidc_login_states (expires_at);
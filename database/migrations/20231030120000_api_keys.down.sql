@@ -0,0 +1,6 @@
+-- API Keys
+
+DROP INDEX api_keys_user_id_index;
+DROP INDEX api_keys_key_hash_index;
+DROP INDEX api_keys_id_index;
+DROP TABLE api_keys;
@@ -0,0 +1,17 @@
+-- API Keys
+
+CREATE TABLE api_keys
+(
+    id           uuid PRIMARY KEY,
+    user_id      uuid REFERENCES users(id) NOT NULL,
+    name         character varying(255) NOT NULL,
+    prefix       character varying(16) NOT NULL, -- Leading characters of the key, shown to tell keys apart
+    key_hash     character varying(88) NOT NULL, -- Base64 SHA-512 of the key
+    scopes       text[] NOT NULL,
+    created_at   timestamp(0) without time zone NOT NULL,
+    last_used_at timestamp(0) without time zone,
+    revoked_at   timestamp(0) without time zone
+);
+CREATE UNIQUE INDEX api_keys_id_index ON api_keys (id uuid_ops);
+CREATE UNIQUE INDEX api_keys_key_hash_index ON api_keys (key_hash);
+CREATE INDEX api_keys_user_id_index ON api_keys (user_id);
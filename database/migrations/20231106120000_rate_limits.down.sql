@@ -0,0 +1,7 @@
+-- Login Failures
+
+DROP TABLE login_failures;
+
+-- Rate Limit Buckets
+
+DROP TABLE rate_limit_buckets;
@@ -0,0 +1,18 @@
+-- Rate Limit Buckets
+
+CREATE TABLE rate_limit_buckets
+(
+    key        character varying(400) PRIMARY KEY, -- A scope and a client, e.g. an ip address or an email
+    tokens     double precision NOT NULL,
+    updated_at timestamp(3) without time zone NOT NULL
+);
+
+-- Login Failures
+
+CREATE TABLE login_failures
+(
+    account      character varying(320) PRIMARY KEY, -- A lowercase email
+    failures     integer NOT NULL,
+    locked_until timestamp(0) without time zone,
+    updated_at   timestamp(0) without time zone NOT NULL
+);
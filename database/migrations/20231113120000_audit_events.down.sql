@@ -0,0 +1,9 @@
+-- Audit events
+
+DROP TRIGGER audit_events_no_truncate ON audit_events;
+DROP TRIGGER audit_events_append_only ON audit_events;
+DROP FUNCTION forbid_audit_event_changes;
+DROP INDEX audit_events_created_at_index;
+DROP INDEX audit_events_user_id_index;
+DROP TABLE audit_events;
+DROP TYPE AuditEventType;
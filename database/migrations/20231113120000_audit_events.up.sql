@@ -0,0 +1,38 @@
+-- Audit events
+
+CREATE TYPE AuditEventType AS ENUM (
+    'registered',
+    'login_succeeded',
+    'login_failed',
+    'token_refreshed',
+    'password_reset',
+    'project_created',
+    'project_read'
+);
+
+CREATE TABLE audit_events
+(
+    id          uuid PRIMARY KEY,
+    event_type  AuditEventType NOT NULL,
+    user_id     uuid NOT NULL, -- Not a reference, events outlive users
+    resource_id uuid,
+    ip_address  character varying(45),
+    user_agent  text,
+    created_at  timestamp(3) without time zone NOT NULL
+);
+CREATE INDEX audit_events_user_id_index ON audit_events (user_id, created_at);
+CREATE INDEX audit_events_created_at_index ON audit_events (created_at);
+
+-- Events are only ever appended
+CREATE FUNCTION forbid_audit_event_changes() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_events are append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_events_append_only
+    BEFORE UPDATE OR DELETE ON audit_events
+    FOR EACH ROW EXECUTE FUNCTION forbid_audit_event_changes();
+CREATE TRIGGER audit_events_no_truncate
+    BEFORE TRUNCATE ON audit_events
+    FOR EACH STATEMENT EXECUTE FUNCTION forbid_audit_event_changes();
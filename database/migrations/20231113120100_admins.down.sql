@@ -0,0 +1,3 @@
+-- Admins
+
+DROP TABLE admins;
@@ -0,0 +1,7 @@
+-- Admins, granted by operators straight in the database
+
+CREATE TABLE admins
+(
+    user_id    uuid PRIMARY KEY REFERENCES users(id),
+    created_at timestamp(0) without time zone NOT NULL
+);
@@ -0,0 +1,4 @@
+-- Phone numbers are stored in E.164 format, with a leading plus
+
+ALTER TABLE users
+    ALTER COLUMN phone_number TYPE character varying(15);
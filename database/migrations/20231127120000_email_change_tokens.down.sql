@@ -0,0 +1,6 @@
+-- Email Change Tokens
+
+DROP INDEX email_change_tokens_user_id_index;
+DROP INDEX email_change_tokens_token_hash_index;
+DROP INDEX email_change_tokens_id_index;
+DROP TABLE email_change_tokens;
@@ -0,0 +1,15 @@
+-- Email Change Tokens
+
+CREATE TABLE email_change_tokens
+(
+    id         uuid PRIMARY KEY,
+    user_id    uuid REFERENCES users(id) NOT NULL,
+    new_email  character varying(320) NOT NULL,
+    token_hash character varying(88) NOT NULL, -- Base64 SHA-512 of an opaque token mailed to the new email
+    created_at timestamp(0) without time zone NOT NULL,
+    expires_at timestamp(0) without time zone NOT NULL,
+    used_at    timestamp(0) without time zone
+);
+CREATE UNIQUE INDEX email_change_tokens_id_index ON email_change_tokens (id uuid_ops);
+CREATE UNIQUE INDEX email_change_tokens_token_hash_index ON email_change_tokens (token_hash);
+CREATE INDEX email_change_tokens_user_id_index ON email_change_tokens (user_id);
@@ -0,0 +1,3 @@
+-- Audit Events of credential changes
+
+-- Postgres can not drop enum values, events of these types are kept as they are
@@ -0,0 +1,4 @@
+-- Audit Events of credential changes
+
+ALTER TYPE AuditEventType ADD VALUE 'password_changed' AFTER 'password_reset';
+ALTER TYPE AuditEventType ADD VALUE 'email_changed' AFTER 'password_changed';
@@ -0,0 +1,8 @@
+-- Users
+
+ALTER TABLE users
+DROP COLUMN deleted_at;
+
+-- Audit Events
+
+-- Postgres can not drop enum values, events of this type are kept as they are
@@ -0,0 +1,9 @@
+-- Users
+
+-- A deleted user keeps an anonymised row, so ids in other tables stay valid
+ALTER TABLE users
+ADD COLUMN deleted_at timestamp(0) without time zone;
+
+-- Audit Events
+
+ALTER TYPE AuditEventType ADD VALUE 'account_deleted' AFTER 'email_changed';
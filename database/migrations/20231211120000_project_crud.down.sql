@@ -0,0 +1,15 @@
+-- Project updates, soft deletion and listing
+
+-- Postgres can not drop enum values, events of these types are kept as they are
+DROP INDEX company_projects_project_id_index;
+DROP INDEX project_members_user_id_index;
+DROP INDEX projects_updated_at_and_id_index;
+DROP INDEX projects_created_at_and_id_index;
+DROP INDEX projects_user_id_index;
+
+DELETE FROM company_projects WHERE project_id IN (SELECT id FROM projects WHERE deleted_at IS NOT NULL);
+DELETE FROM project_members WHERE project_id IN (SELECT id FROM projects WHERE deleted_at IS NOT NULL);
+DELETE FROM projects WHERE deleted_at IS NOT NULL;
+ALTER TABLE projects DROP COLUMN deleted_at;
+ALTER TABLE projects ALTER COLUMN updated_at TYPE timestamp(0) without time zone;
+ALTER TABLE projects ALTER COLUMN created_at TYPE timestamp(0) without time zone;
@@ -0,0 +1,15 @@
+-- Project updates, soft deletion and listing
+
+-- Microseconds keep projects of the same second apart in lists, updated_at is the ETag of a project
+ALTER TABLE projects ALTER COLUMN created_at TYPE timestamp(6) without time zone;
+ALTER TABLE projects ALTER COLUMN updated_at TYPE timestamp(6) without time zone;
+ALTER TABLE projects ADD COLUMN deleted_at timestamp(0) without time zone;
+
+CREATE INDEX projects_user_id_index ON projects (user_id) WHERE deleted_at IS NULL;
+CREATE INDEX projects_created_at_and_id_index ON projects (created_at, id) WHERE deleted_at IS NULL;
+CREATE INDEX projects_updated_at_and_id_index ON projects (updated_at, id) WHERE deleted_at IS NULL;
+CREATE INDEX project_members_user_id_index ON project_members (user_id);
+CREATE INDEX company_projects_project_id_index ON company_projects (project_id);
+
+ALTER TYPE AuditEventType ADD VALUE 'project_updated' AFTER 'project_read';
+ALTER TYPE AuditEventType ADD VALUE 'project_deleted' AFTER 'project_updated';
@@ -0,0 +1,9 @@
+-- Project Invitations
+
+DROP TABLE project_invitations;
+
+-- Project Member roles
+
+ALTER TABLE project_members DROP COLUMN role;
+
+DROP TYPE ProjectMemberRole;
@@ -0,0 +1,25 @@
+-- Project Member roles
+
+CREATE TYPE ProjectMemberRole AS ENUM ('owner', 'editor', 'viewer');
+
+-- Members could update projects before roles existed
+ALTER TABLE project_members ADD COLUMN role ProjectMemberRole NOT NULL DEFAULT 'editor';
+ALTER TABLE project_members ALTER COLUMN role DROP DEFAULT;
+
+-- Project Invitations of people who have no account yet
+
+CREATE TABLE project_invitations
+(
+    id          uuid PRIMARY KEY,
+    project_id  uuid REFERENCES projects(id) NOT NULL,
+    email       character varying(320) NOT NULL,
+    role        ProjectMemberRole NOT NULL,
+    invited_by  uuid REFERENCES users(id) NOT NULL,
+    token_hash  character varying(88) NOT NULL, -- Base64 SHA-512 of an opaque token mailed to the email
+    created_at  timestamp(0) without time zone NOT NULL,
+    expires_at  timestamp(0) without time zone NOT NULL,
+    accepted_at timestamp(0) without time zone
+);
+CREATE UNIQUE INDEX project_invitations_id_index ON project_invitations (id uuid_ops);
+CREATE UNIQUE INDEX project_invitations_token_hash_index ON project_invitations (token_hash);
+CREATE INDEX project_invitations_project_id_index ON project_invitations (project_id);
@@ -0,0 +1,11 @@
+-- Company Invitations
+
+DROP TABLE company_invitations;
+
+-- Company Member roles
+
+DROP INDEX company_members_company_id_index;
+
+ALTER TABLE company_members DROP COLUMN role;
+
+DROP TYPE CompanyMemberRole;
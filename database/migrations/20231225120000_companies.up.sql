@@ -0,0 +1,29 @@
+-- Company Member roles
+
+CREATE TYPE CompanyMemberRole AS ENUM ('owner', 'admin', 'member');
+
+-- Members ran companies together before roles existed, the longest standing one owns it
+ALTER TABLE company_members ADD COLUMN role CompanyMemberRole NOT NULL DEFAULT 'admin';
+ALTER TABLE company_members ALTER COLUMN role DROP DEFAULT;
+UPDATE company_members SET role = 'owner'
+WHERE id IN (SELECT DISTINCT ON (company_id) id FROM company_members ORDER BY company_id, created_at, id);
+
+CREATE INDEX company_members_company_id_index ON company_members (company_id);
+
+-- Company Invitations of people who have no account yet
+
+CREATE TABLE company_invitations
+(
+    id          uuid PRIMARY KEY,
+    company_id  uuid REFERENCES companies(id) NOT NULL,
+    email       character varying(320) NOT NULL,
+    role        CompanyMemberRole NOT NULL,
+    invited_by  uuid REFERENCES users(id) NOT NULL,
+    token_hash  character varying(88) NOT NULL, -- Base64 SHA-512 of an opaque token mailed to the email
+    created_at  timestamp(0) without time zone NOT NULL,
+    expires_at  timestamp(0) without time zone NOT NULL,
+    accepted_at timestamp(0) without time zone
+);
+CREATE UNIQUE INDEX company_invitations_id_index ON company_invitations (id uuid_ops);
+CREATE UNIQUE INDEX company_invitations_token_hash_index ON company_invitations (token_hash);
+CREATE INDEX company_invitations_company_id_index ON company_invitations (company_id);
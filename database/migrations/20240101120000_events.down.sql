@@ -0,0 +1,8 @@
+-- Events
+
+DROP TRIGGER events_notify ON events;
+DROP FUNCTION notify_event;
+DROP INDEX events_user_id_index;
+DROP INDEX events_chat_id_index;
+DROP TABLE events;
+DROP TYPE EventType;
@@ -0,0 +1,35 @@
+-- Events every instance delivers to connected users, their ids are cursors clients resume from
+
+CREATE TYPE EventType AS ENUM (
+    'message_created',
+    'message_updated',
+    'message_deleted',
+    'read',
+    'member_added',
+    'member_updated',
+    'member_removed'
+);
+
+CREATE TABLE events
+(
+    id         bigserial PRIMARY KEY,
+    chat_id    uuid REFERENCES chats(id) NOT NULL,
+    event_type EventType NOT NULL,
+    user_id    uuid REFERENCES users(id) NOT NULL, -- Sender, reader or member the event is about
+    message_id uuid REFERENCES chat_messages(id),
+    created_at timestamp(0) without time zone NOT NULL
+);
+CREATE INDEX events_chat_id_index ON events (chat_id, id);
+CREATE INDEX events_user_id_index ON events (user_id, id);
+
+-- Listeners get the id of every new event once its transaction commits
+CREATE FUNCTION notify_event() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('events', NEW.id::text);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER events_notify
+    AFTER INSERT ON events
+    FOR EACH ROW EXECUTE FUNCTION notify_event();
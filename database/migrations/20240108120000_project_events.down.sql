@@ -0,0 +1,10 @@
+-- Project Events
+
+DELETE FROM events WHERE project_id is not null;
+DROP INDEX events_project_id_index;
+ALTER TABLE events
+DROP CONSTRAINT events_chat_or_project,
+DROP COLUMN project_id,
+ALTER COLUMN chat_id SET NOT NULL;
+
+-- Postgres can not drop enum values, the project ones are kept
@@ -0,0 +1,15 @@
+-- Project events share the table with chat events, so one cursor orders both
+
+ALTER TYPE EventType ADD VALUE IF NOT EXISTS 'project_created';
+ALTER TYPE EventType ADD VALUE IF NOT EXISTS 'project_updated';
+ALTER TYPE EventType ADD VALUE IF NOT EXISTS 'project_deleted';
+ALTER TYPE EventType ADD VALUE IF NOT EXISTS 'project_member_added';
+ALTER TYPE EventType ADD VALUE IF NOT EXISTS 'project_member_updated';
+ALTER TYPE EventType ADD VALUE IF NOT EXISTS 'project_member_removed';
+
+-- The user is the one who made a project change or the member it is about
+ALTER TABLE events
+ALTER COLUMN chat_id DROP NOT NULL,
+ADD COLUMN project_id uuid REFERENCES projects(id),
+ADD CONSTRAINT events_chat_or_project CHECK ((chat_id is null) <> (project_id is null));
+CREATE INDEX events_project_id_index ON events (project_id, id);
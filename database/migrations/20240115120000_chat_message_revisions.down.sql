@@ -0,0 +1,4 @@
+-- Chat message edits
+
+DROP TABLE chat_message_revisions;
+ALTER TABLE chat_messages DROP COLUMN edited_at;
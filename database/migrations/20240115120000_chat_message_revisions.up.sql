@@ -0,0 +1,14 @@
+-- Chat message edits
+
+ALTER TABLE chat_messages ADD COLUMN edited_at timestamp(0) without time zone;
+
+-- Chat Message Revisions keep every text a message had before it was edited
+
+CREATE TABLE chat_message_revisions
+(
+    id         uuid PRIMARY KEY,
+    message_id uuid REFERENCES chat_messages(id) NOT NULL,
+    message    text NOT NULL,
+    created_at timestamp(0) without time zone NOT NULL -- When the text was written, not when it was replaced
+);
+CREATE INDEX chat_message_revisions_message_id_index ON chat_message_revisions (message_id, created_at);
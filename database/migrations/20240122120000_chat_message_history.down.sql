@@ -0,0 +1,4 @@
+-- Chat message history
+
+DROP INDEX chat_messages_parent_id_index;
+DROP INDEX chat_messages_chat_id_created_at_index;
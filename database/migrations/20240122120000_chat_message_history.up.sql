@@ -0,0 +1,6 @@
+-- Chat message history is read a page at a time in the order messages were posted
+
+CREATE INDEX chat_messages_chat_id_created_at_index ON chat_messages (chat_id, created_at, id);
+
+-- Threads follow replies from a message down
+CREATE INDEX chat_messages_parent_id_index ON chat_messages (parent_id, created_at, id);
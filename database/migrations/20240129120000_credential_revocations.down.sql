@@ -0,0 +1,6 @@
+-- Credential revocations
+
+DROP TRIGGER api_keys_revoked_notify ON api_keys;
+DROP TRIGGER sessions_revoked_notify ON sessions;
+DROP TRIGGER revoked_tokens_notify ON revoked_tokens;
+DROP FUNCTION notify_credential_revoked;
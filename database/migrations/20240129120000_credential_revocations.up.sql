@@ -0,0 +1,23 @@
+-- Open event streams are closed once the credential they were opened with is revoked,
+-- listeners get the user of every revoked access token, session and API key
+
+CREATE FUNCTION notify_credential_revoked() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('credential_revocations', NEW.user_id::text);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER revoked_tokens_notify
+    AFTER INSERT ON revoked_tokens
+    FOR EACH ROW EXECUTE FUNCTION notify_credential_revoked();
+
+CREATE TRIGGER sessions_revoked_notify
+    AFTER UPDATE OF revoked_at ON sessions
+    FOR EACH ROW WHEN (OLD.revoked_at is null and NEW.revoked_at is not null)
+    EXECUTE FUNCTION notify_credential_revoked();
+
+CREATE TRIGGER api_keys_revoked_notify
+    AFTER UPDATE OF revoked_at ON api_keys
+    FOR EACH ROW WHEN (OLD.revoked_at is null and NEW.revoked_at is not null)
+    EXECUTE FUNCTION notify_credential_revoked();
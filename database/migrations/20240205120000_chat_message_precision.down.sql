@@ -0,0 +1,5 @@
+-- Chat message precision
+
+ALTER TABLE chat_message_revisions ALTER COLUMN created_at TYPE timestamp(0) without time zone;
+ALTER TABLE chat_messages ALTER COLUMN edited_at TYPE timestamp(0) without time zone;
+ALTER TABLE chat_messages ALTER COLUMN created_at TYPE timestamp(0) without time zone;
@@ -0,0 +1,7 @@
+-- Chat message precision
+
+-- Microseconds keep messages of the same second in the order they were posted,
+-- and revisions, which take the time of the message or of its last edit, in the order they were written
+ALTER TABLE chat_messages ALTER COLUMN created_at TYPE timestamp(6) without time zone;
+ALTER TABLE chat_messages ALTER COLUMN edited_at TYPE timestamp(6) without time zone;
+ALTER TABLE chat_message_revisions ALTER COLUMN created_at TYPE timestamp(6) without time zone;
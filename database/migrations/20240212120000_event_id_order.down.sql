@@ -0,0 +1,4 @@
+-- Event id order
+
+DROP TRIGGER events_order_id ON events;
+DROP FUNCTION order_event_id;
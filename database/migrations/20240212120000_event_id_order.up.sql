@@ -0,0 +1,15 @@
+-- Event ids are handed out in the order events commit: the id is taken under a lock held until
+-- the transaction ends, so a reader which sees an event sees every event before it too and
+-- resuming after a cursor skips nothing
+
+CREATE FUNCTION order_event_id() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_advisory_xact_lock('events'::regclass::oid::bigint);
+    NEW.id := nextval('events_id_seq');
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER events_order_id
+    BEFORE INSERT ON events
+    FOR EACH ROW EXECUTE FUNCTION order_event_id();
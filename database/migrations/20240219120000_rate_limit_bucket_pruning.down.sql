@@ -0,0 +1,4 @@
+-- Rate limit bucket pruning
+
+DROP INDEX rate_limit_buckets_full_at_index;
+ALTER TABLE rate_limit_buckets DROP COLUMN full_at;
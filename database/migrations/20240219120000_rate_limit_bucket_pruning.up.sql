@@ -0,0 +1,7 @@
+-- Rate limit bucket pruning
+
+-- A bucket which is full again is the same as a missing one, so it is deleted after that time
+ALTER TABLE rate_limit_buckets ADD COLUMN full_at timestamp(3) without time zone NOT NULL DEFAULT CURRENT_TIMESTAMP;
+ALTER TABLE rate_limit_buckets ALTER COLUMN full_at DROP DEFAULT;
+
+CREATE INDEX rate_limit_buckets_full_at_index ON rate_limit_buckets (full_at);
@@ -0,0 +1,240 @@
+use crate::chats::{ChatMemberRole, ChatType};
+use crate::companies::CompanyMemberRole;
+use crate::projects::ProjectMemberRole;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// How a user is related to a project
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProjectAccess {
+    pub is_owner: bool,
+    /// `None` for users who are not members
+    pub role: Option<ProjectMemberRole>,
+    /// A member of a company the project belongs to
+    pub is_company_member: bool,
+}
+
+/// Relations of a user to an existing project, fails with `RowNotFound` for an unknown or deleted project
+pub async fn get_project_access(
+    pool: &PgPool,
+    project_id: Uuid,
+    user_id: Uuid,
+) -> Result<ProjectAccess, sqlx::Error> {
+    sqlx::query_as!(
+        ProjectAccess,
+        r#"
+                SELECT
+                    projects.user_id = $2 as "is_owner!",
+                    (
+                        SELECT project_members.role FROM project_members
+                        WHERE project_members.project_id = projects.id and project_members.user_id = $2
+                    ) as "role?: _",
+                    EXISTS (
+                        SELECT 1 FROM company_projects
+                        JOIN company_members ON company_members.company_id = company_projects.company_id
+                        WHERE company_projects.project_id = projects.id and company_members.user_id = $2
+                    ) as "is_company_member!"
+                FROM projects
+                WHERE projects.id = $1 and projects.deleted_at is null
+            "#,
+        project_id,
+        user_id,
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// How a user is related to a company
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompanyAccess {
+    /// `None` for users who are not members
+    pub role: Option<CompanyMemberRole>,
+}
+
+/// Relations of a user to an existing company, fails with `RowNotFound` for an unknown company
+pub async fn get_company_access(
+    pool: &PgPool,
+    company_id: Uuid,
+    user_id: Uuid,
+) -> Result<CompanyAccess, sqlx::Error> {
+    sqlx::query_as!(
+        CompanyAccess,
+        r#"
+                SELECT
+                    (
+                        SELECT company_members.role FROM company_members
+                        WHERE company_members.company_id = companies.id and company_members.user_id = $2
+                    ) as "role?: _"
+                FROM companies
+                WHERE companies.id = $1
+            "#,
+        company_id,
+        user_id,
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// How a user is related to a chat
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatAccess {
+    pub chat_type: ChatType,
+    /// `None` for users who have never been members
+    pub role: Option<ChatMemberRole>,
+}
+
+/// Relations of a user to an existing chat, fails with `RowNotFound` for an unknown chat
+///
+/// Chat members are stored by a user alias, users without an alias are members of no chat.
+pub async fn get_chat_access(
+    pool: &PgPool,
+    chat_id: Uuid,
+    user_id: Uuid,
+) -> Result<ChatAccess, sqlx::Error> {
+    sqlx::query_as!(
+        ChatAccess,
+        r#"
+                SELECT
+                    chats.type as "chat_type!: _",
+                    (
+                        SELECT chat_member.role FROM chat_member
+                        JOIN users ON users.alias = chat_member.member
+                        WHERE chat_member.chat_id = chats.id and users.id = $2
+                    ) as "role?: _"
+                FROM chats
+                WHERE chats.id = $1
+            "#,
+        chat_id,
+        user_id,
+    )
+    .fetch_one(pool)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chats::insert_chat_member;
+    use crate::chats::tests::{create_chat, create_user};
+    use crate::companies::tests::create_company;
+    use crate::companies::{insert_company_member, CompanyMemberRole};
+    use crate::pg_pool;
+    use crate::projects::tests::create_project;
+    use crate::projects::{insert_company_project, insert_project_member, ProjectMemberRole};
+
+    #[tokio::test]
+    async fn test_project_access() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let project = create_project(&pool).await;
+        let member = create_user(&pool).await;
+        let company_member = create_user(&pool).await;
+        let stranger = create_user(&pool).await;
+
+        insert_project_member(&pool, project.id, member.id, ProjectMemberRole::Viewer)
+            .await
+            .expect("project member is created");
+        let company = create_company(&pool).await;
+        insert_company_project(&pool, company.id, project.id)
+            .await
+            .expect("company project is created");
+        insert_company_member(
+            &pool,
+            company_member.id,
+            company.id,
+            CompanyMemberRole::Member,
+        )
+        .await
+        .expect("company member is created");
+
+        let access = |user_id| get_project_access(&pool, project.id, user_id);
+        assert_eq!(
+            access(project.user_id).await.expect("access of an owner"),
+            ProjectAccess {
+                is_owner: true,
+                role: None,
+                is_company_member: false,
+            }
+        );
+        assert_eq!(
+            access(member.id).await.expect("access of a member"),
+            ProjectAccess {
+                is_owner: false,
+                role: Some(ProjectMemberRole::Viewer),
+                is_company_member: false,
+            }
+        );
+        assert_eq!(
+            access(company_member.id)
+                .await
+                .expect("access of a company member"),
+            ProjectAccess {
+                is_owner: false,
+                role: None,
+                is_company_member: true,
+            }
+        );
+        assert_eq!(
+            access(stranger.id).await.expect("access of a stranger"),
+            ProjectAccess {
+                is_owner: false,
+                role: None,
+                is_company_member: false,
+            }
+        );
+
+        assert!(matches!(
+            get_project_access(&pool, Uuid::new_v4(), stranger.id).await,
+            Err(sqlx::Error::RowNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_company_access() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let company = create_company(&pool).await;
+        let member = create_user(&pool).await;
+        let stranger = create_user(&pool).await;
+
+        insert_company_member(&pool, member.id, company.id, CompanyMemberRole::Admin)
+            .await
+            .expect("company member is created");
+
+        let access = get_company_access(&pool, company.id, member.id)
+            .await
+            .expect("access of a member");
+        assert_eq!(access.role, Some(CompanyMemberRole::Admin));
+
+        let access = get_company_access(&pool, company.id, stranger.id)
+            .await
+            .expect("access of a stranger");
+        assert_eq!(access.role, None);
+    }
+
+    #[tokio::test]
+    async fn test_chat_access() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let chat = create_chat(&pool).await;
+        let admin = create_user(&pool).await;
+        let stranger = create_user(&pool).await;
+
+        insert_chat_member(
+            &pool,
+            chat.id,
+            admin.alias.as_deref().expect("alias"),
+            ChatMemberRole::Admin,
+        )
+        .await
+        .expect("chat member is created");
+
+        let access = get_chat_access(&pool, chat.id, admin.id)
+            .await
+            .expect("access of an admin");
+        assert_eq!(access.chat_type, chat.r#type);
+        assert_eq!(access.role, Some(ChatMemberRole::Admin));
+
+        let access = get_chat_access(&pool, chat.id, stranger.id)
+            .await
+            .expect("access of a stranger");
+        assert_eq!(access.role, None);
+    }
+}
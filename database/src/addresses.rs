@@ -83,6 +83,40 @@ pub async fn get_addresses(pool: &PgPool, id: Uuid) -> Result<Address, sqlx::Err
         .map_err(Into::into)
 }
 
+pub async fn update_addresses<
+    T1: AsRef<str>,
+    T2: AsRef<str>,
+    T3: AsRef<str>,
+    T4: AsRef<str>,
+    T5: AsRef<str>,
+    T6: AsRef<str>,
+    T7: AsRef<str>,
+>(
+    pool: &PgPool,
+    id: Uuid,
+    address_input: &AddressInput<T1, T2, T3, T4, T5, T6, T7>,
+) -> Result<u64, sqlx::Error> {
+    sqlx::query!(
+            r#"
+                UPDATE addresses
+                SET zip_code = $2, country = $3, region = $4, city = $5, district = $6, street = $7, building = $8, apartment = $9, updated_at = CURRENT_TIMESTAMP
+                WHERE id = $1
+            "#,
+            id,
+            address_input.zip_code,
+            address_input.country.as_ref(),
+            address_input.region.as_ref(),
+            address_input.city.as_ref(),
+            address_input.district.as_ref().map(|x| x.as_ref()),
+            address_input.street.as_ref(),
+            address_input.building.as_ref(),
+            address_input.apartment.as_ref(),
+        )
+        .execute(pool)
+        .await
+        .map(|res| res.rows_affected())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -128,5 +162,26 @@ mod tests {
         assert_eq!(street, address.street);
         assert_eq!(building, address.building);
         assert_eq!(apartment, address.apartment);
+
+        let update = AddressInput {
+            zip_code: 10115,
+            country: "de",
+            region,
+            city: "Berlin",
+            district: None::<&str>,
+            street,
+            building,
+            apartment,
+        };
+        let updated = update_addresses(&pool, id, &update)
+            .await
+            .expect("address is updated");
+        assert_eq!(updated, 1);
+        let address = get_addresses(&pool, id)
+            .await
+            .expect("user for given id is expected");
+        assert_eq!(address.zip_code, update.zip_code);
+        assert_eq!(address.city, update.city);
+        assert_eq!(address.district, None);
     }
 }
@@ -0,0 +1,46 @@
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub async fn insert_admin(pool: &PgPool, user_id: Uuid) -> Result<u64, sqlx::Error> {
+    sqlx::query!(
+        r#"
+            INSERT INTO admins ( user_id, created_at )
+            SELECT $1, CURRENT_TIMESTAMP
+            ON CONFLICT (user_id) DO NOTHING
+        "#,
+        user_id,
+    )
+    .execute(pool)
+    .await
+    .map(|res| res.rows_affected())
+}
+
+pub async fn is_admin(pool: &PgPool, user_id: Uuid) -> Result<bool, sqlx::Error> {
+    sqlx::query!(
+        r#"
+                SELECT EXISTS (SELECT 1 FROM admins WHERE user_id = $1) as "is_admin!"
+            "#,
+        user_id,
+    )
+    .fetch_one(pool)
+    .await
+    .map(|x| x.is_admin)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chats::tests::create_user;
+    use crate::pg_pool;
+
+    #[tokio::test]
+    async fn test_is_admin() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let user = create_user(&pool).await;
+
+        assert_eq!(is_admin(&pool, user.id).await.ok(), Some(false));
+        assert_eq!(insert_admin(&pool, user.id).await.ok(), Some(1));
+        assert_eq!(is_admin(&pool, user.id).await.ok(), Some(true));
+        assert_eq!(insert_admin(&pool, user.id).await.ok(), Some(0));
+    }
+}
@@ -0,0 +1,216 @@
+use sqlx::types::time::PrimitiveDateTime;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// A long lived key scripts authenticate with instead of a password
+#[derive(sqlx::FromRow)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub prefix: String,
+    pub key_hash: String,
+    pub scopes: Vec<String>,
+    pub created_at: PrimitiveDateTime,
+    pub last_used_at: Option<PrimitiveDateTime>,
+    pub revoked_at: Option<PrimitiveDateTime>,
+}
+
+#[derive(Debug)]
+pub struct ApiKeyInput<T1: AsRef<str>, T2: AsRef<str>, T3: AsRef<str>> {
+    pub user_id: Uuid,
+    pub name: T1,
+    pub prefix: T2,
+    pub key_hash: T3,
+    pub scopes: Vec<String>,
+}
+
+pub async fn insert_api_key<T1: AsRef<str>, T2: AsRef<str>, T3: AsRef<str>>(
+    pool: &PgPool,
+    input: &ApiKeyInput<T1, T2, T3>,
+) -> Result<Uuid, sqlx::Error> {
+    sqlx::query!(
+        r#"
+                INSERT INTO api_keys ( id, user_id, name, prefix, key_hash, scopes, created_at )
+                SELECT $1, $2, $3, $4, $5, $6, CURRENT_TIMESTAMP
+                RETURNING id
+            "#,
+        Uuid::new_v4(),
+        input.user_id,
+        input.name.as_ref(),
+        input.prefix.as_ref(),
+        input.key_hash.as_ref(),
+        &input.scopes,
+    )
+    .fetch_one(pool)
+    .await
+    .map(|x| x.id)
+}
+
+/// Not revoked keys of a user, the most recently created first
+pub async fn get_active_api_keys(pool: &PgPool, user_id: Uuid) -> Result<Vec<ApiKey>, sqlx::Error> {
+    sqlx::query_as!(
+        ApiKey,
+        r#"
+                SELECT id, user_id, name, prefix, key_hash, scopes, created_at, last_used_at, revoked_at FROM api_keys
+                WHERE user_id = $1 and revoked_at is null
+                ORDER BY created_at DESC
+            "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Looks a not revoked key up and records it has been used
+pub async fn use_api_key(pool: &PgPool, key_hash: impl AsRef<str>) -> Result<ApiKey, sqlx::Error> {
+    sqlx::query_as!(
+        ApiKey,
+        r#"
+                UPDATE api_keys
+                SET last_used_at = CURRENT_TIMESTAMP
+                WHERE key_hash = $1 and revoked_at is null
+                RETURNING id, user_id, name, prefix, key_hash, scopes, created_at, last_used_at, revoked_at
+            "#,
+        key_hash.as_ref()
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Revokes a key of a given user, returns a number of revoked keys
+pub async fn revoke_api_key(pool: &PgPool, id: Uuid, user_id: Uuid) -> Result<u64, sqlx::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE api_keys
+            SET revoked_at = CURRENT_TIMESTAMP
+            WHERE id = $1 and user_id = $2 and revoked_at is null
+        "#,
+        id,
+        user_id,
+    )
+    .execute(pool)
+    .await
+    .map(|res| res.rows_affected())
+}
+
+/// Revokes every key of a user, returns a number of revoked keys
+pub async fn revoke_user_api_keys(pool: &PgPool, user_id: Uuid) -> Result<u64, sqlx::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE api_keys
+            SET revoked_at = CURRENT_TIMESTAMP
+            WHERE user_id = $1 and revoked_at is null
+        "#,
+        user_id,
+    )
+    .execute(pool)
+    .await
+    .map(|res| res.rows_affected())
+}
+
+/// Whether a key has been revoked, or is gone with its user
+pub async fn is_api_key_revoked(pool: &PgPool, id: Uuid) -> Result<bool, sqlx::Error> {
+    sqlx::query!(
+        r#"
+                SELECT NOT EXISTS(SELECT 1 FROM api_keys WHERE id = $1 and revoked_at is null)
+                    as "revoked!"
+            "#,
+        id
+    )
+    .fetch_one(pool)
+    .await
+    .map(|x| x.revoked)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::chats::tests::create_user;
+    use crate::pg_pool;
+    use crate::utils::random_samples::RandomSample;
+
+    pub fn create_api_key_input(user_id: Uuid) -> ApiKeyInput<String, String, String> {
+        ApiKeyInput {
+            user_id,
+            name: String::new_random(20),
+            prefix: String::new_random(12),
+            key_hash: String::new_random(88),
+            scopes: vec!["projects:read".to_owned(), "chats:write".to_owned()],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_use_api_key() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let user = create_user(&pool).await;
+
+        let input = create_api_key_input(user.id);
+        let id = insert_api_key(&pool, &input)
+            .await
+            .expect("api key is created");
+
+        let api_key = use_api_key(&pool, &input.key_hash)
+            .await
+            .expect("api key for a given hash");
+        assert_eq!(api_key.id, id);
+        assert_eq!(api_key.user_id, user.id);
+        assert_eq!(api_key.name, input.name);
+        assert_eq!(api_key.scopes, input.scopes);
+        assert!(api_key.last_used_at.is_some());
+
+        let api_keys = get_active_api_keys(&pool, user.id)
+            .await
+            .expect("api keys of a user");
+        assert_eq!(api_keys.len(), 1);
+        assert_eq!(api_keys[0].id, id);
+    }
+
+    #[tokio::test]
+    async fn test_revoke_api_key() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let user = create_user(&pool).await;
+        let other_user = create_user(&pool).await;
+
+        let input = create_api_key_input(user.id);
+        let id = insert_api_key(&pool, &input)
+            .await
+            .expect("api key is created");
+
+        let revoked = revoke_api_key(&pool, id, other_user.id)
+            .await
+            .expect("revoke query succeeds");
+        assert_eq!(revoked, 0);
+        assert_eq!(is_api_key_revoked(&pool, id).await.ok(), Some(false));
+
+        let revoked = revoke_api_key(&pool, id, user.id)
+            .await
+            .expect("revoke query succeeds");
+        assert_eq!(revoked, 1);
+
+        assert!(use_api_key(&pool, &input.key_hash).await.is_err());
+        assert_eq!(is_api_key_revoked(&pool, id).await.ok(), Some(true));
+
+        insert_api_key(&pool, &create_api_key_input(user.id))
+            .await
+            .expect("api key is created");
+        insert_api_key(&pool, &create_api_key_input(other_user.id))
+            .await
+            .expect("api key is created");
+        let revoked = revoke_user_api_keys(&pool, user.id)
+            .await
+            .expect("revoke query succeeds");
+        assert_eq!(revoked, 1);
+        assert_eq!(
+            get_active_api_keys(&pool, other_user.id)
+                .await
+                .expect("api keys of a user")
+                .len(),
+            1
+        );
+        assert!(get_active_api_keys(&pool, user.id)
+            .await
+            .expect("api keys of a user")
+            .is_empty());
+    }
+}
@@ -0,0 +1,192 @@
+use sqlx::types::time::PrimitiveDateTime;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, PartialEq, Eq, Copy, sqlx::Type)]
+#[sqlx(rename_all = "snake_case")]
+pub enum AuditEventType {
+    Registered,
+    LoginSucceeded,
+    LoginFailed,
+    TokenRefreshed,
+    PasswordReset,
+    PasswordChanged,
+    EmailChanged,
+    AccountDeleted,
+    ProjectCreated,
+    ProjectRead,
+    ProjectUpdated,
+    ProjectDeleted,
+}
+
+/// Something security relevant a user has done, events are never updated or deleted
+#[derive(Debug, sqlx::FromRow)]
+pub struct AuditEvent {
+    pub id: Uuid,
+    pub event_type: AuditEventType,
+    pub user_id: Uuid,
+    /// A project or another resource the event is about
+    pub resource_id: Option<Uuid>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: PrimitiveDateTime,
+}
+
+#[derive(Debug)]
+pub struct AuditEventInput<T1: AsRef<str>, T2: AsRef<str>> {
+    pub event_type: AuditEventType,
+    pub user_id: Uuid,
+    pub resource_id: Option<Uuid>,
+    pub ip_address: Option<T1>,
+    pub user_agent: Option<T2>,
+}
+
+pub async fn insert_audit_event<T1: AsRef<str>, T2: AsRef<str>>(
+    pool: &PgPool,
+    input: &AuditEventInput<T1, T2>,
+) -> Result<Uuid, sqlx::Error> {
+    sqlx::query!(
+        r#"
+                INSERT INTO audit_events ( id, event_type, user_id, resource_id, ip_address, user_agent, created_at )
+                SELECT $1, $2, $3, $4, $5, $6, CURRENT_TIMESTAMP
+                RETURNING id
+            "#,
+        Uuid::new_v4(),
+        input.event_type as AuditEventType,
+        input.user_id,
+        input.resource_id,
+        input.ip_address.as_ref().map(AsRef::as_ref),
+        input.user_agent.as_ref().map(AsRef::as_ref),
+    )
+    .fetch_one(pool)
+    .await
+    .map(|x| x.id)
+}
+
+/// Conditions of an audit query, unset ones match every event
+#[derive(Debug, Default)]
+pub struct AuditEventFilter {
+    pub user_id: Option<Uuid>,
+    pub event_type: Option<AuditEventType>,
+    /// Inclusive
+    pub from: Option<PrimitiveDateTime>,
+    /// Exclusive
+    pub to: Option<PrimitiveDateTime>,
+}
+
+/// Matching events, the most recent first
+pub async fn get_audit_events(
+    pool: &PgPool,
+    filter: &AuditEventFilter,
+    limit: i64,
+) -> Result<Vec<AuditEvent>, sqlx::Error> {
+    sqlx::query_as!(
+        AuditEvent,
+        r#"
+                SELECT id, event_type as "event_type: _", user_id, resource_id, ip_address, user_agent, created_at
+                FROM audit_events
+                WHERE ($1::uuid is null or user_id = $1)
+                    and ($2::AuditEventType is null or event_type = $2)
+                    and ($3::timestamp is null or created_at >= $3)
+                    and ($4::timestamp is null or created_at < $4)
+                ORDER BY created_at DESC
+                LIMIT $5
+            "#,
+        filter.user_id,
+        filter.event_type as Option<AuditEventType>,
+        filter.from,
+        filter.to,
+        limit,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pg_pool;
+
+    fn create_audit_event_input(
+        event_type: AuditEventType,
+        user_id: Uuid,
+    ) -> AuditEventInput<&'static str, &'static str> {
+        AuditEventInput {
+            event_type,
+            user_id,
+            resource_id: None,
+            ip_address: Some("127.0.0.1"),
+            user_agent: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_filter_audit_events() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let user_id = Uuid::new_v4();
+
+        let registered_id = insert_audit_event(
+            &pool,
+            &create_audit_event_input(AuditEventType::Registered, user_id),
+        )
+        .await
+        .expect("audit event is created");
+        let login_id = insert_audit_event(
+            &pool,
+            &create_audit_event_input(AuditEventType::LoginSucceeded, user_id),
+        )
+        .await
+        .expect("audit event is created");
+
+        let filter = AuditEventFilter {
+            user_id: Some(user_id),
+            ..Default::default()
+        };
+        let events = get_audit_events(&pool, &filter, 10)
+            .await
+            .expect("audit events of a user");
+        let mut ids = events.iter().map(|event| event.id).collect::<Vec<_>>();
+        ids.sort();
+        let mut expected_ids = vec![registered_id, login_id];
+        expected_ids.sort();
+        assert_eq!(ids, expected_ids);
+        assert_eq!(events[0].ip_address.as_deref(), Some("127.0.0.1"));
+
+        let filter = AuditEventFilter {
+            user_id: Some(user_id),
+            event_type: Some(AuditEventType::Registered),
+            ..Default::default()
+        };
+        let events = get_audit_events(&pool, &filter, 10)
+            .await
+            .expect("audit events of a type");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event_type, AuditEventType::Registered);
+
+        let filter = AuditEventFilter {
+            user_id: Some(user_id),
+            to: Some(events[0].created_at),
+            ..Default::default()
+        };
+        let events = get_audit_events(&pool, &filter, 10)
+            .await
+            .expect("audit events before a time");
+        assert!(events.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_audit_events_are_append_only() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let id = insert_audit_event(
+            &pool,
+            &create_audit_event_input(AuditEventType::LoginFailed, Uuid::new_v4()),
+        )
+        .await
+        .expect("audit event is created");
+
+        let result = sqlx::query!("DELETE FROM audit_events WHERE id = $1", id)
+            .execute(&pool)
+            .await;
+        assert!(result.is_err());
+    }
+}
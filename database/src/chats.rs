@@ -1,3 +1,4 @@
+use crate::events::{insert_chat_event, insert_chat_member_event, EventType};
 use sqlx::types::time::PrimitiveDateTime;
 use sqlx::PgPool;
 use uuid::Uuid;
@@ -45,6 +46,56 @@ pub async fn insert_chat(
     .map(|x| x.id)
 }
 
+/// Creates a chat with its creator and first members in one transaction, members are aliases
+pub async fn insert_chat_with_members(
+    pool: &PgPool,
+    r#type: ChatType,
+    title: impl AsRef<str>,
+    description: Option<&str>,
+    creator: impl AsRef<str>,
+    members: &[String],
+) -> Result<Uuid, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let id = sqlx::query!(
+        r#"
+                INSERT INTO chats ( id, type, title, description, created_at, updated_at )
+                SELECT $1, $2, $3, $4, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP
+                RETURNING id
+            "#,
+        Uuid::new_v4(),
+        r#type as ChatType,
+        title.as_ref(),
+        description,
+    )
+    .fetch_one(&mut transaction)
+    .await?
+    .id;
+
+    let creator = (creator.as_ref(), ChatMemberRole::Creator);
+    let members = members
+        .iter()
+        .map(|member| (member.as_str(), ChatMemberRole::Member));
+    for (member, role) in std::iter::once(creator).chain(members) {
+        sqlx::query!(
+            r#"
+                INSERT INTO chat_member ( id, chat_id, member, role, created_at, updated_at )
+                SELECT $1, $2, $3, $4, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP
+            "#,
+            Uuid::new_v4(),
+            id,
+            member,
+            role as ChatMemberRole,
+        )
+        .execute(&mut transaction)
+        .await?;
+        insert_chat_member_event(&mut transaction, id, EventType::MemberAdded, member).await?;
+    }
+
+    transaction.commit().await?;
+    Ok(id)
+}
+
 pub async fn get_chat(pool: &PgPool, id: Uuid) -> Result<Chat, sqlx::Error> {
     sqlx::query_as!(
             Chat,
@@ -59,6 +110,24 @@ pub async fn get_chat(pool: &PgPool, id: Uuid) -> Result<Chat, sqlx::Error> {
         .map_err(Into::into)
 }
 
+/// Chats a user is a member of, the newest first
+pub async fn get_user_chats(pool: &PgPool, user_id: Uuid) -> Result<Vec<Chat>, sqlx::Error> {
+    sqlx::query_as!(
+        Chat,
+        r#"
+                SELECT chats.id, chats.type as "type: _", title, description, chats.avatar, chats.created_at, chats.updated_at
+                FROM chats
+                JOIN chat_member ON chat_member.chat_id = chats.id
+                JOIN users ON users.alias = chat_member.member
+                WHERE users.id = $1 and chat_member.role not in ('left', 'banned')
+                ORDER BY chats.created_at DESC, chats.id
+            "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Copy, sqlx::Type)]
 #[sqlx(rename_all = "snake_case")]
 pub enum ChatMemberRole {
@@ -116,7 +185,115 @@ pub async fn get_chat_member(pool: &PgPool, id: Uuid) -> Result<ChatMember, sqlx
         .map_err(Into::into)
 }
 
-#[derive(sqlx::FromRow)]
+/// Adds a member or brings back one who has left, `None` for somebody who is in the chat or banned
+pub async fn add_chat_member(
+    pool: &PgPool,
+    chat_id: Uuid,
+    member: impl AsRef<str>,
+    role: ChatMemberRole,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let id = sqlx::query!(
+        r#"
+                INSERT INTO chat_member ( id, chat_id, member, role, created_at, updated_at )
+                SELECT $1, $2, $3, $4, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP
+                ON CONFLICT (chat_id, member) DO UPDATE
+                SET role = EXCLUDED.role, updated_at = CURRENT_TIMESTAMP
+                WHERE chat_member.role = 'left'
+                RETURNING id
+            "#,
+        Uuid::new_v4(),
+        chat_id,
+        member.as_ref(),
+        role as ChatMemberRole,
+    )
+    .fetch_optional(&mut transaction)
+    .await?
+    .map(|x| x.id);
+    if id.is_some() {
+        insert_chat_member_event(
+            &mut transaction,
+            chat_id,
+            EventType::MemberAdded,
+            member.as_ref(),
+        )
+        .await?;
+    }
+
+    transaction.commit().await?;
+    Ok(id)
+}
+
+/// A member of a chat with their public profile
+#[derive(Debug, sqlx::FromRow)]
+pub struct ChatMemberProfile {
+    pub user_id: Uuid,
+    pub alias: String,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub role: ChatMemberRole,
+    pub created_at: PrimitiveDateTime,
+}
+
+/// Current members of a chat, its creator and admins first
+pub async fn get_chat_members(
+    pool: &PgPool,
+    chat_id: Uuid,
+) -> Result<Vec<ChatMemberProfile>, sqlx::Error> {
+    sqlx::query_as!(
+        ChatMemberProfile,
+        r#"
+                SELECT users.id as user_id, chat_member.member as alias, users.first_name, users.last_name,
+                    chat_member.role as "role: _", chat_member.created_at
+                FROM chat_member
+                JOIN users ON users.alias = chat_member.member
+                WHERE chat_member.chat_id = $1 and chat_member.role not in ('left', 'banned')
+                ORDER BY chat_member.role, chat_member.created_at, users.id
+            "#,
+        chat_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Changes a role of a current member other than the creator, returns 0 if there is none
+///
+/// Members who leave or are removed get `ChatMemberRole::Left`.
+pub async fn update_chat_member_role(
+    pool: &PgPool,
+    chat_id: Uuid,
+    member: impl AsRef<str>,
+    role: ChatMemberRole,
+) -> Result<u64, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let updated = sqlx::query!(
+        r#"
+            UPDATE chat_member
+            SET role = $3, updated_at = CURRENT_TIMESTAMP
+            WHERE chat_id = $1 and member = $2 and role not in ('creator', 'left', 'banned')
+        "#,
+        chat_id,
+        member.as_ref(),
+        role as ChatMemberRole,
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+    if updated > 0 {
+        let event_type = match role {
+            ChatMemberRole::Left | ChatMemberRole::Banned => EventType::MemberRemoved,
+            _ => EventType::MemberUpdated,
+        };
+        insert_chat_member_event(&mut transaction, chat_id, event_type, member.as_ref()).await?;
+    }
+
+    transaction.commit().await?;
+    Ok(updated)
+}
+
+#[derive(Clone, sqlx::FromRow)]
 pub struct ChatMessage {
     pub id: Uuid,
     pub chat_id: Uuid,
@@ -125,6 +302,8 @@ pub struct ChatMessage {
     pub parent_id: Option<Uuid>,
     pub created_at: PrimitiveDateTime,
     pub updated_at: PrimitiveDateTime,
+    /// When the sender last changed the text
+    pub edited_at: Option<PrimitiveDateTime>,
     pub deleted_at: Option<PrimitiveDateTime>,
 }
 
@@ -135,7 +314,9 @@ pub async fn insert_chat_message(
     message: impl AsRef<str>,
     parent_id: Option<Uuid>,
 ) -> Result<Uuid, sqlx::Error> {
-    sqlx::query!(
+    let mut transaction = pool.begin().await?;
+
+    let id = sqlx::query!(
             r#"
                 INSERT INTO chat_messages ( id, chat_id, sender_id, message, parent_id, created_at, updated_at )
                 SELECT $1, $2, $3, $4, $5, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP
@@ -147,16 +328,27 @@ pub async fn insert_chat_message(
             message.as_ref(),
             parent_id,
         )
-        .fetch_one(pool)
-        .await
-        .map(|x| x.id)
+        .fetch_one(&mut transaction)
+        .await?
+        .id;
+    insert_chat_event(
+        &mut transaction,
+        chat_id,
+        EventType::MessageCreated,
+        sender_id,
+        Some(id),
+    )
+    .await?;
+
+    transaction.commit().await?;
+    Ok(id)
 }
 
 pub async fn get_chat_message(pool: &PgPool, id: Uuid) -> Result<ChatMessage, sqlx::Error> {
     sqlx::query_as!(
             ChatMessage,
             r#"
-                SELECT id, chat_id, sender_id, message, parent_id, created_at, updated_at, deleted_at FROM chat_messages
+                SELECT id, chat_id, sender_id, message, parent_id, created_at, updated_at, edited_at, deleted_at FROM chat_messages
                 WHERE id = $1
             "#,
             id
@@ -166,6 +358,250 @@ pub async fn get_chat_message(pool: &PgPool, id: Uuid) -> Result<ChatMessage, sq
         .map_err(Into::into)
 }
 
+/// A page of messages of a chat, the newest first, optionally before a given message
+///
+/// Deleted messages are kept in their places, so that they show as tombstones.
+pub async fn get_chat_messages(
+    pool: &PgPool,
+    chat_id: Uuid,
+    before: Option<Uuid>,
+    limit: i64,
+) -> Result<Vec<ChatMessage>, sqlx::Error> {
+    sqlx::query_as!(
+        ChatMessage,
+        r#"
+                SELECT id, chat_id, sender_id, message, parent_id, created_at, updated_at, edited_at, deleted_at FROM chat_messages
+                WHERE chat_id = $1
+                    and ($2::uuid is null or (created_at, id) < (
+                        SELECT created_at, id FROM chat_messages WHERE id = $2 and chat_id = $1
+                    ))
+                ORDER BY created_at DESC, id DESC
+                LIMIT $3
+            "#,
+        chat_id,
+        before,
+        limit,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// A page of messages of a chat after a given message, the oldest first
+pub async fn get_chat_messages_after(
+    pool: &PgPool,
+    chat_id: Uuid,
+    after: Uuid,
+    limit: i64,
+) -> Result<Vec<ChatMessage>, sqlx::Error> {
+    sqlx::query_as!(
+        ChatMessage,
+        r#"
+                SELECT id, chat_id, sender_id, message, parent_id, created_at, updated_at, edited_at, deleted_at FROM chat_messages
+                WHERE chat_id = $1
+                    and (created_at, id) > (
+                        SELECT created_at, id FROM chat_messages WHERE id = $2 and chat_id = $1
+                    )
+                ORDER BY created_at, id
+                LIMIT $3
+            "#,
+        chat_id,
+        after,
+        limit,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// A message with up to `limit` messages before and after it, the oldest first
+///
+/// Fails with `RowNotFound` for a message of another chat.
+pub async fn get_chat_message_context(
+    pool: &PgPool,
+    chat_id: Uuid,
+    id: Uuid,
+    limit: i64,
+) -> Result<Vec<ChatMessage>, sqlx::Error> {
+    let message = get_chat_message(pool, id).await?;
+    if message.chat_id != chat_id {
+        return Err(sqlx::Error::RowNotFound);
+    }
+
+    let mut messages = get_chat_messages(pool, chat_id, Some(id), limit).await?;
+    messages.reverse();
+    messages.push(message);
+    messages.extend(get_chat_messages_after(pool, chat_id, id, limit).await?);
+    Ok(messages)
+}
+
+/// Replies to a message and the replies to them, the oldest first, optionally after a given one
+pub async fn get_chat_thread(
+    pool: &PgPool,
+    chat_id: Uuid,
+    parent_id: Uuid,
+    after: Option<Uuid>,
+    limit: i64,
+) -> Result<Vec<ChatMessage>, sqlx::Error> {
+    sqlx::query_as!(
+        ChatMessage,
+        r#"
+                WITH RECURSIVE thread AS (
+                    SELECT id, chat_id, sender_id, message, parent_id, created_at, updated_at, edited_at, deleted_at FROM chat_messages
+                    WHERE parent_id = $2 and chat_id = $1
+                    UNION ALL
+                    SELECT replies.id, replies.chat_id, replies.sender_id, replies.message, replies.parent_id,
+                        replies.created_at, replies.updated_at, replies.edited_at, replies.deleted_at
+                    FROM chat_messages replies
+                    JOIN thread ON replies.parent_id = thread.id
+                )
+                SELECT id as "id!", chat_id as "chat_id!", sender_id as "sender_id!", message as "message!", parent_id,
+                    created_at as "created_at!", updated_at as "updated_at!", edited_at, deleted_at
+                FROM thread
+                WHERE $3::uuid is null or (created_at, id) > (
+                    SELECT created_at, id FROM chat_messages WHERE id = $3 and chat_id = $1
+                )
+                ORDER BY created_at, id
+                LIMIT $4
+            "#,
+        chat_id,
+        parent_id,
+        after,
+        limit,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Replaces the text of a message, the previous one is kept as a revision
+///
+/// `None` for a message of another chat or sender and for a deleted one.
+pub async fn update_chat_message(
+    pool: &PgPool,
+    chat_id: Uuid,
+    id: Uuid,
+    sender_id: Uuid,
+    message: impl AsRef<str>,
+) -> Result<Option<ChatMessage>, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let previous = sqlx::query!(
+        r#"
+            SELECT message, coalesce(edited_at, created_at) as "written_at!" FROM chat_messages
+            WHERE id = $1 and chat_id = $2 and sender_id = $3 and deleted_at is null
+            FOR UPDATE
+        "#,
+        id,
+        chat_id,
+        sender_id,
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+    let Some(previous) = previous else {
+        return Ok(None);
+    };
+
+    sqlx::query!(
+        r#"
+            INSERT INTO chat_message_revisions ( id, message_id, message, created_at )
+            SELECT $1, $2, $3, $4
+        "#,
+        Uuid::new_v4(),
+        id,
+        previous.message,
+        previous.written_at,
+    )
+    .execute(&mut transaction)
+    .await?;
+    let message = sqlx::query_as!(
+        ChatMessage,
+        r#"
+            UPDATE chat_messages
+            SET message = $2, edited_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP
+            WHERE id = $1
+            RETURNING id, chat_id, sender_id, message, parent_id, created_at, updated_at, edited_at, deleted_at
+        "#,
+        id,
+        message.as_ref(),
+    )
+    .fetch_one(&mut transaction)
+    .await?;
+    insert_chat_event(
+        &mut transaction,
+        chat_id,
+        EventType::MessageUpdated,
+        sender_id,
+        Some(id),
+    )
+    .await?;
+
+    transaction.commit().await?;
+    Ok(Some(message))
+}
+
+/// Soft deletes a message on behalf of a user, returns 0 for an unknown or already deleted one
+///
+/// The text and its revisions are kept, responses show the message as a tombstone.
+pub async fn delete_chat_message(
+    pool: &PgPool,
+    chat_id: Uuid,
+    id: Uuid,
+    user_id: Uuid,
+) -> Result<u64, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let deleted = sqlx::query!(
+        r#"
+            UPDATE chat_messages
+            SET deleted_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP
+            WHERE id = $1 and chat_id = $2 and deleted_at is null
+        "#,
+        id,
+        chat_id,
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+    if deleted > 0 {
+        insert_chat_event(
+            &mut transaction,
+            chat_id,
+            EventType::MessageDeleted,
+            user_id,
+            Some(id),
+        )
+        .await?;
+    }
+
+    transaction.commit().await?;
+    Ok(deleted)
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct ChatMessageRevision {
+    pub id: Uuid,
+    pub message_id: Uuid,
+    pub message: String,
+    /// When the text was written
+    pub created_at: PrimitiveDateTime,
+}
+
+/// Earlier texts of a message, the oldest first
+pub async fn get_chat_message_revisions(
+    pool: &PgPool,
+    message_id: Uuid,
+) -> Result<Vec<ChatMessageRevision>, sqlx::Error> {
+    sqlx::query_as!(
+        ChatMessageRevision,
+        r#"
+                SELECT id, message_id, message, created_at FROM chat_message_revisions
+                WHERE message_id = $1
+                ORDER BY created_at, id
+            "#,
+        message_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
@@ -196,7 +632,7 @@ pub mod tests {
         assert_eq!(avatar, chat.avatar.expect("last name"));
     }
 
-    async fn create_chat(pool: &PgPool) -> Chat {
+    pub async fn create_chat(pool: &PgPool) -> Chat {
         let r#type = ChatType::Channel;
         let title = "chat title";
         let description = "chat description";
@@ -293,4 +729,395 @@ pub mod tests {
         assert_eq!(message.parent_id, Some(chat_parent_message_id));
         assert_eq!(message.deleted_at, None);
     }
+
+    #[tokio::test]
+    async fn test_manage_chat_members() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let creator = create_user(&pool).await;
+        let member = create_user(&pool).await;
+        let newcomer = create_user(&pool).await;
+        let alias = |user: &User| user.alias.clone().expect("alias is expected");
+
+        let id = insert_chat_with_members(
+            &pool,
+            ChatType::Group,
+            "group",
+            None,
+            alias(&creator),
+            &[alias(&member)],
+        )
+        .await
+        .expect("chat is created");
+
+        let chats = get_user_chats(&pool, member.id).await.expect("user chats");
+        assert_eq!(chats.iter().map(|x| x.id).collect::<Vec<_>>(), vec![id]);
+        assert!(get_user_chats(&pool, newcomer.id)
+            .await
+            .expect("user chats")
+            .is_empty());
+
+        let added = add_chat_member(&pool, id, alias(&newcomer), ChatMemberRole::Admin)
+            .await
+            .expect("member is added");
+        assert!(added.is_some());
+        let added = add_chat_member(&pool, id, alias(&newcomer), ChatMemberRole::Member)
+            .await
+            .expect("query succeeded");
+        assert_eq!(added, None);
+
+        let members = |pool: PgPool| async move {
+            get_chat_members(&pool, id)
+                .await
+                .expect("chat members")
+                .into_iter()
+                .map(|x| (x.user_id, x.role))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            members(pool.clone()).await,
+            vec![
+                (creator.id, ChatMemberRole::Creator),
+                (newcomer.id, ChatMemberRole::Admin),
+                (member.id, ChatMemberRole::Member),
+            ]
+        );
+
+        let updated = update_chat_member_role(&pool, id, alias(&creator), ChatMemberRole::Left)
+            .await
+            .expect("query succeeded");
+        assert_eq!(updated, 0);
+        let updated = update_chat_member_role(&pool, id, alias(&member), ChatMemberRole::Left)
+            .await
+            .expect("member has left");
+        assert_eq!(updated, 1);
+        assert!(get_user_chats(&pool, member.id)
+            .await
+            .expect("user chats")
+            .is_empty());
+        let added = add_chat_member(&pool, id, alias(&member), ChatMemberRole::Member)
+            .await
+            .expect("member is back");
+        assert!(added.is_some());
+
+        // Memberships follow a renamed user
+        let renamed = crate::users::UserProfileUpdate {
+            alias: Some(format!("renamed:{}", id)),
+            first_name: None,
+            last_name: None,
+            phone_number: None,
+            language_code: "en".to_owned(),
+            country_code: None,
+        };
+        crate::users::update_user_profile(&pool, &member.id, &renamed)
+            .await
+            .expect("profile is updated");
+        assert_eq!(
+            members(pool.clone()).await,
+            vec![
+                (creator.id, ChatMemberRole::Creator),
+                (newcomer.id, ChatMemberRole::Admin),
+                (member.id, ChatMemberRole::Member),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cleared_alias_does_not_pass_memberships_on() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let creator = create_user(&pool).await;
+        let member = create_user(&pool).await;
+        let claimer = create_user(&pool).await;
+        let alias = member.alias.clone().expect("alias is expected");
+
+        let id = insert_chat_with_members(
+            &pool,
+            ChatType::Group,
+            "group",
+            None,
+            creator.alias.clone().expect("alias is expected"),
+            std::slice::from_ref(&alias),
+        )
+        .await
+        .expect("chat is created");
+
+        let profile = |alias: Option<String>| crate::users::UserProfileUpdate {
+            alias,
+            first_name: None,
+            last_name: None,
+            phone_number: None,
+            language_code: "en".to_owned(),
+            country_code: None,
+        };
+        crate::users::update_user_profile(&pool, &member.id, &profile(None))
+            .await
+            .expect("alias is cleared");
+        assert!(get_user_chats(&pool, member.id)
+            .await
+            .expect("user chats")
+            .is_empty());
+
+        crate::users::update_user_profile(&pool, &claimer.id, &profile(Some(alias.clone())))
+            .await
+            .expect("alias is claimed");
+        assert!(get_user_chats(&pool, claimer.id)
+            .await
+            .expect("user chats")
+            .is_empty());
+        let members = get_chat_members(&pool, id).await.expect("chat members");
+        assert_eq!(members.len(), 1);
+        assert_eq!(members[0].user_id, creator.id);
+
+        // The old member can be invited again under a new alias
+        crate::users::update_user_profile(&pool, &member.id, &profile(Some(format!("back:{id}"))))
+            .await
+            .expect("alias is set");
+        let added = add_chat_member(&pool, id, format!("back:{id}"), ChatMemberRole::Member)
+            .await
+            .expect("member is added");
+        assert!(added.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_get_chat_messages_a_page_at_a_time() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let chat = create_chat(&pool).await;
+        let user = create_user(&pool).await;
+
+        let mut ids = Vec::new();
+        for message in ["first", "second", "third"] {
+            let id = insert_chat_message(&pool, chat.id, user.id, message, None)
+                .await
+                .expect("message is created");
+            ids.push(id);
+        }
+        let all = get_chat_messages(&pool, chat.id, None, 10)
+            .await
+            .expect("messages");
+        assert_eq!(all.len(), 3);
+
+        let first_page = get_chat_messages(&pool, chat.id, None, 2)
+            .await
+            .expect("messages");
+        let second_page = get_chat_messages(&pool, chat.id, Some(first_page[1].id), 2)
+            .await
+            .expect("messages");
+        let mut paged = first_page
+            .iter()
+            .chain(&second_page)
+            .map(|x| x.id)
+            .collect::<Vec<_>>();
+        assert_eq!(paged, all.iter().map(|x| x.id).collect::<Vec<_>>());
+        paged.sort();
+        ids.sort();
+        assert_eq!(paged, ids);
+    }
+
+    #[tokio::test]
+    async fn test_keep_back_to_back_messages_in_order() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let chat = create_chat(&pool).await;
+        let user = create_user(&pool).await;
+
+        let root = insert_chat_message(&pool, chat.id, user.id, "root", None)
+            .await
+            .expect("message is created");
+        let mut posted = vec![root];
+        for message in ["first", "second", "third", "fourth", "fifth"] {
+            let id = insert_chat_message(&pool, chat.id, user.id, message, Some(root))
+                .await
+                .expect("message is created");
+            posted.push(id);
+        }
+
+        let mut newest_first = posted.clone();
+        newest_first.reverse();
+        let all = get_chat_messages(&pool, chat.id, None, 10)
+            .await
+            .expect("messages");
+        assert_eq!(ids(&all), newest_first);
+
+        let after = get_chat_messages_after(&pool, chat.id, root, 3)
+            .await
+            .expect("messages");
+        assert_eq!(ids(&after), posted[1..4]);
+
+        let thread = get_chat_thread(&pool, chat.id, root, None, 10)
+            .await
+            .expect("thread");
+        assert_eq!(ids(&thread), posted[1..]);
+    }
+
+    /// Posts a message some seconds ago, so that the order of seeded messages does not depend on timing
+    async fn seed_chat_message(
+        pool: &PgPool,
+        chat_id: Uuid,
+        sender_id: Uuid,
+        parent_id: Option<Uuid>,
+        seconds_ago: i32,
+    ) -> Uuid {
+        sqlx::query!(
+            r#"
+                INSERT INTO chat_messages ( id, chat_id, sender_id, message, parent_id, created_at, updated_at )
+                SELECT $1, $2, $3, $4, $5, CURRENT_TIMESTAMP - $6 * interval '1 second', CURRENT_TIMESTAMP - $6 * interval '1 second'
+                RETURNING id
+            "#,
+            Uuid::new_v4(),
+            chat_id,
+            sender_id,
+            std::format!("{seconds_ago} seconds ago"),
+            parent_id,
+            seconds_ago as f64,
+        )
+        .fetch_one(pool)
+        .await
+        .expect("message is seeded")
+        .id
+    }
+
+    fn ids(messages: &[ChatMessage]) -> Vec<Uuid> {
+        messages.iter().map(|x| x.id).collect()
+    }
+
+    #[tokio::test]
+    async fn test_page_through_seeded_history_both_ways() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let chat = create_chat(&pool).await;
+        let user = create_user(&pool).await;
+
+        let mut seeded = Vec::new();
+        for seconds_ago in (1..=10).rev() {
+            seeded.push(seed_chat_message(&pool, chat.id, user.id, None, seconds_ago).await);
+        }
+
+        let newest = get_chat_messages(&pool, chat.id, None, 4)
+            .await
+            .expect("messages");
+        assert_eq!(
+            ids(&newest),
+            vec![seeded[9], seeded[8], seeded[7], seeded[6]]
+        );
+        let older = get_chat_messages(&pool, chat.id, Some(seeded[6]), 3)
+            .await
+            .expect("messages");
+        assert_eq!(ids(&older), vec![seeded[5], seeded[4], seeded[3]]);
+
+        let newer = get_chat_messages_after(&pool, chat.id, seeded[2], 3)
+            .await
+            .expect("messages");
+        assert_eq!(ids(&newer), seeded[3..6].to_vec());
+        let none = get_chat_messages_after(&pool, chat.id, seeded[9], 3)
+            .await
+            .expect("messages");
+        assert!(none.is_empty());
+
+        let context = get_chat_message_context(&pool, chat.id, seeded[5], 2)
+            .await
+            .expect("context");
+        assert_eq!(ids(&context), seeded[3..8].to_vec());
+        let context = get_chat_message_context(&pool, chat.id, seeded[0], 2)
+            .await
+            .expect("context");
+        assert_eq!(ids(&context), seeded[0..3].to_vec());
+
+        let other_chat = create_chat(&pool).await;
+        let other_chat_messages = get_chat_messages_after(&pool, other_chat.id, seeded[0], 3)
+            .await
+            .expect("messages");
+        assert!(other_chat_messages.is_empty());
+        let other_chat_context = get_chat_message_context(&pool, other_chat.id, seeded[0], 2).await;
+        assert!(matches!(other_chat_context, Err(sqlx::Error::RowNotFound)));
+    }
+
+    #[tokio::test]
+    async fn test_follow_a_seeded_thread() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let chat = create_chat(&pool).await;
+        let user = create_user(&pool).await;
+
+        let root = seed_chat_message(&pool, chat.id, user.id, None, 10).await;
+        let reply = seed_chat_message(&pool, chat.id, user.id, Some(root), 9).await;
+        seed_chat_message(&pool, chat.id, user.id, None, 8).await;
+        let nested_reply = seed_chat_message(&pool, chat.id, user.id, Some(reply), 7).await;
+        let late_reply = seed_chat_message(&pool, chat.id, user.id, Some(root), 6).await;
+
+        let thread = get_chat_thread(&pool, chat.id, root, None, 10)
+            .await
+            .expect("thread");
+        assert_eq!(ids(&thread), vec![reply, nested_reply, late_reply]);
+        let page = get_chat_thread(&pool, chat.id, root, Some(reply), 1)
+            .await
+            .expect("thread");
+        assert_eq!(ids(&page), vec![nested_reply]);
+
+        let subthread = get_chat_thread(&pool, chat.id, reply, None, 10)
+            .await
+            .expect("thread");
+        assert_eq!(ids(&subthread), vec![nested_reply]);
+        let empty = get_chat_thread(&pool, chat.id, late_reply, None, 10)
+            .await
+            .expect("thread");
+        assert!(empty.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_edit_and_delete_chat_message() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let chat = create_chat(&pool).await;
+        let sender = create_user(&pool).await;
+        let moderator = create_user(&pool).await;
+
+        let id = insert_chat_message(&pool, chat.id, sender.id, "first", None)
+            .await
+            .expect("message is created");
+        let original = get_chat_message(&pool, id).await.expect("message");
+        assert_eq!(original.edited_at, None);
+
+        let not_sender = update_chat_message(&pool, chat.id, id, moderator.id, "taken over")
+            .await
+            .expect("query succeeded");
+        assert!(not_sender.is_none());
+
+        for text in ["second", "third"] {
+            let edited = update_chat_message(&pool, chat.id, id, sender.id, text)
+                .await
+                .expect("query succeeded")
+                .expect("message is edited");
+            assert_eq!(edited.message, text);
+            assert!(edited.edited_at.is_some());
+        }
+        let revisions = get_chat_message_revisions(&pool, id)
+            .await
+            .expect("revisions");
+        let mut texts = revisions
+            .iter()
+            .map(|x| x.message.as_str())
+            .collect::<Vec<_>>();
+        texts.sort();
+        assert_eq!(texts, vec!["first", "second"]);
+        assert_eq!(revisions[0].created_at, original.created_at);
+
+        assert_eq!(
+            delete_chat_message(&pool, chat.id, id, moderator.id)
+                .await
+                .expect("message is deleted"),
+            1
+        );
+        assert_eq!(
+            delete_chat_message(&pool, chat.id, id, moderator.id)
+                .await
+                .expect("query succeeded"),
+            0
+        );
+        let edit_deleted = update_chat_message(&pool, chat.id, id, sender.id, "fourth")
+            .await
+            .expect("query succeeded");
+        assert!(edit_deleted.is_none());
+
+        let messages = get_chat_messages(&pool, chat.id, None, 10)
+            .await
+            .expect("messages");
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].message, "third");
+        assert!(messages[0].deleted_at.is_some());
+    }
 }
@@ -1,8 +1,9 @@
+use crate::addresses::AddressInput;
 use sqlx::types::time::PrimitiveDateTime;
 use sqlx::PgPool;
 use uuid::Uuid;
 
-#[derive(sqlx::FromRow)]
+#[derive(Debug, sqlx::FromRow)]
 pub struct Company {
     pub id: Uuid,
     pub name: String,
@@ -45,11 +46,125 @@ pub async fn get_company(pool: &PgPool, id: Uuid) -> Result<Company, sqlx::Error
     .map_err(Into::into)
 }
 
+/// Creates a company at a new address with its first owner at once
+pub async fn insert_company_with_owner<
+    T: AsRef<str>,
+    T1: AsRef<str>,
+    T2: AsRef<str>,
+    T3: AsRef<str>,
+    T4: AsRef<str>,
+    T5: AsRef<str>,
+    T6: AsRef<str>,
+    T7: AsRef<str>,
+>(
+    pool: &PgPool,
+    name: T,
+    address: &AddressInput<T1, T2, T3, T4, T5, T6, T7>,
+    owner_id: Uuid,
+) -> Result<Uuid, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let address_id = sqlx::query!(
+            r#"
+                INSERT INTO addresses ( id, zip_code, country, region, city, district, street, building, apartment, created_at, updated_at )
+                SELECT $1, $2, $3, $4, $5, $6, $7, $8, $9, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP
+                RETURNING id
+            "#,
+            Uuid::new_v4(),
+            address.zip_code,
+            address.country.as_ref(),
+            address.region.as_ref(),
+            address.city.as_ref(),
+            address.district.as_ref().map(|x| x.as_ref()),
+            address.street.as_ref(),
+            address.building.as_ref(),
+            address.apartment.as_ref(),
+        )
+        .fetch_one(&mut transaction)
+        .await?
+        .id;
+
+    let company_id = sqlx::query!(
+        r#"
+                INSERT INTO companies ( id, name, address_id, created_at, updated_at )
+                SELECT $1, $2, $3, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP
+                RETURNING id
+            "#,
+        Uuid::new_v4(),
+        name.as_ref(),
+        address_id,
+    )
+    .fetch_one(&mut transaction)
+    .await?
+    .id;
+
+    sqlx::query!(
+        r#"
+                INSERT INTO company_members ( id, user_id, company_id, role, created_at, updated_at )
+                SELECT $1, $2, $3, 'owner', CURRENT_TIMESTAMP, CURRENT_TIMESTAMP
+            "#,
+        Uuid::new_v4(),
+        owner_id,
+        company_id,
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    transaction.commit().await?;
+    Ok(company_id)
+}
+
+/// Companies a user is a member of, by name
+pub async fn get_user_companies(pool: &PgPool, user_id: Uuid) -> Result<Vec<Company>, sqlx::Error> {
+    sqlx::query_as!(
+        Company,
+        r#"
+                SELECT companies.id, companies.name, companies.address_id, companies.created_at, companies.updated_at
+                FROM companies
+                JOIN company_members ON company_members.company_id = companies.id
+                WHERE company_members.user_id = $1
+                ORDER BY companies.name, companies.id
+            "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn update_company_name(
+    pool: &PgPool,
+    id: Uuid,
+    name: impl AsRef<str>,
+) -> Result<u64, sqlx::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE companies
+            SET name = $2, updated_at = CURRENT_TIMESTAMP
+            WHERE id = $1
+        "#,
+        id,
+        name.as_ref(),
+    )
+    .execute(pool)
+    .await
+    .map(|res| res.rows_affected())
+}
+
+/// What a member may do, the order goes from the most to the least privileged one
+#[derive(Debug, Clone, PartialEq, Eq, Copy, sqlx::Type)]
+#[sqlx(rename_all = "snake_case")]
+pub enum CompanyMemberRole {
+    Owner,
+    Admin,
+    Member,
+}
+
 #[derive(sqlx::FromRow)]
 pub struct CompanyMember {
     pub id: Uuid,
     pub user_id: Uuid,
     pub company_id: Uuid,
+    pub role: CompanyMemberRole,
     pub created_at: PrimitiveDateTime,
     pub updated_at: PrimitiveDateTime,
 }
@@ -58,16 +173,18 @@ pub async fn insert_company_member(
     pool: &PgPool,
     user_id: Uuid,
     company_id: Uuid,
+    role: CompanyMemberRole,
 ) -> Result<Uuid, sqlx::Error> {
     sqlx::query!(
         r#"
-                INSERT INTO company_members ( id, user_id, company_id, created_at, updated_at )
-                SELECT $1, $2, $3, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP
+                INSERT INTO company_members ( id, user_id, company_id, role, created_at, updated_at )
+                SELECT $1, $2, $3, $4, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP
                 RETURNING id
             "#,
         Uuid::new_v4(),
         user_id,
         company_id,
+        role as CompanyMemberRole,
     )
     .fetch_one(pool)
     .await
@@ -78,7 +195,7 @@ pub async fn get_company_member(pool: &PgPool, id: Uuid) -> Result<CompanyMember
     sqlx::query_as!(
         CompanyMember,
         r#"
-                SELECT id, user_id, company_id, created_at, updated_at FROM company_members
+                SELECT id, user_id, company_id, role as "role: _", created_at, updated_at FROM company_members
                 WHERE id = $1
             "#,
         id
@@ -88,10 +205,98 @@ pub async fn get_company_member(pool: &PgPool, id: Uuid) -> Result<CompanyMember
     .map_err(Into::into)
 }
 
+/// A member of a company with the user's name
+#[derive(Debug, sqlx::FromRow)]
+pub struct CompanyMemberProfile {
+    pub user_id: Uuid,
+    pub email: String,
+    pub alias: Option<String>,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub role: CompanyMemberRole,
+    pub created_at: PrimitiveDateTime,
+}
+
+/// Members of a company by role, the longest standing first
+pub async fn get_company_members(
+    pool: &PgPool,
+    company_id: Uuid,
+) -> Result<Vec<CompanyMemberProfile>, sqlx::Error> {
+    sqlx::query_as!(
+        CompanyMemberProfile,
+        r#"
+                SELECT
+                    users.id as user_id,
+                    users.email,
+                    users.alias,
+                    users.first_name,
+                    users.last_name,
+                    company_members.role as "role: _",
+                    company_members.created_at
+                FROM company_members
+                JOIN users ON users.id = company_members.user_id
+                WHERE company_members.company_id = $1
+                ORDER BY company_members.role, company_members.created_at, users.id
+            "#,
+        company_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Changes a role of a member, returns 0 for somebody who is not one or the last owner
+pub async fn update_company_member_role(
+    pool: &PgPool,
+    company_id: Uuid,
+    user_id: Uuid,
+    role: CompanyMemberRole,
+) -> Result<u64, sqlx::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE company_members
+            SET role = $3::CompanyMemberRole, updated_at = CURRENT_TIMESTAMP
+            WHERE company_id = $1 and user_id = $2
+                and (role <> 'owner' or $3::CompanyMemberRole = 'owner' or EXISTS (
+                    SELECT 1 FROM company_members owners
+                    WHERE owners.company_id = $1 and owners.user_id <> $2 and owners.role = 'owner'
+                ))
+        "#,
+        company_id,
+        user_id,
+        role as CompanyMemberRole,
+    )
+    .execute(pool)
+    .await
+    .map(|res| res.rows_affected())
+}
+
+/// Removes a member, returns 0 for somebody who is not one or the last owner
+pub async fn delete_company_member(
+    pool: &PgPool,
+    company_id: Uuid,
+    user_id: Uuid,
+) -> Result<u64, sqlx::Error> {
+    sqlx::query!(
+        r#"
+            DELETE FROM company_members
+            WHERE company_id = $1 and user_id = $2
+                and (role <> 'owner' or EXISTS (
+                    SELECT 1 FROM company_members owners
+                    WHERE owners.company_id = $1 and owners.user_id <> $2 and owners.role = 'owner'
+                ))
+        "#,
+        company_id,
+        user_id,
+    )
+    .execute(pool)
+    .await
+    .map(|res| res.rows_affected())
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
-    use crate::addresses::{get_addresses, insert_addresses, Address, AddressInput};
+    use crate::addresses::{get_addresses, insert_addresses, Address};
     use crate::chats::tests::create_user;
     use crate::pg_pool;
 
@@ -166,9 +371,10 @@ pub mod tests {
         let company = create_company(&pool).await;
         let user = create_user(&pool).await;
 
-        let company_member_id = insert_company_member(&pool, user.id, company.id)
-            .await
-            .expect("company member");
+        let company_member_id =
+            insert_company_member(&pool, user.id, company.id, CompanyMemberRole::Member)
+                .await
+                .expect("company member");
 
         let company_member = get_company_member(&pool, company_member_id)
             .await
@@ -176,5 +382,107 @@ pub mod tests {
 
         assert_eq!(company_member.company_id, company.id);
         assert_eq!(company_member.user_id, user.id);
+        assert_eq!(company_member.role, CompanyMemberRole::Member);
+    }
+
+    #[tokio::test]
+    async fn test_create_company_with_owner() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let owner = create_user(&pool).await;
+
+        let address = AddressInput {
+            zip_code: 76236,
+            country: "sw",
+            region: "region",
+            city: "city",
+            district: None::<&str>,
+            street: "street",
+            building: "building",
+            apartment: "apartment",
+        };
+        let id = insert_company_with_owner(&pool, "company", &address, owner.id)
+            .await
+            .expect("company is created");
+
+        let companies = get_user_companies(&pool, owner.id)
+            .await
+            .expect("companies of a user");
+        assert_eq!(companies.iter().map(|x| x.id).collect::<Vec<_>>(), vec![id]);
+        let members = get_company_members(&pool, id)
+            .await
+            .expect("members of a company");
+        assert_eq!(
+            members
+                .iter()
+                .map(|x| (x.user_id, x.role))
+                .collect::<Vec<_>>(),
+            vec![(owner.id, CompanyMemberRole::Owner)]
+        );
+
+        let updated = update_company_name(&pool, id, "new name")
+            .await
+            .expect("company is renamed");
+        assert_eq!(updated, 1);
+        let company = get_company(&pool, id).await.expect("company");
+        assert_eq!(company.name, "new name");
+    }
+
+    #[tokio::test]
+    async fn test_company_keeps_an_owner() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let company = create_company(&pool).await;
+        let owner = create_user(&pool).await;
+        let admin = create_user(&pool).await;
+
+        insert_company_member(&pool, owner.id, company.id, CompanyMemberRole::Owner)
+            .await
+            .expect("owner added");
+        insert_company_member(&pool, admin.id, company.id, CompanyMemberRole::Admin)
+            .await
+            .expect("admin added");
+
+        let role = |user_id, role| update_company_member_role(&pool, company.id, user_id, role);
+        assert_eq!(
+            role(owner.id, CompanyMemberRole::Member)
+                .await
+                .expect("query succeeded"),
+            0
+        );
+        assert_eq!(
+            delete_company_member(&pool, company.id, owner.id)
+                .await
+                .expect("query succeeded"),
+            0
+        );
+
+        assert_eq!(
+            role(admin.id, CompanyMemberRole::Owner)
+                .await
+                .expect("admin promoted"),
+            1
+        );
+        assert_eq!(
+            role(owner.id, CompanyMemberRole::Member)
+                .await
+                .expect("owner demoted"),
+            1
+        );
+        assert_eq!(
+            delete_company_member(&pool, company.id, owner.id)
+                .await
+                .expect("member removed"),
+            1
+        );
+
+        let members = get_company_members(&pool, company.id)
+            .await
+            .expect("members of a company");
+        assert_eq!(
+            members
+                .iter()
+                .map(|x| (x.user_id, x.role))
+                .collect::<Vec<_>>(),
+            vec![(admin.id, CompanyMemberRole::Owner)]
+        );
     }
 }
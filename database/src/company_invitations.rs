@@ -0,0 +1,254 @@
+use crate::companies::CompanyMemberRole;
+use sqlx::types::time::PrimitiveDateTime;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// An invitation to a company for somebody who may have no account yet, accepted with a mailed token
+#[derive(Debug, sqlx::FromRow)]
+pub struct CompanyInvitation {
+    pub id: Uuid,
+    pub company_id: Uuid,
+    pub email: String,
+    pub role: CompanyMemberRole,
+    pub invited_by: Uuid,
+    pub token_hash: String,
+    pub created_at: PrimitiveDateTime,
+    pub expires_at: PrimitiveDateTime,
+    pub accepted_at: Option<PrimitiveDateTime>,
+}
+
+#[derive(Debug)]
+pub struct CompanyInvitationInput<T1: AsRef<str>, T2: AsRef<str>> {
+    pub company_id: Uuid,
+    pub email: T1,
+    pub role: CompanyMemberRole,
+    pub invited_by: Uuid,
+    pub token_hash: T2,
+    pub expires_at: PrimitiveDateTime,
+}
+
+pub async fn insert_company_invitation<T1: AsRef<str>, T2: AsRef<str>>(
+    pool: &PgPool,
+    input: &CompanyInvitationInput<T1, T2>,
+) -> Result<Uuid, sqlx::Error> {
+    sqlx::query!(
+        r#"
+                INSERT INTO company_invitations ( id, company_id, email, role, invited_by, token_hash, created_at, expires_at )
+                SELECT $1, $2, $3, $4, $5, $6, CURRENT_TIMESTAMP, $7
+                RETURNING id
+            "#,
+        Uuid::new_v4(),
+        input.company_id,
+        input.email.as_ref(),
+        input.role as CompanyMemberRole,
+        input.invited_by,
+        input.token_hash.as_ref(),
+        input.expires_at,
+    )
+    .fetch_one(pool)
+    .await
+    .map(|x| x.id)
+}
+
+pub async fn get_company_invitation_by_hash(
+    pool: &PgPool,
+    token_hash: impl AsRef<str>,
+) -> Result<CompanyInvitation, sqlx::Error> {
+    sqlx::query_as!(
+        CompanyInvitation,
+        r#"
+                SELECT id, company_id, email, role as "role: _", invited_by, token_hash, created_at, expires_at, accepted_at
+                FROM company_invitations
+                WHERE token_hash = $1
+            "#,
+        token_hash.as_ref()
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Invitations of a company which are neither accepted nor expired, the newest first
+pub async fn get_pending_company_invitations(
+    pool: &PgPool,
+    company_id: Uuid,
+) -> Result<Vec<CompanyInvitation>, sqlx::Error> {
+    sqlx::query_as!(
+        CompanyInvitation,
+        r#"
+                SELECT id, company_id, email, role as "role: _", invited_by, token_hash, created_at, expires_at, accepted_at
+                FROM company_invitations
+                WHERE company_id = $1 and accepted_at is null and expires_at > CURRENT_TIMESTAMP
+                ORDER BY created_at DESC, id
+            "#,
+        company_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Withdraws an invitation which is not accepted yet, returns 0 if there is none
+pub async fn delete_company_invitation(
+    pool: &PgPool,
+    company_id: Uuid,
+    id: Uuid,
+) -> Result<u64, sqlx::Error> {
+    sqlx::query!(
+        r#"
+            DELETE FROM company_invitations
+            WHERE id = $1 and company_id = $2 and accepted_at is null
+        "#,
+        id,
+        company_id,
+    )
+    .execute(pool)
+    .await
+    .map(|res| res.rows_affected())
+}
+
+/// Makes a user a member with the invited role, returns the company
+///
+/// `None` when the invitation has been accepted or is expired.
+/// Members keep their current role.
+pub async fn accept_company_invitation(
+    pool: &PgPool,
+    id: Uuid,
+    user_id: Uuid,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let accepted = sqlx::query!(
+        r#"
+            UPDATE company_invitations
+            SET accepted_at = CURRENT_TIMESTAMP
+            WHERE id = $1 and accepted_at is null and expires_at > CURRENT_TIMESTAMP
+            RETURNING company_id, role as "role: CompanyMemberRole"
+        "#,
+        id,
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+    let Some(accepted) = accepted else {
+        return Ok(None);
+    };
+
+    sqlx::query!(
+        r#"
+            INSERT INTO company_members ( id, company_id, user_id, role, created_at, updated_at )
+            SELECT $1, $2, $3, $4, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP
+            ON CONFLICT (user_id, company_id) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        accepted.company_id,
+        user_id,
+        accepted.role as CompanyMemberRole,
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    transaction.commit().await?;
+    Ok(Some(accepted.company_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chats::tests::create_user;
+    use crate::companies::get_company_members;
+    use crate::companies::tests::create_company;
+    use crate::pg_pool;
+    use crate::utils::random_samples::RandomSample;
+    use sqlx::types::time::OffsetDateTime;
+    use time::Duration;
+
+    fn create_company_invitation_input(
+        company_id: Uuid,
+        invited_by: Uuid,
+        expires_in: Duration,
+    ) -> CompanyInvitationInput<String, String> {
+        let expires_at = OffsetDateTime::now_utc() + expires_in;
+
+        CompanyInvitationInput {
+            company_id,
+            email: format!("{}@test.test", String::new_random(16)),
+            role: CompanyMemberRole::Admin,
+            invited_by,
+            token_hash: String::new_random(88),
+            expires_at: PrimitiveDateTime::new(expires_at.date(), expires_at.time()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_company_invitation_can_be_accepted_once() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let company = create_company(&pool).await;
+        let inviter = create_user(&pool).await;
+        let user = create_user(&pool).await;
+
+        let input = create_company_invitation_input(company.id, inviter.id, Duration::hours(1));
+        let id = insert_company_invitation(&pool, &input)
+            .await
+            .expect("company invitation is created");
+
+        let invitation = get_company_invitation_by_hash(&pool, &input.token_hash)
+            .await
+            .expect("company invitation for a given hash");
+        assert_eq!(invitation.id, id);
+        assert_eq!(invitation.email, input.email);
+        let pending = get_pending_company_invitations(&pool, company.id)
+            .await
+            .expect("pending invitations");
+        assert_eq!(pending.iter().map(|x| x.id).collect::<Vec<_>>(), vec![id]);
+
+        let accepted = accept_company_invitation(&pool, id, user.id)
+            .await
+            .expect("invitation is accepted");
+        assert_eq!(accepted, Some(company.id));
+        let accepted = accept_company_invitation(&pool, id, user.id)
+            .await
+            .expect("query succeeded");
+        assert_eq!(accepted, None);
+
+        let members = get_company_members(&pool, company.id)
+            .await
+            .expect("members returned");
+        assert_eq!(
+            members
+                .iter()
+                .map(|x| (x.user_id, x.role))
+                .collect::<Vec<_>>(),
+            vec![(user.id, CompanyMemberRole::Admin)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_expired_or_revoked_company_invitation_can_not_be_accepted() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let company = create_company(&pool).await;
+        let inviter = create_user(&pool).await;
+        let user = create_user(&pool).await;
+
+        let input = create_company_invitation_input(company.id, inviter.id, Duration::hours(-1));
+        let expired_id = insert_company_invitation(&pool, &input)
+            .await
+            .expect("company invitation is created");
+        assert_eq!(
+            accept_company_invitation(&pool, expired_id, user.id)
+                .await
+                .expect("query succeeded"),
+            None
+        );
+
+        let input = create_company_invitation_input(company.id, inviter.id, Duration::hours(1));
+        let revoked_id = insert_company_invitation(&pool, &input)
+            .await
+            .expect("company invitation is created");
+        let deleted = delete_company_invitation(&pool, company.id, revoked_id)
+            .await
+            .expect("invitation is revoked");
+        assert_eq!(deleted, 1);
+        assert!(matches!(
+            get_company_invitation_by_hash(&pool, &input.token_hash).await,
+            Err(sqlx::Error::RowNotFound)
+        ));
+    }
+}
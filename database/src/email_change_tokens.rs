@@ -0,0 +1,134 @@
+use sqlx::types::time::PrimitiveDateTime;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// A pending change of an email, confirmed by a token mailed to the new address
+#[derive(sqlx::FromRow)]
+pub struct EmailChangeToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub new_email: String,
+    pub token_hash: String,
+    pub created_at: PrimitiveDateTime,
+    pub expires_at: PrimitiveDateTime,
+    pub used_at: Option<PrimitiveDateTime>,
+}
+
+#[derive(Debug)]
+pub struct EmailChangeTokenInput<T1: AsRef<str>, T2: AsRef<str>> {
+    pub user_id: Uuid,
+    pub new_email: T1,
+    pub token_hash: T2,
+    pub expires_at: PrimitiveDateTime,
+}
+
+pub async fn insert_email_change_token<T1: AsRef<str>, T2: AsRef<str>>(
+    pool: &PgPool,
+    input: &EmailChangeTokenInput<T1, T2>,
+) -> Result<Uuid, sqlx::Error> {
+    sqlx::query!(
+        r#"
+                INSERT INTO email_change_tokens ( id, user_id, new_email, token_hash, created_at, expires_at )
+                SELECT $1, $2, $3, $4, CURRENT_TIMESTAMP, $5
+                RETURNING id
+            "#,
+        Uuid::new_v4(),
+        input.user_id,
+        input.new_email.as_ref(),
+        input.token_hash.as_ref(),
+        input.expires_at,
+    )
+    .fetch_one(pool)
+    .await
+    .map(|x| x.id)
+}
+
+pub async fn get_email_change_token_by_hash(
+    pool: &PgPool,
+    token_hash: impl AsRef<str>,
+) -> Result<EmailChangeToken, sqlx::Error> {
+    sqlx::query_as!(
+        EmailChangeToken,
+        r#"
+                SELECT id, user_id, new_email, token_hash, created_at, expires_at, used_at FROM email_change_tokens
+                WHERE token_hash = $1
+            "#,
+        token_hash.as_ref()
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Marks a token as used, returns 0 if it has already been used or is expired
+pub async fn use_email_change_token(pool: &PgPool, id: Uuid) -> Result<u64, sqlx::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE email_change_tokens
+            SET used_at = CURRENT_TIMESTAMP
+            WHERE id = $1 and used_at is null and expires_at > CURRENT_TIMESTAMP
+        "#,
+        id,
+    )
+    .execute(pool)
+    .await
+    .map(|res| res.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chats::tests::create_user;
+    use crate::pg_pool;
+    use crate::utils::random_samples::RandomSample;
+    use sqlx::types::time::OffsetDateTime;
+    use time::Duration;
+
+    fn create_email_change_token_input(
+        user_id: Uuid,
+        expires_in: Duration,
+    ) -> EmailChangeTokenInput<String, String> {
+        let expires_at = OffsetDateTime::now_utc() + expires_in;
+
+        EmailChangeTokenInput {
+            user_id,
+            new_email: format!("{}@test.test", String::new_random(16)),
+            token_hash: String::new_random(88),
+            expires_at: PrimitiveDateTime::new(expires_at.date(), expires_at.time()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_email_change_token_can_be_used_once() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let user = create_user(&pool).await;
+
+        let input = create_email_change_token_input(user.id, Duration::hours(1));
+        let id = insert_email_change_token(&pool, &input)
+            .await
+            .expect("email change token is created");
+
+        let token = get_email_change_token_by_hash(&pool, &input.token_hash)
+            .await
+            .expect("email change token for a given hash");
+        assert_eq!(token.id, id);
+        assert_eq!(token.user_id, user.id);
+        assert_eq!(token.new_email, input.new_email);
+        assert_eq!(token.used_at, None);
+
+        assert_eq!(use_email_change_token(&pool, id).await.expect("used"), 1);
+        assert_eq!(use_email_change_token(&pool, id).await.expect("used"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_expired_email_change_token_can_not_be_used() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let user = create_user(&pool).await;
+
+        let input = create_email_change_token_input(user.id, -Duration::minutes(1));
+        let id = insert_email_change_token(&pool, &input)
+            .await
+            .expect("email change token is created");
+
+        assert_eq!(use_email_change_token(&pool, id).await.expect("used"), 0);
+    }
+}
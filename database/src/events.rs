@@ -0,0 +1,643 @@
+use crate::chats::ChatMessage;
+use sqlx::types::time::PrimitiveDateTime;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+/// Channel the ids of new events are sent to
+pub const EVENTS_CHANNEL: &str = "events";
+/// Channel of typing indicators, they are only sent and never stored
+pub const CHAT_TYPING_CHANNEL: &str = "chat_typing";
+/// Channel of users whose access token, session or API key has been revoked
+pub const CREDENTIAL_REVOCATIONS_CHANNEL: &str = "credential_revocations";
+
+#[derive(Debug, Clone, PartialEq, Eq, Copy, sqlx::Type)]
+#[sqlx(rename_all = "snake_case")]
+pub enum EventType {
+    MessageCreated,
+    MessageUpdated,
+    MessageDeleted,
+    Read,
+    MemberAdded,
+    MemberUpdated,
+    MemberRemoved,
+    ProjectCreated,
+    ProjectUpdated,
+    ProjectDeleted,
+    ProjectMemberAdded,
+    ProjectMemberUpdated,
+    ProjectMemberRemoved,
+}
+
+impl EventType {
+    /// Whether the event is about a chat member, which is the event's user
+    pub fn is_member_event(&self) -> bool {
+        matches!(
+            self,
+            EventType::MemberAdded | EventType::MemberUpdated | EventType::MemberRemoved
+        )
+    }
+
+    /// Whether the event is about a project member, which is the event's user
+    pub fn is_project_member_event(&self) -> bool {
+        matches!(
+            self,
+            EventType::ProjectMemberAdded
+                | EventType::ProjectMemberUpdated
+                | EventType::ProjectMemberRemoved
+        )
+    }
+}
+
+/// Something that happened in a chat or a project, `id` orders events and is the cursor clients resume from
+pub struct Event {
+    pub id: i64,
+    /// Set for chat events
+    pub chat_id: Option<Uuid>,
+    /// Set for project events
+    pub project_id: Option<Uuid>,
+    pub event_type: EventType,
+    /// Sender of a message, reader, member the event is about or who changed a project
+    pub user_id: Uuid,
+    pub message_id: Option<Uuid>,
+    /// The message as it is now, for events about one
+    pub message: Option<ChatMessage>,
+    pub created_at: PrimitiveDateTime,
+}
+
+/// Records an event about a message or a read receipt as a part of a bigger change
+pub(crate) async fn insert_chat_event(
+    transaction: &mut Transaction<'_, Postgres>,
+    chat_id: Uuid,
+    event_type: EventType,
+    user_id: Uuid,
+    message_id: Option<Uuid>,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query!(
+        r#"
+                INSERT INTO events ( chat_id, event_type, user_id, message_id, created_at )
+                SELECT $1, $2, $3, $4, CURRENT_TIMESTAMP
+                RETURNING id
+            "#,
+        chat_id,
+        event_type as EventType,
+        user_id,
+        message_id,
+    )
+    .fetch_one(transaction)
+    .await
+    .map(|x| x.id)
+}
+
+/// Records an event about a chat member, chat members are stored by their aliases
+pub(crate) async fn insert_chat_member_event(
+    transaction: &mut Transaction<'_, Postgres>,
+    chat_id: Uuid,
+    event_type: EventType,
+    member: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+                INSERT INTO events ( chat_id, event_type, user_id, created_at )
+                SELECT $1, $2, users.id, CURRENT_TIMESTAMP FROM users
+                WHERE users.alias = $3
+            "#,
+        chat_id,
+        event_type as EventType,
+        member,
+    )
+    .execute(transaction)
+    .await
+    .map(|_| ())
+}
+
+/// Records a change of a project or of its members as a part of the change
+pub(crate) async fn insert_project_event(
+    transaction: &mut Transaction<'_, Postgres>,
+    project_id: Uuid,
+    event_type: EventType,
+    user_id: Uuid,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query!(
+        r#"
+                INSERT INTO events ( project_id, event_type, user_id, created_at )
+                SELECT $1, $2, $3, CURRENT_TIMESTAMP
+                RETURNING id
+            "#,
+        project_id,
+        event_type as EventType,
+        user_id,
+    )
+    .fetch_one(transaction)
+    .await
+    .map(|x| x.id)
+}
+
+/// An event joined with its message, if it has one
+struct EventRow {
+    id: i64,
+    chat_id: Option<Uuid>,
+    project_id: Option<Uuid>,
+    event_type: EventType,
+    user_id: Uuid,
+    message_id: Option<Uuid>,
+    created_at: PrimitiveDateTime,
+    message_chat_id: Option<Uuid>,
+    sender_id: Option<Uuid>,
+    message: Option<String>,
+    parent_id: Option<Uuid>,
+    message_created_at: Option<PrimitiveDateTime>,
+    message_updated_at: Option<PrimitiveDateTime>,
+    edited_at: Option<PrimitiveDateTime>,
+    deleted_at: Option<PrimitiveDateTime>,
+}
+
+impl From<EventRow> for Event {
+    fn from(row: EventRow) -> Self {
+        let message = match (
+            row.message_id,
+            row.message_chat_id,
+            row.sender_id,
+            row.message,
+            row.message_created_at,
+            row.message_updated_at,
+        ) {
+            (
+                Some(id),
+                Some(chat_id),
+                Some(sender_id),
+                Some(message),
+                Some(created_at),
+                Some(updated_at),
+            ) => Some(ChatMessage {
+                id,
+                chat_id,
+                sender_id,
+                message,
+                parent_id: row.parent_id,
+                created_at,
+                updated_at,
+                edited_at: row.edited_at,
+                deleted_at: row.deleted_at,
+            }),
+            _ => None,
+        };
+
+        Event {
+            id: row.id,
+            chat_id: row.chat_id,
+            project_id: row.project_id,
+            event_type: row.event_type,
+            user_id: row.user_id,
+            message_id: row.message_id,
+            message,
+            created_at: row.created_at,
+        }
+    }
+}
+
+pub async fn get_event(pool: &PgPool, id: i64) -> Result<Event, sqlx::Error> {
+    sqlx::query_as!(
+        EventRow,
+        r#"
+                SELECT events.id, events.chat_id, events.project_id, event_type as "event_type: _", user_id, message_id,
+                    events.created_at, chat_messages.chat_id as "message_chat_id?", chat_messages.sender_id as "sender_id?",
+                    chat_messages.message as "message?", chat_messages.parent_id, chat_messages.created_at as "message_created_at?",
+                    chat_messages.updated_at as "message_updated_at?", chat_messages.edited_at, chat_messages.deleted_at
+                FROM events
+                LEFT JOIN chat_messages ON chat_messages.id = events.message_id
+                WHERE events.id = $1
+            "#,
+        id
+    )
+    .fetch_one(pool)
+    .await
+    .map(Into::into)
+}
+
+/// Events after a cursor a user may see
+///
+/// These are events of chats the user is a member of, of projects the user owns, is a member of
+/// or sees through a company and events about the user's own memberships.
+/// Ids follow the order events commit in, so no event commits after a later cursor.
+pub async fn get_user_events(
+    pool: &PgPool,
+    user_id: Uuid,
+    after: i64,
+    limit: i64,
+) -> Result<Vec<Event>, sqlx::Error> {
+    sqlx::query_as!(
+        EventRow,
+        r#"
+                SELECT events.id, events.chat_id, events.project_id, event_type as "event_type: _", user_id, message_id,
+                    events.created_at, chat_messages.chat_id as "message_chat_id?", chat_messages.sender_id as "sender_id?",
+                    chat_messages.message as "message?", chat_messages.parent_id, chat_messages.created_at as "message_created_at?",
+                    chat_messages.updated_at as "message_updated_at?", chat_messages.edited_at, chat_messages.deleted_at
+                FROM events
+                LEFT JOIN chat_messages ON chat_messages.id = events.message_id
+                WHERE events.id > $2 and (
+                    events.chat_id in (
+                        SELECT chat_member.chat_id FROM chat_member
+                        JOIN users ON users.alias = chat_member.member
+                        WHERE users.id = $1 and chat_member.role not in ('left', 'banned')
+                    )
+                    or events.project_id in (
+                        SELECT id FROM projects WHERE user_id = $1
+                        UNION
+                        SELECT project_id FROM project_members WHERE user_id = $1
+                        UNION
+                        SELECT company_projects.project_id FROM company_projects
+                        JOIN company_members ON company_members.company_id = company_projects.company_id
+                        WHERE company_members.user_id = $1
+                    )
+                    or (events.user_id = $1 and event_type in (
+                        'member_added', 'member_updated', 'member_removed',
+                        'project_member_added', 'project_member_updated', 'project_member_removed'
+                    ))
+                )
+                ORDER BY events.id
+                LIMIT $3
+            "#,
+        user_id,
+        after,
+        limit
+    )
+    .fetch_all(pool)
+    .await
+    .map(|rows| rows.into_iter().map(Into::into).collect())
+}
+
+/// Users who see the events of a project, the same ones `get_user_events` returns them to
+pub async fn get_project_audience(
+    pool: &PgPool,
+    project_id: Uuid,
+) -> Result<Vec<Uuid>, sqlx::Error> {
+    sqlx::query!(
+        r#"
+                SELECT user_id as "user_id!" FROM projects WHERE id = $1
+                UNION
+                SELECT user_id FROM project_members WHERE project_id = $1
+                UNION
+                SELECT company_members.user_id FROM company_projects
+                JOIN company_members ON company_members.company_id = company_projects.company_id
+                WHERE company_projects.project_id = $1
+            "#,
+        project_id
+    )
+    .fetch_all(pool)
+    .await
+    .map(|rows| rows.into_iter().map(|x| x.user_id).collect())
+}
+
+/// Id of the latest event, where clients without a cursor start from
+pub async fn get_last_event_id(pool: &PgPool) -> Result<i64, sqlx::Error> {
+    sqlx::query!(r#"SELECT coalesce(max(id), 0) as "id!" FROM events"#)
+        .fetch_one(pool)
+        .await
+        .map(|x| x.id)
+}
+
+/// Moves the read marker of a member to a message of the chat, `None` for somebody who is not a member
+pub async fn mark_chat_read(
+    pool: &PgPool,
+    chat_id: Uuid,
+    user_id: Uuid,
+    message_id: Uuid,
+) -> Result<Option<i64>, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let updated = sqlx::query!(
+        r#"
+            UPDATE chat_member
+            SET last_read_message_id = $3, updated_at = CURRENT_TIMESTAMP
+            FROM users
+            WHERE users.alias = chat_member.member and users.id = $2
+                and chat_member.chat_id = $1 and chat_member.role not in ('left', 'banned')
+                and exists (SELECT 1 FROM chat_messages WHERE id = $3 and chat_id = $1)
+        "#,
+        chat_id,
+        user_id,
+        message_id,
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+    if updated == 0 {
+        return Ok(None);
+    }
+
+    let id = insert_chat_event(
+        &mut transaction,
+        chat_id,
+        EventType::Read,
+        user_id,
+        Some(message_id),
+    )
+    .await?;
+
+    transaction.commit().await?;
+    Ok(Some(id))
+}
+
+/// Tells every listener a member is typing in a chat
+pub async fn notify_chat_typing(
+    pool: &PgPool,
+    chat_id: Uuid,
+    user_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "SELECT pg_notify($1, $2)",
+        CHAT_TYPING_CHANNEL,
+        std::format!("{chat_id} {user_id}"),
+    )
+    .execute(pool)
+    .await
+    .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chats::tests::create_user;
+    use crate::chats::{
+        insert_chat_message, insert_chat_with_members, update_chat_member_role, ChatMemberRole,
+        ChatType,
+    };
+    use crate::companies::tests::create_company;
+    use crate::companies::{insert_company_member, CompanyMemberRole};
+    use crate::pg_pool;
+    use crate::projects::tests::create_project;
+    use crate::projects::{
+        delete_project_member, get_project, insert_company_project, insert_project_member,
+        update_project, ProjectMemberRole, ProjectUpdate,
+    };
+    use crate::users::User;
+    use sqlx::postgres::PgListener;
+
+    fn alias(user: &User) -> String {
+        user.alias.clone().expect("alias is expected")
+    }
+
+    #[tokio::test]
+    async fn test_replay_chat_events_after_a_cursor() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let creator = create_user(&pool).await;
+        let member = create_user(&pool).await;
+
+        let chat_id = insert_chat_with_members(
+            &pool,
+            ChatType::Group,
+            "group",
+            None,
+            alias(&creator),
+            &[alias(&member)],
+        )
+        .await
+        .expect("chat is created");
+
+        let events = get_user_events(&pool, member.id, 0, 100)
+            .await
+            .expect("events");
+        let joined = events
+            .iter()
+            .filter(|event| event.chat_id == Some(chat_id))
+            .map(|event| (event.event_type, event.user_id))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            joined,
+            vec![
+                (EventType::MemberAdded, creator.id),
+                (EventType::MemberAdded, member.id),
+            ]
+        );
+        let cursor = events.last().expect("an event").id;
+
+        let message_id = insert_chat_message(&pool, chat_id, creator.id, "hello", None)
+            .await
+            .expect("message is created");
+        let read_id = mark_chat_read(&pool, chat_id, member.id, message_id)
+            .await
+            .expect("query succeeded")
+            .expect("read receipt");
+
+        let events = get_user_events(&pool, member.id, cursor, 100)
+            .await
+            .expect("events");
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].event_type, EventType::MessageCreated);
+        assert_eq!(events[0].user_id, creator.id);
+        assert_eq!(
+            events[0].message.as_ref().map(|x| x.message.as_str()),
+            Some("hello")
+        );
+        assert_eq!(events[1].id, read_id);
+        assert_eq!(events[1].event_type, EventType::Read);
+        assert_eq!(events[1].message_id, Some(message_id));
+
+        update_chat_member_role(&pool, chat_id, alias(&member), ChatMemberRole::Left)
+            .await
+            .expect("member has left");
+        insert_chat_message(&pool, chat_id, creator.id, "after", None)
+            .await
+            .expect("message is created");
+
+        let events = get_user_events(&pool, member.id, read_id, 100)
+            .await
+            .expect("events");
+        assert_eq!(
+            events
+                .iter()
+                .map(|event| (event.event_type, event.user_id))
+                .collect::<Vec<_>>(),
+            vec![(EventType::MemberRemoved, member.id)]
+        );
+        assert_eq!(
+            mark_chat_read(&pool, chat_id, member.id, message_id)
+                .await
+                .expect("query succeeded"),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replay_project_events_to_members() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let project = create_project(&pool).await;
+        let member = create_user(&pool).await;
+        let company_member = create_user(&pool).await;
+
+        let company = create_company(&pool).await;
+        insert_company_member(
+            &pool,
+            company_member.id,
+            company.id,
+            CompanyMemberRole::Member,
+        )
+        .await
+        .expect("company member is added");
+        insert_company_project(&pool, company.id, project.id)
+            .await
+            .expect("project is shared");
+
+        let created = get_user_events(&pool, project.user_id, 0, 100)
+            .await
+            .expect("events");
+        assert_eq!(
+            created
+                .iter()
+                .map(|event| (event.project_id, event.event_type))
+                .collect::<Vec<_>>(),
+            vec![(Some(project.id), EventType::ProjectCreated)]
+        );
+        let cursor = created[0].id;
+
+        insert_project_member(&pool, project.id, member.id, ProjectMemberRole::Viewer)
+            .await
+            .expect("member is added");
+        let update = ProjectUpdate {
+            name: "renamed".to_string(),
+            description: "project description".to_string(),
+        };
+        update_project(
+            &pool,
+            &project.id,
+            &update,
+            project.updated_at,
+            project.user_id,
+        )
+        .await
+        .expect("query succeeded")
+        .expect("project is updated");
+
+        let mut audience = get_project_audience(&pool, project.id)
+            .await
+            .expect("audience");
+        audience.sort();
+        let mut expected = vec![project.user_id, member.id, company_member.id];
+        expected.sort();
+        assert_eq!(audience, expected);
+
+        let expected = vec![
+            (EventType::ProjectMemberAdded, member.id),
+            (EventType::ProjectUpdated, project.user_id),
+        ];
+        for user_id in [project.user_id, member.id, company_member.id] {
+            let events = get_user_events(&pool, user_id, cursor, 100)
+                .await
+                .expect("events");
+            assert_eq!(
+                events
+                    .iter()
+                    .map(|event| (event.event_type, event.user_id))
+                    .collect::<Vec<_>>(),
+                expected
+            );
+        }
+        let cursor = get_last_event_id(&pool).await.expect("last event id");
+
+        delete_project_member(&pool, project.id, member.id)
+            .await
+            .expect("member is removed");
+        update_project(
+            &pool,
+            &project.id,
+            &update,
+            get_project(&pool, &project.id)
+                .await
+                .expect("project")
+                .updated_at,
+            project.user_id,
+        )
+        .await
+        .expect("query succeeded")
+        .expect("project is updated");
+
+        let events = get_user_events(&pool, member.id, cursor, 100)
+            .await
+            .expect("events");
+        assert_eq!(
+            events
+                .iter()
+                .map(|event| (event.event_type, event.user_id))
+                .collect::<Vec<_>>(),
+            vec![(EventType::ProjectMemberRemoved, member.id)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_hand_out_ids_in_commit_order() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let creator = create_user(&pool).await;
+        let chat_id =
+            insert_chat_with_members(&pool, ChatType::Group, "group", None, alias(&creator), &[])
+                .await
+                .expect("chat is created");
+
+        let mut first = pool.begin().await.expect("transaction");
+        let first_id = insert_chat_event(&mut first, chat_id, EventType::Read, creator.id, None)
+            .await
+            .expect("event is inserted");
+
+        let second = tokio::spawn({
+            let pool = pool.clone();
+            async move {
+                let mut second = pool.begin().await.expect("transaction");
+                let id = insert_chat_event(&mut second, chat_id, EventType::Read, creator.id, None)
+                    .await
+                    .expect("event is inserted");
+                second.commit().await.expect("committed");
+                id
+            }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        assert!(!second.is_finished());
+
+        first.commit().await.expect("committed");
+        let second_id = second.await.expect("second insert");
+        assert!(second_id > first_id);
+    }
+
+    #[tokio::test]
+    async fn test_notify_listeners() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let creator = create_user(&pool).await;
+
+        let mut listener = PgListener::connect_with(&pool)
+            .await
+            .expect("listener is connected");
+        listener
+            .listen_all([EVENTS_CHANNEL, CHAT_TYPING_CHANNEL])
+            .await
+            .expect("listening");
+
+        let chat_id =
+            insert_chat_with_members(&pool, ChatType::Group, "group", None, alias(&creator), &[])
+                .await
+                .expect("chat is created");
+        notify_chat_typing(&pool, chat_id, creator.id)
+            .await
+            .expect("typing is sent");
+
+        // Other tests write events at the same time, so look for the ones of this chat
+        let mut event = None;
+        let mut typing = None;
+        while event.is_none() || typing.is_none() {
+            let notification = listener.recv().await.expect("notification");
+            match notification.channel() {
+                EVENTS_CHANNEL => {
+                    let id = notification.payload().parse().expect("event id");
+                    let chat_event = get_event(&pool, id).await.expect("event");
+                    if chat_event.chat_id == Some(chat_id) {
+                        event = Some(chat_event);
+                    }
+                }
+                _ if notification.payload() == std::format!("{chat_id} {}", creator.id) => {
+                    typing = Some(());
+                }
+                _ => {}
+            }
+        }
+        let event = event.expect("chat event");
+        assert_eq!(event.event_type, EventType::MemberAdded);
+        assert_eq!(event.user_id, creator.id);
+    }
+}
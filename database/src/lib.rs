@@ -1,7 +1,24 @@
+pub mod access;
 pub mod addresses;
+pub mod admins;
+pub mod api_keys;
+pub mod audit_events;
 pub mod chats;
 pub mod companies;
+pub mod company_invitations;
+pub mod email_change_tokens;
+pub mod events;
+pub mod mfa;
+pub mod oidc_login_states;
+pub mod password_reset_tokens;
+pub mod project_invitations;
 pub mod projects;
+pub mod rate_limits;
+pub mod refresh_tokens;
+pub mod revoked_tokens;
+pub mod sessions;
+pub mod user_data;
+pub mod user_identities;
 pub mod users;
 pub mod utils;
 
@@ -0,0 +1,187 @@
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Stores a TOTP secret waiting for confirmation, returns 0 if TOTP is already enabled
+pub async fn set_totp_secret(
+    pool: &PgPool,
+    user_id: Uuid,
+    secret: impl AsRef<str>,
+) -> Result<u64, sqlx::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE users
+            SET totp_secret = $2, updated_at = CURRENT_TIMESTAMP
+            WHERE id = $1 and totp_enabled_at is null
+        "#,
+        user_id,
+        secret.as_ref(),
+    )
+    .execute(pool)
+    .await
+    .map(|res| res.rows_affected())
+}
+
+/// Enables TOTP confirmed with a code of a given time step and replaces recovery codes
+///
+/// Returns 0 without touching recovery codes if there is no secret or TOTP is already enabled.
+pub async fn enable_totp<T: AsRef<str>>(
+    pool: &PgPool,
+    user_id: Uuid,
+    step: i64,
+    recovery_code_hashes: &[T],
+) -> Result<u64, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let enabled = sqlx::query!(
+        r#"
+            UPDATE users
+            SET totp_enabled_at = CURRENT_TIMESTAMP, totp_last_step = $2, updated_at = CURRENT_TIMESTAMP
+            WHERE id = $1 and totp_secret is not null and totp_enabled_at is null
+        "#,
+        user_id,
+        step,
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+    if enabled == 0 {
+        transaction.rollback().await?;
+        return Ok(0);
+    }
+
+    sqlx::query!(
+        r#"
+            DELETE FROM mfa_recovery_codes
+            WHERE user_id = $1
+        "#,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    for code_hash in recovery_code_hashes {
+        sqlx::query!(
+            r#"
+                INSERT INTO mfa_recovery_codes ( id, user_id, code_hash, created_at )
+                SELECT $1, $2, $3, CURRENT_TIMESTAMP
+            "#,
+            Uuid::new_v4(),
+            user_id,
+            code_hash.as_ref(),
+        )
+        .execute(&mut transaction)
+        .await?;
+    }
+
+    transaction.commit().await?;
+
+    Ok(enabled)
+}
+
+/// Accepts a TOTP code of a given time step, returns 0 if this or a later step was used already
+pub async fn use_totp_step(pool: &PgPool, user_id: Uuid, step: i64) -> Result<u64, sqlx::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE users
+            SET totp_last_step = $2
+            WHERE id = $1 and totp_enabled_at is not null and (totp_last_step is null or totp_last_step < $2)
+        "#,
+        user_id,
+        step,
+    )
+    .execute(pool)
+    .await
+    .map(|res| res.rows_affected())
+}
+
+/// Marks a recovery code as used, returns 0 if it is unknown or has already been used
+pub async fn use_recovery_code(
+    pool: &PgPool,
+    user_id: Uuid,
+    code_hash: impl AsRef<str>,
+) -> Result<u64, sqlx::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE mfa_recovery_codes
+            SET used_at = CURRENT_TIMESTAMP
+            WHERE user_id = $1 and code_hash = $2 and used_at is null
+        "#,
+        user_id,
+        code_hash.as_ref(),
+    )
+    .execute(pool)
+    .await
+    .map(|res| res.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chats::tests::create_user;
+    use crate::pg_pool;
+    use crate::users::get_user;
+    use crate::utils::random_samples::RandomSample;
+
+    #[tokio::test]
+    async fn test_enable_totp() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let user = create_user(&pool).await;
+
+        // Nothing to confirm without a secret
+        let enabled = enable_totp::<String>(&pool, user.id, 1, &[])
+            .await
+            .expect("enable query");
+        assert_eq!(enabled, 0);
+
+        let secret = String::new_random(32);
+        let updated = set_totp_secret(&pool, user.id, &secret)
+            .await
+            .expect("secret is set");
+        assert_eq!(updated, 1);
+
+        let code_hashes = vec![String::new_random(88), String::new_random(88)];
+        let enabled = enable_totp(&pool, user.id, 1, &code_hashes)
+            .await
+            .expect("enable query");
+        assert_eq!(enabled, 1);
+
+        let user = get_user(&pool, &user.id)
+            .await
+            .expect("user for given id is expected");
+        assert_eq!(user.totp_secret, Some(secret));
+        assert!(user.totp_enabled_at.is_some());
+
+        // An enabled secret can not be swapped
+        let updated = set_totp_secret(&pool, user.id, String::new_random(32))
+            .await
+            .expect("secret query");
+        assert_eq!(updated, 0);
+    }
+
+    #[tokio::test]
+    async fn test_totp_steps_and_recovery_codes_are_used_once() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let user = create_user(&pool).await;
+
+        set_totp_secret(&pool, user.id, String::new_random(32))
+            .await
+            .expect("secret is set");
+        let code_hash = String::new_random(88);
+        enable_totp(&pool, user.id, 10, &[&code_hash])
+            .await
+            .expect("totp is enabled");
+
+        assert_eq!(use_totp_step(&pool, user.id, 10).await.expect("used"), 0);
+        assert_eq!(use_totp_step(&pool, user.id, 11).await.expect("used"), 1);
+        assert_eq!(use_totp_step(&pool, user.id, 11).await.expect("used"), 0);
+
+        let used = use_recovery_code(&pool, user.id, &code_hash)
+            .await
+            .expect("used");
+        assert_eq!(used, 1);
+        let used = use_recovery_code(&pool, user.id, &code_hash)
+            .await
+            .expect("used");
+        assert_eq!(used, 0);
+    }
+}
@@ -0,0 +1,153 @@
+use sqlx::types::time::PrimitiveDateTime;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// A sign in through an identity provider waiting for the user to come back
+#[derive(sqlx::FromRow)]
+pub struct OidcLoginState {
+    pub id: Uuid,
+    pub state_hash: String,
+    pub provider: String,
+    pub nonce: String,
+    pub code_verifier: String,
+    pub device_name: Option<String>,
+    pub created_at: PrimitiveDateTime,
+    pub expires_at: PrimitiveDateTime,
+}
+
+#[derive(Debug)]
+pub struct OidcLoginStateInput<
+    T1: AsRef<str>,
+    T2: AsRef<str>,
+    T3: AsRef<str>,
+    T4: AsRef<str>,
+    T5: AsRef<str>,
+> {
+    pub state_hash: T1,
+    pub provider: T2,
+    pub nonce: T3,
+    pub code_verifier: T4,
+    pub device_name: Option<T5>,
+    pub expires_at: PrimitiveDateTime,
+}
+
+/// Stores a login state, expired ones are purged on the way
+pub async fn insert_oidc_login_state<
+    T1: AsRef<str>,
+    T2: AsRef<str>,
+    T3: AsRef<str>,
+    T4: AsRef<str>,
+    T5: AsRef<str>,
+>(
+    pool: &PgPool,
+    input: &OidcLoginStateInput<T1, T2, T3, T4, T5>,
+) -> Result<Uuid, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+            DELETE FROM oidc_login_states
+            WHERE expires_at < CURRENT_TIMESTAMP
+        "#,
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    let id = sqlx::query!(
+        r#"
+                INSERT INTO oidc_login_states ( id, state_hash, provider, nonce, code_verifier, device_name, created_at, expires_at )
+                SELECT $1, $2, $3, $4, $5, $6, CURRENT_TIMESTAMP, $7
+                RETURNING id
+            "#,
+        Uuid::new_v4(),
+        input.state_hash.as_ref(),
+        input.provider.as_ref(),
+        input.nonce.as_ref(),
+        input.code_verifier.as_ref(),
+        input.device_name.as_ref().map(|x| x.as_ref()),
+        input.expires_at,
+    )
+    .fetch_one(&mut transaction)
+    .await?
+    .id;
+
+    transaction.commit().await?;
+
+    Ok(id)
+}
+
+/// Removes a not expired login state and returns it, so each one is used once
+pub async fn take_oidc_login_state(
+    pool: &PgPool,
+    state_hash: impl AsRef<str>,
+) -> Result<OidcLoginState, sqlx::Error> {
+    sqlx::query_as!(
+        OidcLoginState,
+        r#"
+                DELETE FROM oidc_login_states
+                WHERE state_hash = $1 and expires_at > CURRENT_TIMESTAMP
+                RETURNING id, state_hash, provider, nonce, code_verifier, device_name, created_at, expires_at
+            "#,
+        state_hash.as_ref()
+    )
+    .fetch_one(pool)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pg_pool;
+    use crate::utils::random_samples::RandomSample;
+    use sqlx::types::time::OffsetDateTime;
+    use time::Duration;
+
+    fn create_oidc_login_state_input(
+        expires_in: Duration,
+    ) -> OidcLoginStateInput<String, &'static str, String, String, &'static str> {
+        let expires_at = OffsetDateTime::now_utc() + expires_in;
+
+        OidcLoginStateInput {
+            state_hash: String::new_random(88),
+            provider: "test",
+            nonce: String::new_random(43),
+            code_verifier: String::new_random(43),
+            device_name: Some("laptop"),
+            expires_at: PrimitiveDateTime::new(expires_at.date(), expires_at.time()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_login_state_is_taken_once() {
+        let pool = pg_pool().await.expect("pool is expected");
+
+        let input = create_oidc_login_state_input(Duration::minutes(10));
+        let id = insert_oidc_login_state(&pool, &input)
+            .await
+            .expect("login state is created");
+
+        let state = take_oidc_login_state(&pool, &input.state_hash)
+            .await
+            .expect("login state for a given hash");
+        assert_eq!(state.id, id);
+        assert_eq!(state.nonce, input.nonce);
+        assert_eq!(state.code_verifier, input.code_verifier);
+        assert_eq!(state.device_name.as_deref(), Some("laptop"));
+
+        let result = take_oidc_login_state(&pool, &input.state_hash).await;
+        assert!(matches!(result, Err(sqlx::Error::RowNotFound)));
+    }
+
+    #[tokio::test]
+    async fn test_expired_login_state_is_not_taken() {
+        let pool = pg_pool().await.expect("pool is expected");
+
+        let input = create_oidc_login_state_input(-Duration::minutes(1));
+        insert_oidc_login_state(&pool, &input)
+            .await
+            .expect("login state is created");
+
+        let result = take_oidc_login_state(&pool, &input.state_hash).await;
+        assert!(matches!(result, Err(sqlx::Error::RowNotFound)));
+    }
+}
@@ -0,0 +1,270 @@
+use sqlx::types::time::PrimitiveDateTime;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(sqlx::FromRow)]
+pub struct PasswordResetToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub token_hash: String,
+    pub created_at: PrimitiveDateTime,
+    pub expires_at: PrimitiveDateTime,
+    pub used_at: Option<PrimitiveDateTime>,
+}
+
+#[derive(Debug)]
+pub struct PasswordResetTokenInput<T: AsRef<str>> {
+    pub user_id: Uuid,
+    pub token_hash: T,
+    pub expires_at: PrimitiveDateTime,
+}
+
+pub async fn insert_password_reset_token<T: AsRef<str>>(
+    pool: &PgPool,
+    input: &PasswordResetTokenInput<T>,
+) -> Result<Uuid, sqlx::Error> {
+    sqlx::query!(
+        r#"
+                INSERT INTO password_reset_tokens ( id, user_id, token_hash, created_at, expires_at )
+                SELECT $1, $2, $3, CURRENT_TIMESTAMP, $4
+                RETURNING id
+            "#,
+        Uuid::new_v4(),
+        input.user_id,
+        input.token_hash.as_ref(),
+        input.expires_at,
+    )
+    .fetch_one(pool)
+    .await
+    .map(|x| x.id)
+}
+
+pub async fn get_password_reset_token_by_hash(
+    pool: &PgPool,
+    token_hash: impl AsRef<str>,
+) -> Result<PasswordResetToken, sqlx::Error> {
+    sqlx::query_as!(
+        PasswordResetToken,
+        r#"
+                SELECT id, user_id, token_hash, created_at, expires_at, used_at FROM password_reset_tokens
+                WHERE token_hash = $1
+            "#,
+        token_hash.as_ref()
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Marks a token as used and sets a new password, revoking every session with its refresh
+/// tokens and every API key of the user
+///
+/// Nothing changes and 0 is returned if the token has already been used or is expired.
+pub async fn reset_password(
+    pool: &PgPool,
+    id: Uuid,
+    password_hash: impl AsRef<str>,
+) -> Result<u64, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let user_id = match sqlx::query!(
+        r#"
+            UPDATE password_reset_tokens
+            SET used_at = CURRENT_TIMESTAMP
+            WHERE id = $1 and used_at is null and expires_at > CURRENT_TIMESTAMP
+            RETURNING user_id
+        "#,
+        id,
+    )
+    .fetch_optional(&mut transaction)
+    .await?
+    {
+        Some(token) => token.user_id,
+        None => return Ok(0),
+    };
+
+    sqlx::query!(
+        r#"
+            UPDATE users
+            SET password_hash = $2, updated_at = CURRENT_TIMESTAMP
+            WHERE id = $1
+        "#,
+        user_id,
+        password_hash.as_ref(),
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    sqlx::query!(
+        r#"
+            UPDATE sessions
+            SET revoked_at = CURRENT_TIMESTAMP
+            WHERE user_id = $1 and revoked_at is null
+        "#,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    sqlx::query!(
+        r#"
+            UPDATE refresh_tokens
+            SET revoked_at = CURRENT_TIMESTAMP
+            WHERE user_id = $1 and revoked_at is null
+        "#,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    sqlx::query!(
+        r#"
+            UPDATE api_keys
+            SET revoked_at = CURRENT_TIMESTAMP
+            WHERE user_id = $1 and revoked_at is null
+        "#,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(1)
+}
+
+/// Number of tokens issued to a user since a given time
+pub async fn count_password_reset_tokens_since(
+    pool: &PgPool,
+    user_id: Uuid,
+    since: PrimitiveDateTime,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query!(
+        r#"
+                SELECT count(*) as "count!" FROM password_reset_tokens
+                WHERE user_id = $1 and created_at >= $2
+            "#,
+        user_id,
+        since,
+    )
+    .fetch_one(pool)
+    .await
+    .map(|x| x.count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_keys::tests::create_api_key_input;
+    use crate::api_keys::{insert_api_key, is_api_key_revoked};
+    use crate::chats::tests::create_user;
+    use crate::pg_pool;
+    use crate::sessions::get_active_sessions;
+    use crate::sessions::tests::create_session;
+    use crate::users::get_user;
+    use crate::utils::random_samples::RandomSample;
+    use sqlx::types::time::OffsetDateTime;
+    use time::Duration;
+
+    fn create_password_reset_token_input(
+        user_id: Uuid,
+        expires_in: Duration,
+    ) -> PasswordResetTokenInput<String> {
+        let expires_at = OffsetDateTime::now_utc() + expires_in;
+
+        PasswordResetTokenInput {
+            user_id,
+            token_hash: String::new_random(88),
+            expires_at: PrimitiveDateTime::new(expires_at.date(), expires_at.time()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_password_reset_token_can_be_used_once() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let user = create_user(&pool).await;
+        create_session(&pool, user.id).await;
+        let api_key_id = insert_api_key(&pool, &create_api_key_input(user.id))
+            .await
+            .expect("API key is created");
+
+        let input = create_password_reset_token_input(user.id, Duration::hours(1));
+        let id = insert_password_reset_token(&pool, &input)
+            .await
+            .expect("password reset token is created");
+
+        let token = get_password_reset_token_by_hash(&pool, &input.token_hash)
+            .await
+            .expect("password reset token for a given hash");
+        assert_eq!(token.id, id);
+        assert_eq!(token.user_id, user.id);
+        assert_eq!(token.used_at, None);
+
+        assert_eq!(
+            reset_password(&pool, id, "new hash").await.expect("reset"),
+            1
+        );
+        assert_eq!(
+            reset_password(&pool, id, "other hash")
+                .await
+                .expect("reset"),
+            0
+        );
+
+        let user = get_user(&pool, &user.id).await.expect("user");
+        assert_eq!(user.password_hash, "new hash");
+        let sessions = get_active_sessions(&pool, user.id)
+            .await
+            .expect("sessions query");
+        assert!(sessions.is_empty());
+        assert!(is_api_key_revoked(&pool, api_key_id)
+            .await
+            .expect("revocation query"));
+    }
+
+    #[tokio::test]
+    async fn test_expired_password_reset_token_can_not_be_used() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let user = create_user(&pool).await;
+        create_session(&pool, user.id).await;
+
+        let input = create_password_reset_token_input(user.id, -Duration::minutes(1));
+        let id = insert_password_reset_token(&pool, &input)
+            .await
+            .expect("password reset token is created");
+
+        assert_eq!(
+            reset_password(&pool, id, "new hash").await.expect("reset"),
+            0
+        );
+
+        let password_hash = get_user(&pool, &user.id).await.expect("user").password_hash;
+        assert_eq!(password_hash, user.password_hash);
+        let sessions = get_active_sessions(&pool, user.id)
+            .await
+            .expect("sessions query");
+        assert_eq!(sessions.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_count_password_reset_tokens_since() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let user = create_user(&pool).await;
+
+        for _ in 0..2 {
+            let input = create_password_reset_token_input(user.id, Duration::hours(1));
+            insert_password_reset_token(&pool, &input)
+                .await
+                .expect("password reset token is created");
+        }
+
+        let hour_ago = OffsetDateTime::now_utc() - Duration::hours(1);
+        let count = count_password_reset_tokens_since(
+            &pool,
+            user.id,
+            PrimitiveDateTime::new(hour_ago.date(), hour_ago.time()),
+        )
+        .await
+        .expect("count query");
+        assert_eq!(count, 2);
+    }
+}
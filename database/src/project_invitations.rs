@@ -0,0 +1,291 @@
+use crate::events::{insert_project_event, EventType};
+use crate::projects::ProjectMemberRole;
+use sqlx::types::time::PrimitiveDateTime;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// An invitation to a project for somebody who may have no account yet, accepted with a mailed token
+#[derive(Debug, sqlx::FromRow)]
+pub struct ProjectInvitation {
+    pub id: Uuid,
+    pub project_id: Uuid,
+    pub email: String,
+    pub role: ProjectMemberRole,
+    pub invited_by: Uuid,
+    pub token_hash: String,
+    pub created_at: PrimitiveDateTime,
+    pub expires_at: PrimitiveDateTime,
+    pub accepted_at: Option<PrimitiveDateTime>,
+}
+
+#[derive(Debug)]
+pub struct ProjectInvitationInput<T1: AsRef<str>, T2: AsRef<str>> {
+    pub project_id: Uuid,
+    pub email: T1,
+    pub role: ProjectMemberRole,
+    pub invited_by: Uuid,
+    pub token_hash: T2,
+    pub expires_at: PrimitiveDateTime,
+}
+
+pub async fn insert_project_invitation<T1: AsRef<str>, T2: AsRef<str>>(
+    pool: &PgPool,
+    input: &ProjectInvitationInput<T1, T2>,
+) -> Result<Uuid, sqlx::Error> {
+    sqlx::query!(
+        r#"
+                INSERT INTO project_invitations ( id, project_id, email, role, invited_by, token_hash, created_at, expires_at )
+                SELECT $1, $2, $3, $4, $5, $6, CURRENT_TIMESTAMP, $7
+                RETURNING id
+            "#,
+        Uuid::new_v4(),
+        input.project_id,
+        input.email.as_ref(),
+        input.role as ProjectMemberRole,
+        input.invited_by,
+        input.token_hash.as_ref(),
+        input.expires_at,
+    )
+    .fetch_one(pool)
+    .await
+    .map(|x| x.id)
+}
+
+pub async fn get_project_invitation_by_hash(
+    pool: &PgPool,
+    token_hash: impl AsRef<str>,
+) -> Result<ProjectInvitation, sqlx::Error> {
+    sqlx::query_as!(
+        ProjectInvitation,
+        r#"
+                SELECT id, project_id, email, role as "role: _", invited_by, token_hash, created_at, expires_at, accepted_at
+                FROM project_invitations
+                WHERE token_hash = $1
+            "#,
+        token_hash.as_ref()
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Invitations of a project which are neither accepted nor expired, the newest first
+pub async fn get_pending_project_invitations(
+    pool: &PgPool,
+    project_id: Uuid,
+) -> Result<Vec<ProjectInvitation>, sqlx::Error> {
+    sqlx::query_as!(
+        ProjectInvitation,
+        r#"
+                SELECT id, project_id, email, role as "role: _", invited_by, token_hash, created_at, expires_at, accepted_at
+                FROM project_invitations
+                WHERE project_id = $1 and accepted_at is null and expires_at > CURRENT_TIMESTAMP
+                ORDER BY created_at DESC, id
+            "#,
+        project_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Withdraws an invitation which is not accepted yet, returns 0 if there is none
+pub async fn delete_project_invitation(
+    pool: &PgPool,
+    project_id: Uuid,
+    id: Uuid,
+) -> Result<u64, sqlx::Error> {
+    sqlx::query!(
+        r#"
+            DELETE FROM project_invitations
+            WHERE id = $1 and project_id = $2 and accepted_at is null
+        "#,
+        id,
+        project_id,
+    )
+    .execute(pool)
+    .await
+    .map(|res| res.rows_affected())
+}
+
+/// Makes a user a member with the invited role, returns the project
+///
+/// `None` when the invitation has been accepted, is expired or its project is deleted.
+/// Members keep their current role.
+pub async fn accept_project_invitation(
+    pool: &PgPool,
+    id: Uuid,
+    user_id: Uuid,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let accepted = sqlx::query!(
+        r#"
+            UPDATE project_invitations
+            SET accepted_at = CURRENT_TIMESTAMP
+            FROM projects
+            WHERE project_invitations.id = $1 and project_invitations.accepted_at is null
+                and project_invitations.expires_at > CURRENT_TIMESTAMP
+                and projects.id = project_invitations.project_id and projects.deleted_at is null
+            RETURNING project_invitations.project_id, project_invitations.role as "role: ProjectMemberRole"
+        "#,
+        id,
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+    let Some(accepted) = accepted else {
+        return Ok(None);
+    };
+
+    let added = sqlx::query!(
+        r#"
+            INSERT INTO project_members ( id, project_id, user_id, role, created_at, updated_at )
+            SELECT $1, $2, $3, $4, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP
+            ON CONFLICT (project_id, user_id) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        accepted.project_id,
+        user_id,
+        accepted.role as ProjectMemberRole,
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+    if added > 0 {
+        insert_project_event(
+            &mut transaction,
+            accepted.project_id,
+            EventType::ProjectMemberAdded,
+            user_id,
+        )
+        .await?;
+    }
+
+    transaction.commit().await?;
+    Ok(Some(accepted.project_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chats::tests::create_user;
+    use crate::pg_pool;
+    use crate::projects::tests::create_project;
+    use crate::projects::{delete_project, get_project_members};
+    use crate::utils::random_samples::RandomSample;
+    use sqlx::types::time::OffsetDateTime;
+    use time::Duration;
+
+    fn create_project_invitation_input(
+        project_id: Uuid,
+        invited_by: Uuid,
+        expires_in: Duration,
+    ) -> ProjectInvitationInput<String, String> {
+        let expires_at = OffsetDateTime::now_utc() + expires_in;
+
+        ProjectInvitationInput {
+            project_id,
+            email: format!("{}@test.test", String::new_random(16)),
+            role: ProjectMemberRole::Viewer,
+            invited_by,
+            token_hash: String::new_random(88),
+            expires_at: PrimitiveDateTime::new(expires_at.date(), expires_at.time()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_project_invitation_can_be_accepted_once() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let project = create_project(&pool).await;
+        let user = create_user(&pool).await;
+
+        let input =
+            create_project_invitation_input(project.id, project.user_id, Duration::hours(1));
+        let id = insert_project_invitation(&pool, &input)
+            .await
+            .expect("project invitation is created");
+
+        let invitation = get_project_invitation_by_hash(&pool, &input.token_hash)
+            .await
+            .expect("project invitation for a given hash");
+        assert_eq!(invitation.id, id);
+        assert_eq!(invitation.email, input.email);
+        let pending = get_pending_project_invitations(&pool, project.id)
+            .await
+            .expect("pending invitations");
+        assert_eq!(pending.iter().map(|x| x.id).collect::<Vec<_>>(), vec![id]);
+
+        let accepted = accept_project_invitation(&pool, id, user.id)
+            .await
+            .expect("invitation is accepted");
+        assert_eq!(accepted, Some(project.id));
+        let accepted = accept_project_invitation(&pool, id, user.id)
+            .await
+            .expect("query succeeded");
+        assert_eq!(accepted, None);
+
+        let members = get_project_members(&pool, project.id)
+            .await
+            .expect("members returned");
+        assert_eq!(
+            members
+                .iter()
+                .map(|x| (x.user_id, x.role))
+                .collect::<Vec<_>>(),
+            vec![
+                (project.user_id, ProjectMemberRole::Owner),
+                (user.id, ProjectMemberRole::Viewer),
+            ]
+        );
+        assert!(get_pending_project_invitations(&pool, project.id)
+            .await
+            .expect("pending invitations")
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_expired_or_revoked_project_invitation_can_not_be_accepted() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let project = create_project(&pool).await;
+        let user = create_user(&pool).await;
+
+        let input =
+            create_project_invitation_input(project.id, project.user_id, Duration::hours(-1));
+        let expired_id = insert_project_invitation(&pool, &input)
+            .await
+            .expect("project invitation is created");
+        assert_eq!(
+            accept_project_invitation(&pool, expired_id, user.id)
+                .await
+                .expect("query succeeded"),
+            None
+        );
+
+        let input =
+            create_project_invitation_input(project.id, project.user_id, Duration::hours(1));
+        let revoked_id = insert_project_invitation(&pool, &input)
+            .await
+            .expect("project invitation is created");
+        let deleted = delete_project_invitation(&pool, project.id, revoked_id)
+            .await
+            .expect("invitation is revoked");
+        assert_eq!(deleted, 1);
+        assert!(matches!(
+            get_project_invitation_by_hash(&pool, &input.token_hash).await,
+            Err(sqlx::Error::RowNotFound)
+        ));
+
+        let input =
+            create_project_invitation_input(project.id, project.user_id, Duration::hours(1));
+        let id = insert_project_invitation(&pool, &input)
+            .await
+            .expect("project invitation is created");
+        delete_project(&pool, &project.id, project.user_id)
+            .await
+            .expect("project deleted");
+        assert_eq!(
+            accept_project_invitation(&pool, id, user.id)
+                .await
+                .expect("query succeeded"),
+            None
+        );
+    }
+}
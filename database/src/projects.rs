@@ -1,8 +1,9 @@
+use crate::events::{insert_project_event, EventType};
 use sqlx::types::time::PrimitiveDateTime;
 use sqlx::PgPool;
 use uuid::Uuid;
 
-#[derive(sqlx::FromRow)]
+#[derive(Debug, sqlx::FromRow)]
 pub struct Project {
     pub id: Uuid,
     pub name: String,
@@ -23,7 +24,9 @@ pub async fn insert_project<T1: AsRef<str>, T2: AsRef<str>>(
     pool: &PgPool,
     project: &ProjectInput<T1, T2>,
 ) -> Result<Uuid, sqlx::Error> {
-    sqlx::query!(
+    let mut transaction = pool.begin().await?;
+
+    let id = sqlx::query!(
         r#"
                 INSERT INTO projects ( id, name, description, user_id, created_at, updated_at )
                 SELECT $1, $2, $3, $4, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP
@@ -34,9 +37,19 @@ pub async fn insert_project<T1: AsRef<str>, T2: AsRef<str>>(
         project.description.as_ref(),
         project.user_id
     )
-    .fetch_one(pool)
-    .await
-    .map(|x| x.id)
+    .fetch_one(&mut transaction)
+    .await?
+    .id;
+    insert_project_event(
+        &mut transaction,
+        id,
+        EventType::ProjectCreated,
+        project.user_id,
+    )
+    .await?;
+
+    transaction.commit().await?;
+    Ok(id)
 }
 
 pub async fn get_project(pool: &PgPool, id: &Uuid) -> Result<Project, sqlx::Error> {
@@ -44,7 +57,7 @@ pub async fn get_project(pool: &PgPool, id: &Uuid) -> Result<Project, sqlx::Erro
         Project,
         r#"
                 SELECT id, name, description, created_at, user_id, updated_at FROM projects
-                WHERE id = $1
+                WHERE id = $1 and deleted_at is null
             "#,
         id
     )
@@ -53,6 +66,198 @@ pub async fn get_project(pool: &PgPool, id: &Uuid) -> Result<Project, sqlx::Erro
     .map_err(Into::into)
 }
 
+/// Conditions of a project list, unset ones match every project the user can read
+#[derive(Debug, Default, Clone)]
+pub struct ProjectFilter {
+    pub owner_id: Option<Uuid>,
+    pub company_id: Option<Uuid>,
+    pub member_id: Option<Uuid>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ProjectSort {
+    #[default]
+    CreatedAt,
+    UpdatedAt,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/// The last project of a page, the next page starts right after it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProjectCursor {
+    /// `created_at` or `updated_at`, whichever the list is sorted by
+    pub sorted_at: PrimitiveDateTime,
+    pub id: Uuid,
+}
+
+impl ProjectCursor {
+    pub fn new(project: &Project, sort: ProjectSort) -> Self {
+        ProjectCursor {
+            sorted_at: match sort {
+                ProjectSort::CreatedAt => project.created_at,
+                ProjectSort::UpdatedAt => project.updated_at,
+            },
+            id: project.id,
+        }
+    }
+}
+
+/// A page of projects a user owns, is a member of or reads through a company
+///
+/// Ties of the sort column are broken by id, so pages neither skip nor repeat projects.
+pub async fn get_projects(
+    pool: &PgPool,
+    user_id: Uuid,
+    filter: &ProjectFilter,
+    sort: ProjectSort,
+    order: SortOrder,
+    after: Option<ProjectCursor>,
+    limit: i64,
+) -> Result<Vec<Project>, sqlx::Error> {
+    let by_updated_at = sort == ProjectSort::UpdatedAt;
+    let after_sorted_at = after.map(|cursor| cursor.sorted_at);
+    let after_id = after.map(|cursor| cursor.id);
+
+    match order {
+        SortOrder::Asc => {
+            sqlx::query_as!(
+                Project,
+                r#"
+                SELECT p.id, p.name, p.description, p.created_at, p.user_id, p.updated_at FROM projects p
+                WHERE p.deleted_at is null
+                    and (
+                        p.user_id = $1
+                        or EXISTS (SELECT 1 FROM project_members pm WHERE pm.project_id = p.id and pm.user_id = $1)
+                        or EXISTS (
+                            SELECT 1 FROM company_projects cp
+                            JOIN company_members cm ON cm.company_id = cp.company_id
+                            WHERE cp.project_id = p.id and cm.user_id = $1
+                        )
+                    )
+                    and ($2::uuid is null or p.user_id = $2)
+                    and ($3::uuid is null or EXISTS (SELECT 1 FROM company_projects cp WHERE cp.project_id = p.id and cp.company_id = $3))
+                    and ($4::uuid is null or EXISTS (SELECT 1 FROM project_members pm WHERE pm.project_id = p.id and pm.user_id = $4))
+                    and ($6::timestamp is null or (CASE WHEN $5 THEN p.updated_at ELSE p.created_at END, p.id) > ($6, $7))
+                ORDER BY CASE WHEN $5 THEN p.updated_at ELSE p.created_at END ASC, p.id ASC
+                LIMIT $8
+            "#,
+                user_id,
+                filter.owner_id,
+                filter.company_id,
+                filter.member_id,
+                by_updated_at,
+                after_sorted_at,
+                after_id,
+                limit,
+            )
+            .fetch_all(pool)
+            .await
+        }
+        SortOrder::Desc => {
+            sqlx::query_as!(
+                Project,
+                r#"
+                SELECT p.id, p.name, p.description, p.created_at, p.user_id, p.updated_at FROM projects p
+                WHERE p.deleted_at is null
+                    and (
+                        p.user_id = $1
+                        or EXISTS (SELECT 1 FROM project_members pm WHERE pm.project_id = p.id and pm.user_id = $1)
+                        or EXISTS (
+                            SELECT 1 FROM company_projects cp
+                            JOIN company_members cm ON cm.company_id = cp.company_id
+                            WHERE cp.project_id = p.id and cm.user_id = $1
+                        )
+                    )
+                    and ($2::uuid is null or p.user_id = $2)
+                    and ($3::uuid is null or EXISTS (SELECT 1 FROM company_projects cp WHERE cp.project_id = p.id and cp.company_id = $3))
+                    and ($4::uuid is null or EXISTS (SELECT 1 FROM project_members pm WHERE pm.project_id = p.id and pm.user_id = $4))
+                    and ($6::timestamp is null or (CASE WHEN $5 THEN p.updated_at ELSE p.created_at END, p.id) < ($6, $7))
+                ORDER BY CASE WHEN $5 THEN p.updated_at ELSE p.created_at END DESC, p.id DESC
+                LIMIT $8
+            "#,
+                user_id,
+                filter.owner_id,
+                filter.company_id,
+                filter.member_id,
+                by_updated_at,
+                after_sorted_at,
+                after_id,
+                limit,
+            )
+            .fetch_all(pool)
+            .await
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProjectUpdate {
+    pub name: String,
+    pub description: String,
+}
+
+/// Writes a project unless it changed after `updated_at`, `None` when it did or is gone
+pub async fn update_project(
+    pool: &PgPool,
+    id: &Uuid,
+    update: &ProjectUpdate,
+    updated_at: PrimitiveDateTime,
+    user_id: Uuid,
+) -> Result<Option<Project>, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let project = sqlx::query_as!(
+        Project,
+        r#"
+                UPDATE projects
+                SET name = $2, description = $3, updated_at = GREATEST(CURRENT_TIMESTAMP::timestamp, updated_at + interval '1 microsecond')
+                WHERE id = $1 and updated_at = $4 and deleted_at is null
+                RETURNING id, name, description, created_at, user_id, updated_at
+            "#,
+        id,
+        update.name,
+        update.description,
+        updated_at,
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+    if project.is_some() {
+        insert_project_event(&mut transaction, *id, EventType::ProjectUpdated, user_id).await?;
+    }
+
+    transaction.commit().await?;
+    Ok(project)
+}
+
+/// Hides a project, its members and company links are kept
+pub async fn delete_project(pool: &PgPool, id: &Uuid, user_id: Uuid) -> Result<u64, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let deleted = sqlx::query!(
+        r#"
+                UPDATE projects
+                SET deleted_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP
+                WHERE id = $1 and deleted_at is null
+            "#,
+        id,
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+    if deleted > 0 {
+        insert_project_event(&mut transaction, *id, EventType::ProjectDeleted, user_id).await?;
+    }
+
+    transaction.commit().await?;
+    Ok(deleted)
+}
+
 #[derive(sqlx::FromRow)]
 pub struct CompanyProject {
     pub id: Uuid,
@@ -82,6 +287,22 @@ pub async fn insert_company_project(
     .map(|x| x.id)
 }
 
+/// Detaches a project from a company, returns 0 if it is not attached
+pub async fn delete_company_project(
+    pool: &PgPool,
+    company_id: Uuid,
+    project_id: Uuid,
+) -> Result<u64, sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM company_projects WHERE company_id = $1 and project_id = $2",
+        company_id,
+        project_id,
+    )
+    .execute(pool)
+    .await
+    .map(|res| res.rows_affected())
+}
+
 pub async fn get_company_project(pool: &PgPool, id: Uuid) -> Result<CompanyProject, sqlx::Error> {
     sqlx::query_as!(
         CompanyProject,
@@ -96,11 +317,23 @@ pub async fn get_company_project(pool: &PgPool, id: Uuid) -> Result<CompanyProje
     .map_err(Into::into)
 }
 
+/// What a member may do, owners also manage members
+///
+/// Variants are ordered from the most to the least privileged one.
+#[derive(Debug, Clone, PartialEq, Eq, Copy, sqlx::Type)]
+#[sqlx(rename_all = "snake_case")]
+pub enum ProjectMemberRole {
+    Owner,
+    Editor,
+    Viewer,
+}
+
 #[derive(sqlx::FromRow)]
 pub struct ProjectMember {
     pub id: Uuid,
     pub project_id: Uuid,
     pub user_id: Uuid,
+    pub role: ProjectMemberRole,
     pub created_at: PrimitiveDateTime,
     pub updated_at: PrimitiveDateTime,
 }
@@ -109,27 +342,41 @@ pub async fn insert_project_member(
     pool: &PgPool,
     project_id: Uuid,
     member_id: Uuid,
+    role: ProjectMemberRole,
 ) -> Result<Uuid, sqlx::Error> {
-    sqlx::query!(
+    let mut transaction = pool.begin().await?;
+
+    let id = sqlx::query!(
         r#"
-                INSERT INTO project_members ( id, project_id, user_id, created_at, updated_at )
-                SELECT $1, $2, $3, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP
+                INSERT INTO project_members ( id, project_id, user_id, role, created_at, updated_at )
+                SELECT $1, $2, $3, $4, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP
                 RETURNING id
             "#,
         Uuid::new_v4(),
         project_id,
         member_id,
+        role as ProjectMemberRole,
     )
-    .fetch_one(pool)
-    .await
-    .map(|x| x.id)
+    .fetch_one(&mut transaction)
+    .await?
+    .id;
+    insert_project_event(
+        &mut transaction,
+        project_id,
+        EventType::ProjectMemberAdded,
+        member_id,
+    )
+    .await?;
+
+    transaction.commit().await?;
+    Ok(id)
 }
 
 pub async fn get_project_member(pool: &PgPool, id: Uuid) -> Result<ProjectMember, sqlx::Error> {
     sqlx::query_as!(
         ProjectMember,
         r#"
-                SELECT id, project_id, user_id, created_at, updated_at FROM project_members
+                SELECT id, project_id, user_id, role as "role: _", created_at, updated_at FROM project_members
                 WHERE id = $1
             "#,
         id
@@ -139,14 +386,127 @@ pub async fn get_project_member(pool: &PgPool, id: Uuid) -> Result<ProjectMember
     .map_err(Into::into)
 }
 
+/// A member of a project with the user's name, the project owner has the owner role
+#[derive(Debug, sqlx::FromRow)]
+pub struct ProjectMemberProfile {
+    pub user_id: Uuid,
+    pub email: String,
+    pub alias: Option<String>,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub role: ProjectMemberRole,
+    pub created_at: PrimitiveDateTime,
+}
+
+/// Members of a project starting with its owner, empty for an unknown or deleted project
+pub async fn get_project_members(
+    pool: &PgPool,
+    project_id: Uuid,
+) -> Result<Vec<ProjectMemberProfile>, sqlx::Error> {
+    sqlx::query_as!(
+        ProjectMemberProfile,
+        r#"
+                SELECT
+                    users.id as "user_id!",
+                    users.email as "email!",
+                    users.alias,
+                    users.first_name,
+                    users.last_name,
+                    members.role as "role!: _",
+                    members.created_at as "created_at!"
+                FROM (
+                    SELECT 0 as position, user_id, 'owner'::ProjectMemberRole as role, created_at FROM projects
+                    WHERE id = $1 and deleted_at is null
+                    UNION ALL
+                    SELECT 1, project_members.user_id, project_members.role, project_members.created_at FROM project_members
+                    JOIN projects ON projects.id = project_members.project_id
+                    WHERE project_members.project_id = $1 and project_members.user_id <> projects.user_id
+                        and projects.deleted_at is null
+                ) members
+                JOIN users ON users.id = members.user_id
+                ORDER BY members.position, members.role, members.created_at, users.id
+            "#,
+        project_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Changes a role of a member, returns 0 for somebody who is not one
+pub async fn update_project_member_role(
+    pool: &PgPool,
+    project_id: Uuid,
+    user_id: Uuid,
+    role: ProjectMemberRole,
+) -> Result<u64, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let updated = sqlx::query!(
+        r#"
+            UPDATE project_members
+            SET role = $3, updated_at = CURRENT_TIMESTAMP
+            WHERE project_id = $1 and user_id = $2
+        "#,
+        project_id,
+        user_id,
+        role as ProjectMemberRole,
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+    if updated > 0 {
+        insert_project_event(
+            &mut transaction,
+            project_id,
+            EventType::ProjectMemberUpdated,
+            user_id,
+        )
+        .await?;
+    }
+
+    transaction.commit().await?;
+    Ok(updated)
+}
+
+/// Removes a member, returns 0 for somebody who is not one
+pub async fn delete_project_member(
+    pool: &PgPool,
+    project_id: Uuid,
+    user_id: Uuid,
+) -> Result<u64, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let deleted = sqlx::query!(
+        "DELETE FROM project_members WHERE project_id = $1 and user_id = $2",
+        project_id,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+    if deleted > 0 {
+        insert_project_event(
+            &mut transaction,
+            project_id,
+            EventType::ProjectMemberRemoved,
+            user_id,
+        )
+        .await?;
+    }
+
+    transaction.commit().await?;
+    Ok(deleted)
+}
+
 #[cfg(test)]
-mod tests {
+pub mod tests {
     use super::*;
     use crate::chats::tests::create_user;
     use crate::companies::tests::create_company;
+    use crate::companies::{insert_company_member, CompanyMemberRole};
     use crate::pg_pool;
 
-    async fn create_project(pool: &PgPool) -> Project {
+    pub async fn create_project(pool: &PgPool) -> Project {
         let user_id = create_user(pool).await.id;
 
         let project_input = ProjectInput {
@@ -186,6 +546,174 @@ mod tests {
         assert_eq!(project.user_id, project_input.user_id);
     }
 
+    async fn create_user_project(pool: &PgPool, user_id: Uuid) -> Project {
+        let project_id = insert_project(
+            pool,
+            &ProjectInput {
+                name: "project name",
+                description: "project description",
+                user_id,
+            },
+        )
+        .await
+        .expect("project created");
+        get_project(pool, &project_id)
+            .await
+            .expect("project returned")
+    }
+
+    #[tokio::test]
+    async fn test_get_projects() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let user = create_user(&pool).await;
+        let other_user = create_user(&pool).await;
+
+        let owned = create_user_project(&pool, user.id).await;
+        let joined = create_user_project(&pool, other_user.id).await;
+        insert_project_member(&pool, joined.id, user.id, ProjectMemberRole::Viewer)
+            .await
+            .expect("member added");
+        let through_company = create_user_project(&pool, other_user.id).await;
+        let company = create_company(&pool).await;
+        insert_company_member(&pool, user.id, company.id, CompanyMemberRole::Member)
+            .await
+            .expect("company member added");
+        insert_company_project(&pool, company.id, through_company.id)
+            .await
+            .expect("company project created");
+        create_user_project(&pool, other_user.id).await;
+
+        let ids = |projects: Vec<Project>| projects.into_iter().map(|x| x.id).collect::<Vec<_>>();
+        let get = |filter: ProjectFilter, order, after, limit| {
+            let pool = pool.clone();
+            async move {
+                get_projects(
+                    &pool,
+                    user.id,
+                    &filter,
+                    ProjectSort::CreatedAt,
+                    order,
+                    after,
+                    limit,
+                )
+                .await
+                .expect("projects returned")
+            }
+        };
+
+        let all = get(ProjectFilter::default(), SortOrder::Asc, None, 10).await;
+        assert_eq!(ids(all), vec![owned.id, joined.id, through_company.id]);
+
+        let first_page = get(ProjectFilter::default(), SortOrder::Desc, None, 2).await;
+        let after = ProjectCursor::new(
+            first_page.last().expect("a project"),
+            ProjectSort::CreatedAt,
+        );
+        assert_eq!(ids(first_page), vec![through_company.id, joined.id]);
+        let second_page = get(ProjectFilter::default(), SortOrder::Desc, Some(after), 2).await;
+        assert_eq!(ids(second_page), vec![owned.id]);
+
+        let filter = ProjectFilter {
+            owner_id: Some(user.id),
+            ..Default::default()
+        };
+        assert_eq!(
+            ids(get(filter, SortOrder::Asc, None, 10).await),
+            vec![owned.id]
+        );
+        let filter = ProjectFilter {
+            member_id: Some(user.id),
+            ..Default::default()
+        };
+        assert_eq!(
+            ids(get(filter, SortOrder::Asc, None, 10).await),
+            vec![joined.id]
+        );
+        let filter = ProjectFilter {
+            company_id: Some(company.id),
+            ..Default::default()
+        };
+        assert_eq!(
+            ids(get(filter, SortOrder::Asc, None, 10).await),
+            vec![through_company.id]
+        );
+
+        delete_project(&pool, &owned.id, owned.user_id)
+            .await
+            .expect("project deleted");
+        let all = get(ProjectFilter::default(), SortOrder::Asc, None, 10).await;
+        assert_eq!(ids(all), vec![joined.id, through_company.id]);
+    }
+
+    #[tokio::test]
+    async fn test_update_project() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let project = create_project(&pool).await;
+        let update = ProjectUpdate {
+            name: "new name".to_owned(),
+            description: "new description".to_owned(),
+        };
+
+        let updated = update_project(
+            &pool,
+            &project.id,
+            &update,
+            project.updated_at,
+            project.user_id,
+        )
+        .await
+        .expect("query succeeded")
+        .expect("project updated");
+        assert_eq!(updated.name, update.name);
+        assert_eq!(updated.description, update.description);
+        assert!(updated.updated_at > project.updated_at);
+
+        let stale = update_project(
+            &pool,
+            &project.id,
+            &update,
+            project.updated_at,
+            project.user_id,
+        )
+        .await
+        .expect("query succeeded");
+        assert!(stale.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_delete_project() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let project = create_project(&pool).await;
+
+        let deleted = delete_project(&pool, &project.id, project.user_id)
+            .await
+            .expect("project deleted");
+        assert_eq!(deleted, 1);
+
+        let error = get_project(&pool, &project.id)
+            .await
+            .expect_err("deleted projects are hidden");
+        assert!(matches!(error, sqlx::Error::RowNotFound));
+        let deleted = delete_project(&pool, &project.id, project.user_id)
+            .await
+            .expect("query succeeded");
+        assert_eq!(deleted, 0);
+        let update = ProjectUpdate {
+            name: "new name".to_owned(),
+            description: "new description".to_owned(),
+        };
+        let updated = update_project(
+            &pool,
+            &project.id,
+            &update,
+            project.updated_at,
+            project.user_id,
+        )
+        .await
+        .expect("query succeeded");
+        assert!(updated.is_none());
+    }
+
     #[tokio::test]
     async fn test_create_company_project() {
         let pool = pg_pool().await.expect("pool is expected");
@@ -202,6 +730,11 @@ mod tests {
 
         assert_eq!(company_project.project_id, project.id);
         assert_eq!(company_project.company_id, company.id);
+
+        let deleted = delete_company_project(&pool, company.id, project.id)
+            .await
+            .expect("company project deleted");
+        assert_eq!(deleted, 1);
     }
 
     #[tokio::test]
@@ -211,14 +744,86 @@ mod tests {
         let project = create_project(&pool).await;
         let user = create_user(&pool).await;
 
-        let project_member_id = insert_project_member(&pool, project.id, user.id)
-            .await
-            .expect("company project created");
+        let project_member_id =
+            insert_project_member(&pool, project.id, user.id, ProjectMemberRole::Editor)
+                .await
+                .expect("company project created");
         let project_member = get_project_member(&pool, project_member_id)
             .await
             .expect("company project returned");
 
         assert_eq!(project_member.project_id, project.id);
         assert_eq!(project_member.user_id, user.id);
+        assert_eq!(project_member.role, ProjectMemberRole::Editor);
+    }
+
+    #[tokio::test]
+    async fn test_manage_project_members() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let project = create_project(&pool).await;
+        let viewer = create_user(&pool).await;
+        let editor = create_user(&pool).await;
+
+        insert_project_member(&pool, project.id, viewer.id, ProjectMemberRole::Viewer)
+            .await
+            .expect("viewer added");
+        insert_project_member(&pool, project.id, editor.id, ProjectMemberRole::Editor)
+            .await
+            .expect("editor added");
+        let error = insert_project_member(&pool, project.id, editor.id, ProjectMemberRole::Viewer)
+            .await
+            .expect_err("a user is a member once");
+        assert!(matches!(error, sqlx::Error::Database(_)));
+
+        let members = get_project_members(&pool, project.id)
+            .await
+            .expect("members returned");
+        assert_eq!(
+            members
+                .iter()
+                .map(|x| (x.user_id, x.role))
+                .collect::<Vec<_>>(),
+            vec![
+                (project.user_id, ProjectMemberRole::Owner),
+                (editor.id, ProjectMemberRole::Editor),
+                (viewer.id, ProjectMemberRole::Viewer),
+            ]
+        );
+
+        let updated =
+            update_project_member_role(&pool, project.id, viewer.id, ProjectMemberRole::Owner)
+                .await
+                .expect("role updated");
+        assert_eq!(updated, 1);
+        let deleted = delete_project_member(&pool, project.id, editor.id)
+            .await
+            .expect("member deleted");
+        assert_eq!(deleted, 1);
+        let deleted = delete_project_member(&pool, project.id, editor.id)
+            .await
+            .expect("query succeeded");
+        assert_eq!(deleted, 0);
+
+        let members = get_project_members(&pool, project.id)
+            .await
+            .expect("members returned");
+        assert_eq!(
+            members
+                .iter()
+                .map(|x| (x.user_id, x.role))
+                .collect::<Vec<_>>(),
+            vec![
+                (project.user_id, ProjectMemberRole::Owner),
+                (viewer.id, ProjectMemberRole::Owner),
+            ]
+        );
+
+        delete_project(&pool, &project.id, project.user_id)
+            .await
+            .expect("project deleted");
+        assert!(get_project_members(&pool, project.id)
+            .await
+            .expect("members returned")
+            .is_empty());
     }
 }
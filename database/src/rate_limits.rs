@@ -0,0 +1,229 @@
+use sqlx::types::time::PrimitiveDateTime;
+use sqlx::PgPool;
+
+/// Takes a token from a bucket, returns seconds until the next token when it is empty
+///
+/// A bucket starts full and refills continuously up to its capacity. Buckets full again are
+/// the same as missing ones, those not locked by other requests are purged on the way.
+pub async fn take_bucket_token(
+    pool: &PgPool,
+    key: impl AsRef<str>,
+    capacity: f64,
+    refill_per_second: f64,
+) -> Result<Option<f64>, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+            DELETE FROM rate_limit_buckets
+            WHERE key IN (
+                SELECT key FROM rate_limit_buckets
+                WHERE full_at <= CURRENT_TIMESTAMP
+                FOR UPDATE SKIP LOCKED
+            )
+        "#,
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    sqlx::query!(
+        r#"
+            INSERT INTO rate_limit_buckets ( key, tokens, updated_at, full_at )
+            SELECT $1, $2, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP
+            ON CONFLICT (key) DO NOTHING
+        "#,
+        key.as_ref(),
+        capacity,
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    let tokens = sqlx::query!(
+        r#"
+            SELECT LEAST($2, tokens + EXTRACT(EPOCH FROM (CURRENT_TIMESTAMP - updated_at))::float8 * $3) as "tokens!"
+            FROM rate_limit_buckets
+            WHERE key = $1
+            FOR UPDATE
+        "#,
+        key.as_ref(),
+        capacity,
+        refill_per_second,
+    )
+    .fetch_one(&mut transaction)
+    .await?
+    .tokens;
+
+    let allowed = tokens >= 1.0;
+    let tokens_left = if allowed { tokens - 1.0 } else { tokens };
+    sqlx::query!(
+        r#"
+            UPDATE rate_limit_buckets
+            SET tokens = $2, updated_at = CURRENT_TIMESTAMP, full_at = CURRENT_TIMESTAMP + make_interval(secs => $3)
+            WHERE key = $1
+        "#,
+        key.as_ref(),
+        tokens_left,
+        (capacity - tokens_left) / refill_per_second,
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok((!allowed).then(|| (1.0 - tokens) / refill_per_second))
+}
+
+/// Until when logins into an account are locked, `None` when they are not
+pub async fn get_login_lockout(
+    pool: &PgPool,
+    account: impl AsRef<str>,
+) -> Result<Option<PrimitiveDateTime>, sqlx::Error> {
+    sqlx::query!(
+        r#"
+                SELECT locked_until as "locked_until!" FROM login_failures
+                WHERE account = $1 and locked_until > CURRENT_TIMESTAMP
+            "#,
+        account.as_ref()
+    )
+    .fetch_optional(pool)
+    .await
+    .map(|x| x.map(|x| x.locked_until))
+}
+
+/// Counts a failed login, returns failures since the last successful one
+pub async fn record_login_failure(
+    pool: &PgPool,
+    account: impl AsRef<str>,
+) -> Result<i32, sqlx::Error> {
+    sqlx::query!(
+        r#"
+                INSERT INTO login_failures ( account, failures, updated_at )
+                SELECT $1, 1, CURRENT_TIMESTAMP
+                ON CONFLICT (account) DO UPDATE
+                SET failures = login_failures.failures + 1, updated_at = CURRENT_TIMESTAMP
+                RETURNING failures
+            "#,
+        account.as_ref()
+    )
+    .fetch_one(pool)
+    .await
+    .map(|x| x.failures)
+}
+
+/// Locks logins into an account for a number of seconds, returns until when
+pub async fn lock_login(
+    pool: &PgPool,
+    account: impl AsRef<str>,
+    seconds: i64,
+) -> Result<PrimitiveDateTime, sqlx::Error> {
+    sqlx::query!(
+        r#"
+                UPDATE login_failures
+                SET locked_until = CURRENT_TIMESTAMP + make_interval(secs => $2), updated_at = CURRENT_TIMESTAMP
+                WHERE account = $1
+                RETURNING locked_until as "locked_until!"
+            "#,
+        account.as_ref(),
+        seconds as f64,
+    )
+    .fetch_one(pool)
+    .await
+    .map(|x| x.locked_until)
+}
+
+/// Forgets failed logins into an account after a successful one
+pub async fn reset_login_failures(
+    pool: &PgPool,
+    account: impl AsRef<str>,
+) -> Result<u64, sqlx::Error> {
+    sqlx::query!(
+        r#"
+            DELETE FROM login_failures
+            WHERE account = $1
+        "#,
+        account.as_ref()
+    )
+    .execute(pool)
+    .await
+    .map(|res| res.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pg_pool;
+    use crate::utils::random_samples::RandomSample;
+
+    #[tokio::test]
+    async fn test_bucket_runs_out_of_tokens() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let key = std::format!("test:{}", String::new_random(32));
+
+        for _ in 0..3 {
+            let wait = take_bucket_token(&pool, &key, 3.0, 0.1)
+                .await
+                .expect("token query succeeds");
+            assert_eq!(wait, None);
+        }
+
+        let wait = take_bucket_token(&pool, &key, 3.0, 0.1)
+            .await
+            .expect("token query succeeds")
+            .expect("bucket is empty");
+        assert!(wait > 0.0 && wait <= 10.0);
+    }
+
+    #[tokio::test]
+    async fn test_full_buckets_are_purged() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let key = std::format!("test:{}", String::new_random(32));
+        let count = |key: String| {
+            let pool = pool.clone();
+            async move {
+                sqlx::query!(
+                    r#"SELECT count(*) as "count!" FROM rate_limit_buckets WHERE key = $1"#,
+                    key,
+                )
+                .fetch_one(&pool)
+                .await
+                .expect("count query")
+                .count
+            }
+        };
+
+        take_bucket_token(&pool, &key, 1.0, 10.0)
+            .await
+            .expect("token query succeeds");
+        assert_eq!(count(key.clone()).await, 1);
+
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        let other_key = std::format!("test:{}", String::new_random(32));
+        take_bucket_token(&pool, &other_key, 3.0, 0.1)
+            .await
+            .expect("token query succeeds");
+        assert_eq!(count(key).await, 0);
+        assert_eq!(count(other_key).await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_login_lockout() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let account = std::format!("{}@test.test", String::new_random(32));
+
+        assert_eq!(record_login_failure(&pool, &account).await.ok(), Some(1));
+        assert_eq!(record_login_failure(&pool, &account).await.ok(), Some(2));
+        assert_eq!(get_login_lockout(&pool, &account).await.ok(), Some(None));
+
+        let locked_until = lock_login(&pool, &account, 60)
+            .await
+            .expect("login is locked");
+        assert_eq!(
+            get_login_lockout(&pool, &account).await.ok(),
+            Some(Some(locked_until))
+        );
+
+        assert_eq!(reset_login_failures(&pool, &account).await.ok(), Some(1));
+        assert_eq!(get_login_lockout(&pool, &account).await.ok(), Some(None));
+        assert_eq!(record_login_failure(&pool, &account).await.ok(), Some(1));
+    }
+}
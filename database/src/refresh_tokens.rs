@@ -0,0 +1,184 @@
+use sqlx::types::time::PrimitiveDateTime;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(sqlx::FromRow)]
+pub struct RefreshToken {
+    pub id: Uuid,
+    pub family_id: Uuid,
+    pub user_id: Uuid,
+    pub token_hash: String,
+    pub created_at: PrimitiveDateTime,
+    pub expires_at: PrimitiveDateTime,
+    pub used_at: Option<PrimitiveDateTime>,
+    pub revoked_at: Option<PrimitiveDateTime>,
+}
+
+#[derive(Debug)]
+pub struct RefreshTokenInput<T: AsRef<str>> {
+    pub family_id: Uuid,
+    pub user_id: Uuid,
+    pub token_hash: T,
+    pub expires_at: PrimitiveDateTime,
+}
+
+pub async fn insert_refresh_token<T: AsRef<str>>(
+    pool: &PgPool,
+    input: &RefreshTokenInput<T>,
+) -> Result<Uuid, sqlx::Error> {
+    sqlx::query!(
+        r#"
+                INSERT INTO refresh_tokens ( id, family_id, user_id, token_hash, created_at, expires_at )
+                SELECT $1, $2, $3, $4, CURRENT_TIMESTAMP, $5
+                RETURNING id
+            "#,
+        Uuid::new_v4(),
+        input.family_id,
+        input.user_id,
+        input.token_hash.as_ref(),
+        input.expires_at,
+    )
+    .fetch_one(pool)
+    .await
+    .map(|x| x.id)
+}
+
+pub async fn get_refresh_token_by_hash(
+    pool: &PgPool,
+    token_hash: impl AsRef<str>,
+) -> Result<RefreshToken, sqlx::Error> {
+    sqlx::query_as!(
+        RefreshToken,
+        r#"
+                SELECT id, family_id, user_id, token_hash, created_at, expires_at, used_at, revoked_at FROM refresh_tokens
+                WHERE token_hash = $1
+            "#,
+        token_hash.as_ref()
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Marks a token as rotated, returns 0 if it has already been used or revoked
+pub async fn use_refresh_token(pool: &PgPool, id: Uuid) -> Result<u64, sqlx::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE refresh_tokens
+            SET used_at = CURRENT_TIMESTAMP
+            WHERE id = $1 and used_at is null and revoked_at is null
+        "#,
+        id,
+    )
+    .execute(pool)
+    .await
+    .map(|res| res.rows_affected())
+}
+
+pub async fn revoke_refresh_token_family(
+    pool: &PgPool,
+    family_id: Uuid,
+) -> Result<u64, sqlx::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE refresh_tokens
+            SET revoked_at = CURRENT_TIMESTAMP
+            WHERE family_id = $1 and revoked_at is null
+        "#,
+        family_id,
+    )
+    .execute(pool)
+    .await
+    .map(|res| res.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chats::tests::create_user;
+    use crate::pg_pool;
+    use crate::sessions::tests::create_session;
+    use crate::utils::random_samples::RandomSample;
+    use sqlx::types::time::OffsetDateTime;
+    use time::Duration;
+
+    fn create_refresh_token_input(user_id: Uuid, family_id: Uuid) -> RefreshTokenInput<String> {
+        let expires_at = OffsetDateTime::now_utc() + Duration::days(1);
+
+        RefreshTokenInput {
+            family_id,
+            user_id,
+            token_hash: String::new_random(88),
+            expires_at: PrimitiveDateTime::new(expires_at.date(), expires_at.time()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_refresh_token() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let user = create_user(&pool).await;
+        let session = create_session(&pool, user.id).await;
+
+        let input = create_refresh_token_input(user.id, session.id);
+
+        let id = insert_refresh_token(&pool, &input)
+            .await
+            .expect("refresh token is created");
+
+        let token = get_refresh_token_by_hash(&pool, &input.token_hash)
+            .await
+            .expect("refresh token for a given hash");
+
+        assert_eq!(token.id, id);
+        assert_eq!(token.family_id, input.family_id);
+        assert_eq!(token.user_id, user.id);
+        assert_eq!(token.used_at, None);
+        assert_eq!(token.revoked_at, None);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_token_can_be_used_once() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let user = create_user(&pool).await;
+        let session = create_session(&pool, user.id).await;
+
+        let input = create_refresh_token_input(user.id, session.id);
+        let id = insert_refresh_token(&pool, &input)
+            .await
+            .expect("refresh token is created");
+
+        assert_eq!(use_refresh_token(&pool, id).await.expect("used"), 1);
+        assert_eq!(use_refresh_token(&pool, id).await.expect("used"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_revoke_refresh_token_family() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let user = create_user(&pool).await;
+        let family_id = create_session(&pool, user.id).await.id;
+        let other_family_id = create_session(&pool, user.id).await.id;
+
+        let first = create_refresh_token_input(user.id, family_id);
+        let second = create_refresh_token_input(user.id, family_id);
+        let other_family = create_refresh_token_input(user.id, other_family_id);
+        for input in [&first, &second, &other_family] {
+            insert_refresh_token(&pool, input)
+                .await
+                .expect("refresh token is created");
+        }
+
+        let revoked = revoke_refresh_token_family(&pool, family_id)
+            .await
+            .expect("family revoked");
+        assert_eq!(revoked, 2);
+
+        let token = get_refresh_token_by_hash(&pool, &second.token_hash)
+            .await
+            .expect("refresh token for a given hash");
+        assert!(token.revoked_at.is_some());
+
+        let token = get_refresh_token_by_hash(&pool, &other_family.token_hash)
+            .await
+            .expect("refresh token for a given hash");
+        assert_eq!(token.revoked_at, None);
+    }
+}
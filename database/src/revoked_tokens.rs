@@ -0,0 +1,180 @@
+use sqlx::types::time::PrimitiveDateTime;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// An access token killed before its expiration, keyed by its `jti`
+#[derive(Debug)]
+pub struct RevokedTokenInput {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub expires_at: PrimitiveDateTime,
+}
+
+/// Adds a token to the denylist, returns 0 if it is already there
+///
+/// Entries of already expired tokens are purged on the way, such tokens are rejected anyway.
+pub async fn insert_revoked_token(
+    pool: &PgPool,
+    input: &RevokedTokenInput,
+) -> Result<u64, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+            DELETE FROM revoked_tokens
+            WHERE expires_at < CURRENT_TIMESTAMP
+        "#,
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    let inserted = sqlx::query!(
+        r#"
+                INSERT INTO revoked_tokens ( id, user_id, revoked_at, expires_at )
+                SELECT $1, $2, CURRENT_TIMESTAMP, $3
+                ON CONFLICT (id) DO NOTHING
+            "#,
+        input.id,
+        input.user_id,
+        input.expires_at,
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+
+    transaction.commit().await?;
+
+    Ok(inserted)
+}
+
+/// Takes a token off the denylist, returns a number of removed entries
+pub async fn delete_revoked_token(pool: &PgPool, id: Uuid) -> Result<u64, sqlx::Error> {
+    sqlx::query!(
+        r#"
+            DELETE FROM revoked_tokens
+            WHERE id = $1
+        "#,
+        id,
+    )
+    .execute(pool)
+    .await
+    .map(|res| res.rows_affected())
+}
+
+/// Whether a token is denylisted itself or was issued for a session revoked since
+pub async fn is_token_revoked(
+    pool: &PgPool,
+    id: Uuid,
+    session_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query!(
+        r#"
+                SELECT EXISTS(SELECT 1 FROM revoked_tokens WHERE id = $1)
+                    OR EXISTS(SELECT 1 FROM sessions WHERE id = $2 and revoked_at is not null)
+                    as "revoked!"
+            "#,
+        id,
+        session_id
+    )
+    .fetch_one(pool)
+    .await
+    .map(|x| x.revoked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chats::tests::create_user;
+    use crate::pg_pool;
+    use crate::sessions::revoke_session;
+    use crate::sessions::tests::create_session;
+    use sqlx::types::time::OffsetDateTime;
+    use time::Duration;
+
+    fn create_revoked_token_input(user_id: Uuid, expires_in: Duration) -> RevokedTokenInput {
+        let expires_at = OffsetDateTime::now_utc() + expires_in;
+
+        RevokedTokenInput {
+            id: Uuid::new_v4(),
+            user_id,
+            expires_at: PrimitiveDateTime::new(expires_at.date(), expires_at.time()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_revoke_token() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let user = create_user(&pool).await;
+
+        let input = create_revoked_token_input(user.id, Duration::minutes(15));
+
+        assert!(!is_token_revoked(&pool, input.id, Uuid::new_v4())
+            .await
+            .expect("query"));
+
+        let inserted = insert_revoked_token(&pool, &input)
+            .await
+            .expect("token is revoked");
+        assert_eq!(inserted, 1);
+        // Revoking twice is fine, only the first one counts
+        let inserted = insert_revoked_token(&pool, &input)
+            .await
+            .expect("token is revoked");
+        assert_eq!(inserted, 0);
+
+        assert!(is_token_revoked(&pool, input.id, Uuid::new_v4())
+            .await
+            .expect("query"));
+
+        let deleted = delete_revoked_token(&pool, input.id)
+            .await
+            .expect("token is taken off");
+        assert_eq!(deleted, 1);
+        assert!(!is_token_revoked(&pool, input.id, Uuid::new_v4())
+            .await
+            .expect("query"));
+    }
+
+    #[tokio::test]
+    async fn test_expired_entries_are_purged() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let user = create_user(&pool).await;
+
+        let expired = create_revoked_token_input(user.id, -Duration::minutes(1));
+        insert_revoked_token(&pool, &expired)
+            .await
+            .expect("token is revoked");
+
+        let input = create_revoked_token_input(user.id, Duration::minutes(15));
+        insert_revoked_token(&pool, &input)
+            .await
+            .expect("token is revoked");
+
+        assert!(!is_token_revoked(&pool, expired.id, Uuid::new_v4())
+            .await
+            .expect("query"));
+        assert!(is_token_revoked(&pool, input.id, Uuid::new_v4())
+            .await
+            .expect("query"));
+    }
+
+    #[tokio::test]
+    async fn test_tokens_of_revoked_sessions_are_revoked() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let user = create_user(&pool).await;
+        let session = create_session(&pool, user.id).await;
+
+        let token_id = Uuid::new_v4();
+        assert!(!is_token_revoked(&pool, token_id, session.id)
+            .await
+            .expect("query"));
+
+        revoke_session(&pool, session.id, user.id)
+            .await
+            .expect("session is revoked");
+
+        assert!(is_token_revoked(&pool, token_id, session.id)
+            .await
+            .expect("query"));
+    }
+}
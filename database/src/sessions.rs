@@ -0,0 +1,301 @@
+use sqlx::types::time::PrimitiveDateTime;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// A signed in device, its id is the family id of the refresh tokens it rotates
+#[derive(sqlx::FromRow)]
+pub struct Session {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub device_name: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: PrimitiveDateTime,
+    pub last_seen_at: PrimitiveDateTime,
+    pub revoked_at: Option<PrimitiveDateTime>,
+}
+
+#[derive(Debug)]
+pub struct SessionInput<T1: AsRef<str>, T2: AsRef<str>, T3: AsRef<str>> {
+    pub user_id: Uuid,
+    pub device_name: Option<T1>,
+    pub user_agent: Option<T2>,
+    pub ip_address: Option<T3>,
+}
+
+pub async fn insert_session<T1: AsRef<str>, T2: AsRef<str>, T3: AsRef<str>>(
+    pool: &PgPool,
+    input: &SessionInput<T1, T2, T3>,
+) -> Result<Uuid, sqlx::Error> {
+    sqlx::query!(
+        r#"
+                INSERT INTO sessions ( id, user_id, device_name, user_agent, ip_address, created_at, last_seen_at )
+                SELECT $1, $2, $3, $4, $5, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP
+                RETURNING id
+            "#,
+        Uuid::new_v4(),
+        input.user_id,
+        input.device_name.as_ref().map(|x| x.as_ref()),
+        input.user_agent.as_ref().map(|x| x.as_ref()),
+        input.ip_address.as_ref().map(|x| x.as_ref()),
+    )
+    .fetch_one(pool)
+    .await
+    .map(|x| x.id)
+}
+
+pub async fn get_session(pool: &PgPool, id: Uuid) -> Result<Session, sqlx::Error> {
+    sqlx::query_as!(
+        Session,
+        r#"
+                SELECT id, user_id, device_name, user_agent, ip_address, created_at, last_seen_at, revoked_at FROM sessions
+                WHERE id = $1
+            "#,
+        id
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Not revoked sessions of a user, the most recently seen first
+pub async fn get_active_sessions(
+    pool: &PgPool,
+    user_id: Uuid,
+) -> Result<Vec<Session>, sqlx::Error> {
+    sqlx::query_as!(
+        Session,
+        r#"
+                SELECT id, user_id, device_name, user_agent, ip_address, created_at, last_seen_at, revoked_at FROM sessions
+                WHERE user_id = $1 and revoked_at is null
+                ORDER BY last_seen_at DESC
+            "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn touch_session(pool: &PgPool, id: Uuid) -> Result<u64, sqlx::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE sessions
+            SET last_seen_at = CURRENT_TIMESTAMP
+            WHERE id = $1
+        "#,
+        id,
+    )
+    .execute(pool)
+    .await
+    .map(|res| res.rows_affected())
+}
+
+/// Revokes a session of a given user with all its refresh tokens, returns a number of revoked sessions
+pub async fn revoke_session(pool: &PgPool, id: Uuid, user_id: Uuid) -> Result<u64, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let revoked = sqlx::query!(
+        r#"
+            UPDATE sessions
+            SET revoked_at = CURRENT_TIMESTAMP
+            WHERE id = $1 and user_id = $2 and revoked_at is null
+        "#,
+        id,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+
+    sqlx::query!(
+        r#"
+            UPDATE refresh_tokens
+            SET revoked_at = CURRENT_TIMESTAMP
+            WHERE family_id = $1 and user_id = $2 and revoked_at is null
+        "#,
+        id,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(revoked)
+}
+
+/// Revokes every session of a user with all their refresh tokens, returns a number of revoked sessions
+pub async fn revoke_user_sessions(pool: &PgPool, user_id: Uuid) -> Result<u64, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let revoked = sqlx::query!(
+        r#"
+            UPDATE sessions
+            SET revoked_at = CURRENT_TIMESTAMP
+            WHERE user_id = $1 and revoked_at is null
+        "#,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+
+    sqlx::query!(
+        r#"
+            UPDATE refresh_tokens
+            SET revoked_at = CURRENT_TIMESTAMP
+            WHERE user_id = $1 and revoked_at is null
+        "#,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(revoked)
+}
+
+/// Revokes every session of a user but a given one, with their refresh tokens,
+/// returns a number of revoked sessions
+pub async fn revoke_other_user_sessions(
+    pool: &PgPool,
+    user_id: Uuid,
+    session_id: Uuid,
+) -> Result<u64, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let revoked = sqlx::query!(
+        r#"
+            UPDATE sessions
+            SET revoked_at = CURRENT_TIMESTAMP
+            WHERE user_id = $1 and id <> $2 and revoked_at is null
+        "#,
+        user_id,
+        session_id,
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+
+    sqlx::query!(
+        r#"
+            UPDATE refresh_tokens
+            SET revoked_at = CURRENT_TIMESTAMP
+            WHERE user_id = $1 and family_id <> $2 and revoked_at is null
+        "#,
+        user_id,
+        session_id,
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(revoked)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::chats::tests::create_user;
+    use crate::pg_pool;
+
+    pub async fn create_session(pool: &PgPool, user_id: Uuid) -> Session {
+        let input = SessionInput {
+            user_id,
+            device_name: Some("phone"),
+            user_agent: Some("exchange/1.0"),
+            ip_address: Some("127.0.0.1"),
+        };
+
+        let id = insert_session(pool, &input)
+            .await
+            .expect("session is created");
+
+        get_session(pool, id).await.expect("session for a given id")
+    }
+
+    #[tokio::test]
+    async fn test_create_session() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let user = create_user(&pool).await;
+
+        let session = create_session(&pool, user.id).await;
+
+        assert_eq!(session.user_id, user.id);
+        assert_eq!(session.device_name.as_deref(), Some("phone"));
+        assert_eq!(session.user_agent.as_deref(), Some("exchange/1.0"));
+        assert_eq!(session.ip_address.as_deref(), Some("127.0.0.1"));
+        assert_eq!(session.revoked_at, None);
+    }
+
+    #[tokio::test]
+    async fn test_revoke_session() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let user = create_user(&pool).await;
+        let other_user = create_user(&pool).await;
+
+        let first = create_session(&pool, user.id).await;
+        let second = create_session(&pool, user.id).await;
+
+        let revoked = revoke_session(&pool, first.id, other_user.id)
+            .await
+            .expect("revoke query");
+        assert_eq!(revoked, 0);
+
+        let revoked = revoke_session(&pool, first.id, user.id)
+            .await
+            .expect("revoke query");
+        assert_eq!(revoked, 1);
+
+        let sessions = get_active_sessions(&pool, user.id)
+            .await
+            .expect("active sessions");
+        assert_eq!(
+            sessions.iter().map(|x| x.id).collect::<Vec<_>>(),
+            vec![second.id]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_revoke_user_sessions() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let user = create_user(&pool).await;
+
+        create_session(&pool, user.id).await;
+        create_session(&pool, user.id).await;
+
+        let revoked = revoke_user_sessions(&pool, user.id)
+            .await
+            .expect("revoke query");
+        assert_eq!(revoked, 2);
+
+        let sessions = get_active_sessions(&pool, user.id)
+            .await
+            .expect("active sessions");
+        assert!(sessions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_revoke_other_user_sessions() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let user = create_user(&pool).await;
+
+        let current = create_session(&pool, user.id).await;
+        create_session(&pool, user.id).await;
+        create_session(&pool, user.id).await;
+
+        let revoked = revoke_other_user_sessions(&pool, user.id, current.id)
+            .await
+            .expect("revoke query");
+        assert_eq!(revoked, 2);
+
+        let sessions = get_active_sessions(&pool, user.id)
+            .await
+            .expect("active sessions");
+        assert_eq!(
+            sessions.iter().map(|x| x.id).collect::<Vec<_>>(),
+            vec![current.id]
+        );
+    }
+}
@@ -0,0 +1,532 @@
+use crate::audit_events::AuditEvent;
+use crate::chats::{ChatMember, ChatMessage};
+use crate::companies::CompanyMember;
+use crate::projects::{Project, ProjectMember};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Everything stored about a user apart from the `users` row
+pub struct UserData {
+    pub projects: Vec<Project>,
+    pub project_memberships: Vec<ProjectMember>,
+    pub company_memberships: Vec<CompanyMember>,
+    pub chat_memberships: Vec<ChatMember>,
+    pub chat_messages: Vec<ChatMessage>,
+    pub audit_events: Vec<AuditEvent>,
+}
+
+/// Reads data of a user from a single snapshot
+pub async fn get_user_data(pool: &PgPool, user_id: Uuid) -> Result<UserData, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+    sqlx::query!("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+        .execute(&mut transaction)
+        .await?;
+
+    let projects = sqlx::query_as!(
+        Project,
+        r#"
+                SELECT id, name, description, created_at, user_id, updated_at FROM projects
+                WHERE user_id = $1
+                ORDER BY created_at
+            "#,
+        user_id
+    )
+    .fetch_all(&mut transaction)
+    .await?;
+
+    let project_memberships = sqlx::query_as!(
+        ProjectMember,
+        r#"
+                SELECT id, project_id, user_id, role as "role: _", created_at, updated_at FROM project_members
+                WHERE user_id = $1
+                ORDER BY created_at
+            "#,
+        user_id
+    )
+    .fetch_all(&mut transaction)
+    .await?;
+
+    let company_memberships = sqlx::query_as!(
+        CompanyMember,
+        r#"
+                SELECT id, user_id, company_id, role as "role: _", created_at, updated_at FROM company_members
+                WHERE user_id = $1
+                ORDER BY created_at
+            "#,
+        user_id
+    )
+    .fetch_all(&mut transaction)
+    .await?;
+
+    let chat_memberships = sqlx::query_as!(
+        ChatMember,
+        r#"
+                SELECT chat_member.id, chat_id, member, role as "role: _", last_read_message_id, chat_member.created_at, chat_member.updated_at
+                FROM chat_member
+                JOIN users ON users.alias = chat_member.member
+                WHERE users.id = $1
+                ORDER BY chat_member.created_at
+            "#,
+        user_id
+    )
+    .fetch_all(&mut transaction)
+    .await?;
+
+    let chat_messages = sqlx::query_as!(
+        ChatMessage,
+        r#"
+                SELECT id, chat_id, sender_id, message, parent_id, created_at, updated_at, edited_at, deleted_at FROM chat_messages
+                WHERE sender_id = $1
+                ORDER BY created_at
+            "#,
+        user_id
+    )
+    .fetch_all(&mut transaction)
+    .await?;
+
+    let audit_events = sqlx::query_as!(
+        AuditEvent,
+        r#"
+                SELECT id, event_type as "event_type: _", user_id, resource_id, ip_address, user_agent, created_at
+                FROM audit_events
+                WHERE user_id = $1
+                ORDER BY created_at
+            "#,
+        user_id
+    )
+    .fetch_all(&mut transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(UserData {
+        projects,
+        project_memberships,
+        company_memberships,
+        chat_memberships,
+        chat_messages,
+        audit_events,
+    })
+}
+
+/// What happened to the data of a deleted user
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DeletedUserData {
+    /// Projects handed over to their longest standing member
+    pub transferred_projects: u64,
+    /// Projects without other members
+    pub deleted_projects: u64,
+    pub redacted_messages: u64,
+}
+
+/// Erases a user, fails with `RowNotFound` for an unknown or already deleted user
+///
+/// The `users` row is anonymised rather than deleted, so messages and audit events keep
+/// a valid id. Authored messages and their revisions are redacted, memberships, credentials and sessions are
+/// dropped. Audit events are append-only and kept as they are.
+pub async fn delete_user(pool: &PgPool, user_id: Uuid) -> Result<DeletedUserData, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let user = sqlx::query!(
+        r#"
+                SELECT alias, email FROM users
+                WHERE id = $1 and deleted_at is null
+                FOR UPDATE
+            "#,
+        user_id
+    )
+    .fetch_one(&mut transaction)
+    .await?;
+
+    // Chat members are stored by an alias, which is cleared below
+    sqlx::query!(
+        r#"
+            UPDATE chat_member
+            SET member = $2, role = 'left', updated_at = CURRENT_TIMESTAMP
+            WHERE member = $1
+        "#,
+        user.alias,
+        std::format!("deleted:{user_id}"),
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    sqlx::query!(
+        r#"
+            DELETE FROM chat_message_revisions
+            WHERE message_id IN (SELECT id FROM chat_messages WHERE sender_id = $1)
+        "#,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await?;
+    let redacted_messages = sqlx::query!(
+        r#"
+            UPDATE chat_messages
+            SET message = '', deleted_at = COALESCE(deleted_at, CURRENT_TIMESTAMP), updated_at = CURRENT_TIMESTAMP
+            WHERE sender_id = $1 and message <> ''
+        "#,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+
+    let transferred_projects = sqlx::query!(
+        r#"
+            UPDATE projects
+            SET user_id = heir.user_id, updated_at = CURRENT_TIMESTAMP
+            FROM (
+                SELECT DISTINCT ON (project_id) project_id, user_id FROM project_members
+                WHERE user_id <> $1
+                ORDER BY project_id, role, created_at
+            ) heir
+            WHERE projects.id = heir.project_id and projects.user_id = $1
+        "#,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await?
+    .rows_affected();
+
+    sqlx::query!(
+        r#"
+            DELETE FROM project_invitations
+            WHERE invited_by = $1
+                or project_id IN (SELECT id FROM projects WHERE user_id = $1)
+        "#,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+            DELETE FROM project_members
+            WHERE user_id = $1
+                or project_id IN (SELECT id FROM projects WHERE user_id = $1)
+        "#,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+            DELETE FROM company_projects
+            WHERE project_id IN (SELECT id FROM projects WHERE user_id = $1)
+        "#,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+            DELETE FROM events
+            WHERE project_id IN (SELECT id FROM projects WHERE user_id = $1)
+        "#,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await?;
+    let deleted_projects = sqlx::query!("DELETE FROM projects WHERE user_id = $1", user_id)
+        .execute(&mut transaction)
+        .await?
+        .rows_affected();
+
+    // Companies the user owns alone go to the most privileged, longest standing member
+    sqlx::query!(
+        r#"
+            UPDATE company_members
+            SET role = 'owner', updated_at = CURRENT_TIMESTAMP
+            WHERE id IN (
+                SELECT DISTINCT ON (heir.company_id) heir.id FROM company_members heir
+                JOIN company_members owned ON owned.company_id = heir.company_id
+                WHERE owned.user_id = $1 and owned.role = 'owner' and heir.user_id <> $1
+                    and NOT EXISTS (
+                        SELECT 1 FROM company_members owners
+                        WHERE owners.company_id = heir.company_id and owners.role = 'owner' and owners.user_id <> $1
+                    )
+                ORDER BY heir.company_id, heir.role, heir.created_at
+            )
+        "#,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query!("DELETE FROM company_members WHERE user_id = $1", user_id)
+        .execute(&mut transaction)
+        .await?;
+    sqlx::query!(
+        "DELETE FROM company_invitations WHERE invited_by = $1",
+        user_id
+    )
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query!("DELETE FROM user_identities WHERE user_id = $1", user_id)
+        .execute(&mut transaction)
+        .await?;
+    sqlx::query!("DELETE FROM mfa_recovery_codes WHERE user_id = $1", user_id)
+        .execute(&mut transaction)
+        .await?;
+    sqlx::query!(
+        "DELETE FROM password_reset_tokens WHERE user_id = $1",
+        user_id
+    )
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM email_change_tokens WHERE user_id = $1",
+        user_id
+    )
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query!("DELETE FROM admins WHERE user_id = $1", user_id)
+        .execute(&mut transaction)
+        .await?;
+    sqlx::query!(
+        "DELETE FROM login_failures WHERE account = lower($1)",
+        user.email
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    sqlx::query!(
+        r#"
+            UPDATE api_keys
+            SET revoked_at = CURRENT_TIMESTAMP
+            WHERE user_id = $1 and revoked_at is null
+        "#,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+            UPDATE sessions
+            SET revoked_at = COALESCE(revoked_at, CURRENT_TIMESTAMP), device_name = null, user_agent = null, ip_address = null
+            WHERE user_id = $1
+        "#,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+            UPDATE refresh_tokens
+            SET revoked_at = CURRENT_TIMESTAMP
+            WHERE user_id = $1 and revoked_at is null
+        "#,
+        user_id,
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    // No password matches "!" and the email is no address, so nobody signs in as the user again
+    sqlx::query!(
+        r#"
+            UPDATE users
+            SET alias = null, first_name = null, last_name = null, email = $2, password_hash = '!',
+                phone_number = null, avatar = null, country_code = null, email_verified_at = null,
+                totp_secret = null, totp_enabled_at = null, totp_last_step = null,
+                updated_at = CURRENT_TIMESTAMP, deleted_at = CURRENT_TIMESTAMP
+            WHERE id = $1
+        "#,
+        user_id,
+        std::format!("deleted:{user_id}"),
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(DeletedUserData {
+        transferred_projects,
+        deleted_projects,
+        redacted_messages,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit_events::{insert_audit_event, AuditEventInput, AuditEventType};
+    use crate::chats::tests::{create_chat, create_user};
+    use crate::chats::{
+        get_chat_member, get_chat_message, insert_chat_member, insert_chat_message, ChatMemberRole,
+    };
+    use crate::companies::tests::create_company;
+    use crate::companies::{get_company_members, insert_company_member, CompanyMemberRole};
+    use crate::pg_pool;
+    use crate::project_invitations::{insert_project_invitation, ProjectInvitationInput};
+    use crate::projects::{
+        get_project, insert_project, insert_project_member, ProjectInput, ProjectMemberRole,
+    };
+    use crate::users::get_user;
+    use crate::utils::random_samples::RandomSample;
+    use sqlx::types::time::{OffsetDateTime, PrimitiveDateTime};
+    use time::Duration;
+
+    async fn create_owned_project(pool: &PgPool, user_id: Uuid) -> Uuid {
+        insert_project(
+            pool,
+            &ProjectInput {
+                name: "project name",
+                description: "project description",
+                user_id,
+            },
+        )
+        .await
+        .expect("project created")
+    }
+
+    #[tokio::test]
+    async fn test_get_user_data() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let user = create_user(&pool).await;
+        let chat = create_chat(&pool).await;
+
+        let project_id = create_owned_project(&pool, user.id).await;
+        insert_chat_member(
+            &pool,
+            chat.id,
+            user.alias.as_deref().expect("alias"),
+            ChatMemberRole::Member,
+        )
+        .await
+        .expect("chat member created");
+        insert_chat_message(&pool, chat.id, user.id, "hello", None)
+            .await
+            .expect("chat message created");
+        insert_audit_event(
+            &pool,
+            &AuditEventInput::<&str, &str> {
+                event_type: AuditEventType::Registered,
+                user_id: user.id,
+                resource_id: None,
+                ip_address: None,
+                user_agent: None,
+            },
+        )
+        .await
+        .expect("audit event is created");
+
+        let data = get_user_data(&pool, user.id).await.expect("user data");
+        assert_eq!(
+            data.projects.iter().map(|x| x.id).collect::<Vec<_>>(),
+            vec![project_id]
+        );
+        assert!(data.project_memberships.is_empty());
+        assert!(data.company_memberships.is_empty());
+        assert_eq!(data.chat_memberships.len(), 1);
+        assert_eq!(data.chat_messages[0].message, "hello");
+        assert_eq!(data.audit_events.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_delete_user() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let user = create_user(&pool).await;
+        let viewer = create_user(&pool).await;
+        let member = create_user(&pool).await;
+        let chat = create_chat(&pool).await;
+
+        let shared_project_id = create_owned_project(&pool, user.id).await;
+        insert_project_member(
+            &pool,
+            shared_project_id,
+            viewer.id,
+            ProjectMemberRole::Viewer,
+        )
+        .await
+        .expect("project member is created");
+        insert_project_member(
+            &pool,
+            shared_project_id,
+            member.id,
+            ProjectMemberRole::Editor,
+        )
+        .await
+        .expect("project member is created");
+        let expires_at = OffsetDateTime::now_utc() + Duration::hours(1);
+        insert_project_invitation(
+            &pool,
+            &ProjectInvitationInput {
+                project_id: shared_project_id,
+                email: format!("{}@test.test", String::new_random(16)),
+                role: ProjectMemberRole::Viewer,
+                invited_by: user.id,
+                token_hash: String::new_random(88),
+                expires_at: PrimitiveDateTime::new(expires_at.date(), expires_at.time()),
+            },
+        )
+        .await
+        .expect("project invitation is created");
+        let own_project_id = create_owned_project(&pool, user.id).await;
+        let company = create_company(&pool).await;
+        insert_company_member(&pool, user.id, company.id, CompanyMemberRole::Owner)
+            .await
+            .expect("company owner is created");
+        insert_company_member(&pool, member.id, company.id, CompanyMemberRole::Member)
+            .await
+            .expect("company member is created");
+        let chat_member_id = insert_chat_member(
+            &pool,
+            chat.id,
+            user.alias.as_deref().expect("alias"),
+            ChatMemberRole::Member,
+        )
+        .await
+        .expect("chat member created");
+        let message_id = insert_chat_message(&pool, chat.id, user.id, "hello", None)
+            .await
+            .expect("chat message created");
+
+        let deleted = delete_user(&pool, user.id).await.expect("user is deleted");
+        assert_eq!(
+            deleted,
+            DeletedUserData {
+                transferred_projects: 1,
+                deleted_projects: 1,
+                redacted_messages: 1,
+            }
+        );
+
+        let project = get_project(&pool, &shared_project_id)
+            .await
+            .expect("transferred project");
+        assert_eq!(project.user_id, member.id);
+        assert!(get_project(&pool, &own_project_id).await.is_err());
+        let company_members = get_company_members(&pool, company.id)
+            .await
+            .expect("company members");
+        assert_eq!(
+            company_members
+                .iter()
+                .map(|x| (x.user_id, x.role))
+                .collect::<Vec<_>>(),
+            vec![(member.id, CompanyMemberRole::Owner)]
+        );
+
+        let message = get_chat_message(&pool, message_id)
+            .await
+            .expect("redacted message");
+        assert_eq!(message.message, "");
+        assert!(message.deleted_at.is_some());
+
+        let chat_member = get_chat_member(&pool, chat_member_id)
+            .await
+            .expect("chat member");
+        assert_eq!(chat_member.role, ChatMemberRole::Left);
+        assert_ne!(Some(chat_member.member), user.alias);
+
+        let deleted_user = get_user(&pool, &user.id).await.expect("anonymised user");
+        assert!(deleted_user.deleted_at.is_some());
+        assert_eq!(deleted_user.alias, None);
+        assert_eq!(deleted_user.first_name, None);
+        assert_ne!(deleted_user.email, user.email);
+
+        assert!(matches!(
+            delete_user(&pool, user.id).await,
+            Err(sqlx::Error::RowNotFound)
+        ));
+    }
+}
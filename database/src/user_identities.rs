@@ -0,0 +1,202 @@
+use crate::users::{User, UserInput};
+use sqlx::types::time::PrimitiveDateTime;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// An account of a user at an external identity provider
+#[derive(sqlx::FromRow)]
+pub struct UserIdentity {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub issuer: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub created_at: PrimitiveDateTime,
+}
+
+#[derive(Debug)]
+pub struct UserIdentityInput<T1: AsRef<str>, T2: AsRef<str>, T3: AsRef<str>> {
+    pub user_id: Uuid,
+    pub issuer: T1,
+    pub subject: T2,
+    pub email: Option<T3>,
+}
+
+pub async fn insert_user_identity<T1: AsRef<str>, T2: AsRef<str>, T3: AsRef<str>>(
+    pool: &PgPool,
+    input: &UserIdentityInput<T1, T2, T3>,
+) -> Result<Uuid, sqlx::Error> {
+    sqlx::query!(
+        r#"
+                INSERT INTO user_identities ( id, user_id, issuer, subject, email, created_at )
+                SELECT $1, $2, $3, $4, $5, CURRENT_TIMESTAMP
+                RETURNING id
+            "#,
+        Uuid::new_v4(),
+        input.user_id,
+        input.issuer.as_ref(),
+        input.subject.as_ref(),
+        input.email.as_ref().map(|x| x.as_ref()),
+    )
+    .fetch_one(pool)
+    .await
+    .map(|x| x.id)
+}
+
+pub async fn get_user_identities(
+    pool: &PgPool,
+    user_id: Uuid,
+) -> Result<Vec<UserIdentity>, sqlx::Error> {
+    sqlx::query_as!(
+        UserIdentity,
+        r#"
+                SELECT id, user_id, issuer, subject, email, created_at FROM user_identities
+                WHERE user_id = $1
+                ORDER BY created_at
+            "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// A user linked to an identity, `RowNotFound` if nobody is
+pub async fn get_user_by_identity(
+    pool: &PgPool,
+    issuer: impl AsRef<str>,
+    subject: impl AsRef<str>,
+) -> Result<User, sqlx::Error> {
+    sqlx::query_as!(
+            User,
+            r#"
+                SELECT u.id, u.alias, u.first_name, u.last_name, u.email, u.password_hash, u.phone_number, u.language_code, u.avatar, u.country_code, u.created_at, u.updated_at, u.accessed_at, u.email_verified_at, u.totp_secret, u.totp_enabled_at, u.deleted_at FROM users u
+                JOIN user_identities i ON i.user_id = u.id
+                WHERE i.issuer = $1 and i.subject = $2
+            "#,
+            issuer.as_ref(),
+            subject.as_ref()
+        )
+        .fetch_one(pool)
+        .await
+}
+
+/// Registers a user signed up through an identity provider, linked to the identity right away
+///
+/// The email is trusted as verified only when the provider says so.
+pub async fn insert_user_with_identity<I1: AsRef<str>, I2: AsRef<str>>(
+    pool: &PgPool,
+    user_input: &UserInput,
+    issuer: I1,
+    subject: I2,
+    email_verified: bool,
+) -> Result<Uuid, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let user_id = sqlx::query!(
+            r#"
+                INSERT INTO users ( id, alias, first_name, last_name, email, password_hash, phone_number, language_code, avatar, country_code, created_at, updated_at, accessed_at, email_verified_at )
+                SELECT $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP, CASE WHEN $11 THEN CURRENT_TIMESTAMP END
+                RETURNING id
+            "#,
+            user_input.user_id,
+            user_input.alias,
+            user_input.first_name,
+            user_input.last_name,
+            user_input.email,
+            user_input.password_hash,
+            user_input.phone_number,
+            user_input.language_code,
+            user_input.avatar,
+            user_input.country_code,
+            email_verified
+        )
+        .fetch_one(&mut transaction)
+        .await?
+        .id;
+
+    sqlx::query!(
+        r#"
+                INSERT INTO user_identities ( id, user_id, issuer, subject, email, created_at )
+                SELECT $1, $2, $3, $4, $5, CURRENT_TIMESTAMP
+            "#,
+        Uuid::new_v4(),
+        user_id,
+        issuer.as_ref(),
+        subject.as_ref(),
+        user_input.email,
+    )
+    .execute(&mut transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(user_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chats::tests::create_user;
+    use crate::pg_pool;
+    use crate::users::tests::create_random_user_inputs;
+    use crate::utils::random_samples::RandomSample;
+
+    const ISSUER: &str = "https://idp.test.test";
+
+    #[tokio::test]
+    async fn test_link_an_identity() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let user = create_user(&pool).await;
+        let subject = String::new_random(32);
+
+        let result = get_user_by_identity(&pool, ISSUER, &subject).await;
+        assert!(matches!(result, Err(sqlx::Error::RowNotFound)));
+
+        insert_user_identity(
+            &pool,
+            &UserIdentityInput {
+                user_id: user.id,
+                issuer: ISSUER,
+                subject: &subject,
+                email: Some(&user.email),
+            },
+        )
+        .await
+        .expect("identity is linked");
+
+        let linked_user = get_user_by_identity(&pool, ISSUER, &subject)
+            .await
+            .expect("a linked user");
+        assert_eq!(linked_user.id, user.id);
+
+        let identities = get_user_identities(&pool, user.id)
+            .await
+            .expect("identities of a user");
+        assert_eq!(identities.len(), 1);
+        assert_eq!(identities[0].subject, subject);
+    }
+
+    #[tokio::test]
+    async fn test_insert_user_with_identity() {
+        let pool = pg_pool().await.expect("pool is expected");
+        let user_input = create_random_user_inputs();
+        let subject = String::new_random(32);
+
+        let id = insert_user_with_identity(&pool, &user_input, ISSUER, &subject, true)
+            .await
+            .expect("user is created");
+
+        let user = get_user_by_identity(&pool, ISSUER, &subject)
+            .await
+            .expect("a linked user");
+        assert_eq!(user.id, id);
+        assert_eq!(user.email, user_input.email);
+        assert!(user.email_verified_at.is_some());
+
+        // An identity links a single user
+        let result =
+            insert_user_with_identity(&pool, &create_random_user_inputs(), ISSUER, &subject, false)
+                .await;
+        assert!(result.is_err());
+    }
+}
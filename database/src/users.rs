@@ -9,10 +9,7 @@ pub struct User {
     pub first_name: Option<String>,
     pub last_name: Option<String>,
     pub email: String,
-    pub password_salt: String,
-    pub password_sha512: String,
-    pub access_token: String,
-    pub previous_access_token: Option<String>,
+    pub password_hash: String,
     pub phone_number: Option<String>,
     pub language_code: String,
     pub avatar: Option<String>,
@@ -20,120 +17,55 @@ pub struct User {
     pub created_at: PrimitiveDateTime,
     pub updated_at: PrimitiveDateTime,
     pub accessed_at: PrimitiveDateTime,
+    pub email_verified_at: Option<PrimitiveDateTime>,
+    pub totp_secret: Option<String>,
+    pub totp_enabled_at: Option<PrimitiveDateTime>,
+    /// Set once the account is deleted, the other personal fields are cleared then
+    pub deleted_at: Option<PrimitiveDateTime>,
 }
 
-#[derive(Debug)]
-pub struct UserInput<
-    T1: AsRef<str>,
-    T2: AsRef<str>,
-    T3: AsRef<str>,
-    T4: AsRef<str>,
-    T5: AsRef<str>,
-    T6: AsRef<str>,
-    T7: AsRef<str>,
-    T8: AsRef<str>,
-    T9: AsRef<str>,
-    T10: AsRef<str>,
-    T11: AsRef<str>,
-> {
+#[derive(Debug, Clone)]
+pub struct UserInput {
     pub user_id: Uuid,
-    pub alias: Option<T1>,
-    pub first_name: Option<T2>,
-    pub last_name: Option<T3>,
-    pub email: T4,
-    pub password_salt: T5,
-    pub password_sha512: T6,
-    pub access_token: T7,
-    pub phone_number: Option<T8>,
-    pub language_code: T9,
-    pub avatar: Option<T10>,
-    pub country_code: Option<T11>,
+    pub alias: Option<String>,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub email: String,
+    pub password_hash: String,
+    pub phone_number: Option<String>,
+    pub language_code: String,
+    pub avatar: Option<String>,
+    pub country_code: Option<String>,
 }
 
-pub async fn insert_user<
-    T1: AsRef<str>,
-    T2: AsRef<str>,
-    T3: AsRef<str>,
-    T4: AsRef<str>,
-    T5: AsRef<str>,
-    T6: AsRef<str>,
-    T7: AsRef<str>,
-    T8: AsRef<str>,
-    T9: AsRef<str>,
-    T10: AsRef<str>,
-    T11: AsRef<str>,
->(
-    pool: &PgPool,
-    user_input: &UserInput<T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11>,
-) -> Result<Uuid, sqlx::Error> {
+pub async fn insert_user(pool: &PgPool, user_input: &UserInput) -> Result<Uuid, sqlx::Error> {
     sqlx::query!(
             r#"
-                INSERT INTO users ( id, alias, first_name, last_name, email, password_salt, password_sha512, access_token, phone_number, language_code, avatar, country_code, created_at, updated_at, accessed_at )
-                SELECT $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP
+                INSERT INTO users ( id, alias, first_name, last_name, email, password_hash, phone_number, language_code, avatar, country_code, created_at, updated_at, accessed_at )
+                SELECT $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP
                 RETURNING id
             "#,
             user_input.user_id,
-            user_input.alias.as_ref().map(|x| x.as_ref()),
-            user_input.first_name.as_ref().map(|x| x.as_ref()),
-            user_input.last_name.as_ref().map(|x| x.as_ref()),
-            user_input.email.as_ref(),
-            user_input.password_salt.as_ref(),
-            user_input.password_sha512.as_ref(),
-            user_input.access_token.as_ref(),
-            user_input.phone_number.as_ref().map(|x| x.as_ref()),
-            user_input.language_code.as_ref(),
-            user_input.avatar.as_ref().map(|x| x.as_ref()),
-            user_input.country_code.as_ref().map(|x| x.as_ref())
+            user_input.alias,
+            user_input.first_name,
+            user_input.last_name,
+            user_input.email,
+            user_input.password_hash,
+            user_input.phone_number,
+            user_input.language_code,
+            user_input.avatar,
+            user_input.country_code,
         )
         .fetch_one(pool)
         .await
         .map(|x| x.id)
 }
 
-pub async fn get_access_token(pool: &PgPool, id: &Uuid) -> Result<String, sqlx::Error> {
-    struct Result {
-        access_token: String,
-    }
-    sqlx::query_as!(
-        Result,
-        r#"
-                SELECT access_token FROM users
-                WHERE id = $1
-            "#,
-        id
-    )
-    .fetch_one(pool)
-    .await
-    .map(|x| x.access_token)
-    .map_err(Into::into)
-}
-
-pub async fn update_user_token(
-    pool: &PgPool,
-    id: &Uuid,
-    token: impl AsRef<str>,
-    previous_token: impl AsRef<str>,
-) -> Result<u64, sqlx::Error> {
-    sqlx::query!(
-        r#"
-            UPDATE users
-            SET access_token = $1, previous_access_token = $2
-            WHERE id = $3 and (previous_access_token != $2 or previous_access_token is null)
-        "#,
-        token.as_ref(),
-        previous_token.as_ref(),
-        id,
-    )
-    .execute(pool)
-    .await
-    .map(|res| res.rows_affected())
-}
-
 pub async fn get_user(pool: &PgPool, id: &Uuid) -> Result<User, sqlx::Error> {
     sqlx::query_as!(
             User,
             r#"
-                SELECT id, alias, first_name, last_name, email, password_salt, password_sha512, phone_number, access_token, previous_access_token, language_code, avatar, country_code, created_at, updated_at, accessed_at FROM users
+                SELECT id, alias, first_name, last_name, email, password_hash, phone_number, language_code, avatar, country_code, created_at, updated_at, accessed_at, email_verified_at, totp_secret, totp_enabled_at, deleted_at FROM users
                 WHERE id = $1
             "#,
             id
@@ -150,7 +82,7 @@ pub async fn get_user_by_email(
     sqlx::query_as!(
             User,
             r#"
-                SELECT id, alias, first_name, last_name, email, password_salt, password_sha512, phone_number, access_token, previous_access_token, language_code, avatar, country_code, created_at, updated_at, accessed_at FROM users
+                SELECT id, alias, first_name, last_name, email, password_hash, phone_number, language_code, avatar, country_code, created_at, updated_at, accessed_at, email_verified_at, totp_secret, totp_enabled_at, deleted_at FROM users
                 WHERE email = $1
             "#,
             email.as_ref()
@@ -160,40 +92,187 @@ pub async fn get_user_by_email(
         .map_err(Into::into)
 }
 
+pub async fn update_password_hash(
+    pool: &PgPool,
+    id: &Uuid,
+    password_hash: impl AsRef<str>,
+) -> Result<u64, sqlx::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE users
+            SET password_hash = $2, updated_at = CURRENT_TIMESTAMP
+            WHERE id = $1
+        "#,
+        id,
+        password_hash.as_ref(),
+    )
+    .execute(pool)
+    .await
+    .map(|res| res.rows_affected())
+}
+
+/// Marks an email as verified, returns 0 if the user has changed it or it is already verified
+pub async fn verify_email(
+    pool: &PgPool,
+    id: &Uuid,
+    email: impl AsRef<str>,
+) -> Result<u64, sqlx::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE users
+            SET email_verified_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP
+            WHERE id = $1 and email = $2 and email_verified_at is null
+        "#,
+        id,
+        email.as_ref(),
+    )
+    .execute(pool)
+    .await
+    .map(|res| res.rows_affected())
+}
+
+/// Swaps an email for one confirmed through a mailed link, so it is verified at once
+///
+/// Fails with a unique violation of `users_email_index` when another user has the email.
+pub async fn update_email(
+    pool: &PgPool,
+    id: &Uuid,
+    email: impl AsRef<str>,
+) -> Result<u64, sqlx::Error> {
+    sqlx::query!(
+        r#"
+            UPDATE users
+            SET email = $2, email_verified_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP
+            WHERE id = $1
+        "#,
+        id,
+        email.as_ref(),
+    )
+    .execute(pool)
+    .await
+    .map(|res| res.rows_affected())
+}
+
+/// Returns the avatar it replaced, `None` also when the user has no row
+pub async fn update_user_avatar(
+    pool: &PgPool,
+    id: &Uuid,
+    avatar: Option<&str>,
+) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+            UPDATE users u
+            SET avatar = $2, updated_at = CURRENT_TIMESTAMP
+            FROM users old
+            WHERE u.id = $1 AND old.id = u.id
+            RETURNING old.avatar
+        "#,
+        id,
+        avatar,
+    )
+    .fetch_optional(pool)
+    .await
+    .map(Option::flatten)
+}
+
+/// Profile fields a user edits, every field is written as it is
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserProfileUpdate {
+    pub alias: Option<String>,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub phone_number: Option<String>,
+    pub language_code: String,
+    pub country_code: Option<String>,
+}
+
+/// Fails with a unique violation of `users_alias_index` or `users_phone_number_index`
+/// when another user has taken an alias or a phone number
+///
+/// Chat members are stored by an alias, so memberships follow a new alias. Clearing
+/// the alias leaves its chats, as deleting an account does, so that the next user
+/// to claim the alias does not inherit them.
+pub async fn update_user_profile(
+    pool: &PgPool,
+    id: &Uuid,
+    update: &UserProfileUpdate,
+) -> Result<u64, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let previous = sqlx::query!(
+        r#"
+            UPDATE users
+            SET alias = $2, first_name = $3, last_name = $4, phone_number = $5, language_code = $6, country_code = $7, updated_at = CURRENT_TIMESTAMP
+            FROM (SELECT alias FROM users WHERE id = $1 FOR UPDATE) as previous
+            WHERE id = $1
+            RETURNING previous.alias
+        "#,
+        id,
+        update.alias,
+        update.first_name,
+        update.last_name,
+        update.phone_number,
+        update.language_code,
+        update.country_code,
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+    let Some(previous) = previous else {
+        return Ok(0);
+    };
+
+    match (previous.alias, &update.alias) {
+        (Some(previous_alias), Some(alias)) => {
+            sqlx::query!(
+                r#"
+                    UPDATE chat_member
+                    SET member = $2, updated_at = CURRENT_TIMESTAMP
+                    WHERE member = $1
+                "#,
+                previous_alias,
+                alias,
+            )
+            .execute(&mut transaction)
+            .await?;
+        }
+        (Some(previous_alias), None) => {
+            sqlx::query!(
+                r#"
+                    UPDATE chat_member
+                    SET member = $2, role = 'left', updated_at = CURRENT_TIMESTAMP
+                    WHERE member = $1
+                "#,
+                previous_alias,
+                std::format!("deleted:{id}"),
+            )
+            .execute(&mut transaction)
+            .await?;
+        }
+        (None, _) => {}
+    }
+
+    transaction.commit().await?;
+    Ok(1)
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
     use crate::pg_pool;
     use crate::utils::random_samples::RandomSample;
 
-    type TestUserInputs = UserInput<
-        String,
-        String,
-        String,
-        String,
-        String,
-        String,
-        String,
-        String,
-        &'static str,
-        &'static str,
-        &'static str,
-    >;
-
     // TODO generate normal password
-    pub fn create_random_user_inputs() -> TestUserInputs {
+    pub fn create_random_user_inputs() -> UserInput {
         let user_id = Uuid::new_v4();
         let alias = Some(format!("vova:{}", String::new_random(22)));
         let first_name = Some("volodymyr".to_owned());
         let last_name = Some("gorbenko".to_owned());
         let email = format!("em:{:?}@test.test", String::new_random(32));
-        let password_salt = String::new_random(22);
-        let password_sha512 = format!("ph:{}", String::new_random(22));
-        let access_token = String::new_random(1025);
+        let password_hash = format!("ph:{}", String::new_random(22));
         let phone_number = Some(String::new_random(15));
-        let language_code = "ru-ru";
-        let avatar = Some("https://some_image.png");
-        let country_code = Some("SW");
+        let language_code = "ru-ru".to_owned();
+        let avatar = Some("https://some_image.png".to_owned());
+        let country_code = Some("SW".to_owned());
 
         UserInput {
             user_id,
@@ -201,9 +280,7 @@ pub mod tests {
             first_name,
             last_name,
             email,
-            password_salt,
-            password_sha512,
-            access_token,
+            password_hash,
             phone_number,
             language_code,
             avatar,
@@ -229,16 +306,11 @@ pub mod tests {
         assert_eq!(user_input.first_name, user.first_name);
         assert_eq!(user_input.last_name, user.last_name);
         assert_eq!(user_input.email, user.email);
-        assert_eq!(user_input.password_salt, user.password_salt);
-        assert_eq!(user_input.password_sha512, user.password_sha512);
-        assert_eq!(user_input.access_token, user.access_token);
+        assert_eq!(user_input.password_hash, user.password_hash);
         assert_eq!(user_input.phone_number, user.phone_number);
         assert_eq!(user_input.language_code, user.language_code);
-        assert_eq!(user_input.avatar, user.avatar.as_ref().map(|x| x.as_ref()));
-        assert_eq!(
-            user_input.country_code,
-            user.country_code.as_ref().map(|x| x.as_ref())
-        );
+        assert_eq!(user_input.avatar, user.avatar);
+        assert_eq!(user_input.country_code, user.country_code);
     }
 
     #[tokio::test]
@@ -260,20 +332,15 @@ pub mod tests {
         assert_eq!(user_input.first_name, user.first_name);
         assert_eq!(user_input.last_name, user.last_name);
         assert_eq!(user_input.email, user.email);
-        assert_eq!(user_input.password_salt, user.password_salt);
-        assert_eq!(user_input.password_sha512, user.password_sha512);
-        assert_eq!(user_input.access_token, user.access_token);
+        assert_eq!(user_input.password_hash, user.password_hash);
         assert_eq!(user_input.phone_number, user.phone_number);
         assert_eq!(user_input.language_code, user.language_code);
-        assert_eq!(user_input.avatar, user.avatar.as_ref().map(|x| x.as_ref()));
-        assert_eq!(
-            user_input.country_code,
-            user.country_code.as_ref().map(|x| x.as_ref())
-        );
+        assert_eq!(user_input.avatar, user.avatar);
+        assert_eq!(user_input.country_code, user.country_code);
     }
 
     #[tokio::test]
-    async fn test_update_token() {
+    async fn test_update_password_hash() {
         let pool = pg_pool().await.expect("pool is expected");
 
         let user_input = create_random_user_inputs();
@@ -282,19 +349,161 @@ pub mod tests {
             .await
             .expect("user is created");
 
-        let user = get_user_by_email(&pool, &user_input.email)
+        let password_hash = format!("ph:{}", String::new_random(22));
+        let updated = update_password_hash(&pool, &id, &password_hash)
+            .await
+            .expect("password hash is updated");
+        assert_eq!(updated, 1);
+
+        let user = get_user(&pool, &id)
+            .await
+            .expect("user for given id is expected");
+
+        assert_eq!(user.password_hash, password_hash);
+    }
+
+    #[tokio::test]
+    async fn test_verify_email() {
+        let pool = pg_pool().await.expect("pool is expected");
+
+        let user_input = create_random_user_inputs();
+
+        let id = insert_user(&pool, &user_input)
+            .await
+            .expect("user is created");
+
+        let user = get_user(&pool, &id)
             .await
             .expect("user for given id is expected");
+        assert_eq!(user.email_verified_at, None);
 
-        assert_eq!(user_input.access_token, user.access_token);
+        let verified = verify_email(&pool, &id, "other@test.test")
+            .await
+            .expect("verify query");
+        assert_eq!(verified, 0);
 
-        let access_token = String::new_random(1025);
+        let verified = verify_email(&pool, &id, &user_input.email)
+            .await
+            .expect("verify query");
+        assert_eq!(verified, 1);
 
-        _ = update_user_token(&pool, &id, &access_token, &user.access_token).await;
+        let verified = verify_email(&pool, &id, &user_input.email)
+            .await
+            .expect("verify query");
+        assert_eq!(verified, 0);
 
-        let user = get_user_by_email(&pool, &user_input.email)
+        let user = get_user(&pool, &id)
             .await
             .expect("user for given id is expected");
-        assert_eq!(access_token, user.access_token);
+        assert!(user.email_verified_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_update_email() {
+        let pool = pg_pool().await.expect("pool is expected");
+
+        let user_input = create_random_user_inputs();
+        let id = insert_user(&pool, &user_input)
+            .await
+            .expect("user is created");
+        let other_user_input = create_random_user_inputs();
+        insert_user(&pool, &other_user_input)
+            .await
+            .expect("user is created");
+
+        let email = format!("em:{}@test.test", String::new_random(32));
+        let updated = update_email(&pool, &id, &email)
+            .await
+            .expect("email is updated");
+        assert_eq!(updated, 1);
+
+        let user = get_user(&pool, &id)
+            .await
+            .expect("user for given id is expected");
+        assert_eq!(user.email, email);
+        assert!(user.email_verified_at.is_some());
+
+        let error = update_email(&pool, &id, &other_user_input.email)
+            .await
+            .expect_err("email is taken");
+        let constraint = error
+            .as_database_error()
+            .and_then(|error| error.constraint().map(String::from));
+        assert_eq!(constraint.as_deref(), Some("users_email_index"));
+    }
+
+    #[tokio::test]
+    async fn test_update_user_avatar() {
+        let pool = pg_pool().await.expect("pool is expected");
+
+        let user_input = create_random_user_inputs();
+        let id = insert_user(&pool, &user_input)
+            .await
+            .expect("user is created");
+
+        let avatar = format!("https://media.test/{}.png", String::new_random(16));
+        let replaced = update_user_avatar(&pool, &id, Some(&avatar))
+            .await
+            .expect("avatar is updated");
+        assert_eq!(replaced, user_input.avatar);
+
+        let replaced = update_user_avatar(&pool, &id, None)
+            .await
+            .expect("avatar is removed");
+        assert_eq!(replaced, Some(avatar));
+
+        let user = get_user(&pool, &id)
+            .await
+            .expect("user for given id is expected");
+        assert_eq!(user.avatar, None);
+    }
+
+    #[tokio::test]
+    async fn test_update_user_profile() {
+        let pool = pg_pool().await.expect("pool is expected");
+
+        let user_input = create_random_user_inputs();
+        let id = insert_user(&pool, &user_input)
+            .await
+            .expect("user is created");
+        let other_user_input = create_random_user_inputs();
+        insert_user(&pool, &other_user_input)
+            .await
+            .expect("user is created");
+
+        let update = UserProfileUpdate {
+            alias: Some(format!("alias:{}", String::new_random(22))),
+            first_name: None,
+            last_name: Some("last".to_owned()),
+            phone_number: None,
+            language_code: "en".to_owned(),
+            country_code: Some("GB".to_owned()),
+        };
+        let updated = update_user_profile(&pool, &id, &update)
+            .await
+            .expect("profile is updated");
+        assert_eq!(updated, 1);
+
+        let user = get_user(&pool, &id)
+            .await
+            .expect("user for given id is expected");
+        assert_eq!(user.alias, update.alias);
+        assert_eq!(user.first_name, None);
+        assert_eq!(user.last_name, update.last_name);
+        assert_eq!(user.phone_number, None);
+        assert_eq!(user.language_code, update.language_code);
+        assert_eq!(user.country_code, update.country_code);
+
+        let taken_alias = UserProfileUpdate {
+            alias: other_user_input.alias,
+            ..update
+        };
+        let error = update_user_profile(&pool, &id, &taken_alias)
+            .await
+            .expect_err("alias is taken");
+        let constraint = error
+            .as_database_error()
+            .and_then(|error| error.constraint().map(String::from));
+        assert_eq!(constraint.as_deref(), Some("users_alias_index"));
     }
 }